@@ -0,0 +1,18 @@
+-- The Postgres schema stored energy amounts, prices and token balances as DECIMAL(10,2), which
+-- the service reads and writes as f64 and which rounded sub-cent prices and fractional kWh.
+-- Store them as DOUBLE PRECISION, matching the REAL columns of the SQLite schema.
+ALTER TABLE prosumers
+    ALTER COLUMN energy_generated TYPE DOUBLE PRECISION,
+    ALTER COLUMN energy_consumed TYPE DOUBLE PRECISION,
+    ALTER COLUMN grid_tokens TYPE DOUBLE PRECISION,
+    ALTER COLUMN watt_tokens TYPE DOUBLE PRECISION;
+
+ALTER TABLE orders
+    ALTER COLUMN energy_amount TYPE DOUBLE PRECISION,
+    ALTER COLUMN price_per_unit TYPE DOUBLE PRECISION,
+    ALTER COLUMN total_price TYPE DOUBLE PRECISION;
+
+ALTER TABLE trades
+    ALTER COLUMN energy_amount TYPE DOUBLE PRECISION,
+    ALTER COLUMN price_per_unit TYPE DOUBLE PRECISION,
+    ALTER COLUMN total_price TYPE DOUBLE PRECISION;
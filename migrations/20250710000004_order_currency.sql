@@ -0,0 +1,8 @@
+-- Multi-currency order quoting
+-- price_per_unit stays normalized to the base currency so matching is unchanged;
+-- the original quote is kept alongside it.
+
+ALTER TABLE orders ADD COLUMN currency VARCHAR(10) NOT NULL DEFAULT 'GRID';
+ALTER TABLE orders ADD COLUMN quoted_price_per_unit DOUBLE PRECISION NOT NULL DEFAULT 0;
+
+UPDATE orders SET quoted_price_per_unit = price_per_unit;
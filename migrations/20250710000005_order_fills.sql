@@ -0,0 +1,6 @@
+-- Partial fill tracking
+-- filled_amount accumulates matched energy; an order completes once it reaches energy_amount.
+
+ALTER TABLE orders ADD COLUMN filled_amount DOUBLE PRECISION NOT NULL DEFAULT 0;
+
+UPDATE orders SET filled_amount = energy_amount WHERE status = 'completed';
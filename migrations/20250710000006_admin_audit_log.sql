@@ -0,0 +1,12 @@
+-- Audit trail for admin overrides (e.g. force-cancelling another prosumer's order)
+
+CREATE TABLE IF NOT EXISTS admin_audit_log (
+    id UUID PRIMARY KEY,
+    admin_id VARCHAR(255) NOT NULL,
+    action VARCHAR(100) NOT NULL,
+    target_id VARCHAR(255) NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_admin_audit_log_target ON admin_audit_log(target_id);
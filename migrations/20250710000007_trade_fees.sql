@@ -0,0 +1,3 @@
+-- Grid fee charged on each trade, computed from the tiered fee schedule at execution time
+
+ALTER TABLE trades ADD COLUMN grid_fee DOUBLE PRECISION NOT NULL DEFAULT 0;
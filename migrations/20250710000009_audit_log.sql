@@ -0,0 +1,14 @@
+-- Audit trail of every mutating API request (POST/PUT/PATCH/DELETE), including failures
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id UUID PRIMARY KEY,
+    user_id VARCHAR(255),
+    method VARCHAR(10) NOT NULL,
+    path TEXT NOT NULL,
+    request_id VARCHAR(255) NOT NULL,
+    status_code BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log(created_at);
+CREATE INDEX IF NOT EXISTS idx_audit_log_user_id ON audit_log(user_id);
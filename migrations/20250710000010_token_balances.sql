@@ -0,0 +1,34 @@
+-- Token balances move out of fixed prosumer columns into one row per (address, token type),
+-- so new token types only need a row in token_types
+
+CREATE TABLE IF NOT EXISTS token_types (
+    name VARCHAR(64) PRIMARY KEY,
+    description TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO token_types (name, description) VALUES
+    ('grid_tokens', 'GRID utility token'),
+    ('watt_tokens', 'WATT energy credit token')
+ON CONFLICT (name) DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS balances (
+    address VARCHAR(255) NOT NULL REFERENCES prosumers(address) ON DELETE CASCADE,
+    token_type VARCHAR(64) NOT NULL REFERENCES token_types(name),
+    amount DOUBLE PRECISION NOT NULL DEFAULT 0 CHECK (amount >= 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (address, token_type)
+);
+
+CREATE INDEX IF NOT EXISTS idx_balances_token_type ON balances(token_type);
+
+INSERT INTO balances (address, token_type, amount, updated_at)
+SELECT address, 'grid_tokens', CAST(grid_tokens AS DOUBLE PRECISION), COALESCE(updated_at, CURRENT_TIMESTAMP)
+FROM prosumers WHERE COALESCE(grid_tokens, 0) > 0;
+
+INSERT INTO balances (address, token_type, amount, updated_at)
+SELECT address, 'watt_tokens', CAST(watt_tokens AS DOUBLE PRECISION), COALESCE(updated_at, CURRENT_TIMESTAMP)
+FROM prosumers WHERE COALESCE(watt_tokens, 0) > 0;
+
+ALTER TABLE prosumers DROP COLUMN grid_tokens;
+ALTER TABLE prosumers DROP COLUMN watt_tokens;
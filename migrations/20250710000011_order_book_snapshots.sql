@@ -0,0 +1,10 @@
+-- Point-in-time copies of the aggregated order book, used to detect drift against live orders
+
+CREATE TABLE IF NOT EXISTS order_book_snapshots (
+    id UUID PRIMARY KEY,
+    book TEXT NOT NULL, -- JSON-encoded AggregatedBook
+    checksum VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_order_book_snapshots_created_at ON order_book_snapshots(created_at);
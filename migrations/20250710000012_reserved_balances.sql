@@ -0,0 +1,15 @@
+-- Buy orders escrow grid_tokens: `amount` stays the spendable balance and
+-- `reserved_balance` holds what open buy orders have committed.
+ALTER TABLE balances ADD COLUMN reserved_balance DOUBLE PRECISION NOT NULL DEFAULT 0 CHECK (reserved_balance >= 0);
+
+-- Reserve what existing open buy orders still need, capped at each holder's balance
+UPDATE balances
+SET reserved_balance = LEAST(balances.amount, committed.total),
+    amount = balances.amount - LEAST(balances.amount, committed.total)
+FROM (
+    SELECT prosumer_address, SUM((energy_amount - filled_amount) * price_per_unit) AS total
+    FROM orders
+    WHERE order_type = 'buy' AND status IN ('pending', 'active')
+    GROUP BY prosumer_address
+) committed
+WHERE balances.address = committed.prosumer_address AND balances.token_type = 'grid_tokens';
@@ -0,0 +1,8 @@
+-- Open orders past their expires_at are marked `expired` by the expiry sweep
+ALTER TABLE orders DROP CONSTRAINT IF EXISTS orders_status_check;
+ALTER TABLE orders ADD CONSTRAINT orders_status_check
+    CHECK (status IN ('pending', 'active', 'completed', 'cancelled', 'expired'));
+
+-- The sweep looks for open orders by expiry time
+CREATE INDEX IF NOT EXISTS idx_orders_open_expiry ON orders(expires_at)
+    WHERE status IN ('pending', 'active') AND expires_at IS NOT NULL;
@@ -0,0 +1,10 @@
+-- Trades past the retention window are moved here by the archival job. The table mirrors
+-- `trades` column for column (so the two can be UNIONed) but drops the foreign keys, letting
+-- archived history outlive the orders it references.
+CREATE TABLE IF NOT EXISTS trades_archive (LIKE trades INCLUDING DEFAULTS);
+
+ALTER TABLE trades_archive ADD PRIMARY KEY (id);
+
+CREATE INDEX IF NOT EXISTS idx_trades_archive_created_at ON trades_archive(created_at);
+CREATE INDEX IF NOT EXISTS idx_trades_archive_buyer ON trades_archive(buyer_address);
+CREATE INDEX IF NOT EXISTS idx_trades_archive_seller ON trades_archive(seller_address);
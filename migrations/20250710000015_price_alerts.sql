@@ -0,0 +1,16 @@
+-- One-shot alerts on the best bid/ask; an alert is marked triggered the first time it fires
+
+CREATE TABLE IF NOT EXISTS price_alerts (
+    id UUID PRIMARY KEY,
+    address VARCHAR(255) NOT NULL REFERENCES prosumers(address) ON DELETE CASCADE,
+    side VARCHAR(10) NOT NULL,      -- 'bid' or 'ask'
+    direction VARCHAR(10) NOT NULL, -- 'above' or 'below'
+    price DOUBLE PRECISION NOT NULL,
+    triggered BOOLEAN NOT NULL DEFAULT FALSE,
+    triggered_price DOUBLE PRECISION,
+    triggered_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_price_alerts_pending ON price_alerts(triggered, side);
+CREATE INDEX IF NOT EXISTS idx_price_alerts_address ON price_alerts(address);
@@ -0,0 +1,9 @@
+-- Energy and trade value each prosumer has bought plus sold per UTC day, for daily limits
+
+CREATE TABLE IF NOT EXISTS daily_trade_usage (
+    address VARCHAR(255) NOT NULL,
+    trade_date VARCHAR(10) NOT NULL, -- YYYY-MM-DD, UTC
+    energy_traded DOUBLE PRECISION NOT NULL DEFAULT 0,
+    notional_traded DOUBLE PRECISION NOT NULL DEFAULT 0,
+    PRIMARY KEY (address, trade_date)
+);
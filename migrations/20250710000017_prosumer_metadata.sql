@@ -0,0 +1,3 @@
+-- Free-form string tags (region, type, feeder, ...) as a JSON object, used to scope listings and stats
+
+ALTER TABLE prosumers ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,13 @@
+-- History of token transfers between prosumers, for account statements
+
+CREATE TABLE IF NOT EXISTS token_transfers (
+    id UUID PRIMARY KEY,
+    from_address VARCHAR(255) NOT NULL,
+    to_address VARCHAR(255) NOT NULL,
+    token_type VARCHAR(50) NOT NULL,
+    amount DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_token_transfers_from ON token_transfers(from_address, created_at);
+CREATE INDEX IF NOT EXISTS idx_token_transfers_to ON token_transfers(to_address, created_at);
@@ -0,0 +1,13 @@
+-- With a settlement delay configured, trades stay `pending` until `settles_at`, when the
+-- settlement worker pays the seller. Until then either party can move the trade to `disputed`,
+-- which holds the payment until an admin settles it.
+ALTER TABLE trades ADD COLUMN settles_at TIMESTAMPTZ;
+ALTER TABLE trades_archive ADD COLUMN settles_at TIMESTAMPTZ;
+
+ALTER TABLE trades DROP CONSTRAINT IF EXISTS trades_status_check;
+ALTER TABLE trades ADD CONSTRAINT trades_status_check
+    CHECK (status IN ('pending', 'completed', 'failed', 'disputed'));
+
+-- The settlement worker looks for pending trades by due time
+CREATE INDEX IF NOT EXISTS idx_trades_pending_settlement ON trades(settles_at)
+    WHERE status = 'pending';
@@ -0,0 +1,10 @@
+-- Emergency stop for the whole market. A single row: while `halted` is set, order creation,
+-- matching and trade execution are refused until an admin resumes trading.
+CREATE TABLE IF NOT EXISTS market_state (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    halted BOOLEAN NOT NULL DEFAULT FALSE,
+    reason TEXT,
+    updated_at TIMESTAMPTZ
+);
+
+INSERT INTO market_state (id, halted) VALUES (1, FALSE) ON CONFLICT (id) DO NOTHING;
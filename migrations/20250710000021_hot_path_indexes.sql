@@ -0,0 +1,26 @@
+-- Indexes for the hot read paths, so they stop scanning whole tables as the book grows.
+-- Expected plans on PostgreSQL (see tests/indexes.rs):
+--   order listings filtered by status and type   -> Index Scan using idx_orders_status_type_price
+--   the active book, best bid/ask and matching   -> Index Scan using idx_orders_active_book
+--   a prosumer's trade history (buyer OR seller) -> BitmapOr over idx_trades_buyer_created_at
+--                                                   and idx_trades_seller_created_at
+
+-- Order listings (`GET /orders?status=&order_type=`) and the stats counts by status and side.
+-- Covers the status-only index, and the SQLite schema's (order_type, status) one.
+CREATE INDEX IF NOT EXISTS idx_orders_status_type_price ON orders(status, order_type, price_per_unit);
+DROP INDEX IF EXISTS idx_orders_status;
+DROP INDEX IF EXISTS idx_orders_type_status;
+
+-- Only active orders can match; keeping just those makes the index small however long the
+-- order history gets, and sorts each side by price then time priority
+CREATE INDEX IF NOT EXISTS idx_orders_active_book ON orders(order_type, price_per_unit, created_at)
+    WHERE status = 'active';
+
+-- Trade history per party, newest first. These replace the single-column address indexes
+-- (named differently by the SQLite and PostgreSQL schemas), which they cover.
+CREATE INDEX IF NOT EXISTS idx_trades_buyer_created_at ON trades(buyer_address, created_at);
+CREATE INDEX IF NOT EXISTS idx_trades_seller_created_at ON trades(seller_address, created_at);
+DROP INDEX IF EXISTS idx_trades_buyer_address;
+DROP INDEX IF EXISTS idx_trades_seller_address;
+DROP INDEX IF EXISTS idx_trades_buyer;
+DROP INDEX IF EXISTS idx_trades_seller;
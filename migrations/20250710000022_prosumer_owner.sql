@@ -0,0 +1,3 @@
+-- The user who registered the prosumer; only they (or an admin) may close it. NULL for
+-- prosumers registered without authenticating, which only an admin can close.
+ALTER TABLE prosumers ADD COLUMN owner_id TEXT;
@@ -0,0 +1,3 @@
+-- The user who registered the webhook; it is listed and deleted by them (or an admin).
+-- Webhooks registered before ownership existed have none and are left to admins.
+ALTER TABLE webhooks ADD COLUMN owner_id TEXT;
@@ -2,9 +2,13 @@ use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
 use uuid::Uuid;
 use base64::Engine;
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString};
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::{env_parse, is_production_env};
 
 // JWT Claims structure
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -15,6 +19,8 @@ pub struct Claims {
     pub exp: usize,          // Expiration time
     pub iat: usize,          // Issued at
     pub jti: String,         // JWT ID
+    pub iss: String,         // Issuer (JWT_ISSUER)
+    pub aud: String,         // Audience (JWT_AUDIENCE)
 }
 
 // API Key structure
@@ -110,57 +116,292 @@ pub enum AuthError {
     ApiKeyNotFound,
     #[error("User already exists")]
     UserAlreadyExists,
+    #[error("Account is disabled")]
+    AccountDisabled,
+    #[error("Weak password: {0}")]
+    WeakPassword(String),
     #[error("Internal error: {0}")]
     Internal(String),
 }
 
+// Password complexity requirements, applied whenever a password is set
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_letter: bool,
+    pub require_digit: bool,
+    pub require_uppercase: bool,
+    pub require_symbol: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_letter: true,
+            require_digit: true,
+            require_uppercase: false,
+            require_symbol: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // Reads PASSWORD_MIN_LENGTH and PASSWORD_REQUIRE_{LETTER,DIGIT,UPPERCASE,SYMBOL}
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            min_length: env_parse("PASSWORD_MIN_LENGTH", defaults.min_length),
+            require_letter: env_parse("PASSWORD_REQUIRE_LETTER", defaults.require_letter),
+            require_digit: env_parse("PASSWORD_REQUIRE_DIGIT", defaults.require_digit),
+            require_uppercase: env_parse("PASSWORD_REQUIRE_UPPERCASE", defaults.require_uppercase),
+            require_symbol: env_parse("PASSWORD_REQUIRE_SYMBOL", defaults.require_symbol),
+        }
+    }
+
+    pub fn validate(&self, password: &str) -> Result<(), AuthError> {
+        let mut missing = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            missing.push(format!("at least {} characters", self.min_length));
+        }
+        if self.require_letter && !password.chars().any(|c| c.is_alphabetic()) {
+            missing.push("a letter".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            missing.push("a digit".to_string());
+        }
+        if self.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            missing.push("an uppercase letter".to_string());
+        }
+        if self.require_symbol && !password.chars().any(|c| !c.is_alphanumeric() && !c.is_whitespace()) {
+            missing.push("a symbol".to_string());
+        }
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(AuthError::WeakPassword(format!("password must contain {}", missing.join(", "))))
+        }
+    }
+}
+
+// Hash algorithm used for newly stored password and API key hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordHashScheme {
+    Bcrypt,
+    Argon2,
+}
+
+// Work factors bcrypt accepts
+pub const MIN_BCRYPT_COST: u32 = 4;
+pub const MAX_BCRYPT_COST: u32 = 31;
+
+// Hashes secrets with the configured scheme and verifies hashes of either scheme.
+// Both formats are self-describing: bcrypt hashes start with `$2`, argon2 PHC strings with
+// `$argon2`, so existing bcrypt hashes keep verifying after switching to argon2.
+#[derive(Debug, Clone)]
+pub struct PasswordHasher {
+    pub scheme: PasswordHashScheme,
+    pub bcrypt_cost: u32,
+}
+
+impl Default for PasswordHasher {
+    fn default() -> Self {
+        Self {
+            scheme: PasswordHashScheme::Bcrypt,
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+        }
+    }
+}
+
+impl PasswordHasher {
+    // Reads PASSWORD_HASH_SCHEME (bcrypt|argon2) and BCRYPT_COST
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let scheme = match std::env::var("PASSWORD_HASH_SCHEME").map(|v| v.to_lowercase()) {
+            Ok(v) if v == "argon2" => PasswordHashScheme::Argon2,
+            Ok(v) if v == "bcrypt" => PasswordHashScheme::Bcrypt,
+            Ok(v) => {
+                log::warn!("Unknown PASSWORD_HASH_SCHEME '{}', falling back to bcrypt", v);
+                defaults.scheme
+            }
+            Err(_) => defaults.scheme,
+        };
+        let bcrypt_cost = env_parse("BCRYPT_COST", defaults.bcrypt_cost);
+
+        Self { scheme, bcrypt_cost }
+    }
+
+    // A cost bcrypt would refuse only surfaces when the first password is hashed, so it is
+    // checked at startup instead
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_BCRYPT_COST..=MAX_BCRYPT_COST).contains(&self.bcrypt_cost) {
+            return Err(format!(
+                "BCRYPT_COST must be between {} and {}, got {}",
+                MIN_BCRYPT_COST, MAX_BCRYPT_COST, self.bcrypt_cost
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn hash(&self, secret: &str) -> Result<String, AuthError> {
+        match self.scheme {
+            PasswordHashScheme::Bcrypt => bcrypt::hash(secret, self.bcrypt_cost)
+                .map_err(|_| AuthError::Internal("Password hashing failed".to_string())),
+            PasswordHashScheme::Argon2 => {
+                let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+                    .map_err(|_| AuthError::Internal("Salt generation failed".to_string()))?;
+                argon2::Argon2::default()
+                    .hash_password(secret.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(|_| AuthError::Internal("Password hashing failed".to_string()))
+            }
+        }
+    }
+
+    pub fn verify(&self, secret: &str, hash: &str) -> Result<bool, AuthError> {
+        if hash.starts_with("$argon2") {
+            let parsed = PasswordHash::new(hash)
+                .map_err(|_| AuthError::Internal("Malformed password hash".to_string()))?;
+            Ok(argon2::Argon2::default()
+                .verify_password(secret.as_bytes(), &parsed)
+                .is_ok())
+        } else {
+            bcrypt::verify(secret, hash)
+                .map_err(|_| AuthError::Internal("Password verification failed".to_string()))
+        }
+    }
+}
+
+// Used for the bootstrap admin when ADMIN_PASSWORD is unset; refused in production
+const INSECURE_ADMIN_PASSWORD: &str = "admin123";
+
+// Bootstrap admin account created at startup when no admin exists yet
+#[derive(Debug, Clone)]
+pub struct AdminBootstrap {
+    pub username: String,
+    pub email: String,
+    pub password: Option<String>, // None falls back to the insecure development password
+}
+
+impl Default for AdminBootstrap {
+    fn default() -> Self {
+        Self {
+            username: "admin".to_string(),
+            email: "admin@energy-trading.com".to_string(),
+            password: None,
+        }
+    }
+}
+
+impl AdminBootstrap {
+    // Reads ADMIN_USERNAME, ADMIN_EMAIL and ADMIN_PASSWORD
+    pub fn from_env() -> Self {
+        fn env_value(name: &str) -> Option<String> {
+            std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+        }
+
+        let defaults = Self::default();
+        Self {
+            username: env_value("ADMIN_USERNAME").unwrap_or(defaults.username),
+            email: env_value("ADMIN_EMAIL").unwrap_or(defaults.email),
+            password: env_value("ADMIN_PASSWORD"),
+        }
+    }
+
+    // The insecure default password is never allowed in production
+    pub fn validate(&self) -> Result<(), String> {
+        if self.password.is_none() && is_production_env() {
+            return Err("ADMIN_PASSWORD must be set when APP_ENV=production".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub const DEFAULT_JWT_ISSUER: &str = "energy-trading-api";
+pub const DEFAULT_JWT_AUDIENCE: &str = "energy-trading-api";
+
 // In-memory storage for demonstration (in production, use a database)
 pub struct AuthStore {
     pub users: Arc<Mutex<HashMap<String, User>>>,
     pub api_keys: Arc<Mutex<HashMap<String, ApiKey>>>,
     pub jwt_secret: String,
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    pub password_policy: PasswordPolicy,
+    pub password_hasher: PasswordHasher,
+    // Decides token and API key expiry and stamps creation/login times
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for AuthStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AuthStore {
     pub fn new() -> Self {
-        let store = Self {
+        Self {
             users: Arc::new(Mutex::new(HashMap::new())),
             api_keys: Arc::new(Mutex::new(HashMap::new())),
             jwt_secret: std::env::var("JWT_SECRET").unwrap_or_else(|_| {
                 "your-super-secret-jwt-key-change-in-production".to_string()
             }),
-        };
-
-        // Create default admin user
-        store.create_default_admin();
-        store
+            jwt_issuer: std::env::var("JWT_ISSUER").unwrap_or_else(|_| DEFAULT_JWT_ISSUER.to_string()),
+            jwt_audience: std::env::var("JWT_AUDIENCE").unwrap_or_else(|_| DEFAULT_JWT_AUDIENCE.to_string()),
+            password_policy: PasswordPolicy::from_env(),
+            password_hasher: PasswordHasher::from_env(),
+            clock: Arc::new(SystemClock),
+        }
     }
 
-    fn create_default_admin(&self) {
+    // Creates the bootstrap admin unless an admin account already exists; returns whether one
+    // was created. A configured password must satisfy the password policy.
+    pub fn bootstrap_admin(&self, admin: &AdminBootstrap) -> Result<bool, AuthError> {
+        if self.users.lock().unwrap().values().any(|u| u.role == "admin") {
+            return Ok(false);
+        }
+
+        let password = match &admin.password {
+            Some(password) => {
+                self.password_policy.validate(password)?;
+                password.as_str()
+            }
+            None => {
+                log::warn!(
+                    "Bootstrap admin '{}' uses the insecure default password; set ADMIN_PASSWORD outside development",
+                    admin.username
+                );
+                INSECURE_ADMIN_PASSWORD
+            }
+        };
         let admin_user = User {
             id: Uuid::new_v4().to_string(),
-            username: "admin".to_string(),
-            email: "admin@energy-trading.com".to_string(),
-            password_hash: bcrypt::hash("admin123", bcrypt::DEFAULT_COST).unwrap(),
+            username: admin.username.clone(),
+            email: admin.email.clone(),
+            password_hash: self.password_hasher.hash(password)?,
             role: "admin".to_string(),
             is_active: true,
-            created_at: Utc::now(),
+            created_at: self.clock.now(),
             last_login: None,
         };
 
         let mut users = self.users.lock().unwrap();
         users.insert(admin_user.id.clone(), admin_user);
+        Ok(true)
     }
 
     pub fn authenticate_user(&self, username: &str, password: &str) -> Result<User, AuthError> {
-        let users = self.users.lock().unwrap();
+        let mut users = self.users.lock().unwrap();
         
-        let user = users.values()
+        let user = users.values_mut()
             .find(|u| u.username == username && u.is_active)
             .ok_or(AuthError::InvalidCredentials)?;
 
-        if bcrypt::verify(password, &user.password_hash)
-            .map_err(|_| AuthError::Internal("Password verification failed".to_string()))? {
+        if self.password_hasher.verify(password, &user.password_hash)? {
+            user.last_login = Some(self.clock.now());
             Ok(user.clone())
         } else {
             Err(AuthError::InvalidCredentials)
@@ -168,6 +409,8 @@ impl AuthStore {
     }
 
     pub fn create_user(&self, request: CreateUserRequest) -> Result<User, AuthError> {
+        self.password_policy.validate(&request.password)?;
+
         let mut users = self.users.lock().unwrap();
         
         // Check if user already exists
@@ -179,11 +422,10 @@ impl AuthStore {
             id: Uuid::new_v4().to_string(),
             username: request.username,
             email: request.email,
-            password_hash: bcrypt::hash(&request.password, bcrypt::DEFAULT_COST)
-                .map_err(|_| AuthError::Internal("Password hashing failed".to_string()))?,
+            password_hash: self.password_hasher.hash(&request.password)?,
             role: request.role,
             is_active: true,
-            created_at: Utc::now(),
+            created_at: self.clock.now(),
             last_login: None,
         };
 
@@ -192,8 +434,9 @@ impl AuthStore {
     }
 
     pub fn generate_jwt(&self, user: &User) -> Result<String, AuthError> {
-        let exp = (Utc::now() + chrono::Duration::hours(24)).timestamp() as usize;
-        let iat = Utc::now().timestamp() as usize;
+        let now = self.clock.now();
+        let exp = (now + chrono::Duration::hours(24)).timestamp() as usize;
+        let iat = now.timestamp() as usize;
         
         let claims = Claims {
             sub: user.id.clone(),
@@ -202,6 +445,8 @@ impl AuthStore {
             exp,
             iat,
             jti: Uuid::new_v4().to_string(),
+            iss: self.jwt_issuer.clone(),
+            aud: self.jwt_audience.clone(),
         };
 
         encode(
@@ -212,23 +457,35 @@ impl AuthStore {
         .map_err(|_| AuthError::Internal("JWT encoding failed".to_string()))
     }
 
+    // Tokens must carry this service's issuer and audience; anything else is rejected.
+    // Expiry is checked against the store's clock rather than the library's system time.
     pub fn validate_jwt(&self, token: &str) -> Result<Claims, AuthError> {
-        decode::<Claims>(
+        let mut validation = Validation::default();
+        validation.set_issuer(&[&self.jwt_issuer]);
+        validation.set_audience(&[&self.jwt_audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        validation.validate_exp = false;
+
+        let claims = decode::<Claims>(
             token,
             &DecodingKey::from_secret(self.jwt_secret.as_bytes()),
-            &Validation::default(),
+            &validation,
         )
         .map(|data| data.claims)
-        .map_err(|_| AuthError::InvalidToken)
+        .map_err(|_| AuthError::InvalidToken)?;
+        if claims.exp as i64 <= self.clock.now().timestamp() {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(claims)
     }
 
     pub fn create_api_key(&self, user_id: &str, request: CreateApiKeyRequest) -> Result<ApiKeyResponse, AuthError> {
         let key = format!("etapi_{}", base64::engine::general_purpose::STANDARD.encode(rand::random::<[u8; 32]>()));
-        let key_hash = bcrypt::hash(&key, bcrypt::DEFAULT_COST)
-            .map_err(|_| AuthError::Internal("Key hashing failed".to_string()))?;
+        let key_hash = self.password_hasher.hash(&key)?;
 
+        let now = self.clock.now();
         let expires_at = request.expires_in_days.map(|days| {
-            Utc::now() + chrono::Duration::days(days as i64)
+            now + chrono::Duration::days(days as i64)
         });
 
         let api_key = ApiKey {
@@ -238,7 +495,7 @@ impl AuthStore {
             user_id: user_id.to_string(),
             role: "api".to_string(),
             permissions: request.permissions.clone(),
-            created_at: Utc::now(),
+            created_at: now,
             last_used: None,
             expires_at,
             is_active: true,
@@ -258,14 +515,15 @@ impl AuthStore {
 
     pub fn validate_api_key(&self, key: &str) -> Result<ApiKey, AuthError> {
         let mut api_keys = self.api_keys.lock().unwrap();
+        let now = self.clock.now();
         
         for api_key in api_keys.values_mut() {
             if api_key.is_active && 
-               api_key.expires_at.map_or(true, |exp| exp > Utc::now()) &&
-               bcrypt::verify(key, &api_key.key_hash).unwrap_or(false) {
+               api_key.expires_at.is_none_or(|exp| exp > now) &&
+               self.password_hasher.verify(key, &api_key.key_hash).unwrap_or(false) {
                 
                 // Update last used timestamp
-                api_key.last_used = Some(Utc::now());
+                api_key.last_used = Some(now);
                 return Ok(api_key.clone());
             }
         }
@@ -280,6 +538,23 @@ impl AuthStore {
             .ok_or(AuthError::UserNotFound)
     }
 
+    // Soft-deletes a user and revokes every API key they own
+    pub fn deactivate_user(&self, user_id: &str) -> Result<User, AuthError> {
+        let user = {
+            let mut users = self.users.lock().unwrap();
+            let user = users.get_mut(user_id).ok_or(AuthError::UserNotFound)?;
+            user.is_active = false;
+            user.clone()
+        };
+
+        let mut api_keys = self.api_keys.lock().unwrap();
+        for api_key in api_keys.values_mut().filter(|k| k.user_id == user_id) {
+            api_key.is_active = false;
+        }
+
+        Ok(user)
+    }
+
     pub fn verify_jwt(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
         let key = DecodingKey::from_secret(secret.as_ref());
         let validation = Validation::new(Algorithm::HS256);
@@ -301,15 +576,237 @@ pub fn check_permission(user_role: &str, required_permission: &str) -> bool {
     }
 }
 
-// Endpoint permissions mapping
-pub fn get_endpoint_permission(method: &str, path: &str) -> &'static str {
-    match (method, path) {
-        ("GET", _) => "read",
-        ("POST", path) if path.contains("/orders") => "trade",
-        ("POST", path) if path.contains("/cancel") => "cancel_order",
-        ("POST", _) => "create",
-        ("PUT", _) => "update",
-        ("DELETE", _) => "delete",
+// Ordered route -> permission table. The first rule whose method and path pattern match a
+// request decides its permission, so a specific route (`/orders/cancel`) has to be registered
+// before a broader one covering it (`/orders/*`). Patterns use the router's `{param}` syntax
+// for a single segment, and a trailing `*` matches any remaining segments, including none.
+// Requests no rule matches fall back to a permission per method.
+#[derive(Debug, Clone, Default)]
+pub struct PermissionTable {
+    rules: Vec<RoutePermission>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutePermission {
+    pub method: &'static str,
+    pub pattern: &'static str,
+    pub permission: &'static str,
+}
+
+impl RoutePermission {
+    fn matches(&self, method: &str, path: &str) -> bool {
+        if !self.method.eq_ignore_ascii_case(method) {
+            return false;
+        }
+        let mut segments = path.trim_matches('/').split('/').filter(|s| !s.is_empty());
+        for expected in self.pattern.trim_matches('/').split('/').filter(|s| !s.is_empty()) {
+            if expected == "*" {
+                return true;
+            }
+            let is_param = expected.starts_with('{') && expected.ends_with('}');
+            match segments.next() {
+                Some(segment) if is_param || segment == expected => {}
+                _ => return false,
+            }
+        }
+        segments.next().is_none()
+    }
+}
+
+impl PermissionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The API's own routes. Order cancellation is listed ahead of the order rules it would
+    // otherwise fall under.
+    pub fn with_default_routes() -> Self {
+        Self::new()
+            .register("POST", "/orders/cancel", "cancel_order")
+            .register("POST", "/api/energy/orders/cancel", "cancel_order")
+            .register("POST", "/admin/orders/{order_id}/cancel", "cancel_order")
+            .register("DELETE", "/orders/{order_id}", "cancel_order")
+            .register("POST", "/orders/*", "trade")
+            .register("POST", "/api/energy/orders/*", "trade")
+    }
+
+    // Appends a rule; it only applies to requests no earlier rule matched
+    pub fn register(mut self, method: &'static str, pattern: &'static str, permission: &'static str) -> Self {
+        self.rules.push(RoutePermission { method, pattern, permission });
+        self
+    }
+
+    pub fn rules(&self) -> &[RoutePermission] {
+        &self.rules
+    }
+
+    pub fn permission_for(&self, method: &str, path: &str) -> &'static str {
+        let path = path.split('?').next().unwrap_or_default();
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(method, path))
+            .map(|rule| rule.permission)
+            .unwrap_or_else(|| method_permission(method))
+    }
+}
+
+fn method_permission(method: &str) -> &'static str {
+    match method.to_ascii_uppercase().as_str() {
+        "POST" => "create",
+        "PUT" | "PATCH" => "update",
+        "DELETE" => "delete",
         _ => "read",
     }
 }
+
+// Permission an endpoint requires, from the default route table
+pub fn get_endpoint_permission(method: &str, path: &str) -> &'static str {
+    static TABLE: OnceLock<PermissionTable> = OnceLock::new();
+    TABLE.get_or_init(PermissionTable::with_default_routes).permission_for(method, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::with_env;
+
+    #[test]
+    fn bcrypt_cost_must_be_within_bcrypts_range() {
+        for (cost, valid) in [("3", false), ("4", true), ("12", true), ("31", true), ("32", false)] {
+            let hasher = with_env(&[("BCRYPT_COST", cost)], PasswordHasher::from_env);
+            assert_eq!(hasher.bcrypt_cost.to_string(), cost);
+            assert_eq!(hasher.validate().is_ok(), valid, "BCRYPT_COST={}", cost);
+        }
+
+        // Unparseable values fall back to the default like every other setting
+        let hasher = with_env(&[("BCRYPT_COST", "fast")], PasswordHasher::from_env);
+        assert_eq!(hasher.bcrypt_cost, bcrypt::DEFAULT_COST);
+        assert!(hasher.validate().is_ok());
+    }
+
+    #[test]
+    fn argon2_scheme_hashes_and_verifies() {
+        let hasher = with_env(&[("PASSWORD_HASH_SCHEME", "ARGON2")], PasswordHasher::from_env);
+        assert_eq!(hasher.scheme, PasswordHashScheme::Argon2);
+
+        let hash = hasher.hash("s3cret-value").unwrap();
+        assert!(hash.starts_with("$argon2"), "{}", hash);
+        assert!(hasher.verify("s3cret-value", &hash).unwrap());
+        assert!(!hasher.verify("other-value", &hash).unwrap());
+    }
+
+    #[test]
+    fn bcrypt_hashes_still_verify_after_switching_to_argon2() {
+        let bcrypt = PasswordHasher { scheme: PasswordHashScheme::Bcrypt, bcrypt_cost: MIN_BCRYPT_COST };
+        let stored = bcrypt.hash("s3cret-value").unwrap();
+        assert!(stored.starts_with("$2"), "{}", stored);
+
+        let argon2 = PasswordHasher { scheme: PasswordHashScheme::Argon2, ..bcrypt };
+        assert!(argon2.verify("s3cret-value", &stored).unwrap());
+        assert!(!argon2.verify("other-value", &stored).unwrap());
+    }
+
+    // Each rule on its own, so a failure names only what the password lacks
+    #[test]
+    fn password_policy_enforces_each_rule() {
+        let none = PasswordPolicy {
+            min_length: 0,
+            require_letter: false,
+            require_digit: false,
+            require_uppercase: false,
+            require_symbol: false,
+        };
+        let cases = [
+            (PasswordPolicy { min_length: 10, ..none.clone() }, "short", "0123456789", "at least 10 characters"),
+            (PasswordPolicy { require_letter: true, ..none.clone() }, "12345678", "1234567a", "a letter"),
+            (PasswordPolicy { require_digit: true, ..none.clone() }, "password", "passw0rd", "a digit"),
+            (PasswordPolicy { require_uppercase: true, ..none.clone() }, "password", "Password", "an uppercase letter"),
+            (PasswordPolicy { require_symbol: true, ..none.clone() }, "pass word", "pass-word", "a symbol"),
+        ];
+        for (policy, weak, strong, rule) in cases {
+            match policy.validate(weak) {
+                Err(AuthError::WeakPassword(message)) => {
+                    assert_eq!(message, format!("password must contain {}", rule));
+                }
+                other => panic!("{:?} accepted {:?}: {:?}", policy, weak, other),
+            }
+            assert!(policy.validate(strong).is_ok(), "{:?} rejected {:?}", policy, strong);
+        }
+
+        // Every unmet rule is listed at once
+        let strict = PasswordPolicy { require_uppercase: true, require_symbol: true, ..PasswordPolicy::default() };
+        match strict.validate("abc") {
+            Err(AuthError::WeakPassword(message)) => assert_eq!(
+                message,
+                "password must contain at least 8 characters, a digit, an uppercase letter, a symbol"
+            ),
+            other => panic!("accepted a weak password: {:?}", other),
+        }
+        assert!(strict.validate("Str0ng-password").is_ok());
+    }
+
+    #[test]
+    fn password_policy_reads_its_overrides_from_the_environment() {
+        let defaults = with_env(&[], PasswordPolicy::from_env);
+        assert_eq!(defaults, PasswordPolicy::default());
+
+        let policy = with_env(
+            &[
+                ("PASSWORD_MIN_LENGTH", "12"),
+                ("PASSWORD_REQUIRE_LETTER", "false"),
+                ("PASSWORD_REQUIRE_DIGIT", "false"),
+                ("PASSWORD_REQUIRE_UPPERCASE", "true"),
+                ("PASSWORD_REQUIRE_SYMBOL", "true"),
+            ],
+            PasswordPolicy::from_env,
+        );
+        assert_eq!(
+            policy,
+            PasswordPolicy {
+                min_length: 12,
+                require_letter: false,
+                require_digit: false,
+                require_uppercase: true,
+                require_symbol: true,
+            }
+        );
+
+        // Unparseable values keep the default
+        let policy = with_env(&[("PASSWORD_MIN_LENGTH", "long"), ("PASSWORD_REQUIRE_DIGIT", "yes")], PasswordPolicy::from_env);
+        assert_eq!(policy, PasswordPolicy::default());
+    }
+
+    #[test]
+    fn production_requires_an_admin_password() {
+        let without_password = AdminBootstrap::default();
+        let with_password = AdminBootstrap {
+            password: Some("Adm1n-password".to_string()),
+            ..AdminBootstrap::default()
+        };
+        for env in ["production", "prod", " PRODUCTION "] {
+            assert!(with_env(&[("APP_ENV", env)], || without_password.validate()).is_err(), "APP_ENV={:?}", env);
+            assert!(with_env(&[("APP_ENV", env)], || with_password.validate()).is_ok(), "APP_ENV={:?}", env);
+        }
+        for env in ["development", "test", ""] {
+            assert!(with_env(&[("APP_ENV", env)], || without_password.validate()).is_ok(), "APP_ENV={:?}", env);
+        }
+    }
+
+    #[test]
+    fn admin_bootstrap_reads_trimmed_overrides_and_ignores_blank_ones() {
+        let defaults = with_env(&[], AdminBootstrap::from_env);
+        assert_eq!((defaults.username.as_str(), defaults.password), ("admin", None));
+
+        let admin = with_env(
+            &[("ADMIN_USERNAME", " root "), ("ADMIN_EMAIL", "root@example.com"), ("ADMIN_PASSWORD", " S3cret-pass ")],
+            AdminBootstrap::from_env,
+        );
+        assert_eq!(admin.username, "root");
+        assert_eq!(admin.email, "root@example.com");
+        assert_eq!(admin.password.as_deref(), Some("S3cret-pass"));
+
+        let blank = with_env(&[("ADMIN_USERNAME", "  "), ("ADMIN_PASSWORD", "")], AdminBootstrap::from_env);
+        assert_eq!(blank.username, AdminBootstrap::default().username);
+        assert_eq!(blank.password, None);
+    }
+}
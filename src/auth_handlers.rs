@@ -1,11 +1,36 @@
-use ntex::web::HttpResponse;
+use std::sync::Arc;
+
+use ntex::web::{self, HttpResponse};
+use ntex::web::types::State;
 use serde_json::json;
+use uuid::Uuid;
 
-// Placeholder auth handlers for now
-pub async fn login() -> Result<HttpResponse, ntex::web::Error> {
-    Ok(HttpResponse::Ok().json(&json!({
-        "message": "Login endpoint - implementation pending"
-    })))
+use crate::auth::{AuthError, AuthStore, LoginRequest, LoginResponse, UserInfo};
+use crate::config::MigrationConfig;
+use crate::database::{BatchOperation, BatchOperationResult, DatabaseError, DatabaseService};
+use crate::handlers::{batch_failed, database_error};
+use crate::middleware::AdminContext;
+use crate::models::{AuditQuery, CreateTokenTypeRequest, ExecuteTradeBatchRequest, ForceCancelOrderRequest, HaltMarketRequest, PaginatedResponse};
+use crate::webhooks;
+
+pub async fn login(
+    store: State<Arc<AuthStore>>,
+    body: web::types::Json<LoginRequest>,
+) -> Result<HttpResponse, AuthError> {
+    let user = store.authenticate_user(&body.username, &body.password)?;
+    let access_token = store.generate_jwt(&user)?;
+
+    Ok(HttpResponse::Ok().json(&LoginResponse {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: 24 * 60 * 60,
+        user: UserInfo {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            role: user.role,
+        },
+    }))
 }
 
 pub async fn register() -> Result<HttpResponse, ntex::web::Error> {
@@ -13,3 +38,254 @@ pub async fn register() -> Result<HttpResponse, ntex::web::Error> {
         "message": "Register endpoint - implementation pending"
     })))
 }
+
+// Admin: soft-delete a user, revoking their API keys; their JWTs stop working immediately
+pub async fn deactivate_user(
+    AdminContext(admin): AdminContext,
+    store: State<Arc<AuthStore>>,
+    user_id: web::types::Path<String>,
+) -> Result<HttpResponse, AuthError> {
+    let user_id = user_id.into_inner();
+    if user_id == admin.user_id {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "Administrators cannot deactivate their own account"
+        })));
+    }
+
+    let user = store.deactivate_user(&user_id)?;
+    log::info!("User {} deactivated by admin {}", user.id, admin.username);
+
+    Ok(HttpResponse::Ok().json(&json!({
+        "message": "User deactivated successfully",
+        "user_id": user.id,
+        "is_active": user.is_active
+    })))
+}
+
+// Admin: cancel any open order regardless of owner; the admin and reason are audited
+pub async fn force_cancel_order(
+    AdminContext(admin): AdminContext,
+    state: State<Arc<DatabaseService>>,
+    order_id: web::types::Path<String>,
+    body: web::types::Json<ForceCancelOrderRequest>,
+) -> Result<HttpResponse, AuthError> {
+    let order_id = match Uuid::parse_str(&order_id.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "Invalid order ID format"
+        })))
+    };
+
+    let reason = body.reason.trim();
+    if reason.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "A reason is required to force-cancel an order"
+        })));
+    }
+
+    match state.force_cancel_order(order_id, &admin.user_id, reason).await {
+        Ok(order) => {
+            log::warn!("Order {} force-cancelled by admin {}: {}", order.id, admin.username, reason);
+            webhooks::notify(state.get_ref(), webhooks::ORDER_CANCELLED, &order);
+            Ok(HttpResponse::Ok().json(&json!({
+                "message": "Order cancelled successfully",
+                "order": order
+            })))
+        }
+        Err(e) => Ok(database_error("cancel order", e))
+    }
+}
+
+// Admin: executes every trade in one transaction; a trade that fails (e.g. an order that is no
+// longer active) rolls back the ones before it
+pub async fn execute_trade_batch(
+    AdminContext(admin): AdminContext,
+    state: State<Arc<DatabaseService>>,
+    body: web::types::Json<ExecuteTradeBatchRequest>,
+) -> Result<HttpResponse, AuthError> {
+    let operations = body
+        .trades
+        .iter()
+        .map(|trade| BatchOperation::ExecuteTrade {
+            buy_order_id: trade.buy_order_id,
+            sell_order_id: trade.sell_order_id,
+            price_per_unit: trade.price_per_unit,
+        })
+        .collect();
+
+    match state.execute_batch(operations).await {
+        Ok(results) => {
+            let trades: Vec<_> = results
+                .into_iter()
+                .filter_map(|result| match result {
+                    BatchOperationResult::ExecuteTrade { trade } => Some(trade),
+                    _ => None,
+                })
+                .collect();
+            log::info!("{} trades executed as a batch by admin {}", trades.len(), admin.username);
+            for trade in &trades {
+                webhooks::notify(state.get_ref(), webhooks::TRADE_EXECUTED, trade);
+            }
+            webhooks::check_price_alerts(state.get_ref());
+            Ok(HttpResponse::Created().json(&json!({
+                "message": "Trades executed successfully",
+                "trades": trades
+            })))
+        }
+        Err(DatabaseError::BatchFailed { index, source }) => Ok(batch_failed(index, &source)),
+        Err(e) => Ok(database_error("execute trades", e))
+    }
+}
+
+// Admin: resolves a disputed trade by paying the seller the held payment
+pub async fn settle_disputed_trade(
+    AdminContext(admin): AdminContext,
+    state: State<Arc<DatabaseService>>,
+    trade_id: web::types::Path<String>,
+) -> Result<HttpResponse, AuthError> {
+    let trade_id = match Uuid::parse_str(&trade_id.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "Invalid trade ID format"
+        })))
+    };
+
+    match state.settle_disputed_trade(trade_id).await {
+        Ok(trade) => {
+            log::warn!("Disputed trade {} settled by admin {}", trade.id, admin.username);
+            webhooks::notify(state.get_ref(), webhooks::TRADE_SETTLED, &trade);
+            Ok(HttpResponse::Ok().json(&json!({
+                "message": "Trade settled successfully",
+                "trade": trade
+            })))
+        }
+        Err(e) => Ok(database_error("settle trade", e))
+    }
+}
+
+// Admin: emergency stop. Order creation, matching and trade execution return 503 until the
+// market is resumed; reads keep working.
+pub async fn halt_market(
+    AdminContext(admin): AdminContext,
+    state: State<Arc<DatabaseService>>,
+    body: web::types::Json<HaltMarketRequest>,
+) -> Result<HttpResponse, AuthError> {
+    let reason = body.reason.trim();
+    if reason.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "A reason is required to halt the market"
+        })));
+    }
+
+    match state.set_market_halt(true, Some(reason), &admin.user_id).await {
+        Ok(halt) => {
+            log::warn!("Market halted by admin {}: {}", admin.username, reason);
+            Ok(HttpResponse::Ok().json(&halt))
+        }
+        Err(e) => Ok(database_error("halt market", e))
+    }
+}
+
+// Admin: lift a halt
+pub async fn resume_market(
+    AdminContext(admin): AdminContext,
+    state: State<Arc<DatabaseService>>,
+) -> Result<HttpResponse, AuthError> {
+    match state.set_market_halt(false, None, &admin.user_id).await {
+        Ok(halt) => {
+            log::warn!("Market resumed by admin {}", admin.username);
+            Ok(HttpResponse::Ok().json(&halt))
+        }
+        Err(e) => Ok(database_error("resume market", e))
+    }
+}
+
+// Admin: query the audit trail of mutating requests
+pub async fn get_audit_log(
+    _admin: AdminContext,
+    state: State<Arc<DatabaseService>>,
+    query: web::types::Query<AuditQuery>,
+) -> Result<HttpResponse, AuthError> {
+    let query = query.into_inner();
+    let (page, limit) = state.pagination().resolve(query.page, query.limit);
+
+    match state.get_audit_log(page, limit, query.from, query.to, query.user).await {
+        Ok(entries) => Ok(HttpResponse::Ok().json(&PaginatedResponse { items: entries, page, limit })),
+        Err(e) => Ok(database_error("get audit log", e))
+    }
+}
+
+// Admin: recent crossing orders the matcher declined to trade, newest first
+pub async fn get_match_diagnostics(
+    _admin: AdminContext,
+    state: State<Arc<DatabaseService>>,
+) -> Result<HttpResponse, AuthError> {
+    Ok(HttpResponse::Ok().json(&json!({
+        "skipped": state.match_diagnostics()
+    })))
+}
+
+// Admin: order, trade and matching health at a glance
+pub async fn get_system_summary(
+    _admin: AdminContext,
+    state: State<Arc<DatabaseService>>,
+) -> Result<HttpResponse, AuthError> {
+    match state.get_system_summary().await {
+        Ok(summary) => Ok(HttpResponse::Ok().json(&summary)),
+        Err(e) => Ok(database_error("build system summary", e))
+    }
+}
+
+// Admin: version, uptime, connection pool and migration state; /health stays minimal for
+// load balancers
+pub async fn health_details(
+    _admin: AdminContext,
+    state: State<Arc<DatabaseService>>,
+    migrations: State<Arc<MigrationConfig>>,
+) -> Result<HttpResponse, AuthError> {
+    Ok(HttpResponse::Ok().json(&state.get_health_details(&migrations).await))
+}
+
+// Admin: store a snapshot of the aggregated order book
+pub async fn snapshot_order_book(
+    _admin: AdminContext,
+    state: State<Arc<DatabaseService>>,
+) -> Result<HttpResponse, AuthError> {
+    match state.snapshot_order_book().await {
+        Ok(snapshot) => Ok(HttpResponse::Created().json(&snapshot)),
+        Err(e) => Ok(database_error("snapshot order book", e))
+    }
+}
+
+// Admin: compare the live order book against the latest snapshot
+pub async fn verify_order_book(
+    _admin: AdminContext,
+    state: State<Arc<DatabaseService>>,
+) -> Result<HttpResponse, AuthError> {
+    match state.verify_order_book().await {
+        Ok(verification) => Ok(HttpResponse::Ok().json(&verification)),
+        Err(e) => Ok(database_error("verify order book", e))
+    }
+}
+
+// Admin: register a new transferable token type
+pub async fn create_token_type(
+    AdminContext(admin): AdminContext,
+    state: State<Arc<DatabaseService>>,
+    body: web::types::Json<CreateTokenTypeRequest>,
+) -> Result<HttpResponse, AuthError> {
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+
+    match state.register_token_type(&request.name, request.description.as_deref().unwrap_or("")).await {
+        Ok(token_type) => {
+            log::info!("Token type '{}' registered by admin {}", token_type.name, admin.username);
+            Ok(HttpResponse::Created().json(&token_type))
+        }
+        Err(e) => Ok(database_error("create token type", e))
+    }
+}
@@ -0,0 +1,1012 @@
+// Market and API configuration loaded from the environment at startup
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// Prosumer tag overriding MarketConfig::max_open_orders_per_prosumer for that prosumer
+pub const MAX_OPEN_ORDERS_TAG: &str = "max_open_orders";
+
+pub(crate) fn env_parse<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+// Environment variables are process-wide, so unit tests anywhere in the crate that set them
+// take turns through this lock
+#[cfg(test)]
+static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// Runs `f` with `vars` set, removing them again afterwards
+#[cfg(test)]
+pub(crate) fn with_env<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+    let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    for (name, value) in vars {
+        std::env::set_var(name, value);
+    }
+    let result = f();
+    for (name, _) in vars {
+        std::env::remove_var(name);
+    }
+    result
+}
+
+#[derive(Debug, Clone)]
+pub struct MarketConfig {
+    // Smallest energy amount (kWh) an order may carry; 0 disables the check
+    pub min_order_energy: f64,
+    // Prices must be whole multiples of this tick; 0 disables the check
+    pub price_tick: f64,
+    // Grid fee charged on each trade, by trade value
+    pub fee_schedule: FeeSchedule,
+    // How the execution price is chosen when a buy and sell order cross
+    pub match_price_policy: MatchPricePolicy,
+    // Cap open sell orders at the prosumer's uncommitted net generated energy
+    pub enforce_energy_backing: bool,
+    // Decimal places kept on order energy amounts (kWh)
+    pub energy_decimals: u32,
+    // What happens to an energy amount with more decimal places than `energy_decimals`
+    pub energy_precision_policy: EnergyPrecisionPolicy,
+    // Most energy (kWh) one prosumer may buy plus sell per UTC day; 0 disables the check
+    pub daily_energy_limit: f64,
+    // Most trade value one prosumer may buy plus sell per UTC day; 0 disables the check
+    pub daily_notional_limit: f64,
+    // Most pending or active orders one prosumer may have resting at once; 0 disables the
+    // check. A prosumer's `max_open_orders` tag overrides it (market makers, say).
+    pub max_open_orders_per_prosumer: u32,
+    // Seconds between a trade executing and the seller being paid (T+N); 0 settles instantly
+    pub settlement_delay_secs: u64,
+    // Cost basis used for realized P&L when a request doesn't pick one
+    pub pnl_method: PnlMethod,
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        Self {
+            min_order_energy: 0.0,
+            price_tick: 0.0,
+            fee_schedule: FeeSchedule::default(),
+            match_price_policy: MatchPricePolicy::default(),
+            enforce_energy_backing: false,
+            energy_decimals: 3,
+            energy_precision_policy: EnergyPrecisionPolicy::default(),
+            daily_energy_limit: 0.0,
+            daily_notional_limit: 0.0,
+            max_open_orders_per_prosumer: 0,
+            settlement_delay_secs: 0,
+            pnl_method: PnlMethod::default(),
+        }
+    }
+}
+
+impl MarketConfig {
+    // Each field is read from the env var named after it in upper case (MIN_ORDER_ENERGY,
+    // PRICE_TICK, ...), plus the fee schedule (see FeeSchedule::from_env). Unset or
+    // unparsable values keep the default.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            min_order_energy: env_parse("MIN_ORDER_ENERGY", defaults.min_order_energy),
+            price_tick: env_parse("PRICE_TICK", defaults.price_tick),
+            fee_schedule: FeeSchedule::from_env(),
+            match_price_policy: env_parse("MATCH_PRICE_POLICY", defaults.match_price_policy),
+            enforce_energy_backing: env_parse("ENFORCE_ENERGY_BACKING", defaults.enforce_energy_backing),
+            // Beyond ~9 places f64 cannot represent typical kWh amounts exactly anyway
+            energy_decimals: env_parse("ENERGY_DECIMALS", defaults.energy_decimals).min(9),
+            energy_precision_policy: env_parse("ENERGY_PRECISION_POLICY", defaults.energy_precision_policy),
+            daily_energy_limit: env_parse("DAILY_ENERGY_LIMIT", defaults.daily_energy_limit).max(0.0),
+            daily_notional_limit: env_parse("DAILY_NOTIONAL_LIMIT", defaults.daily_notional_limit).max(0.0),
+            max_open_orders_per_prosumer: env_parse("MAX_OPEN_ORDERS_PER_PROSUMER", defaults.max_open_orders_per_prosumer),
+            // A year at most, which also keeps the due time representable
+            settlement_delay_secs: env_parse("SETTLEMENT_DELAY_SECS", defaults.settlement_delay_secs).min(365 * 24 * 3600),
+            pnl_method: env_parse("PNL_METHOD", defaults.pnl_method),
+        }
+    }
+
+    // Execution price for a crossing pair under the configured policy. `buy_is_resting`
+    // says whether the buy order was in the book before the sell order arrived.
+    pub fn execution_price(&self, buy_price: f64, sell_price: f64, buy_is_resting: bool) -> f64 {
+        match self.match_price_policy {
+            MatchPricePolicy::Seller => sell_price,
+            MatchPricePolicy::Buyer => buy_price,
+            MatchPricePolicy::Resting if buy_is_resting => buy_price,
+            MatchPricePolicy::Resting => sell_price,
+            MatchPricePolicy::Midpoint => {
+                let midpoint = (buy_price + sell_price) / 2.0;
+                let midpoint = if self.price_tick > 0.0 {
+                    (midpoint / self.price_tick).round() * self.price_tick
+                } else {
+                    midpoint
+                };
+                // Rounding to the tick must not push the price outside the crossing range
+                midpoint.clamp(sell_price, buy_price)
+            }
+        }
+    }
+
+    pub fn validate_energy_amount(&self, energy_amount: f64) -> Result<(), String> {
+        if !energy_amount.is_finite() || energy_amount <= 0.0 {
+            return Err("energy_amount must be a positive number".to_string());
+        }
+        if energy_amount < self.min_order_energy {
+            return Err(format!(
+                "energy_amount {} is below the minimum order size of {}",
+                energy_amount, self.min_order_energy
+            ));
+        }
+        Ok(())
+    }
+
+    // Applies the precision policy to an incoming energy amount, then validates the result.
+    // Returns the amount to store.
+    pub fn normalize_energy_amount(&self, energy_amount: f64) -> Result<f64, String> {
+        if !energy_amount.is_finite() {
+            return Err("energy_amount must be a positive number".to_string());
+        }
+        let scale = 10f64.powi(self.energy_decimals as i32);
+        let rounded = (energy_amount * scale).round() / scale;
+        if self.energy_precision_policy == EnergyPrecisionPolicy::Reject
+            && (energy_amount * scale - (energy_amount * scale).round()).abs() > 1e-6
+        {
+            return Err(format!(
+                "energy_amount {} has more than {} decimal places",
+                energy_amount, self.energy_decimals
+            ));
+        }
+        if energy_amount > 0.0 && rounded == 0.0 {
+            return Err(format!(
+                "energy_amount {} rounds to zero at {} decimal places",
+                energy_amount, self.energy_decimals
+            ));
+        }
+        self.validate_energy_amount(rounded)?;
+        Ok(rounded)
+    }
+
+    // Checks that a trade of `energy` kWh worth `notional` fits in what is left of the
+    // prosumer's daily allowance, given what they have already traded today
+    pub fn check_daily_limits(&self, address: &str, used_energy: f64, used_notional: f64, energy: f64, notional: f64) -> Result<(), String> {
+        // Small tolerance so a trade that exactly uses up the allowance is not rejected
+        // over floating point noise
+        const EPSILON: f64 = 1e-9;
+        if self.daily_energy_limit > 0.0 && used_energy + energy > self.daily_energy_limit + EPSILON {
+            return Err(format!(
+                "Trade of {} kWh exceeds the daily energy limit for '{}' ({} of {} kWh used today)",
+                energy, address, used_energy, self.daily_energy_limit
+            ));
+        }
+        if self.daily_notional_limit > 0.0 && used_notional + notional > self.daily_notional_limit + EPSILON {
+            return Err(format!(
+                "Trade worth {} exceeds the daily notional limit for '{}' ({} of {} used today)",
+                notional, address, used_notional, self.daily_notional_limit
+            ));
+        }
+        Ok(())
+    }
+
+    // Checks that a prosumer with `open_orders` resting may place one more. `tags` is the
+    // prosumer's metadata; a parsable `max_open_orders` tag replaces the market-wide cap.
+    pub fn check_open_order_limit(&self, address: &str, tags: &BTreeMap<String, String>, open_orders: i64) -> Result<(), String> {
+        let limit = tags
+            .get(MAX_OPEN_ORDERS_TAG)
+            .and_then(|v| v.trim().parse::<u32>().ok())
+            .unwrap_or(self.max_open_orders_per_prosumer);
+        if limit > 0 && open_orders >= i64::from(limit) {
+            return Err(format!(
+                "'{}' already has {} open orders; the maximum is {}",
+                address, open_orders, limit
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_price(&self, price: f64) -> Result<(), String> {
+        if !price.is_finite() || price <= 0.0 {
+            return Err("price_per_unit must be a positive number".to_string());
+        }
+        if self.price_tick > 0.0 {
+            let ticks = price / self.price_tick;
+            if (ticks - ticks.round()).abs() > 1e-6 {
+                return Err(format!(
+                    "price_per_unit {} is not a multiple of the price tick {}",
+                    price, self.price_tick
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Execution price policy for crossing orders:
+// - seller:   the sell order's price (default)
+// - buyer:    the buy order's price
+// - midpoint: (buy_price + sell_price) / 2, rounded to the price tick
+// - resting:  the price of whichever order was in the book first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchPricePolicy {
+    #[default]
+    Seller,
+    Buyer,
+    Midpoint,
+    Resting,
+}
+
+impl std::str::FromStr for MatchPricePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "seller" => Ok(MatchPricePolicy::Seller),
+            "buyer" => Ok(MatchPricePolicy::Buyer),
+            "midpoint" => Ok(MatchPricePolicy::Midpoint),
+            "resting" => Ok(MatchPricePolicy::Resting),
+            other => Err(format!("unknown match price policy '{}'", other)),
+        }
+    }
+}
+
+// Handling of energy amounts more precise than ENERGY_DECIMALS:
+// - round:  round half away from zero to ENERGY_DECIMALS places (default)
+// - reject: refuse the request with a validation error
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnergyPrecisionPolicy {
+    #[default]
+    Round,
+    Reject,
+}
+
+impl std::str::FromStr for EnergyPrecisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "round" => Ok(EnergyPrecisionPolicy::Round),
+            "reject" => Ok(EnergyPrecisionPolicy::Reject),
+            other => Err(format!("unknown energy precision policy '{}'", other)),
+        }
+    }
+}
+
+// Lot accounting for realized P&L, i.e. which purchases a sale is matched against:
+// - average_cost: every sale costs the running average price of the energy held (default)
+// - fifo:         a sale uses up the oldest purchased lots first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PnlMethod {
+    #[default]
+    AverageCost,
+    Fifo,
+}
+
+impl std::str::FromStr for PnlMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "average_cost" => Ok(PnlMethod::AverageCost),
+            "fifo" => Ok(PnlMethod::Fifo),
+            other => Err(format!("unknown P&L method '{}'", other)),
+        }
+    }
+}
+
+// One band of the fee schedule: trades with `min_volume <= total_price < max_volume` pay `rate`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub min_volume: f64,
+    pub max_volume: Option<f64>, // None = unbounded
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub tiers: Vec<FeeTier>,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self::flat(0.0)
+    }
+}
+
+impl FeeSchedule {
+    pub fn flat(rate: f64) -> Self {
+        Self {
+            tiers: vec![FeeTier { min_volume: 0.0, max_volume: None, rate }],
+        }
+    }
+
+    // Reads GRID_FEE_TIERS as a JSON array of tiers, e.g.
+    // [{"min_volume":0,"max_volume":1000,"rate":0.02},{"min_volume":1000,"max_volume":null,"rate":0.01}],
+    // falling back to a single flat GRID_FEE_RATE (default 0)
+    pub fn from_env() -> Self {
+        if let Ok(spec) = std::env::var("GRID_FEE_TIERS") {
+            match serde_json::from_str::<Vec<FeeTier>>(&spec).map_err(|e| e.to_string()).and_then(Self::new) {
+                Ok(schedule) => return schedule,
+                Err(e) => log::warn!("Ignoring invalid GRID_FEE_TIERS: {}", e),
+            }
+        }
+        Self::flat(env_parse("GRID_FEE_RATE", 0.0))
+    }
+
+    pub fn new(mut tiers: Vec<FeeTier>) -> Result<Self, String> {
+        if tiers.is_empty() {
+            return Err("fee schedule must contain at least one tier".to_string());
+        }
+        for tier in &tiers {
+            if !tier.rate.is_finite() || tier.rate < 0.0 || tier.rate >= 1.0 {
+                return Err(format!("fee rate {} must be in [0, 1)", tier.rate));
+            }
+            if tier.max_volume.is_some_and(|max| max <= tier.min_volume) {
+                return Err(format!("fee tier starting at {} has an empty range", tier.min_volume));
+            }
+        }
+        tiers.sort_by(|a, b| a.min_volume.total_cmp(&b.min_volume));
+        Ok(Self { tiers })
+    }
+
+    // Rate for a trade worth `total_price`; bands are inclusive below and exclusive above
+    pub fn rate_for(&self, total_price: f64) -> f64 {
+        self.tiers
+            .iter()
+            .find(|tier| total_price >= tier.min_volume && tier.max_volume.is_none_or(|max| total_price < max))
+            .map(|tier| tier.rate)
+            .unwrap_or(0.0)
+    }
+
+    pub fn fee_for(&self, total_price: f64) -> f64 {
+        total_price * self.rate_for(total_price)
+    }
+}
+
+// Token balances granted to prosumers created through the API; zero unless configured
+#[derive(Debug, Clone, Default)]
+pub struct InitialBalanceConfig {
+    pub grid_tokens: f64,
+    pub watt_tokens: f64,
+}
+
+impl InitialBalanceConfig {
+    // Reads INITIAL_GRID_BALANCE and INITIAL_WATT_BALANCE; negative values are treated as zero
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            grid_tokens: env_parse("INITIAL_GRID_BALANCE", defaults.grid_tokens).max(0.0),
+            watt_tokens: env_parse("INITIAL_WATT_BALANCE", defaults.watt_tokens).max(0.0),
+        }
+    }
+}
+
+// Page size used by list endpoints when the client does not pass `limit`
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+// Largest `limit` a list endpoint will honour; larger values are clamped
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(Debug, Clone)]
+pub struct PaginationConfig {
+    pub default_page_size: u32,
+    pub max_page_size: u32,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            default_page_size: DEFAULT_PAGE_SIZE,
+            max_page_size: MAX_PAGE_SIZE,
+        }
+    }
+}
+
+impl PaginationConfig {
+    // Reads DEFAULT_PAGE_SIZE and MAX_PAGE_SIZE
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let max_page_size = env_parse("MAX_PAGE_SIZE", defaults.max_page_size).max(1);
+        Self {
+            default_page_size: env_parse("DEFAULT_PAGE_SIZE", defaults.default_page_size).clamp(1, max_page_size),
+            max_page_size,
+        }
+    }
+
+    // Effective (page, limit): page is 1-based and limit is clamped to the configured maximum
+    pub fn resolve(&self, page: Option<u32>, limit: Option<u32>) -> (u32, u32) {
+        let page = page.unwrap_or(1).max(1);
+        let limit = limit
+            .unwrap_or(self.default_page_size)
+            .clamp(1, self.max_page_size);
+        (page, limit)
+    }
+}
+
+// Upper bounds on free-text request fields, checked at the API boundary
+#[derive(Debug, Clone)]
+pub struct FieldLimits {
+    pub max_address_length: usize,
+    pub max_name_length: usize,
+    pub max_description_length: usize,
+}
+
+impl Default for FieldLimits {
+    fn default() -> Self {
+        Self {
+            max_address_length: 128,
+            max_name_length: 256,
+            max_description_length: 4096,
+        }
+    }
+}
+
+impl FieldLimits {
+    // Reads MAX_ADDRESS_LENGTH, MAX_NAME_LENGTH and MAX_DESCRIPTION_LENGTH
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_address_length: env_parse("MAX_ADDRESS_LENGTH", defaults.max_address_length),
+            max_name_length: env_parse("MAX_NAME_LENGTH", defaults.max_name_length),
+            max_description_length: env_parse("MAX_DESCRIPTION_LENGTH", defaults.max_description_length),
+        }
+    }
+}
+
+// Trims surrounding whitespace and rejects empty, over-long or control-character values
+pub fn sanitize_text(field: &str, value: &str, max_length: usize) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(format!("{} must not be empty", field));
+    }
+    if value.chars().count() > max_length {
+        return Err(format!("{} must be at most {} characters", field, max_length));
+    }
+    if value.chars().any(char::is_control) {
+        return Err(format!("{} must not contain control characters", field));
+    }
+    Ok(value.to_string())
+}
+
+// Longest prosumer tag key; keys are restricted to ASCII letters, digits, '_' and '-' so they
+// can be used in JSON paths as-is
+pub const MAX_TAG_KEY_LENGTH: usize = 64;
+
+pub fn sanitize_tag_key(key: &str) -> Result<String, String> {
+    let key = key.trim();
+    if key.is_empty() || key.len() > MAX_TAG_KEY_LENGTH {
+        return Err(format!("tag keys must be 1 to {} characters", MAX_TAG_KEY_LENGTH));
+    }
+    if !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(format!("tag key '{}' may only contain letters, digits, '_' and '-'", key));
+    }
+    Ok(key.to_string())
+}
+
+// Bounds on a single match run. Each pass executes at most `batch_size` fills from a fresh plan
+// of the book; passes repeat until nothing more crosses or `max_trades_per_run` trades have
+// been made.
+#[derive(Debug, Clone)]
+pub struct MatchBatchConfig {
+    pub batch_size: usize,
+    pub max_trades_per_run: usize,
+}
+
+impl Default for MatchBatchConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            max_trades_per_run: 10_000,
+        }
+    }
+}
+
+impl MatchBatchConfig {
+    // Reads MATCH_BATCH_SIZE and MATCH_MAX_TRADES_PER_RUN
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            batch_size: env_parse("MATCH_BATCH_SIZE", defaults.batch_size).max(1),
+            max_trades_per_run: env_parse("MATCH_MAX_TRADES_PER_RUN", defaults.max_trades_per_run).max(1),
+        }
+    }
+}
+
+// Background order matching, off unless AUTO_MATCH_ENABLED is set
+#[derive(Debug, Clone)]
+pub struct AutoMatchConfig {
+    pub enabled: bool,
+    pub interval_ms: u64,
+    // Idle cycles stretch the interval up to this multiple of `interval_ms`
+    pub max_backoff_factor: u32,
+}
+
+impl Default for AutoMatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: 5_000,
+            max_backoff_factor: 12,
+        }
+    }
+}
+
+impl AutoMatchConfig {
+    // Reads AUTO_MATCH_ENABLED and AUTO_MATCH_INTERVAL_MS
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_parse("AUTO_MATCH_ENABLED", defaults.enabled),
+            interval_ms: env_parse("AUTO_MATCH_INTERVAL_MS", defaults.interval_ms).max(1),
+            max_backoff_factor: defaults.max_backoff_factor,
+        }
+    }
+}
+
+// Environments where test-only features (`--seed`, the faucet) refuse to run
+const PROTECTED_ENVIRONMENTS: [&str; 2] = ["production", "prod"];
+
+// True when APP_ENV names a production environment
+pub fn is_production_env() -> bool {
+    let env = std::env::var("APP_ENV").unwrap_or_default();
+    PROTECTED_ENVIRONMENTS.contains(&env.trim().to_ascii_lowercase().as_str())
+}
+
+// Test-environment token faucet. Each drip credits `grid_amount` GRID and `watt_amount` WATT;
+// an address can drip again once `cooldown_secs` have passed. Never enabled in production.
+#[derive(Debug, Clone)]
+pub struct FaucetConfig {
+    pub enabled: bool,
+    pub grid_amount: f64,
+    pub watt_amount: f64,
+    pub cooldown_secs: u64,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            grid_amount: 100.0,
+            watt_amount: 100.0,
+            cooldown_secs: 3_600,
+        }
+    }
+}
+
+impl FaucetConfig {
+    // Reads FAUCET_ENABLED, FAUCET_GRID_AMOUNT, FAUCET_WATT_AMOUNT and FAUCET_COOLDOWN_SECS
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let mut enabled = env_parse("FAUCET_ENABLED", defaults.enabled);
+        if enabled && is_production_env() {
+            log::warn!("FAUCET_ENABLED is ignored in production");
+            enabled = false;
+        }
+        Self {
+            enabled,
+            grid_amount: env_parse("FAUCET_GRID_AMOUNT", defaults.grid_amount).max(0.0),
+            watt_amount: env_parse("FAUCET_WATT_AMOUNT", defaults.watt_amount).max(0.0),
+            cooldown_secs: env_parse("FAUCET_COOLDOWN_SECS", defaults.cooldown_secs),
+        }
+    }
+}
+
+// Trade history archival, off unless TRADE_ARCHIVE_ENABLED is set. Trades created more than
+// `retention_days` ago are moved from `trades` into `trades_archive` every `interval_secs`.
+#[derive(Debug, Clone)]
+pub struct TradeArchiveConfig {
+    pub enabled: bool,
+    pub retention_days: i64,
+    pub interval_secs: u64,
+}
+
+impl Default for TradeArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_days: 90,
+            interval_secs: 3_600,
+        }
+    }
+}
+
+impl TradeArchiveConfig {
+    // Reads TRADE_ARCHIVE_ENABLED, TRADE_RETENTION_DAYS and TRADE_ARCHIVE_INTERVAL_SECS
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_parse("TRADE_ARCHIVE_ENABLED", defaults.enabled),
+            retention_days: env_parse("TRADE_RETENTION_DAYS", defaults.retention_days).max(1),
+            interval_secs: env_parse("TRADE_ARCHIVE_INTERVAL_SECS", defaults.interval_secs).max(1),
+        }
+    }
+}
+
+// Response compression, negotiated from Accept-Encoding. Bodies smaller than
+// `min_size` bytes are sent as-is since compressing them rarely pays off.
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    pub min_size: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size: 1024,
+        }
+    }
+}
+
+impl CompressionConfig {
+    // Reads COMPRESSION_ENABLED and COMPRESSION_MIN_SIZE
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_parse("COMPRESSION_ENABLED", defaults.enabled),
+            min_size: env_parse("COMPRESSION_MIN_SIZE", defaults.min_size),
+        }
+    }
+}
+
+// Per-caller request quota reported in the X-RateLimit-* headers of authenticated
+// responses. Requests are counted in fixed windows of `window_secs` seconds; nothing is
+// throttled yet, so the quota is advisory.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub requests_per_window: u32,
+    pub window_secs: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_window: 1000,
+            window_secs: 60,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    // Reads RATE_LIMIT_REQUESTS and RATE_LIMIT_WINDOW_SECS
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            requests_per_window: env_parse("RATE_LIMIT_REQUESTS", defaults.requests_per_window),
+            window_secs: env_parse("RATE_LIMIT_WINDOW_SECS", defaults.window_secs).max(1),
+        }
+    }
+}
+
+pub const DEFAULT_MIGRATIONS_TABLE: &str = "_sqlx_migrations";
+
+// Schema migrations at startup. Read replicas set RUN_MIGRATIONS=false so the service
+// never attempts DDL. MIGRATIONS_BASELINE marks every migration up to that version as
+// applied without running it, for databases whose schema was created out of band.
+#[derive(Debug, Clone)]
+pub struct MigrationConfig {
+    pub run: bool,
+    pub table: String,
+    pub baseline: Option<i64>,
+}
+
+impl Default for MigrationConfig {
+    fn default() -> Self {
+        Self {
+            run: true,
+            table: DEFAULT_MIGRATIONS_TABLE.to_string(),
+            baseline: None,
+        }
+    }
+}
+
+impl MigrationConfig {
+    // Reads RUN_MIGRATIONS, MIGRATIONS_TABLE and MIGRATIONS_BASELINE
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            run: env_parse("RUN_MIGRATIONS", defaults.run),
+            table: std::env::var("MIGRATIONS_TABLE")
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .unwrap_or(defaults.table),
+            baseline: std::env::var("MIGRATIONS_BASELINE").ok().and_then(|v| v.trim().parse().ok()),
+        }
+    }
+
+    // The table name is interpolated into DDL, so only plain identifiers are accepted
+    pub fn validate(&self) -> Result<(), String> {
+        let valid = self.table.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && self.table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("MIGRATIONS_TABLE '{}' is not a valid identifier", self.table));
+        }
+        Ok(())
+    }
+}
+
+// Request body size caps. Oversized bodies are rejected with 413 before any JSON is parsed;
+// the batch endpoints (`server::BATCH_ROUTES`) get their own, larger allowance.
+#[derive(Debug, Clone)]
+pub struct BodyLimitConfig {
+    pub max_body_bytes: usize,
+    pub max_batch_body_bytes: usize,
+}
+
+impl Default for BodyLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 256 * 1024,
+            max_batch_body_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+impl BodyLimitConfig {
+    // Reads MAX_BODY_BYTES and MAX_BATCH_BODY_BYTES
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_body_bytes: env_parse("MAX_BODY_BYTES", defaults.max_body_bytes),
+            max_batch_body_bytes: env_parse("MAX_BATCH_BODY_BYTES", defaults.max_batch_body_bytes),
+        }
+    }
+}
+
+// Cross-origin access. CORS_ALLOWED_ORIGINS is a comma-separated allowlist, `*` allows any
+// origin. Cookie-based clients need CORS_ALLOW_CREDENTIALS=true, which browsers only honour
+// when the matched origin is reflected, so it cannot be combined with `*`.
+// CORS_MAX_AGE_SECS is how long browsers may cache a preflight result; 0 leaves it to them.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+            allow_credentials: false,
+            max_age_secs: 600,
+        }
+    }
+}
+
+impl CorsConfig {
+    // Reads CORS_ALLOWED_ORIGINS, CORS_ALLOW_CREDENTIALS and CORS_MAX_AGE_SECS
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            allowed_origins: std::env::var("CORS_ALLOWED_ORIGINS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                        .filter(|origin| !origin.is_empty())
+                        .collect::<Vec<_>>()
+                })
+                .ok()
+                .filter(|origins| !origins.is_empty())
+                .unwrap_or(defaults.allowed_origins),
+            allow_credentials: env_parse("CORS_ALLOW_CREDENTIALS", defaults.allow_credentials),
+            max_age_secs: env_parse("CORS_MAX_AGE_SECS", defaults.max_age_secs),
+        }
+    }
+
+    pub fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    // Credentials require an explicit allowlist; origins must be absolute http(s) URLs
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allow_credentials && (self.allows_any_origin() || self.allowed_origins.is_empty()) {
+            return Err(
+                "CORS_ALLOW_CREDENTIALS=true requires an explicit CORS_ALLOWED_ORIGINS list, not '*'".to_string(),
+            );
+        }
+        if let Some(origin) = self.allowed_origins.iter().find(|origin| {
+            origin.as_str() != "*"
+                && !(origin.starts_with("http://") || origin.starts_with("https://"))
+        }) {
+            return Err(format!("CORS origin '{}' must start with http:// or https://", origin));
+        }
+        Ok(())
+    }
+}
+
+// Where the HTTP server listens. BIND_ADDRESS accepts an IPv4 or IPv6 address (the
+// configured port is used), a full `addr:port` / `[v6]:port`, or `unix:/path/to.sock`
+// for a Unix domain socket. Defaults to 127.0.0.1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindAddress {
+    Tcp(std::net::SocketAddr),
+    Unix(std::path::PathBuf),
+}
+
+impl BindAddress {
+    pub fn from_env(port: u16) -> Result<Self, String> {
+        match std::env::var("BIND_ADDRESS") {
+            Ok(value) if !value.trim().is_empty() => Self::parse(value.trim(), port),
+            _ => Ok(BindAddress::Tcp(std::net::SocketAddr::from(([127, 0, 0, 1], port)))),
+        }
+    }
+
+    pub fn parse(value: &str, port: u16) -> Result<Self, String> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("BIND_ADDRESS 'unix:' needs a socket path".to_string());
+            }
+            return Ok(BindAddress::Unix(path.into()));
+        }
+        if let Ok(addr) = value.parse::<std::net::SocketAddr>() {
+            return Ok(BindAddress::Tcp(addr));
+        }
+        let ip = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')).unwrap_or(value);
+        ip.parse::<std::net::IpAddr>()
+            .map(|ip| BindAddress::Tcp(std::net::SocketAddr::new(ip, port)))
+            .map_err(|_| format!("BIND_ADDRESS '{}' is not an IP address, socket address or unix: path", value))
+    }
+}
+
+impl std::fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindAddress::Tcp(addr) => write!(f, "{}", addr),
+            BindAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn three_tier_schedule() -> FeeSchedule {
+        FeeSchedule::new(vec![
+            FeeTier { min_volume: 1_000.0, max_volume: None, rate: 0.01 },
+            FeeTier { min_volume: 0.0, max_volume: Some(100.0), rate: 0.03 },
+            FeeTier { min_volume: 100.0, max_volume: Some(1_000.0), rate: 0.02 },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn fee_tiers_apply_the_rate_of_each_band() {
+        let schedule = three_tier_schedule();
+        assert_eq!(schedule.rate_for(50.0), 0.03);
+        assert_eq!(schedule.rate_for(500.0), 0.02);
+        assert_eq!(schedule.rate_for(5_000.0), 0.01);
+        assert_eq!(schedule.fee_for(50.0), 50.0 * 0.03);
+        assert_eq!(schedule.fee_for(5_000.0), 50.0);
+    }
+
+    #[test]
+    fn fee_tier_boundaries_are_inclusive_below_and_exclusive_above() {
+        let schedule = three_tier_schedule();
+        assert_eq!(schedule.rate_for(0.0), 0.03);
+        assert_eq!(schedule.rate_for(99.99), 0.03);
+        assert_eq!(schedule.rate_for(100.0), 0.02);
+        assert_eq!(schedule.rate_for(999.99), 0.02);
+        assert_eq!(schedule.rate_for(1_000.0), 0.01);
+        assert_eq!(schedule.fee_for(100.0), 2.0);
+        assert_eq!(schedule.fee_for(1_000.0), 10.0);
+    }
+
+    #[test]
+    fn trade_outside_every_tier_pays_no_fee() {
+        let schedule = FeeSchedule::new(vec![FeeTier { min_volume: 10.0, max_volume: Some(20.0), rate: 0.05 }]).unwrap();
+        assert_eq!(schedule.fee_for(5.0), 0.0);
+        assert_eq!(schedule.fee_for(20.0), 0.0);
+    }
+
+    #[test]
+    fn fee_schedule_rejects_bad_tiers() {
+        assert!(FeeSchedule::new(Vec::new()).is_err());
+        assert!(FeeSchedule::new(vec![FeeTier { min_volume: 0.0, max_volume: None, rate: 1.0 }]).is_err());
+        assert!(FeeSchedule::new(vec![FeeTier { min_volume: 0.0, max_volume: None, rate: -0.01 }]).is_err());
+        assert!(FeeSchedule::new(vec![FeeTier { min_volume: 100.0, max_volume: Some(100.0), rate: 0.01 }]).is_err());
+    }
+
+    #[test]
+    fn energy_amounts_are_rounded_to_the_configured_decimals() {
+        let market = MarketConfig::default();
+        assert_eq!(market.normalize_energy_amount(0.3333333), Ok(0.333));
+        assert_eq!(market.normalize_energy_amount(2.0), Ok(2.0));
+        let err = market.normalize_energy_amount(0.0004).unwrap_err();
+        assert!(err.contains("rounds to zero"), "{}", err);
+    }
+
+    #[test]
+    fn reject_policy_refuses_amounts_finer_than_the_configured_decimals() {
+        let market = MarketConfig {
+            energy_precision_policy: EnergyPrecisionPolicy::Reject,
+            ..MarketConfig::default()
+        };
+        assert_eq!(market.normalize_energy_amount(1.25), Ok(1.25));
+        let err = market.normalize_energy_amount(0.3333333).unwrap_err();
+        assert!(err.contains("decimal places"), "{}", err);
+    }
+
+    fn daily_limits(energy: f64, notional: f64) -> MarketConfig {
+        MarketConfig {
+            daily_energy_limit: energy,
+            daily_notional_limit: notional,
+            ..MarketConfig::default()
+        }
+    }
+
+    #[test]
+    fn trade_that_uses_up_the_daily_allowance_exactly_is_allowed() {
+        let market = daily_limits(100.0, 20.0);
+        assert!(market.check_daily_limits("0xa", 60.0, 12.0, 40.0, 8.0).is_ok());
+        // Accumulated floating point noise doesn't tip it over
+        assert!(market.check_daily_limits("0xa", 0.1 + 0.2, 0.0, 99.7, 0.0).is_ok());
+    }
+
+    #[test]
+    fn trade_beyond_the_daily_energy_limit_is_rejected() {
+        let market = daily_limits(100.0, 0.0);
+        let err = market.check_daily_limits("0xa", 60.0, 0.0, 40.5, 0.0).unwrap_err();
+        assert!(err.contains("daily energy limit"), "{}", err);
+    }
+
+    #[test]
+    fn trade_beyond_the_daily_notional_limit_is_rejected() {
+        let market = daily_limits(0.0, 20.0);
+        let err = market.check_daily_limits("0xa", 0.0, 15.0, 1.0, 5.01).unwrap_err();
+        assert!(err.contains("daily notional limit"), "{}", err);
+    }
+
+    #[test]
+    fn zero_daily_limits_are_disabled() {
+        let market = daily_limits(0.0, 0.0);
+        assert!(market.check_daily_limits("0xa", 1e9, 1e9, 1e9, 1e9).is_ok());
+    }
+
+    #[test]
+    fn pagination_clamps_the_limit_and_starts_at_page_one() {
+        let pagination = PaginationConfig { default_page_size: 20, max_page_size: 50 };
+        assert_eq!(pagination.resolve(None, None), (1, 20));
+        assert_eq!(pagination.resolve(Some(3), Some(10)), (3, 10));
+        assert_eq!(pagination.resolve(Some(1), Some(500)), (1, 50));
+        assert_eq!(pagination.resolve(Some(0), Some(0)), (1, 1));
+        assert_eq!(pagination.resolve(Some(u32::MAX), Some(u32::MAX)), (u32::MAX, 50));
+    }
+
+    #[test]
+    fn bind_address_accepts_ipv4_ipv6_and_unix_sockets() {
+        let tcp = |addr: &str| BindAddress::Tcp(addr.parse().unwrap());
+        assert_eq!(BindAddress::parse("0.0.0.0", 8080), Ok(tcp("0.0.0.0:8080")));
+        assert_eq!(BindAddress::parse("10.0.0.1:9000", 8080), Ok(tcp("10.0.0.1:9000")));
+        assert_eq!(BindAddress::parse("::", 8080), Ok(tcp("[::]:8080")));
+        assert_eq!(BindAddress::parse("[::1]", 8080), Ok(tcp("[::1]:8080")));
+        assert_eq!(BindAddress::parse("[fd00::1]:9000", 8080), Ok(tcp("[fd00::1]:9000")));
+        assert_eq!(
+            BindAddress::parse("unix:/run/energy/api.sock", 8080),
+            Ok(BindAddress::Unix("/run/energy/api.sock".into()))
+        );
+        assert_eq!(BindAddress::parse("unix:/run/energy/api.sock", 8080).unwrap().to_string(), "unix:/run/energy/api.sock");
+    }
+
+    #[test]
+    fn bind_address_rejects_anything_else() {
+        for value in ["unix:", "localhost", "example.com:80", "256.0.0.1", "::1:8080:", "[::1", "10.0.0.1:99999"] {
+            assert!(BindAddress::parse(value, 8080).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn faucet_is_only_enabled_outside_production() {
+        for env in ["test", "development", ""] {
+            let config = with_env(&[("FAUCET_ENABLED", "true"), ("APP_ENV", env)], FaucetConfig::from_env);
+            assert!(config.enabled, "APP_ENV={:?}", env);
+        }
+        for env in ["production", "prod", " Production "] {
+            let config = with_env(&[("FAUCET_ENABLED", "true"), ("APP_ENV", env)], FaucetConfig::from_env);
+            assert!(!config.enabled, "APP_ENV={:?}", env);
+        }
+        assert!(!with_env(&[], FaucetConfig::from_env).enabled);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+// Currency every order price is normalized to before matching
+pub const DEFAULT_BASE_CURRENCY: &str = "GRID";
+
+// Source of exchange rates used to normalize cross-currency order prices.
+// Implement this to plug in a live feed; the default is a static table from env.
+pub trait ExchangeRateSource: Send + Sync {
+    // Currency that normalized prices are expressed in
+    fn base_currency(&self) -> &str;
+
+    // Units of the base currency per one unit of `currency`, if the currency is known
+    fn rate(&self, currency: &str) -> Option<f64>;
+
+    // Converts a price quoted in `currency` into the base currency
+    fn to_base(&self, currency: &str, price: f64) -> Option<f64> {
+        self.rate(currency).map(|rate| price * rate)
+    }
+}
+
+// Fixed rate table, configured via EXCHANGE_RATES="USD=35.0,EUR=38.5"
+#[derive(Debug, Clone)]
+pub struct StaticRateTable {
+    base_currency: String,
+    rates: HashMap<String, f64>,
+}
+
+impl StaticRateTable {
+    pub fn new(base_currency: &str, rates: HashMap<String, f64>) -> Self {
+        let base_currency = base_currency.to_uppercase();
+        let mut rates: HashMap<String, f64> = rates
+            .into_iter()
+            .map(|(currency, rate)| (currency.to_uppercase(), rate))
+            .collect();
+        rates.insert(base_currency.clone(), 1.0);
+
+        Self { base_currency, rates }
+    }
+
+    pub fn from_env() -> Self {
+        let base_currency = std::env::var("BASE_CURRENCY")
+            .unwrap_or_else(|_| DEFAULT_BASE_CURRENCY.to_string());
+        let rates = std::env::var("EXCHANGE_RATES")
+            .map(|spec| Self::parse_rates(&spec))
+            .unwrap_or_default();
+
+        Self::new(&base_currency, rates)
+    }
+
+    fn parse_rates(spec: &str) -> HashMap<String, f64> {
+        let mut rates = HashMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((currency, rate)) => match rate.trim().parse::<f64>() {
+                    Ok(rate) if rate.is_finite() && rate > 0.0 => {
+                        rates.insert(currency.trim().to_string(), rate);
+                    }
+                    _ => log::warn!("Ignoring invalid exchange rate entry '{}'", entry),
+                },
+                None => log::warn!("Ignoring invalid exchange rate entry '{}'", entry),
+            }
+        }
+        rates
+    }
+}
+
+impl ExchangeRateSource for StaticRateTable {
+    fn base_currency(&self) -> &str {
+        &self.base_currency
+    }
+
+    fn rate(&self, currency: &str) -> Option<f64> {
+        self.rates.get(&currency.to_uppercase()).copied()
+    }
+}
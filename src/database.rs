@@ -1,19 +1,58 @@
-use sqlx::{Pool, Sqlite, postgres::Postgres, Row, FromRow, sqlite::SqliteConnectOptions};
+use sqlx::{Pool, Sqlite, postgres::{PgConnectOptions, Postgres}, Row, FromRow, Transaction, Connection, sqlite::SqliteConnectOptions};
+use sqlx::migrate::Migration;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::str::FromStr;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::{env_parse, sanitize_tag_key, FeeSchedule, FieldLimits, InitialBalanceConfig, MarketConfig, MatchBatchConfig, MatchPricePolicy, MigrationConfig, PaginationConfig, PnlMethod};
+use crate::currency::{ExchangeRateSource, StaticRateTable};
+use crate::matching::{self, AggregatedBook, MatchDiagnostics, MatchSkip, MatchingEngine, OrderBook, PriceLevel, ProposedTrade, SkipReason};
+use crate::telemetry;
 
 #[derive(Debug, thiserror::Error)]
 pub enum DatabaseError {
     #[error("Database error: {0}")]
-    SqlxError(#[from] sqlx::Error),
+    SqlxError(sqlx::Error),
     #[error("Migration error: {0}")]
     MigrateError(#[from] sqlx::migrate::MigrateError),
     #[error("Record not found: {0}")]
     NotFound(String),
     #[error("Validation error: {0}")]
     Validation(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    // A balance shortfall, with the amount needed and what was actually spendable
+    #[error("Insufficient {token_type}: {required} required but only {available} available")]
+    InsufficientBalance { token_type: String, required: f64, available: f64 },
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+    #[error("Batch operation {index} failed: {source}")]
+    BatchFailed { index: usize, source: Box<DatabaseError> },
+    #[error("Market is halted: {0}")]
+    MarketHalted(String),
+    // A statement ran past DB_STATEMENT_TIMEOUT_MS (or, on SQLite, waited that long for a lock)
+    #[error("Query timed out: {0}")]
+    Timeout(String),
+}
+
+// Postgres query_canceled (raised by statement_timeout), SQLite SQLITE_BUSY and
+// SQLITE_BUSY_TIMEOUT (raised once busy_timeout runs out)
+const TIMEOUT_ERROR_CODES: [&str; 3] = ["57014", "5", "773"];
+
+impl From<sqlx::Error> for DatabaseError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::Database(db) if db.code().is_some_and(|code| TIMEOUT_ERROR_CODES.contains(&code.as_ref())) => {
+                DatabaseError::Timeout(db.message().to_string())
+            }
+            _ => DatabaseError::SqlxError(e),
+        }
+    }
 }
 
 // Database models
@@ -61,8 +100,12 @@ pub struct Order {
     pub prosumer_address: String,
     pub order_type: String, // "buy" or "sell"
     pub energy_amount: f64,
-    pub price_per_unit: f64,
+    pub price_per_unit: f64, // normalized to the base currency
     pub total_price: f64,
+    pub currency: String, // currency the order was quoted in
+    pub quoted_price_per_unit: f64, // price as submitted, in `currency`
+    pub filled_amount: f64, // energy matched so far across all trades
+    pub remaining_amount: f64, // energy_amount - filled_amount
     pub status: String, // "pending", "active", "completed", "cancelled"
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -79,9 +122,11 @@ pub struct Trade {
     pub energy_amount: f64,
     pub price_per_unit: f64,
     pub total_price: f64,
-    pub status: String, // "pending", "completed", "failed"
+    pub grid_fee: f64, // fee charged per the tiered schedule, in the base currency
+    pub status: String, // "pending", "completed", "failed", "disputed"
     pub executed_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    pub settles_at: Option<DateTime<Utc>>, // when a delayed trade pays out; None if it settled on execution
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -90,10 +135,109 @@ pub struct MarketStats {
     pub total_orders: i64,
     pub total_trades: i64,
     pub total_energy_traded: f64,
-    pub total_volume: f64,
+    pub total_volume: f64, // same as gross_volume, kept for existing clients
+    pub gross_volume: f64, // what buyers paid
+    pub net_volume: f64, // sellers' share net of fees: gross_volume - total_fees_collected
+    pub total_fees_collected: f64,
     pub average_price: f64,
     pub active_buy_orders: i64,
     pub active_sell_orders: i64,
+    pub imbalance: Option<f64>, // remaining bid vs. ask energy on the book, -1..1
+    pub min_order_energy: f64,
+    pub price_tick: f64,
+    pub fee_schedule: FeeSchedule,
+    pub match_price_policy: MatchPricePolicy,
+    pub energy_decimals: u32,
+    pub daily_energy_limit: f64, // 0 = no limit
+    pub daily_notional_limit: f64, // 0 = no limit
+    pub halted: bool,
+    pub halt_reason: Option<String>,
+}
+
+// Emergency stop set by an admin. While halted, order creation, matching and trade execution
+// are refused; reads are unaffected.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarketHalt {
+    pub halted: bool,
+    pub reason: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketTimeseriesPoint {
+    pub bucket_start: DateTime<Utc>,
+    pub trade_count: i64,
+    pub energy_traded: f64,
+    pub volume: f64,
+    pub average_price: f64,
+    // Prices of the first and last trade in the bucket and the range between; 0 without trades
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+}
+
+// One bucket of completed trades as aggregated by the database; `bucket` counts intervals
+// since the Unix epoch
+#[derive(Debug, FromRow)]
+struct TimeseriesBucketRow {
+    bucket: i64,
+    trade_count: i64,
+    energy_traded: f64,
+    volume: f64,
+    average_price: f64,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+}
+
+// Metric a page of prosumer stats is ranked by, highest first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProsumerStatsSort {
+    EnergyGenerated,
+    EnergyConsumed,
+    NetEnergy,
+    GridTokens,
+    OrdersCount,
+    TradesCount,
+    TotalEnergyTraded,
+    #[default]
+    TotalVolume,
+}
+
+impl ProsumerStatsSort {
+    // Output column of the stats query; never built from user input
+    fn column(&self) -> &'static str {
+        match self {
+            ProsumerStatsSort::EnergyGenerated => "energy_generated",
+            ProsumerStatsSort::EnergyConsumed => "energy_consumed",
+            ProsumerStatsSort::NetEnergy => "net_energy",
+            ProsumerStatsSort::GridTokens => "grid_tokens",
+            ProsumerStatsSort::OrdersCount => "orders_count",
+            ProsumerStatsSort::TradesCount => "trades_count",
+            ProsumerStatsSort::TotalEnergyTraded => "total_energy_traded",
+            ProsumerStatsSort::TotalVolume => "total_volume",
+        }
+    }
+}
+
+impl FromStr for ProsumerStatsSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "energy_generated" => Ok(ProsumerStatsSort::EnergyGenerated),
+            "energy_consumed" => Ok(ProsumerStatsSort::EnergyConsumed),
+            "net_energy" => Ok(ProsumerStatsSort::NetEnergy),
+            "grid_tokens" => Ok(ProsumerStatsSort::GridTokens),
+            "orders_count" => Ok(ProsumerStatsSort::OrdersCount),
+            "trades_count" => Ok(ProsumerStatsSort::TradesCount),
+            "total_energy_traded" => Ok(ProsumerStatsSort::TotalEnergyTraded),
+            "total_volume" => Ok(ProsumerStatsSort::TotalVolume),
+            other => Err(format!("unknown sort metric '{}'", other)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -111,6 +255,355 @@ pub struct ProsumerStats {
     pub total_volume: f64,
 }
 
+// Energy still committed in active orders: the unfilled remainder, as filled energy has traded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProsumerExposure {
+    pub address: String,
+    pub active_buy_orders: i64,
+    pub active_sell_orders: i64,
+    pub buy_energy_committed: f64,
+    pub sell_energy_committed: f64,
+    pub net_position: f64, // buy_energy_committed - sell_energy_committed
+}
+
+// Snapshot of a prosumer's open (pending or active) orders; energy is what remains unfilled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenOrderSummary {
+    pub address: String,
+    pub open_buy_orders: i64,
+    pub open_sell_orders: i64,
+    pub buy_energy_open: f64,
+    pub sell_energy_open: f64,
+    pub best_bid: Option<f64>, // highest price among the prosumer's own buy orders
+    pub best_ask: Option<f64>, // lowest price among the prosumer's own sell orders
+}
+
+// Result of closing a prosumer: the open orders that were cancelled and the balances swept to
+// the destination, one recorded transfer per token type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProsumerClosure {
+    pub address: String,
+    pub destination_address: String,
+    pub cancelled_orders: Vec<Order>,
+    pub sweeps: Vec<BalanceSweep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceSweep {
+    pub transfer_id: Uuid,
+    pub token_type: String,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenBalance {
+    pub address: String,
+    pub grid_tokens: f64,
+    pub watt_tokens: f64,
+    pub staked: f64, // always 0 until staking is tracked in the database
+    pub reserved_grid_tokens: f64, // escrowed by open buy orders, not included in grid_tokens
+    pub tokens: BTreeMap<String, f64>, // every non-empty balance, keyed by token type
+}
+
+// Outcome of a dry-run transfer. Balances are the projected post-transfer values when the
+// transfer would succeed and the current, untouched values when it would not.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferPreview {
+    pub would_succeed: bool,
+    pub reason: Option<String>,
+    pub token_type: String,
+    pub from_balance: f64,
+    pub to_balance: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkBalances {
+    pub balances: Vec<TokenBalance>,
+    pub not_found: Vec<String>,
+}
+
+// A trade seen from one prosumer's side; `role` is "buyer" or "seller"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProsumerTrade {
+    #[serde(flatten)]
+    pub trade: Trade,
+    pub role: String,
+}
+
+// An order with every trade it took part in, as either side
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderWithTrades {
+    #[serde(flatten)]
+    pub order: Order,
+    pub trades: Vec<Trade>,
+}
+
+// A prosumer with its order and trade counts, for detail pages that would otherwise
+// need a second call to the stats endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProsumerWithCounts {
+    #[serde(flatten)]
+    pub prosumer: Prosumer,
+    pub orders_count: i64,
+    pub trades_count: i64,
+}
+
+// Registered HTTP callback; `secret` signs each delivery and is only shown on creation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub events: Vec<String>,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub is_active: bool,
+    pub failure_count: i64,
+    pub created_at: DateTime<Utc>,
+    // The user who registered it
+    pub owner_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TokenTransfer {
+    pub id: Uuid,
+    pub from_address: String,
+    pub to_address: String,
+    pub token_type: String,
+    pub amount: f64,
+    pub created_at: DateTime<Utc>,
+}
+
+// A prosumer's activity in [from, to): current balances plus the transfers, orders and trades
+// in the window. Trades are matched on execution time and cover unarchived trades only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountStatement {
+    pub address: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub balance: TokenBalance,
+    pub transfers: Vec<TokenTransfer>,
+    pub orders: Vec<Order>,
+    pub trades: Vec<Trade>,
+    pub generated_at: DateTime<Utc>,
+}
+
+// Realized profit and loss on a prosumer's sales in [from, to), per UTC day. Each sale is
+// costed against the energy the prosumer bought before it (archived trades included) under
+// `method`; energy sold beyond what was bought is its own generation and costs nothing.
+// Only completed trades count.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProsumerPnl {
+    pub address: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub method: PnlMethod,
+    pub energy_sold: f64,
+    pub proceeds: f64,
+    pub cost_basis: f64,
+    pub realized_pnl: f64,
+    pub periods: Vec<PnlPeriod>, // days with at least one sale, oldest first
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PnlPeriod {
+    pub period_start: DateTime<Utc>,
+    pub energy_sold: f64,
+    pub proceeds: f64,
+    pub cost_basis: f64,
+    pub realized_pnl: f64,
+}
+
+impl AccountStatement {
+    // One row per balance and activity line. `side` is buy/sell for orders and trades and
+    // in/out for transfers; `amount` is the token amount or trade/order value.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("record_type,id,timestamp,side,counterparty,token_type,energy_amount,price_per_unit,amount,status\n");
+        let mut row = |fields: [&str; 10]| {
+            csv.push_str(&fields.map(csv_field).join(","));
+            csv.push('\n');
+        };
+        let generated_at = self.generated_at.to_rfc3339();
+        for (token_type, amount) in &self.balance.tokens {
+            row(["balance", "", &generated_at, "", "", token_type, "", "", &amount.to_string(), ""]);
+        }
+        for transfer in &self.transfers {
+            let (side, counterparty) = if transfer.from_address == self.address {
+                ("out", &transfer.to_address)
+            } else {
+                ("in", &transfer.from_address)
+            };
+            row([
+                "transfer", &transfer.id.to_string(), &transfer.created_at.to_rfc3339(), side, counterparty,
+                &transfer.token_type, "", "", &transfer.amount.to_string(), "",
+            ]);
+        }
+        for order in &self.orders {
+            row([
+                "order", &order.id.to_string(), &order.created_at.to_rfc3339(), &order.order_type, "",
+                &order.currency, &order.energy_amount.to_string(), &order.quoted_price_per_unit.to_string(),
+                &order.total_price.to_string(), &order.status,
+            ]);
+        }
+        for trade in &self.trades {
+            let (side, counterparty) = if trade.buyer_address == self.address {
+                ("buy", &trade.seller_address)
+            } else {
+                ("sell", &trade.buyer_address)
+            };
+            row([
+                "trade", &trade.id.to_string(), &trade.executed_at.to_rfc3339(), side, counterparty, "",
+                &trade.energy_amount.to_string(), &trade.price_per_unit.to_string(),
+                &trade.total_price.to_string(), &trade.status,
+            ]);
+        }
+        csv
+    }
+}
+
+// Quotes a CSV field when it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Fires once when the best bid or ask (`side`) moves to or past `price` in `direction`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PriceAlert {
+    pub id: Uuid,
+    pub address: String,
+    pub side: String,      // "bid" or "ask"
+    pub direction: String, // "above" or "below"
+    pub price: f64,
+    pub triggered: bool,
+    pub triggered_price: Option<f64>,
+    pub triggered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PriceAlert {
+    fn is_crossed(&self, best: f64) -> bool {
+        match self.direction.as_str() {
+            "above" => best >= self.price,
+            _ => best <= self.price,
+        }
+    }
+}
+
+// A transferable token; `name` is what transfers pass as `token_type`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TokenType {
+    pub name: String,
+    pub description: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Stored copy of the aggregated order book
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBookSnapshot {
+    pub id: Uuid,
+    pub book: AggregatedBook,
+    pub checksum: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// A price level whose energy or order count differs between the snapshot and the live book;
+// a level missing on one side shows up with zeros there
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelDrift {
+    pub side: String, // "bid" or "ask"
+    pub price: f64,
+    pub snapshot_energy: f64,
+    pub current_energy: f64,
+    pub snapshot_orders: i64,
+    pub current_orders: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBookVerification {
+    pub snapshot_id: Uuid,
+    pub snapshot_at: DateTime<Utc>,
+    pub snapshot_checksum: String,
+    pub current_checksum: String,
+    pub drifted: bool,
+    pub changed_levels: Vec<LevelDrift>,
+}
+
+// One mutating API request, as recorded by the audit middleware
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub user_id: Option<String>, // None for unauthenticated requests
+    pub method: String,
+    pub path: String,
+    pub request_id: String,
+    pub status_code: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+// Outcome of one matching run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MatchResult {
+    pub trades: Vec<Trade>,
+    pub matched_energy: f64,
+    pub total_value: f64,
+}
+
+// One-glance operational summary for on-call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemSummary {
+    pub active_orders: i64,
+    pub pending_trades: i64,
+    pub last_match_at: Option<DateTime<Utc>>, // last completed match_orders run since startup
+    pub last_trade_at: Option<DateTime<Utc>>,
+    pub db_latency_ms: f64, // round trip of a trivial query
+    pub generated_at: DateTime<Utc>,
+}
+
+// Applied vs. bundled migrations, read from the bookkeeping table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationStatus {
+    pub latest_applied: Option<i64>,
+    pub applied: usize,
+    pub pending: Vec<i64>,
+    pub failed: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseHealth {
+    pub backend: String,
+    pub reachable: bool,
+    pub latency_ms: Option<f64>, // round trip of a trivial query
+    pub pool_size: u32,
+    pub pool_idle: usize,
+    pub error: Option<String>,
+}
+
+// Operator-facing health report; `status` is "degraded" when either database is unreachable
+// or migrations are pending or failed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthDetails {
+    pub status: String,
+    pub version: String,
+    pub uptime_secs: u64,
+    pub database: DatabaseHealth,
+    pub replica: Option<DatabaseHealth>, // only when DATABASE_REPLICA_URL is set
+    pub migrations: Option<MigrationStatus>,
+    pub migrations_error: Option<String>,
+}
+
+// Execution report for a single order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderFills {
+    pub order_id: Uuid,
+    pub energy_amount: f64,
+    pub filled_amount: f64,
+    pub remaining_amount: f64,
+    pub fills: Vec<Trade>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseStats {
     pub total_users: i64,
@@ -121,7 +614,53 @@ pub struct DatabaseStats {
     pub database_size: Option<String>,
 }
 
+// A single step of an atomic multi-operation batch
+#[derive(Debug, Clone)]
+pub enum BatchOperation {
+    CreateProsumer(Prosumer),
+    Transfer {
+        from_address: String,
+        to_address: String,
+        amount: f64,
+        token_type: String,
+    },
+    CreateOrder(Order),
+    // Not exposed through POST /batch; lets internal callers such as the demo seed fill
+    // orders created earlier in the same batch
+    ExecuteTrade {
+        buy_order_id: Uuid,
+        sell_order_id: Uuid,
+        price_per_unit: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperationResult {
+    CreateProsumer { prosumer: Prosumer },
+    Transfer { transfer_id: String },
+    CreateOrder { order: Order },
+    ExecuteTrade { trade: Trade },
+}
+
 // Database row types for SQLx
+#[derive(FromRow)]
+struct OrderBookSnapshotRow {
+    id: Uuid,
+    book: String,
+    checksum: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct OpenOrderSideRow {
+    order_type: String,
+    order_count: i64,
+    energy_open: f64,
+    max_price: Option<f64>,
+    min_price: Option<f64>,
+}
+
 #[derive(FromRow)]
 struct ProsumerRow {
     pub address: String,
@@ -159,6 +698,9 @@ struct OrderRow {
     pub energy_amount: f64,
     pub price_per_unit: f64,
     pub total_price: f64,
+    pub currency: String,
+    pub quoted_price_per_unit: f64,
+    pub filled_amount: f64,
     pub status: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -174,6 +716,10 @@ impl From<OrderRow> for Order {
             energy_amount: row.energy_amount,
             price_per_unit: row.price_per_unit,
             total_price: row.total_price,
+            currency: row.currency,
+            quoted_price_per_unit: row.quoted_price_per_unit,
+            filled_amount: row.filled_amount,
+            remaining_amount: (row.energy_amount - row.filled_amount).max(0.0),
             status: row.status,
             created_at: row.created_at,
             updated_at: row.updated_at,
@@ -192,9 +738,38 @@ struct TradeRow {
     pub energy_amount: f64,
     pub price_per_unit: f64,
     pub total_price: f64,
+    pub grid_fee: f64,
     pub status: String,
     pub executed_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    pub settles_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow)]
+struct WebhookRow {
+    pub id: Uuid,
+    pub url: String,
+    pub events: String,
+    pub secret: String,
+    pub is_active: bool,
+    pub failure_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub owner_id: Option<String>,
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Webhook {
+            id: row.id,
+            url: row.url,
+            events: row.events.split(',').filter(|e| !e.is_empty()).map(str::to_string).collect(),
+            secret: row.secret,
+            is_active: row.is_active,
+            failure_count: row.failure_count,
+            created_at: row.created_at,
+            owner_id: row.owner_id,
+        }
+    }
 }
 
 impl From<TradeRow> for Trade {
@@ -208,9 +783,11 @@ impl From<TradeRow> for Trade {
             energy_amount: row.energy_amount,
             price_per_unit: row.price_per_unit,
             total_price: row.total_price,
+            grid_fee: row.grid_fee,
             status: row.status,
             executed_at: row.executed_at,
             created_at: row.created_at,
+            settles_at: row.settles_at,
         }
     }
 }
@@ -223,824 +800,5471 @@ pub enum DatabasePool {
 
 pub struct DatabaseService {
     pool: DatabasePool,
+    // Optional read replica (DATABASE_REPLICA_URL); reads fall back to `pool` without one
+    replica: Option<DatabasePool>,
+    exchange_rates: Arc<dyn ExchangeRateSource>,
+    market_config: MarketConfig,
+    pagination: PaginationConfig,
+    initial_balances: InitialBalanceConfig,
+    field_limits: FieldLimits,
+    matching_engine: Arc<dyn MatchingEngine>,
+    match_batch: MatchBatchConfig,
+    match_lock: Mutex<()>,
+    match_diagnostics: MatchDiagnostics,
+    // False on SQLite older than MIN_SQLITE_RETURNING_VERSION
+    sqlite_returning: bool,
+    // Operations slower than this are logged as warnings; 0 disables the log
+    slow_query_ms: u64,
+    // Applied to every connection, the replica's included; 0 leaves statements unbounded
+    statement_timeout_ms: u64,
+    // Market stats per tag scope, reused for `stats_cache_secs` or until a trade executes
+    stats_cache_secs: u64,
+    market_stats_cache: std::sync::Mutex<MarketStatsCache>,
+    last_match_at: std::sync::Mutex<Option<DateTime<Utc>>>,
+    started_at: std::time::Instant,
+    // Decides expiry, settlement and timestamps; a MockClock in tests
+    clock: Arc<dyn Clock>,
 }
 
-impl DatabaseService {
-    pub async fn new(database_url: &str) -> Result<Self, DatabaseError> {
-        let pool = if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
-            DatabasePool::Postgres(Pool::<Postgres>::connect(database_url).await?)
-        } else {
-            // For SQLite, use custom connection options to create database if missing
-            let sqlite_options = SqliteConnectOptions::from_str(database_url)?
-                .create_if_missing(true);
-            DatabasePool::Sqlite(Pool::<Sqlite>::connect_with(sqlite_options).await?)
-        };
-        
-        Ok(Self { pool })
+// Columns list endpoints may be sorted by; anything else is rejected before reaching SQL
+pub const PROSUMER_SORT_COLUMNS: [&str; 6] = ["created_at", "updated_at", "name", "address", "energy_generated", "energy_consumed"];
+pub const ORDER_SORT_COLUMNS: [&str; 7] = ["created_at", "updated_at", "price_per_unit", "energy_amount", "total_price", "filled_amount", "expires_at"];
+pub const TRADE_SORT_COLUMNS: [&str; 5] = ["created_at", "executed_at", "price_per_unit", "energy_amount", "total_price"];
+
+// ORDER BY for a list query, parsed from `column[:asc|desc]` against an allowlist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub column: &'static str,
+    pub descending: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self { column: "created_at", descending: true }
     }
+}
 
-    pub async fn run_migrations(&self) -> Result<(), DatabaseError> {
-        match &self.pool {
-            DatabasePool::Postgres(pool) => {
-                sqlx::migrate!("./migrations").run(pool).await?;
-            }
-            DatabasePool::Sqlite(pool) => {
-                sqlx::migrate!("./migrations").run(pool).await?;
-            }
-        }
-        Ok(())
+impl SortOrder {
+    // The direction defaults to ascending when omitted
+    pub fn parse(value: &str, allowed: &[&'static str]) -> Result<Self, String> {
+        let (column, direction) = value.trim().split_once(':').unwrap_or((value.trim(), "asc"));
+        let column = allowed
+            .iter()
+            .find(|allowed| **allowed == column.trim())
+            .ok_or_else(|| format!("cannot sort by '{}'; expected one of: {}", column.trim(), allowed.join(", ")))?;
+        let descending = match direction.trim().to_lowercase().as_str() {
+            "asc" => false,
+            "desc" => true,
+            other => return Err(format!("sort direction must be 'asc' or 'desc', got '{}'", other)),
+        };
+        Ok(Self { column, descending })
     }
 
-    pub async fn create_prosumer(&self, prosumer: Prosumer) -> Result<Prosumer, DatabaseError> {
-        let query = r#"
-            INSERT INTO prosumers (address, name, energy_generated, energy_consumed, grid_tokens, watt_tokens, is_active, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-            RETURNING *
-        "#;
-        
-        match &self.pool {
-            DatabasePool::Postgres(pool) => {
-                let row = sqlx::query_as::<_, ProsumerRow>(query)
-                    .bind(&prosumer.address)
-                    .bind(&prosumer.name)
-                    .bind(prosumer.energy_generated)
-                    .bind(prosumer.energy_consumed)
-                    .bind(prosumer.grid_tokens)
-                    .bind(prosumer.watt_tokens)
-                    .bind(prosumer.is_active)
-                    .bind(prosumer.created_at)
-                    .bind(prosumer.updated_at)
-                    .fetch_one(pool)
-                    .await?;
-                Ok(row.into())
-            }
-            DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query_as::<_, ProsumerRow>(query)
-                    .bind(&prosumer.address)
-                    .bind(&prosumer.name)
-                    .bind(prosumer.energy_generated)
-                    .bind(prosumer.energy_consumed)
-                    .bind(prosumer.grid_tokens)
-                    .bind(prosumer.watt_tokens)
-                    .bind(prosumer.is_active)
-                    .bind(prosumer.created_at)
-                    .bind(prosumer.updated_at)
-                    .fetch_one(pool)
-                    .await?;
-                Ok(row.into())
-            }
-        }
+    fn to_sql(self, table_alias: &str) -> String {
+        format!("{}{} {}", table_alias, self.column, if self.descending { "DESC" } else { "ASC" })
     }
+}
 
-    pub async fn get_prosumer(&self, address: &str) -> Result<Prosumer, DatabaseError> {
-        let query = "SELECT * FROM prosumers WHERE address = $1";
-        
-        match &self.pool {
-            DatabasePool::Postgres(pool) => {
-                let row = sqlx::query_as::<_, ProsumerRow>(query)
-                    .bind(address)
-                    .fetch_optional(pool)
-                    .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
-                }
-            }
-            DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query_as::<_, ProsumerRow>(query)
-                    .bind(address)
-                    .fetch_optional(pool)
-                    .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
-                }
-            }
+// Restricts prosumer listings and stats to prosumers whose metadata has `key` set to `value`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TagFilter {
+    pub key: String,
+    pub value: String,
+}
+
+impl TagFilter {
+    // Builds the filters for a `tag=key:value` and/or `region=value` query
+    pub fn from_query(tag: Option<&str>, region: Option<&str>) -> Result<Vec<Self>, String> {
+        let mut filters = Vec::new();
+        if let Some(tag) = tag {
+            let (key, value) = tag
+                .split_once(':')
+                .ok_or_else(|| format!("tag filter must be 'key:value', got '{}'", tag))?;
+            filters.push(Self { key: sanitize_tag_key(key)?, value: value.trim().to_string() });
         }
+        if let Some(region) = region {
+            filters.push(Self { key: "region".to_string(), value: region.trim().to_string() });
+        }
+        Ok(filters)
     }
 
-    pub async fn get_prosumers(&self, page: u32, limit: u32) -> Result<Vec<Prosumer>, DatabaseError> {
-        let offset = (page - 1) * limit;
-        let query = "SELECT * FROM prosumers ORDER BY created_at DESC LIMIT $1 OFFSET $2";
-        
-        match &self.pool {
-            DatabasePool::Postgres(pool) => {
-                let rows = sqlx::query_as::<_, ProsumerRow>(query)
-                    .bind(limit as i64)
-                    .bind(offset as i64)
-                    .fetch_all(pool)
-                    .await?;
-                Ok(rows.into_iter().map(|row| row.into()).collect())
-            }
-            DatabasePool::Sqlite(pool) => {
-                let rows = sqlx::query_as::<_, ProsumerRow>(query)
-                    .bind(limit as i64)
-                    .bind(offset as i64)
-                    .fetch_all(pool)
-                    .await?;
-                Ok(rows.into_iter().map(|row| row.into()).collect())
-            }
+    // SQL condition on `p.metadata` for all filters, binding each key and value as numbered
+    // parameters from `$first_param` on. Keys are bound rather than inlined; sanitize_tag_key
+    // keeps them safe inside the SQLite JSON path.
+    fn to_sql(filters: &[Self], pool: &DatabasePool, first_param: usize) -> String {
+        if filters.is_empty() {
+            return "1 = 1".to_string();
         }
+        filters
+            .iter()
+            .enumerate()
+            .map(|(i, _)| {
+                let (key, value) = (first_param + 2 * i, first_param + 2 * i + 1);
+                match pool {
+                    DatabasePool::Postgres(_) => format!("(p.metadata::jsonb ->> ${}) = ${}", key, value),
+                    DatabasePool::Sqlite(_) => format!("json_extract(p.metadata, '$.' || ${}) = ${}", key, value),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" AND ")
     }
+}
 
-    pub async fn update_prosumer(&self, address: &str, name: Option<String>, energy_generated: Option<f64>, energy_consumed: Option<f64>) -> Result<Prosumer, DatabaseError> {
-        let query = r#"
+// Default SLOW_QUERY_MS threshold
+pub const DEFAULT_SLOW_QUERY_MS: u64 = 500;
+
+// Default DB_STATEMENT_TIMEOUT_MS
+pub const DEFAULT_STATEMENT_TIMEOUT_MS: u64 = 30_000;
+
+// Default STATS_CACHE_SECS: how long a market stats result is served from memory
+pub const DEFAULT_STATS_CACHE_SECS: u64 = 10;
+
+// Market stats by tag scope, with when they were computed
+type MarketStatsCache = HashMap<Vec<TagFilter>, (DateTime<Utc>, MarketStats)>;
+
+// Times one database operation and warns on drop if it exceeded the slow-query
+// threshold. Dropping covers early returns and errors as well as success.
+// Also owns a `db` span for the operation. It is opened inside whatever span is current
+// (the request span for API calls) and closes when the timer drops.
+struct QueryTimer {
+    operation: &'static str,
+    started: std::time::Instant,
+    threshold_ms: u64,
+    span: tracing::Span,
+}
+
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        let elapsed_ms = self.started.elapsed().as_millis();
+        if self.threshold_ms > 0 && elapsed_ms >= u128::from(self.threshold_ms) {
+            tracing::warn!(
+                parent: &self.span,
+                "Slow query: {} took {} ms (threshold {} ms)",
+                self.operation, elapsed_ms, self.threshold_ms
+            );
+        }
+    }
+}
+
+// Upper bound on addresses accepted by a single bulk balance query
+pub const MAX_BULK_BALANCE_ADDRESSES: usize = 100;
+
+// First SQLite release that understands `RETURNING`. Older versions are supported for
+// order writes through an insert/update followed by a select-by-key.
+pub const MIN_SQLITE_RETURNING_VERSION: &str = "3.35.0";
+
+fn sqlite_supports_returning(version: &str) -> bool {
+    let parse = |v: &str| -> Vec<u32> { v.split('.').map(|part| part.trim().parse().unwrap_or(0)).collect() };
+    parse(version) >= parse(MIN_SQLITE_RETURNING_VERSION)
+}
+
+fn without_returning(query: &str) -> String {
+    match query.rfind("RETURNING") {
+        Some(index) => query[..index].to_string(),
+        None => query.to_string(),
+    }
+}
+
+// Upper bound on operations accepted by a single atomic batch
+pub const MAX_BATCH_OPERATIONS: usize = 50;
+
+const INSERT_PROSUMER_QUERY: &str = r#"
+    INSERT INTO prosumers (address, name, energy_generated, energy_consumed, is_active, created_at, updated_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+"#;
+
+const SET_PROSUMER_OWNER_QUERY: &str = "UPDATE prosumers SET owner_id = $2 WHERE address = $1";
+const PROSUMER_OWNER_QUERY: &str = "SELECT owner_id FROM prosumers WHERE address = $1";
+
+// Prosumer columns plus the two built-in token balances, which live in `balances`.
+// Append a WHERE/ORDER clause using the `p` alias.
+const PROSUMER_SELECT: &str = r#"
+    SELECT p.address, p.name, p.energy_generated, p.energy_consumed,
+           CAST(COALESCE((SELECT b.amount FROM balances b WHERE b.address = p.address AND b.token_type = 'grid_tokens'), 0) AS DOUBLE PRECISION) as grid_tokens,
+           CAST(COALESCE((SELECT b.amount FROM balances b WHERE b.address = p.address AND b.token_type = 'watt_tokens'), 0) AS DOUBLE PRECISION) as watt_tokens,
+           p.is_active, p.created_at, p.updated_at
+    FROM prosumers p
+"#;
+
+// Adds `amount` to a balance, creating the row on first credit
+const CREDIT_BALANCE_QUERY: &str = r#"
+    INSERT INTO balances (address, token_type, amount, updated_at)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (address, token_type) DO UPDATE SET amount = balances.amount + excluded.amount, updated_at = excluded.updated_at
+"#;
+
+const DEBIT_BALANCE_QUERY: &str = "UPDATE balances SET amount = amount - $1, updated_at = $2 WHERE address = $3 AND token_type = $4";
+
+// `from_address` of transfers recording newly issued tokens, such as a new prosumer's
+// starting balance
+pub const MINT_ADDRESS: &str = "mint";
+
+const INSERT_TRANSFER_QUERY: &str = r#"
+    INSERT INTO token_transfers (id, from_address, to_address, token_type, amount, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6)
+"#;
+
+// Energy and trade value a prosumer has bought plus sold on one UTC day (YYYY-MM-DD)
+const DAILY_USAGE_QUERY: &str = "SELECT energy_traded, notional_traded FROM daily_trade_usage WHERE address = $1 AND trade_date = $2";
+
+const ADD_DAILY_USAGE_QUERY: &str = r#"
+    INSERT INTO daily_trade_usage (address, trade_date, energy_traded, notional_traded)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (address, trade_date) DO UPDATE SET
+        energy_traded = daily_trade_usage.energy_traded + excluded.energy_traded,
+        notional_traded = daily_trade_usage.notional_traded + excluded.notional_traded
+"#;
+
+const BALANCE_QUERY: &str = "SELECT amount FROM balances WHERE address = $1 AND token_type = $2";
+
+// Every non-empty balance of a prosumer, swept when it is closed
+const SWEEPABLE_BALANCES_QUERY: &str = "SELECT token_type, amount FROM balances WHERE address = $1 AND amount > 0 ORDER BY token_type";
+
+// Trades whose payment has not been settled yet; a prosumer with any cannot be closed
+const UNSETTLED_TRADES_QUERY: &str = r#"
+    SELECT COUNT(*) FROM trades
+    WHERE (buyer_address = $1 OR seller_address = $1) AND status IN ('pending', 'disputed')
+"#;
+
+const OPEN_ORDERS_OF_QUERY: &str = "SELECT * FROM orders WHERE prosumer_address = $1 AND status IN ('pending', 'active')";
+
+const CANCEL_OPEN_ORDERS_QUERY: &str = r#"
+    UPDATE orders SET status = 'cancelled', updated_at = $2
+    WHERE prosumer_address = $1 AND status IN ('pending', 'active')
+    RETURNING *
+"#;
+
+// Token that buy orders are paid in and escrowed from
+const ESCROW_TOKEN_TYPE: &str = "grid_tokens";
+
+const ESCROW_BALANCE_QUERY: &str = "SELECT amount, reserved_balance FROM balances WHERE address = $1 AND token_type = $2";
+
+// Shifts tokens between the spendable and reserved parts of a balance
+const ESCROW_UPDATE_QUERY: &str = r#"
+    UPDATE balances
+    SET amount = amount + $1, reserved_balance = reserved_balance + $2, updated_at = $3
+    WHERE address = $4 AND token_type = $5
+"#;
+
+const INSERT_ORDER_QUERY: &str = r#"
+    INSERT INTO orders (id, prosumer_address, order_type, energy_amount, price_per_unit, total_price, status, created_at, updated_at, expires_at, currency, quoted_price_per_unit)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+    RETURNING *
+"#;
+
+const INSERT_TRADE_QUERY: &str = r#"
+    INSERT INTO trades (id, buy_order_id, sell_order_id, buyer_address, seller_address, energy_amount, price_per_unit, total_price, status, executed_at, created_at, grid_fee, settles_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+    RETURNING *
+"#;
+
+// Adds a fill to an order, completing it once nothing remains. Matches no row when the order
+// has closed or no longer has the fill's energy open.
+const FILL_ORDER_QUERY: &str = r#"
+    UPDATE orders
+    SET filled_amount = filled_amount + $1,
+        status = CASE WHEN filled_amount + $1 >= energy_amount - 1e-9 THEN 'completed' ELSE status END,
+        updated_at = $2
+    WHERE id = $3 AND status IN ('pending', 'active') AND energy_amount - filled_amount >= $1 - 1e-9
+"#;
+
+// Every trade an order took part in, oldest first. Trades count in any status: a pending
+// trade has already filled the order and a disputed one keeps its fill. Archived trades are
+// included so the fills of an old order still add up to its filled amount.
+const ORDER_TRADES_QUERY: &str = r#"
+    SELECT * FROM trades WHERE buy_order_id = $1 OR sell_order_id = $1
+    UNION ALL
+    SELECT * FROM trades_archive WHERE buy_order_id = $1 OR sell_order_id = $1
+    ORDER BY executed_at ASC
+"#;
+
+// A prosumer's completed trades up to a point in time, live and archived, in execution order
+const PNL_TRADES_QUERY: &str = r#"
+    SELECT * FROM trades WHERE (buyer_address = $1 OR seller_address = $1) AND status = 'completed' AND executed_at < $2
+    UNION ALL
+    SELECT * FROM trades_archive WHERE (buyer_address = $1 OR seller_address = $1) AND status = 'completed' AND executed_at < $2
+    ORDER BY executed_at ASC, id ASC
+"#;
+
+// P&L periods are UTC days
+const PNL_PERIOD_SECS: i64 = 86_400;
+
+// A prosumer's net generated energy and the energy already offered on its open sell orders
+const ENERGY_BACKING_QUERY: &str = r#"
+    SELECT CAST(p.energy_generated - p.energy_consumed AS DOUBLE PRECISION) as net_energy,
+           CAST(COALESCE((
+               SELECT SUM(o.energy_amount - o.filled_amount) FROM orders o
+               WHERE o.prosumer_address = p.address AND o.order_type = 'sell'
+                 AND o.status IN ('pending', 'active')
+           ), 0) AS DOUBLE PRECISION) as committed
+    FROM prosumers p
+    WHERE p.address = $1
+"#;
+
+// The prosumer's tags and how many orders it has resting, for the open-order cap
+const OPEN_ORDERS_QUERY: &str = r#"
+    SELECT p.metadata,
+           (SELECT COUNT(*) FROM orders o
+            WHERE o.prosumer_address = p.address AND o.status IN ('pending', 'active')) as open_orders
+    FROM prosumers p
+    WHERE p.address = $1
+"#;
+
+const MARKET_HALT_QUERY: &str = "SELECT halted, reason, updated_at FROM market_state WHERE id = 1";
+const SET_MARKET_HALT_QUERY: &str = r#"
+    INSERT INTO market_state (id, halted, reason, updated_at)
+    VALUES (1, $1, $2, $3)
+    ON CONFLICT (id) DO UPDATE SET halted = excluded.halted, reason = excluded.reason, updated_at = excluded.updated_at
+"#;
+
+// Upper bound on buckets per time-series request, to keep responses small
+pub const MAX_TIMESERIES_BUCKETS: i64 = 10_000;
+// Widest timeseries bucket, one year
+pub const MAX_TIMESERIES_INTERVAL_SECS: i64 = 366 * 24 * 60 * 60;
+
+// Completed trades in [$1, $2) grouped into $3-second buckets. The bucket expression is the only
+// difference between the backends; open and close come from the earliest and latest trade.
+const TIMESERIES_AGGREGATE: &str = r#"
+    SELECT bucket,
+           COUNT(*) AS trade_count,
+           CAST(SUM(energy_amount) AS DOUBLE PRECISION) AS energy_traded,
+           CAST(SUM(total_price) AS DOUBLE PRECISION) AS volume,
+           CAST(AVG(price_per_unit) AS DOUBLE PRECISION) AS average_price,
+           CAST(MAX(open) AS DOUBLE PRECISION) AS open,
+           CAST(MAX(price_per_unit) AS DOUBLE PRECISION) AS high,
+           CAST(MIN(price_per_unit) AS DOUBLE PRECISION) AS low,
+           CAST(MAX(close) AS DOUBLE PRECISION) AS close
+    FROM (
+        SELECT {bucket} AS bucket, energy_amount, total_price, price_per_unit,
+               FIRST_VALUE(price_per_unit) OVER (PARTITION BY {bucket} ORDER BY executed_at ASC, id ASC) AS open,
+               FIRST_VALUE(price_per_unit) OVER (PARTITION BY {bucket} ORDER BY executed_at DESC, id DESC) AS close
+        FROM trades
+        WHERE status = 'completed' AND executed_at >= $1 AND executed_at < $2
+    ) bucketed
+    GROUP BY bucket
+    ORDER BY bucket
+"#;
+const TIMESERIES_BUCKET_POSTGRES: &str = "CAST(FLOOR(EXTRACT(EPOCH FROM executed_at) / $3) AS BIGINT)";
+const TIMESERIES_BUCKET_SQLITE: &str = "CAST(strftime('%s', executed_at) AS INTEGER) / $3";
+
+// Number of `interval_secs` buckets from the one containing `from` up to `to`, or an error
+// when the interval is out of range or the range spans more than MAX_TIMESERIES_BUCKETS
+pub fn timeseries_bucket_count(interval_secs: i64, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<i64, String> {
+    if !(1..=MAX_TIMESERIES_INTERVAL_SECS).contains(&interval_secs) {
+        return Err(format!("interval_secs must be between 1 and {}", MAX_TIMESERIES_INTERVAL_SECS));
+    }
+    if from >= to {
+        return Err("from must be before to".to_string());
+    }
+
+    // Timestamps chrono can represent and a bounded interval keep this within i64
+    let span = to.timestamp() - bucket_start(from, interval_secs).timestamp();
+    let bucket_count = (span + interval_secs - 1) / interval_secs;
+    if bucket_count > MAX_TIMESERIES_BUCKETS {
+        return Err(format!(
+            "Requested range spans {} buckets; maximum is {}",
+            bucket_count, MAX_TIMESERIES_BUCKETS
+        ));
+    }
+    Ok(bucket_count)
+}
+
+// Start of the fixed-width bucket containing `ts`, aligned to the Unix epoch
+pub fn bucket_start(ts: DateTime<Utc>, interval_secs: i64) -> DateTime<Utc> {
+    let secs = ts.timestamp();
+    let aligned = secs - secs.rem_euclid(interval_secs);
+    DateTime::from_timestamp(aligned, 0).unwrap_or(ts)
+}
+
+// Builds the trade that fills `buy` against `sell`. Without an explicit amount it fills as
+// much as both have remaining; without an explicit price the execution price follows the
+// configured match price policy. `now` stamps the trade and decides whether an order expired.
+fn build_fill(
+    buy: &Order,
+    sell: &Order,
+    price_per_unit: Option<f64>,
+    energy_amount: Option<f64>,
+    market: &MarketConfig,
+    now: DateTime<Utc>,
+) -> Result<Trade, DatabaseError> {
+    if buy.order_type != "buy" || sell.order_type != "sell" {
+        return Err(DatabaseError::Validation("Trade requires one buy order and one sell order".to_string()));
+    }
+    for order in [buy, sell] {
+        if order.status != "active" {
+            return Err(DatabaseError::Conflict(format!("Order '{}' is not active", order.id)));
+        }
+        // Expired but not yet swept by the expiry worker
+        if order.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(DatabaseError::Conflict(format!("Order '{}' has expired", order.id)));
+        }
+    }
+    if buy.prosumer_address == sell.prosumer_address {
+        return Err(DatabaseError::Validation("Cannot trade against your own order".to_string()));
+    }
+
+    let price = price_per_unit.unwrap_or_else(|| {
+        market.execution_price(buy.price_per_unit, sell.price_per_unit, buy.created_at <= sell.created_at)
+    });
+    if !price.is_finite() || price < sell.price_per_unit || price > buy.price_per_unit {
+        return Err(DatabaseError::Validation(format!(
+            "Price {} is outside the crossing range [{}, {}]",
+            price, sell.price_per_unit, buy.price_per_unit
+        )));
+    }
+
+    let available = buy.remaining_amount.min(sell.remaining_amount);
+    if available <= 0.0 {
+        return Err(DatabaseError::Validation("Orders have no remaining energy to fill".to_string()));
+    }
+    let energy_amount = energy_amount.unwrap_or(available);
+    if !energy_amount.is_finite() || energy_amount <= 0.0 || energy_amount > available + 1e-9 {
+        return Err(DatabaseError::Validation(format!(
+            "Fill of {} kWh exceeds the {} kWh both orders have remaining",
+            energy_amount, available
+        )));
+    }
+    let energy_amount = energy_amount.min(available);
+
+    let total_price = energy_amount * price;
+    // With a settlement delay the seller is paid later by the settlement worker
+    let (status, settles_at) = match market.settlement_delay_secs {
+        0 => ("completed", None),
+        delay => ("pending", Some(now + chrono::Duration::seconds(delay as i64))),
+    };
+    Ok(Trade {
+        id: Uuid::new_v4(),
+        buy_order_id: buy.id,
+        sell_order_id: sell.id,
+        buyer_address: buy.prosumer_address.clone(),
+        seller_address: sell.prosumer_address.clone(),
+        energy_amount,
+        price_per_unit: price,
+        total_price,
+        grid_fee: market.fee_schedule.fee_for(total_price),
+        status: status.to_string(),
+        executed_at: now,
+        created_at: now,
+        settles_at,
+    })
+}
+
+// A fill that matched no order row raced a cancel or another fill of the same order
+fn check_fill_applied(id: Uuid, rows_affected: u64) -> Result<(), DatabaseError> {
+    if rows_affected == 0 {
+        return Err(DatabaseError::Conflict(format!(
+            "Order '{}' was closed or filled by another trade",
+            id
+        )));
+    }
+    Ok(())
+}
+
+// Sell orders may only offer energy the prosumer has generated and not yet committed
+fn check_energy_backing(energy_amount: f64, net_energy: f64, committed: f64) -> Result<(), DatabaseError> {
+    let available = (net_energy - committed).max(0.0);
+    if energy_amount > available + 1e-9 {
+        return Err(DatabaseError::Validation(format!(
+            "Sell order of {} kWh exceeds available energy of {} kWh",
+            energy_amount, available
+        )));
+    }
+    Ok(())
+}
+
+// Whether a range starting at `from` (unbounded when None) can contain archived trades,
+// given the creation time of the newest one
+fn range_reaches_archive(from: Option<DateTime<Utc>>, latest_archived: Option<DateTime<Utc>>) -> bool {
+    match (from, latest_archived) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(from), Some(latest)) => from <= latest,
+    }
+}
+
+// An amendment can't shrink an order below what has already been filled
+fn check_amendable(order: &Order, energy_amount: Option<f64>) -> Result<(), DatabaseError> {
+    if let Some(energy_amount) = energy_amount {
+        if energy_amount < order.filled_amount - 1e-9 {
+            return Err(DatabaseError::Validation(format!(
+                "energy_amount {} is below the {} kWh of order '{}' already filled",
+                energy_amount, order.filled_amount, order.id
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Only cancelled orders that still have something left to fill and have not passed their
+// expiry can go back on the book
+fn check_reactivatable(order: &Order, now: DateTime<Utc>) -> Result<(), DatabaseError> {
+    if order.status != "cancelled" {
+        return Err(DatabaseError::Conflict(format!(
+            "Order '{}' is {}; only cancelled orders can be reactivated",
+            order.id, order.status
+        )));
+    }
+    if order.remaining_amount <= 1e-9 {
+        return Err(DatabaseError::Conflict(format!("Order '{}' has nothing left to fill", order.id)));
+    }
+    if order.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(DatabaseError::Conflict(format!("Order '{}' has expired", order.id)));
+    }
+    Ok(())
+}
+
+// Walks `address`'s trades in execution order, keeping the purchased lots it still holds as
+// (energy, price), and totals each sale in [from, to) into the day it happened
+fn realized_pnl(address: &str, trades: &[Trade], method: PnlMethod, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<PnlPeriod> {
+    let mut lots: VecDeque<(f64, f64)> = VecDeque::new();
+    let mut periods: Vec<PnlPeriod> = Vec::new();
+    for trade in trades.iter().filter(|t| t.executed_at < to) {
+        if trade.buyer_address == address {
+            lots.push_back((trade.energy_amount, trade.price_per_unit));
+            continue;
+        }
+        let cost = consume_lots(&mut lots, trade.energy_amount, method);
+        if trade.executed_at < from {
+            continue;
+        }
+        let period_start = bucket_start(trade.executed_at, PNL_PERIOD_SECS);
+        if periods.last().map(|p| p.period_start) != Some(period_start) {
+            periods.push(PnlPeriod { period_start, energy_sold: 0.0, proceeds: 0.0, cost_basis: 0.0, realized_pnl: 0.0 });
+        }
+        if let Some(period) = periods.last_mut() {
+            period.energy_sold += trade.energy_amount;
+            period.proceeds += trade.total_price;
+            period.cost_basis += cost;
+            period.realized_pnl = period.proceeds - period.cost_basis;
+        }
+    }
+    periods
+}
+
+// Takes `energy` out of the held lots and returns its cost. Whatever the lots can't cover
+// was never bought and costs nothing.
+fn consume_lots(lots: &mut VecDeque<(f64, f64)>, energy: f64, method: PnlMethod) -> f64 {
+    match method {
+        PnlMethod::Fifo => {
+            let mut remaining = energy;
+            let mut cost = 0.0;
+            while remaining > 1e-9 {
+                let Some(lot) = lots.front_mut() else { break };
+                let taken = lot.0.min(remaining);
+                cost += taken * lot.1;
+                lot.0 -= taken;
+                remaining -= taken;
+                if lot.0 <= 1e-9 {
+                    lots.pop_front();
+                }
+            }
+            cost
+        }
+        PnlMethod::AverageCost => {
+            let held: f64 = lots.iter().map(|(amount, _)| amount).sum();
+            if held <= 1e-9 {
+                return 0.0;
+            }
+            let average = lots.iter().map(|(amount, price)| amount * price).sum::<f64>() / held;
+            let taken = energy.min(held);
+            // What is left keeps the same average, so one lot stands for all of it
+            lots.clear();
+            if held - taken > 1e-9 {
+                lots.push_back((held - taken, average));
+            }
+            taken * average
+        }
+    }
+}
+
+fn check_no_unsettled_trades(address: &str, unsettled: i64) -> Result<(), DatabaseError> {
+    if unsettled > 0 {
+        return Err(DatabaseError::Conflict(format!(
+            "Prosumer '{}' has {} trade(s) awaiting settlement",
+            address, unsettled
+        )));
+    }
+    Ok(())
+}
+
+// A trade can be disputed by either party while it is pending and before it is due to settle
+fn check_disputable(trade: &Trade, address: &str, now: DateTime<Utc>) -> Result<(), DatabaseError> {
+    if address != trade.buyer_address && address != trade.seller_address {
+        return Err(DatabaseError::Validation(format!("'{}' is not a party to trade '{}'", address, trade.id)));
+    }
+    if trade.status != "pending" {
+        return Err(DatabaseError::Conflict(format!(
+            "Trade '{}' is {}; only trades awaiting settlement can be disputed",
+            trade.id, trade.status
+        )));
+    }
+    if trade.settles_at.is_none_or(|settles_at| settles_at <= now) {
+        return Err(DatabaseError::Conflict(format!("Trade '{}' is past its dispute window", trade.id)));
+    }
+    Ok(())
+}
+
+#[derive(Debug, FromRow)]
+struct BalanceRow {
+    address: String,
+    token_type: String,
+    amount: f64,
+    reserved_balance: f64,
+}
+
+// Fills `tokens` and the escrowed amount from one prosumer's balance rows
+fn add_balance_rows(balance: &mut TokenBalance, rows: Vec<BalanceRow>) {
+    for row in rows {
+        if row.token_type == ESCROW_TOKEN_TYPE {
+            balance.reserved_grid_tokens = row.reserved_balance;
+        }
+        if row.amount > 0.0 {
+            balance.tokens.insert(row.token_type, row.amount);
+        }
+    }
+}
+
+// Tokens an order keeps in escrow: the open remainder of a buy order at its limit price
+fn buy_reservation(order: &Order) -> f64 {
+    if order.order_type == "buy" && matches!(order.status.as_str(), "pending" | "active") {
+        order.remaining_amount.max(0.0) * order.price_per_unit
+    } else {
+        0.0
+    }
+}
+
+// (spendable delta, reserved delta) that moves a buyer's escrow by `delta` tokens: positive
+// reserves from the spendable balance, negative releases at most what is actually reserved
+fn escrow_adjustment(delta: f64, available: f64, reserved: f64) -> Result<(f64, f64), DatabaseError> {
+    if delta > 0.0 {
+        if delta > available + 1e-9 {
+            return Err(DatabaseError::InsufficientBalance {
+                token_type: ESCROW_TOKEN_TYPE.to_string(),
+                required: delta,
+                available,
+            });
+        }
+        let reserve = delta.min(available);
+        Ok((-reserve, reserve))
+    } else {
+        let release = release_amount(-delta, reserved);
+        Ok((release, -release))
+    }
+}
+
+// How much of `reserved` a release of `release` tokens frees. A release that would leave only
+// floating point noise behind frees everything, so an address whose orders have all filled or
+// closed ends with exactly nothing reserved.
+fn release_amount(release: f64, reserved: f64) -> f64 {
+    if reserved - release <= 1e-9 {
+        reserved.max(0.0)
+    } else {
+        release.max(0.0)
+    }
+}
+
+// (spendable delta, reserved delta) for the buyer of a fill. `freed` is the escrow the fill
+// releases from the buy order; the trade total is paid from it and any price improvement is
+// refunded. Orders placed before escrow existed may have less reserved, in which case the
+// spendable balance covers the shortfall.
+fn settlement_adjustment(freed: f64, total_price: f64, available: f64, reserved: f64) -> Result<(f64, f64), DatabaseError> {
+    let from_reserve = release_amount(freed, reserved);
+    let paid_from_reserve = from_reserve.min(total_price);
+    let shortfall = total_price - paid_from_reserve;
+    if shortfall > available + 1e-9 {
+        return Err(DatabaseError::InsufficientBalance {
+            token_type: ESCROW_TOKEN_TYPE.to_string(),
+            required: shortfall,
+            available,
+        });
+    }
+    let refund = from_reserve - paid_from_reserve;
+    Ok((refund - shortfall.min(available), -from_reserve))
+}
+
+// Arbitrary key for the Postgres advisory lock held while migrating, so replicas
+// starting together don't race on DDL
+const MIGRATION_LOCK_ID: i64 = 0x4752_4944_4d49_4752;
+
+fn is_postgres_url(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+// Reachability, round-trip latency and connection counts for one pool
+async fn pool_health(pool: &DatabasePool) -> DatabaseHealth {
+    let started = std::time::Instant::now();
+    let (backend, ping, pool_size, pool_idle) = match pool {
+        DatabasePool::Postgres(pool) => {
+            let ping = sqlx::query("SELECT 1").execute(pool).await.map(|_| started.elapsed());
+            ("postgres", ping, pool.size(), pool.num_idle())
+        }
+        DatabasePool::Sqlite(pool) => {
+            let ping = sqlx::query("SELECT 1").execute(pool).await.map(|_| started.elapsed());
+            ("sqlite", ping, pool.size(), pool.num_idle())
+        }
+    };
+    DatabaseHealth {
+        backend: backend.to_string(),
+        reachable: ping.is_ok(),
+        latency_ms: ping.as_ref().ok().map(|latency| latency.as_secs_f64() * 1000.0),
+        pool_size,
+        pool_idle,
+        error: ping.err().map(|e| e.to_string()),
+    }
+}
+
+// Rejects a database whose recorded migrations failed half-way or no longer match
+// the files on disk
+fn check_applied_migrations(applied: &HashMap<i64, (bool, Vec<u8>)>, migrations: &[&Migration]) -> Result<(), DatabaseError> {
+    for migration in migrations {
+        match applied.get(&migration.version) {
+            Some((false, _)) => {
+                return Err(DatabaseError::Validation(format!(
+                    "Migration {} previously failed; fix the database before restarting",
+                    migration.version
+                )))
+            }
+            Some((true, checksum)) if !checksum.is_empty() && checksum[..] != migration.checksum[..] => {
+                return Err(DatabaseError::Validation(format!(
+                    "Migration {} was modified after it was applied",
+                    migration.version
+                )))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Bookkeeping table DDL per backend; `{table}` is replaced with the configured name
+const POSTGRES_MIGRATIONS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS {table} (
+        version BIGINT PRIMARY KEY,
+        description TEXT NOT NULL,
+        installed_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+        success BOOLEAN NOT NULL,
+        checksum BYTEA NOT NULL,
+        execution_time BIGINT NOT NULL
+    )
+"#;
+
+const SQLITE_MIGRATIONS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS {table} (
+        version BIGINT PRIMARY KEY,
+        description TEXT NOT NULL,
+        installed_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        success BOOLEAN NOT NULL,
+        checksum BLOB NOT NULL,
+        execution_time BIGINT NOT NULL
+    )
+"#;
+
+// Applies `migrations` not yet recorded in `table`, each in its own transaction, and returns
+// the versions it ran. Versions up to `baseline` are recorded without running.
+async fn migrate<DB>(
+    conn: &mut DB::Connection,
+    create_table: &str,
+    table: &str,
+    baseline: Option<i64>,
+    migrations: &[&Migration],
+) -> Result<Vec<i64>, DatabaseError>
+where
+    DB: sqlx::Database,
+    for<'c> &'c mut DB::Connection: sqlx::Executor<'c, Database = DB>,
+    for<'q> <DB as sqlx::database::HasArguments<'q>>::Arguments: sqlx::IntoArguments<'q, DB>,
+    for<'q> i64: sqlx::Type<DB> + sqlx::Encode<'q, DB> + sqlx::Decode<'q, DB>,
+    for<'q> bool: sqlx::Type<DB> + sqlx::Decode<'q, DB>,
+    for<'q> Vec<u8>: sqlx::Type<DB> + sqlx::Decode<'q, DB>,
+    for<'q> &'q [u8]: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+    for<'q> &'q str: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+    for<'r> &'r str: sqlx::ColumnIndex<DB::Row>,
+{
+    sqlx::query::<DB>(&create_table.replace("{table}", table)).execute(&mut *conn).await?;
+
+    let rows = sqlx::query::<DB>(&format!("SELECT version, success, checksum FROM {table}"))
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut applied: HashMap<i64, (bool, Vec<u8>)> = HashMap::new();
+    for row in rows {
+        applied.insert(row.try_get("version")?, (row.try_get("success")?, row.try_get("checksum")?));
+    }
+    check_applied_migrations(&applied, migrations)?;
+
+    let insert = format!(
+        "INSERT INTO {table} (version, description, success, checksum, execution_time) VALUES ($1, $2, TRUE, $3, $4)"
+    );
+    let mut ran = Vec::new();
+    for migration in migrations {
+        if applied.contains_key(&migration.version) {
+            continue;
+        }
+        if baseline.is_some_and(|baseline| migration.version <= baseline) {
+            sqlx::query::<DB>(&insert)
+                .bind(migration.version)
+                .bind(&*migration.description)
+                .bind(&*migration.checksum)
+                .bind(0_i64)
+                .execute(&mut *conn)
+                .await?;
+            log::info!("Baselined migration {} without running it", migration.version);
+            continue;
+        }
+
+        let started = std::time::Instant::now();
+        let mut tx = conn.begin().await?;
+        sqlx::Executor::execute(&mut *tx, &*migration.sql).await?;
+        sqlx::query::<DB>(&insert)
+            .bind(migration.version)
+            .bind(&*migration.description)
+            .bind(&*migration.checksum)
+            .bind(started.elapsed().as_nanos() as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        ran.push(migration.version);
+    }
+    Ok(ran)
+}
+
+// Level-by-level differences between two aggregated books, bids first
+fn diff_order_books(snapshot: &AggregatedBook, current: &AggregatedBook) -> Vec<LevelDrift> {
+    let mut changes = Vec::new();
+    for (side, before, after) in [("bid", &snapshot.bids, &current.bids), ("ask", &snapshot.asks, &current.asks)] {
+        let key = |level: &PriceLevel| format!("{:.6}", level.price);
+        let mut levels: BTreeMap<String, (Option<&PriceLevel>, Option<&PriceLevel>)> = BTreeMap::new();
+        for level in before {
+            levels.entry(key(level)).or_default().0 = Some(level);
+        }
+        for level in after {
+            levels.entry(key(level)).or_default().1 = Some(level);
+        }
+
+        for (old, new) in levels.into_values() {
+            let (snapshot_energy, snapshot_orders) = old.map_or((0.0, 0), |l| (l.energy, l.order_count));
+            let (current_energy, current_orders) = new.map_or((0.0, 0), |l| (l.energy, l.order_count));
+            if (snapshot_energy - current_energy).abs() > 1e-6 || snapshot_orders != current_orders {
+                changes.push(LevelDrift {
+                    side: side.to_string(),
+                    price: old.or(new).map_or(0.0, |l| l.price),
+                    snapshot_energy,
+                    current_energy,
+                    snapshot_orders,
+                    current_orders,
+                });
+            }
+        }
+    }
+    changes
+}
+
+// Postgres cancels any statement running past the timeout, lock waits included
+fn postgres_options(url: &str, statement_timeout_ms: u64) -> Result<PgConnectOptions, DatabaseError> {
+    let options = PgConnectOptions::from_str(url)?;
+    if statement_timeout_ms == 0 {
+        return Ok(options);
+    }
+    Ok(options.options([("statement_timeout", statement_timeout_ms)]))
+}
+
+// SQLite has no statement timeout; the closest is bounding how long a statement waits for a
+// locked database before giving up with SQLITE_BUSY
+fn sqlite_options(url: &str, statement_timeout_ms: u64) -> Result<SqliteConnectOptions, DatabaseError> {
+    let options = SqliteConnectOptions::from_str(url)?;
+    if statement_timeout_ms == 0 {
+        return Ok(options);
+    }
+    Ok(options.busy_timeout(std::time::Duration::from_millis(statement_timeout_ms)))
+}
+
+// Rejects NaN, infinite, zero and negative transfer amounts before any balance is touched
+fn validate_transfer_amount(amount: f64) -> Result<(), DatabaseError> {
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err(DatabaseError::Validation("Transfer amount must be a positive, finite number".to_string()));
+    }
+    Ok(())
+}
+
+// Guards against a balance update that would leave a non-finite or negative value behind
+fn check_balance_update(new_balance: f64) -> Result<(), DatabaseError> {
+    if !new_balance.is_finite() {
+        return Err(DatabaseError::Validation("Transfer would overflow the token balance".to_string()));
+    }
+    if new_balance < 0.0 {
+        return Err(DatabaseError::Validation("Transfer would leave a negative token balance".to_string()));
+    }
+    Ok(())
+}
+
+// Maximum edit distance for an existing address to be offered as a "did you mean" hint
+const ADDRESS_SUGGESTION_MAX_DISTANCE: usize = 2;
+// A candidate must share this many leading or trailing characters with the missing address
+const ADDRESS_SUGGESTION_AFFIX_LENGTH: usize = 4;
+// Most candidate addresses compared per lookup
+const ADDRESS_SUGGESTION_MAX_CANDIDATES: i64 = 100;
+
+// LIKE pattern matching `text` literally, for use with `ESCAPE '\'`
+fn like_literal(text: &str) -> String {
+    text.chars().fold(String::with_capacity(text.len()), |mut escaped, c| {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+// NotFound error for a prosumer address. Handlers may add a "did you mean" hint for callers
+// allowed to see one (see `DatabaseService::closest_address`).
+pub fn prosumer_not_found(address: &str) -> DatabaseError {
+    DatabaseError::NotFound(format!("Prosumer '{}' not found", address))
+}
+
+// Levenshtein edit distance between two strings (insertions, deletions, substitutions)
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+    let mut curr = vec![0; b_chars.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j + 1] + 1).min(curr[j] + 1).min(prev[j] + cost);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    prev[b_chars.len()]
+}
+
+impl DatabaseService {
+    pub async fn new(database_url: &str) -> Result<Self, DatabaseError> {
+        Self::connect(database_url, env_parse("DB_STATEMENT_TIMEOUT_MS", DEFAULT_STATEMENT_TIMEOUT_MS)).await
+    }
+
+    // Connects with an explicit statement timeout instead of DB_STATEMENT_TIMEOUT_MS
+    pub async fn connect(database_url: &str, statement_timeout_ms: u64) -> Result<Self, DatabaseError> {
+        let mut sqlite_returning = true;
+        let pool = if is_postgres_url(database_url) {
+            DatabasePool::Postgres(Pool::<Postgres>::connect_with(postgres_options(database_url, statement_timeout_ms)?).await?)
+        } else {
+            // For SQLite, use custom connection options to create database if missing
+            let sqlite_options = sqlite_options(database_url, statement_timeout_ms)?
+                .create_if_missing(true);
+            let pool = Pool::<Sqlite>::connect_with(sqlite_options).await?;
+
+            let version: String = sqlx::query_scalar("SELECT sqlite_version()").fetch_one(&pool).await?;
+            sqlite_returning = sqlite_supports_returning(&version);
+            if !sqlite_returning {
+                log::warn!(
+                    "SQLite {} predates RETURNING support ({}+); order writes fall back to insert/update then select",
+                    version, MIN_SQLITE_RETURNING_VERSION
+                );
+            }
+            DatabasePool::Sqlite(pool)
+        };
+        
+        Ok(Self {
+            pool,
+            replica: None,
+            sqlite_returning,
+            exchange_rates: Arc::new(StaticRateTable::from_env()),
+            market_config: MarketConfig::from_env(),
+            pagination: PaginationConfig::from_env(),
+            initial_balances: InitialBalanceConfig::from_env(),
+            field_limits: FieldLimits::from_env(),
+            matching_engine: matching::engine_from_env(),
+            match_batch: MatchBatchConfig::from_env(),
+            match_lock: Mutex::new(()),
+            match_diagnostics: MatchDiagnostics::from_env(),
+            slow_query_ms: env_parse("SLOW_QUERY_MS", DEFAULT_SLOW_QUERY_MS),
+            statement_timeout_ms,
+            stats_cache_secs: env_parse("STATS_CACHE_SECS", DEFAULT_STATS_CACHE_SECS),
+            market_stats_cache: std::sync::Mutex::new(HashMap::new()),
+            last_match_at: std::sync::Mutex::new(None),
+            started_at: std::time::Instant::now(),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    // Routes reads to a replica of the primary database. The replica must use the same backend
+    // and is never written to.
+    pub async fn with_replica(mut self, replica_url: &str) -> Result<Self, DatabaseError> {
+        let replica = match &self.pool {
+            DatabasePool::Postgres(_) if is_postgres_url(replica_url) => {
+                let options = postgres_options(replica_url, self.statement_timeout_ms)?;
+                DatabasePool::Postgres(Pool::<Postgres>::connect_with(options).await?)
+            }
+            DatabasePool::Sqlite(_) if !is_postgres_url(replica_url) => {
+                let sqlite_options = sqlite_options(replica_url, self.statement_timeout_ms)?.read_only(true);
+                DatabasePool::Sqlite(Pool::<Sqlite>::connect_with(sqlite_options).await?)
+            }
+            _ => {
+                return Err(DatabaseError::Validation(
+                    "DATABASE_REPLICA_URL must use the same database backend as DATABASE_URL".to_string(),
+                ))
+            }
+        };
+        self.replica = Some(replica);
+        Ok(self)
+    }
+
+    // Pool for queries that only read. Reads may lag writes by the replica's replication delay.
+    fn read_pool(&self) -> &DatabasePool {
+        self.replica.as_ref().unwrap_or(&self.pool)
+    }
+
+    // Pool for anything that writes, or reads that must see the latest writes (validation
+    // ahead of a write, matching, migrations)
+    fn write_pool(&self) -> &DatabasePool {
+        &self.pool
+    }
+
+    pub fn with_slow_query_ms(mut self, slow_query_ms: u64) -> Self {
+        self.slow_query_ms = slow_query_ms;
+        self
+    }
+
+    // 0 turns the market stats cache off
+    pub fn with_stats_cache_secs(mut self, stats_cache_secs: u64) -> Self {
+        self.stats_cache_secs = stats_cache_secs;
+        self
+    }
+
+    pub fn stats_cache_secs(&self) -> u64 {
+        self.stats_cache_secs
+    }
+
+    // Drops cached market stats so the next request recomputes them
+    fn invalidate_market_stats(&self) {
+        self.market_stats_cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    fn time_query(&self, operation: &'static str) -> QueryTimer {
+        QueryTimer {
+            operation,
+            started: std::time::Instant::now(),
+            threshold_ms: self.slow_query_ms,
+            span: tracing::debug_span!("db", operation),
+        }
+    }
+
+    pub fn with_market_config(mut self, market_config: MarketConfig) -> Self {
+        self.market_config = market_config;
+        self
+    }
+
+    pub fn market_config(&self) -> &MarketConfig {
+        &self.market_config
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Current time according to the service's clock
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    pub fn with_pagination(mut self, pagination: PaginationConfig) -> Self {
+        self.pagination = pagination;
+        self
+    }
+
+    pub fn pagination(&self) -> &PaginationConfig {
+        &self.pagination
+    }
+
+    pub fn with_initial_balances(mut self, initial_balances: InitialBalanceConfig) -> Self {
+        self.initial_balances = initial_balances;
+        self
+    }
+
+    pub fn initial_balances(&self) -> &InitialBalanceConfig {
+        &self.initial_balances
+    }
+
+    // Recent matches the engine or trade validation declined, newest first
+    pub fn match_diagnostics(&self) -> Vec<MatchSkip> {
+        self.match_diagnostics.recent()
+    }
+
+    pub fn matching_engine(&self) -> &dyn MatchingEngine {
+        self.matching_engine.as_ref()
+    }
+
+    // Replaces the strategy used by match_orders
+    pub fn with_matching_engine(mut self, matching_engine: Arc<dyn MatchingEngine>) -> Self {
+        self.matching_engine = matching_engine;
+        self
+    }
+
+    pub fn with_match_batch(mut self, match_batch: MatchBatchConfig) -> Self {
+        self.match_batch = match_batch;
+        self
+    }
+
+    pub fn with_field_limits(mut self, field_limits: FieldLimits) -> Self {
+        self.field_limits = field_limits;
+        self
+    }
+
+    pub fn field_limits(&self) -> &FieldLimits {
+        &self.field_limits
+    }
+
+    // Replaces the exchange-rate source used to normalize order prices
+    pub fn with_exchange_rates(mut self, exchange_rates: Arc<dyn ExchangeRateSource>) -> Self {
+        self.exchange_rates = exchange_rates;
+        self
+    }
+
+    pub fn base_currency(&self) -> &str {
+        self.exchange_rates.base_currency()
+    }
+
+    // Converts a price quoted in `currency` into the base currency used for matching
+    pub fn normalize_price(&self, currency: &str, price: f64) -> Result<f64, DatabaseError> {
+        self.exchange_rates
+            .to_base(currency, price)
+            .ok_or_else(|| DatabaseError::Validation(format!("Unsupported currency '{}'", currency)))
+    }
+
+    // Applies pending migrations from ./migrations and returns the versions it ran.
+    // Bookkeeping uses the same layout as sqlx's own `_sqlx_migrations` table, so the
+    // default configuration stays compatible with `sqlx migrate`.
+    pub async fn run_migrations(&self, config: &MigrationConfig) -> Result<Vec<i64>, DatabaseError> {
+        if !config.run {
+            log::info!("RUN_MIGRATIONS is disabled, skipping schema migrations");
+            return Ok(Vec::new());
+        }
+        config.validate().map_err(DatabaseError::Validation)?;
+
+        let migrator = sqlx::migrate!("./migrations");
+        let migrations: Vec<&Migration> = migrator
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+            .collect();
+        let table = &config.table;
+
+        let applied = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut conn = pool.acquire().await?;
+                sqlx::query("SELECT pg_advisory_lock($1)").bind(MIGRATION_LOCK_ID).execute(&mut *conn).await?;
+                let result = migrate::<Postgres>(&mut conn, POSTGRES_MIGRATIONS_TABLE, table, config.baseline, &migrations).await;
+                sqlx::query("SELECT pg_advisory_unlock($1)").bind(MIGRATION_LOCK_ID).execute(&mut *conn).await?;
+                result?
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut conn = pool.acquire().await?;
+                migrate::<Sqlite>(&mut conn, SQLITE_MIGRATIONS_TABLE, table, config.baseline, &migrations).await?
+            }
+        };
+
+        for version in &applied {
+            log::info!("Applied migration {}", version);
+        }
+        Ok(applied)
+    }
+
+    // Finds the existing prosumer address closest to `address`, if one is within the
+    // suggestion threshold. Only addresses of a similar length sharing a leading or trailing
+    // run of characters are compared, at most ADDRESS_SUGGESTION_MAX_CANDIDATES of them, so
+    // the lookup stays cheap however many prosumers exist. Purely advisory, so lookup failures
+    // are swallowed.
+    pub async fn closest_address(&self, address: &str) -> Option<String> {
+        let _timer = self.time_query("closest_address");
+        let query = r#"
+            SELECT address FROM prosumers
+            WHERE LENGTH(address) BETWEEN $1 AND $2
+              AND (address LIKE $3 ESCAPE '\' OR address LIKE $4 ESCAPE '\')
+            LIMIT $5
+        "#;
+        let chars: Vec<char> = address.chars().collect();
+        let min_len = chars.len() as i64 - ADDRESS_SUGGESTION_MAX_DISTANCE as i64;
+        let max_len = chars.len() as i64 + ADDRESS_SUGGESTION_MAX_DISTANCE as i64;
+        let affix = ADDRESS_SUGGESTION_AFFIX_LENGTH.min(chars.len());
+        let prefix = format!("{}%", like_literal(&chars[..affix].iter().collect::<String>()));
+        let suffix = format!("%{}", like_literal(&chars[chars.len() - affix..].iter().collect::<String>()));
+
+        let candidates: Vec<String> = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query_scalar(query)
+                    .bind(min_len)
+                    .bind(max_len)
+                    .bind(&prefix)
+                    .bind(&suffix)
+                    .bind(ADDRESS_SUGGESTION_MAX_CANDIDATES)
+                    .fetch_all(pool)
+                    .await
+                    .ok()?
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query_scalar(query)
+                    .bind(min_len)
+                    .bind(max_len)
+                    .bind(&prefix)
+                    .bind(&suffix)
+                    .bind(ADDRESS_SUGGESTION_MAX_CANDIDATES)
+                    .fetch_all(pool)
+                    .await
+                    .ok()?
+            }
+        };
+
+        candidates
+            .into_iter()
+            .map(|candidate| (levenshtein(address, &candidate), candidate))
+            .filter(|(distance, _)| *distance > 0 && *distance <= ADDRESS_SUGGESTION_MAX_DISTANCE)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate)
+    }
+
+    fn check_open_order_limit(&self, address: &str, (metadata, open_orders): (String, i64)) -> Result<(), DatabaseError> {
+        // Unreadable metadata just means no override
+        let tags: BTreeMap<String, String> = serde_json::from_str(&metadata).unwrap_or_default();
+        self.market_config
+            .check_open_order_limit(address, &tags, open_orders)
+            .map_err(DatabaseError::LimitExceeded)
+    }
+
+    pub async fn create_prosumer(&self, prosumer: Prosumer) -> Result<Prosumer, DatabaseError> {
+        self.create_prosumer_for(prosumer, None).await
+    }
+
+    // Registers the prosumer as belonging to `owner_id`, the authenticated user creating it
+    pub async fn create_prosumer_for(&self, prosumer: Prosumer, owner_id: Option<&str>) -> Result<Prosumer, DatabaseError> {
+        let _timer = self.time_query("create_prosumer");
+        let created = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let created = self.insert_prosumer_postgres(&mut tx, &prosumer).await?;
+                if let Some(owner_id) = owner_id {
+                    sqlx::query(SET_PROSUMER_OWNER_QUERY).bind(&prosumer.address).bind(owner_id).execute(&mut *tx).await?;
+                }
+                tx.commit().await?;
+                created
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let created = self.insert_prosumer_sqlite(&mut tx, &prosumer).await?;
+                if let Some(owner_id) = owner_id {
+                    sqlx::query(SET_PROSUMER_OWNER_QUERY).bind(&prosumer.address).bind(owner_id).execute(&mut *tx).await?;
+                }
+                tx.commit().await?;
+                created
+            }
+        };
+        if prosumer.grid_tokens > 0.0 || prosumer.watt_tokens > 0.0 {
+            log::info!(
+                "Minted initial balance for {}: {} grid_tokens, {} watt_tokens",
+                created.address, prosumer.grid_tokens, prosumer.watt_tokens
+            );
+        }
+        Ok(created)
+    }
+
+    async fn insert_prosumer_postgres(&self, tx: &mut Transaction<'_, Postgres>, prosumer: &Prosumer) -> Result<Prosumer, DatabaseError> {
+        sqlx::query(INSERT_PROSUMER_QUERY)
+            .bind(&prosumer.address)
+            .bind(&prosumer.name)
+            .bind(prosumer.energy_generated)
+            .bind(prosumer.energy_consumed)
+            .bind(prosumer.is_active)
+            .bind(prosumer.created_at)
+            .bind(prosumer.updated_at)
+            .execute(&mut **tx)
+            .await?;
+
+        // The starting balance is minted, and recorded as a transfer from MINT_ADDRESS so the
+        // prosumer's history accounts for every token it holds
+        for (token_type, amount) in [("grid_tokens", prosumer.grid_tokens), ("watt_tokens", prosumer.watt_tokens)] {
+            if amount > 0.0 {
+                sqlx::query(CREDIT_BALANCE_QUERY)
+                    .bind(&prosumer.address)
+                    .bind(token_type)
+                    .bind(amount)
+                    .bind(prosumer.updated_at)
+                    .execute(&mut **tx)
+                    .await?;
+                sqlx::query(INSERT_TRANSFER_QUERY)
+                    .bind(Uuid::new_v4())
+                    .bind(MINT_ADDRESS)
+                    .bind(&prosumer.address)
+                    .bind(token_type)
+                    .bind(amount)
+                    .bind(prosumer.updated_at)
+                    .execute(&mut **tx)
+                    .await?;
+            }
+        }
+
+        let row = sqlx::query_as::<_, ProsumerRow>(&format!("{} WHERE p.address = $1", PROSUMER_SELECT))
+            .bind(&prosumer.address)
+            .fetch_one(&mut **tx)
+            .await?;
+        Ok(row.into())
+    }
+
+    async fn insert_prosumer_sqlite(&self, tx: &mut Transaction<'_, Sqlite>, prosumer: &Prosumer) -> Result<Prosumer, DatabaseError> {
+        sqlx::query(INSERT_PROSUMER_QUERY)
+            .bind(&prosumer.address)
+            .bind(&prosumer.name)
+            .bind(prosumer.energy_generated)
+            .bind(prosumer.energy_consumed)
+            .bind(prosumer.is_active)
+            .bind(prosumer.created_at)
+            .bind(prosumer.updated_at)
+            .execute(&mut **tx)
+            .await?;
+
+        // The starting balance is minted, and recorded as a transfer from MINT_ADDRESS so the
+        // prosumer's history accounts for every token it holds
+        for (token_type, amount) in [("grid_tokens", prosumer.grid_tokens), ("watt_tokens", prosumer.watt_tokens)] {
+            if amount > 0.0 {
+                sqlx::query(CREDIT_BALANCE_QUERY)
+                    .bind(&prosumer.address)
+                    .bind(token_type)
+                    .bind(amount)
+                    .bind(prosumer.updated_at)
+                    .execute(&mut **tx)
+                    .await?;
+                sqlx::query(INSERT_TRANSFER_QUERY)
+                    .bind(Uuid::new_v4())
+                    .bind(MINT_ADDRESS)
+                    .bind(&prosumer.address)
+                    .bind(token_type)
+                    .bind(amount)
+                    .bind(prosumer.updated_at)
+                    .execute(&mut **tx)
+                    .await?;
+            }
+        }
+
+        let row = sqlx::query_as::<_, ProsumerRow>(&format!("{} WHERE p.address = $1", PROSUMER_SELECT))
+            .bind(&prosumer.address)
+            .fetch_one(&mut **tx)
+            .await?;
+        Ok(row.into())
+    }
+
+    // The user who registered the prosumer, if it was registered by an authenticated caller
+    pub async fn get_prosumer_owner(&self, address: &str) -> Result<Option<String>, DatabaseError> {
+        let _timer = self.time_query("get_prosumer_owner");
+        let row: Option<Option<String>> = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_scalar(PROSUMER_OWNER_QUERY).bind(address).fetch_optional(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_scalar(PROSUMER_OWNER_QUERY).bind(address).fetch_optional(pool).await?,
+        };
+        row.ok_or_else(|| DatabaseError::NotFound(format!("Prosumer '{}' not found", address)))
+    }
+
+    pub async fn get_prosumer(&self, address: &str) -> Result<Prosumer, DatabaseError> {
+        let _timer = self.time_query("get_prosumer");
+        let query = &format!("{} WHERE p.address = $1", PROSUMER_SELECT);
+        
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let row = sqlx::query_as::<_, ProsumerRow>(query)
+                    .bind(address)
+                    .fetch_optional(pool)
+                    .await?;
+                match row {
+                    Some(row) => Ok(row.into()),
+                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
+                }
+            }
+            DatabasePool::Sqlite(pool) => {
+                let row = sqlx::query_as::<_, ProsumerRow>(query)
+                    .bind(address)
+                    .fetch_optional(pool)
+                    .await?;
+                match row {
+                    Some(row) => Ok(row.into()),
+                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
+                }
+            }
+        }
+    }
+
+    pub async fn prosumer_exists(&self, address: &str) -> Result<bool, DatabaseError> {
+        let _timer = self.time_query("prosumer_exists");
+        let query = "SELECT COUNT(*) FROM prosumers WHERE address = $1";
+
+        let count: i64 = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query_scalar(query)
+                    .bind(address)
+                    .fetch_one(pool)
+                    .await?
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query_scalar(query)
+                    .bind(address)
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+        Ok(count > 0)
+    }
+
+    // Number of prosumers matching all of `tags`, as listed by get_prosumers
+    pub async fn count_prosumers(&self, tags: &[TagFilter]) -> Result<i64, DatabaseError> {
+        let _timer = self.time_query("count_prosumers");
+        let query = &format!("SELECT COUNT(*) FROM prosumers p WHERE {}", TagFilter::to_sql(tags, self.read_pool(), 1));
+
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query_scalar(query);
+                for tag in tags {
+                    q = q.bind(&tag.key).bind(&tag.value);
+                }
+                Ok(q.fetch_one(pool).await?)
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut q = sqlx::query_scalar(query);
+                for tag in tags {
+                    q = q.bind(&tag.key).bind(&tag.value);
+                }
+                Ok(q.fetch_one(pool).await?)
+            }
+        }
+    }
+
+    pub async fn get_prosumers(&self, page: u32, limit: u32, sort: SortOrder, tags: &[TagFilter]) -> Result<Vec<Prosumer>, DatabaseError> {
+        let _timer = self.time_query("get_prosumers");
+        let offset = page.saturating_sub(1).saturating_mul(limit);
+        let query = &format!(
+            "{} WHERE {} ORDER BY {} LIMIT $1 OFFSET $2",
+            PROSUMER_SELECT,
+            TagFilter::to_sql(tags, self.read_pool(), 3),
+            sort.to_sql("p.")
+        );
+        
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query_as::<_, ProsumerRow>(query)
+                    .bind(limit as i64)
+                    .bind(offset as i64);
+                for tag in tags {
+                    q = q.bind(&tag.key).bind(&tag.value);
+                }
+                let rows = q.fetch_all(pool).await?;
+                Ok(rows.into_iter().map(|row| row.into()).collect())
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut q = sqlx::query_as::<_, ProsumerRow>(query)
+                    .bind(limit as i64)
+                    .bind(offset as i64);
+                for tag in tags {
+                    q = q.bind(&tag.key).bind(&tag.value);
+                }
+                let rows = q.fetch_all(pool).await?;
+                Ok(rows.into_iter().map(|row| row.into()).collect())
+            }
+        }
+    }
+
+    pub async fn get_account_statement(&self, address: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<AccountStatement, DatabaseError> {
+        let _timer = self.time_query("get_account_statement");
+        if from >= to {
+            return Err(DatabaseError::Validation("from must be before to".to_string()));
+        }
+        let balance = self.get_prosumer_balance(address).await?;
+
+        let transfers_query = "SELECT * FROM token_transfers WHERE (from_address = $1 OR to_address = $1) AND created_at >= $2 AND created_at < $3 ORDER BY created_at ASC";
+        let orders_query = "SELECT * FROM orders WHERE prosumer_address = $1 AND created_at >= $2 AND created_at < $3 ORDER BY created_at ASC";
+        let trades_query = "SELECT * FROM trades WHERE (buyer_address = $1 OR seller_address = $1) AND executed_at >= $2 AND executed_at < $3 ORDER BY executed_at ASC";
+
+        let (transfers, orders, trades) = match self.read_pool() {
+            DatabasePool::Postgres(pool) => (
+                sqlx::query_as::<_, TokenTransfer>(transfers_query).bind(address).bind(from).bind(to).fetch_all(pool).await?,
+                sqlx::query_as::<_, OrderRow>(orders_query).bind(address).bind(from).bind(to).fetch_all(pool).await?,
+                sqlx::query_as::<_, TradeRow>(trades_query).bind(address).bind(from).bind(to).fetch_all(pool).await?,
+            ),
+            DatabasePool::Sqlite(pool) => (
+                sqlx::query_as::<_, TokenTransfer>(transfers_query).bind(address).bind(from).bind(to).fetch_all(pool).await?,
+                sqlx::query_as::<_, OrderRow>(orders_query).bind(address).bind(from).bind(to).fetch_all(pool).await?,
+                sqlx::query_as::<_, TradeRow>(trades_query).bind(address).bind(from).bind(to).fetch_all(pool).await?,
+            ),
+        };
+
+        Ok(AccountStatement {
+            address: address.to_string(),
+            from,
+            to,
+            balance,
+            transfers,
+            orders: orders.into_iter().map(Order::from).collect(),
+            trades: trades.into_iter().map(Trade::from).collect(),
+            generated_at: self.now(),
+        })
+    }
+
+    pub async fn get_prosumer_pnl(&self, address: &str, from: DateTime<Utc>, to: DateTime<Utc>, method: PnlMethod) -> Result<ProsumerPnl, DatabaseError> {
+        let _timer = self.time_query("get_prosumer_pnl");
+        if from >= to {
+            return Err(DatabaseError::Validation("from must be before to".to_string()));
+        }
+        if !self.prosumer_exists(address).await? {
+            return Err(prosumer_not_found(address));
+        }
+
+        // Purchases before `from` still make up the cost basis of sales inside the window
+        let rows = match self.read_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_as::<_, TradeRow>(PNL_TRADES_QUERY).bind(address).bind(to).fetch_all(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_as::<_, TradeRow>(PNL_TRADES_QUERY).bind(address).bind(to).fetch_all(pool).await?,
+        };
+        let trades: Vec<Trade> = rows.into_iter().map(Trade::from).collect();
+        let periods = realized_pnl(address, &trades, method, from, to);
+
+        Ok(ProsumerPnl {
+            address: address.to_string(),
+            from,
+            to,
+            method,
+            energy_sold: periods.iter().map(|p| p.energy_sold).sum(),
+            proceeds: periods.iter().map(|p| p.proceeds).sum(),
+            cost_basis: periods.iter().map(|p| p.cost_basis).sum(),
+            realized_pnl: periods.iter().map(|p| p.realized_pnl).sum(),
+            periods,
+        })
+    }
+
+    async fn get_tagged_addresses(&self, tags: &[TagFilter]) -> Result<HashSet<String>, DatabaseError> {
+        let query = &format!("SELECT p.address FROM prosumers p WHERE {}", TagFilter::to_sql(tags, self.read_pool(), 1));
+        let addresses: Vec<String> = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query_scalar(query);
+                for tag in tags {
+                    q = q.bind(&tag.key).bind(&tag.value);
+                }
+                q.fetch_all(pool).await?
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut q = sqlx::query_scalar(query);
+                for tag in tags {
+                    q = q.bind(&tag.key).bind(&tag.value);
+                }
+                q.fetch_all(pool).await?
+            }
+        };
+        Ok(addresses.into_iter().collect())
+    }
+
+    pub async fn get_prosumer_tags(&self, address: &str) -> Result<BTreeMap<String, String>, DatabaseError> {
+        let _timer = self.time_query("get_prosumer_tags");
+        let query = "SELECT metadata FROM prosumers WHERE address = $1";
+
+        let metadata: Option<String> = match self.read_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_scalar(query).bind(address).fetch_optional(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_scalar(query).bind(address).fetch_optional(pool).await?,
+        };
+        let metadata = metadata.ok_or_else(|| DatabaseError::NotFound(format!("Prosumer '{}' not found", address)))?;
+        serde_json::from_str(&metadata)
+            .map_err(|e| DatabaseError::Validation(format!("Stored metadata for '{}' is not valid: {}", address, e)))
+    }
+
+    // Replaces all of a prosumer's tags
+    pub async fn set_prosumer_tags(&self, address: &str, tags: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>, DatabaseError> {
+        let _timer = self.time_query("set_prosumer_tags");
+        let query = "UPDATE prosumers SET metadata = $2, updated_at = $3 WHERE address = $1";
+        let metadata = serde_json::to_string(tags)
+            .map_err(|e| DatabaseError::Validation(format!("Invalid tags: {}", e)))?;
+
+        let rows_affected = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query(query)
+                    .bind(address)
+                    .bind(&metadata)
+                    .bind(self.now())
+                    .execute(pool)
+                    .await?
+                    .rows_affected()
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query(query)
+                    .bind(address)
+                    .bind(&metadata)
+                    .bind(self.now())
+                    .execute(pool)
+                    .await?
+                    .rows_affected()
+            }
+        };
+
+        if rows_affected == 0 {
+            return Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address)));
+        }
+        Ok(tags.clone())
+    }
+
+    pub async fn update_prosumer(&self, address: &str, name: Option<String>, energy_generated: Option<f64>, energy_consumed: Option<f64>) -> Result<Prosumer, DatabaseError> {
+        let _timer = self.time_query("update_prosumer");
+        let query = r#"
             UPDATE prosumers 
             SET name = COALESCE($2, name),
                 energy_generated = COALESCE($3, energy_generated),
                 energy_consumed = COALESCE($4, energy_consumed),
                 updated_at = $5
             WHERE address = $1
+        "#;
+        
+        let rows_affected = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query(query)
+                    .bind(address)
+                    .bind(name.as_deref())
+                    .bind(energy_generated)
+                    .bind(energy_consumed)
+                    .bind(self.now())
+                    .execute(pool)
+                    .await?
+                    .rows_affected()
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query(query)
+                    .bind(address)
+                    .bind(name.as_deref())
+                    .bind(energy_generated)
+                    .bind(energy_consumed)
+                    .bind(self.now())
+                    .execute(pool)
+                    .await?
+                    .rows_affected()
+            }
+        };
+
+        if rows_affected == 0 {
+            return Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address)));
+        }
+        self.get_prosumer(address).await
+    }
+
+    // Deregisters a prosumer: cancels its open orders (releasing their escrow), moves every
+    // remaining balance to `destination` as recorded transfers and deactivates it, all in one
+    // transaction. Refused while any of its trades is still awaiting settlement.
+    pub async fn close_prosumer(&self, address: &str, destination: &str) -> Result<ProsumerClosure, DatabaseError> {
+        let _timer = self.time_query("close_prosumer");
+        if address == destination {
+            return Err(DatabaseError::Validation("Balances cannot be swept to the prosumer being closed".to_string()));
+        }
+        let closure = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let active: Option<bool> = sqlx::query_scalar("SELECT is_active FROM prosumers WHERE address = $1 FOR UPDATE")
+                    .bind(address)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                let destination_active: Option<bool> = sqlx::query_scalar("SELECT is_active FROM prosumers WHERE address = $1")
+                    .bind(destination)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                self.check_closable(address, destination, active, destination_active).await?;
+                let unsettled: i64 = sqlx::query_scalar(UNSETTLED_TRADES_QUERY).bind(address).fetch_one(&mut *tx).await?;
+                check_no_unsettled_trades(address, unsettled)?;
+
+                // Escrow is worked out from the orders as they were before being cancelled
+                let reserved: f64 = sqlx::query_as::<_, OrderRow>(&format!("{} FOR UPDATE", OPEN_ORDERS_OF_QUERY))
+                    .bind(address)
+                    .fetch_all(&mut *tx)
+                    .await?
+                    .into_iter()
+                    .map(|row| buy_reservation(&Order::from(row)))
+                    .sum();
+                let now = self.now();
+                let cancelled_orders: Vec<Order> = sqlx::query_as::<_, OrderRow>(CANCEL_OPEN_ORDERS_QUERY)
+                    .bind(address)
+                    .bind(now)
+                    .fetch_all(&mut *tx)
+                    .await?
+                    .into_iter()
+                    .map(Order::from)
+                    .collect();
+                self.adjust_escrow_postgres(&mut tx, address, -reserved).await?;
+
+                let balances: Vec<(String, f64)> = sqlx::query_as(SWEEPABLE_BALANCES_QUERY).bind(address).fetch_all(&mut *tx).await?;
+                let mut sweeps = Vec::with_capacity(balances.len());
+                for (token_type, amount) in balances {
+                    let transfer_id = Uuid::new_v4();
+                    self.transfer_tokens_postgres(&mut tx, transfer_id, address, destination, amount, &token_type).await?;
+                    sweeps.push(BalanceSweep { transfer_id, token_type, amount });
+                }
+
+                sqlx::query("UPDATE prosumers SET is_active = FALSE, updated_at = $2 WHERE address = $1")
+                    .bind(address)
+                    .bind(now)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+                ProsumerClosure { address: address.to_string(), destination_address: destination.to_string(), cancelled_orders, sweeps }
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let active: Option<bool> = sqlx::query_scalar("SELECT is_active FROM prosumers WHERE address = $1")
+                    .bind(address)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                let destination_active: Option<bool> = sqlx::query_scalar("SELECT is_active FROM prosumers WHERE address = $1")
+                    .bind(destination)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                self.check_closable(address, destination, active, destination_active).await?;
+                let unsettled: i64 = sqlx::query_scalar(UNSETTLED_TRADES_QUERY).bind(address).fetch_one(&mut *tx).await?;
+                check_no_unsettled_trades(address, unsettled)?;
+
+                // Escrow is worked out from the orders as they were before being cancelled
+                let reserved: f64 = sqlx::query_as::<_, OrderRow>(OPEN_ORDERS_OF_QUERY)
+                    .bind(address)
+                    .fetch_all(&mut *tx)
+                    .await?
+                    .into_iter()
+                    .map(|row| buy_reservation(&Order::from(row)))
+                    .sum();
+                let now = self.now();
+                let cancelled_orders: Vec<Order> = sqlx::query_as::<_, OrderRow>(CANCEL_OPEN_ORDERS_QUERY)
+                    .bind(address)
+                    .bind(now)
+                    .fetch_all(&mut *tx)
+                    .await?
+                    .into_iter()
+                    .map(Order::from)
+                    .collect();
+                self.adjust_escrow_sqlite(&mut tx, address, -reserved).await?;
+
+                let balances: Vec<(String, f64)> = sqlx::query_as(SWEEPABLE_BALANCES_QUERY).bind(address).fetch_all(&mut *tx).await?;
+                let mut sweeps = Vec::with_capacity(balances.len());
+                for (token_type, amount) in balances {
+                    let transfer_id = Uuid::new_v4();
+                    self.transfer_tokens_sqlite(&mut tx, transfer_id, address, destination, amount, &token_type).await?;
+                    sweeps.push(BalanceSweep { transfer_id, token_type, amount });
+                }
+
+                sqlx::query("UPDATE prosumers SET is_active = FALSE, updated_at = $2 WHERE address = $1")
+                    .bind(address)
+                    .bind(now)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+                ProsumerClosure { address: address.to_string(), destination_address: destination.to_string(), cancelled_orders, sweeps }
+            }
+        };
+        Ok(closure)
+    }
+
+    async fn check_closable(&self, address: &str, destination: &str, active: Option<bool>, destination_active: Option<bool>) -> Result<(), DatabaseError> {
+        match active {
+            None => return Err(prosumer_not_found(address)),
+            Some(false) => return Err(DatabaseError::Conflict(format!("Prosumer '{}' is already closed", address))),
+            Some(true) => {}
+        }
+        match destination_active {
+            None => Err(prosumer_not_found(destination)),
+            Some(false) => Err(DatabaseError::Validation(format!("Destination prosumer '{}' is inactive", destination))),
+            Some(true) => Ok(()),
+        }
+    }
+
+    pub async fn create_order(&self, order: Order) -> Result<Order, DatabaseError> {
+        let _timer = self.time_query("create_order");
+        self.check_market_open().await?;
+        let order = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let order = self.insert_order_postgres(&mut tx, order).await?;
+                tx.commit().await?;
+                order
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let order = self.insert_order_sqlite(&mut tx, order).await?;
+                tx.commit().await?;
+                order
+            }
+        };
+        telemetry::record_order_created(&order.order_type);
+        Ok(order)
+    }
+
+    // Normalize the quoted price so orders in different currencies can be matched
+    fn prepare_order(&self, mut order: Order) -> Result<Order, DatabaseError> {
+        order.energy_amount = self.market_config.normalize_energy_amount(order.energy_amount).map_err(DatabaseError::Validation)?;
+        order.remaining_amount = order.energy_amount - order.filled_amount;
+        self.market_config.validate_price(order.quoted_price_per_unit).map_err(DatabaseError::Validation)?;
+
+        order.currency = order.currency.to_uppercase();
+        order.price_per_unit = self.normalize_price(&order.currency, order.quoted_price_per_unit)?;
+        order.total_price = order.energy_amount * order.price_per_unit;
+        Ok(order)
+    }
+
+    async fn insert_order_postgres(&self, tx: &mut Transaction<'_, Postgres>, order: Order) -> Result<Order, DatabaseError> {
+        let order = self.prepare_order(order)?;
+
+        let prosumer_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM prosumers WHERE address = $1")
+            .bind(&order.prosumer_address)
+            .fetch_one(&mut **tx)
+            .await?;
+        if prosumer_count == 0 {
+            return Err(prosumer_not_found(&order.prosumer_address));
+        }
+
+        // The prosumer row lock makes concurrent orders from one prosumer take turns here
+        let open_orders = sqlx::query_as(&format!("{} FOR UPDATE OF p", OPEN_ORDERS_QUERY))
+            .bind(&order.prosumer_address)
+            .fetch_one(&mut **tx)
+            .await?;
+        self.check_open_order_limit(&order.prosumer_address, open_orders)?;
+
+        if self.market_config.enforce_energy_backing && order.order_type == "sell" {
+            let (net_energy, committed): (f64, f64) = sqlx::query_as(ENERGY_BACKING_QUERY)
+                .bind(&order.prosumer_address)
+                .fetch_one(&mut **tx)
+                .await?;
+            check_energy_backing(order.energy_amount, net_energy, committed)?;
+        }
+
+        let row = sqlx::query_as::<_, OrderRow>(INSERT_ORDER_QUERY)
+            .bind(order.id)
+            .bind(&order.prosumer_address)
+            .bind(&order.order_type)
+            .bind(order.energy_amount)
+            .bind(order.price_per_unit)
+            .bind(order.total_price)
+            .bind(&order.status)
+            .bind(order.created_at)
+            .bind(order.updated_at)
+            .bind(order.expires_at)
+            .bind(&order.currency)
+            .bind(order.quoted_price_per_unit)
+            .fetch_one(&mut **tx)
+            .await?;
+        let order = Order::from(row);
+        self.adjust_escrow_postgres(tx, &order.prosumer_address, buy_reservation(&order)).await?;
+        Ok(order)
+    }
+
+    async fn insert_order_sqlite(&self, tx: &mut Transaction<'_, Sqlite>, order: Order) -> Result<Order, DatabaseError> {
+        let order = self.prepare_order(order)?;
+
+        let prosumer_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM prosumers WHERE address = $1")
+            .bind(&order.prosumer_address)
+            .fetch_one(&mut **tx)
+            .await?;
+        if prosumer_count == 0 {
+            return Err(prosumer_not_found(&order.prosumer_address));
+        }
+
+        let open_orders = sqlx::query_as(OPEN_ORDERS_QUERY)
+            .bind(&order.prosumer_address)
+            .fetch_one(&mut **tx)
+            .await?;
+        self.check_open_order_limit(&order.prosumer_address, open_orders)?;
+
+        if self.market_config.enforce_energy_backing && order.order_type == "sell" {
+            let (net_energy, committed): (f64, f64) = sqlx::query_as(ENERGY_BACKING_QUERY)
+                .bind(&order.prosumer_address)
+                .fetch_one(&mut **tx)
+                .await?;
+            check_energy_backing(order.energy_amount, net_energy, committed)?;
+        }
+
+        if !self.sqlite_returning {
+            sqlx::query(&without_returning(INSERT_ORDER_QUERY))
+                .bind(order.id)
+                .bind(&order.prosumer_address)
+                .bind(&order.order_type)
+                .bind(order.energy_amount)
+                .bind(order.price_per_unit)
+                .bind(order.total_price)
+                .bind(&order.status)
+                .bind(order.created_at)
+                .bind(order.updated_at)
+                .bind(order.expires_at)
+                .bind(&order.currency)
+                .bind(order.quoted_price_per_unit)
+                .execute(&mut **tx)
+                .await?;
+            let row = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                .bind(order.id)
+                .fetch_one(&mut **tx)
+                .await?;
+            let order = Order::from(row);
+            self.adjust_escrow_sqlite(tx, &order.prosumer_address, buy_reservation(&order)).await?;
+            return Ok(order);
+        }
+
+        let row = sqlx::query_as::<_, OrderRow>(INSERT_ORDER_QUERY)
+            .bind(order.id)
+            .bind(&order.prosumer_address)
+            .bind(&order.order_type)
+            .bind(order.energy_amount)
+            .bind(order.price_per_unit)
+            .bind(order.total_price)
+            .bind(&order.status)
+            .bind(order.created_at)
+            .bind(order.updated_at)
+            .bind(order.expires_at)
+            .bind(&order.currency)
+            .bind(order.quoted_price_per_unit)
+            .fetch_one(&mut **tx)
+            .await?;
+        let order = Order::from(row);
+        self.adjust_escrow_sqlite(tx, &order.prosumer_address, buy_reservation(&order)).await?;
+        Ok(order)
+    }
+
+    // Moves a buyer's grid_tokens into (positive `delta`) or out of escrow
+    async fn adjust_escrow_postgres(&self, tx: &mut Transaction<'_, Postgres>, address: &str, delta: f64) -> Result<(), DatabaseError> {
+        if delta == 0.0 {
+            return Ok(());
+        }
+        let (available, reserved): (f64, f64) = sqlx::query_as(ESCROW_BALANCE_QUERY)
+            .bind(address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or((0.0, 0.0));
+        let (spendable_delta, reserved_delta) = escrow_adjustment(delta, available, reserved)?;
+        sqlx::query(ESCROW_UPDATE_QUERY)
+            .bind(spendable_delta)
+            .bind(reserved_delta)
+            .bind(self.now())
+            .bind(address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    async fn adjust_escrow_sqlite(&self, tx: &mut Transaction<'_, Sqlite>, address: &str, delta: f64) -> Result<(), DatabaseError> {
+        if delta == 0.0 {
+            return Ok(());
+        }
+        let (available, reserved): (f64, f64) = sqlx::query_as(ESCROW_BALANCE_QUERY)
+            .bind(address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or((0.0, 0.0));
+        let (spendable_delta, reserved_delta) = escrow_adjustment(delta, available, reserved)?;
+        sqlx::query(ESCROW_UPDATE_QUERY)
+            .bind(spendable_delta)
+            .bind(reserved_delta)
+            .bind(self.now())
+            .bind(address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    // Adds a fill to both parties' usage for the day, rejecting it if either would go over
+    // the configured daily limits
+    async fn record_daily_usage_postgres(&self, tx: &mut Transaction<'_, Postgres>, trade: &Trade) -> Result<(), DatabaseError> {
+        let trade_date = trade.executed_at.format("%Y-%m-%d").to_string();
+        for address in [&trade.buyer_address, &trade.seller_address] {
+            let (used_energy, used_notional): (f64, f64) = sqlx::query_as(DAILY_USAGE_QUERY)
+                .bind(address)
+                .bind(&trade_date)
+                .fetch_optional(&mut **tx)
+                .await?
+                .unwrap_or((0.0, 0.0));
+            self.market_config
+                .check_daily_limits(address, used_energy, used_notional, trade.energy_amount, trade.total_price)
+                .map_err(DatabaseError::LimitExceeded)?;
+            sqlx::query(ADD_DAILY_USAGE_QUERY)
+                .bind(address)
+                .bind(&trade_date)
+                .bind(trade.energy_amount)
+                .bind(trade.total_price)
+                .execute(&mut **tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn record_daily_usage_sqlite(&self, tx: &mut Transaction<'_, Sqlite>, trade: &Trade) -> Result<(), DatabaseError> {
+        let trade_date = trade.executed_at.format("%Y-%m-%d").to_string();
+        for address in [&trade.buyer_address, &trade.seller_address] {
+            let (used_energy, used_notional): (f64, f64) = sqlx::query_as(DAILY_USAGE_QUERY)
+                .bind(address)
+                .bind(&trade_date)
+                .fetch_optional(&mut **tx)
+                .await?
+                .unwrap_or((0.0, 0.0));
+            self.market_config
+                .check_daily_limits(address, used_energy, used_notional, trade.energy_amount, trade.total_price)
+                .map_err(DatabaseError::LimitExceeded)?;
+            sqlx::query(ADD_DAILY_USAGE_QUERY)
+                .bind(address)
+                .bind(&trade_date)
+                .bind(trade.energy_amount)
+                .bind(trade.total_price)
+                .execute(&mut **tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Pays for a fill from the buyer's escrow (see `settlement_adjustment`) and credits the seller
+    async fn settle_fill_postgres(&self, tx: &mut Transaction<'_, Postgres>, trade: &Trade, freed: f64) -> Result<(), DatabaseError> {
+        let (available, reserved): (f64, f64) = sqlx::query_as(ESCROW_BALANCE_QUERY)
+            .bind(&trade.buyer_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or((0.0, 0.0));
+        let (spendable_delta, mut reserved_delta) = settlement_adjustment(freed, trade.total_price, available, reserved)?;
+        // A trade settling later keeps its payment reserved until then
+        let pending = trade.status == "pending";
+        if pending {
+            reserved_delta += trade.total_price;
+        }
+        let now = self.now();
+        sqlx::query(ESCROW_UPDATE_QUERY)
+            .bind(spendable_delta)
+            .bind(reserved_delta)
+            .bind(now)
+            .bind(&trade.buyer_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .execute(&mut **tx)
+            .await?;
+        if !pending {
+            sqlx::query(CREDIT_BALANCE_QUERY)
+                .bind(&trade.seller_address)
+                .bind(ESCROW_TOKEN_TYPE)
+                .bind(trade.total_price)
+                .bind(now)
+                .execute(&mut **tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Pays the seller of a delayed trade out of the payment reserved from the buyer at execution
+    async fn release_settlement_postgres(&self, tx: &mut Transaction<'_, Postgres>, trade: &Trade) -> Result<(), DatabaseError> {
+        let (_, reserved): (f64, f64) = sqlx::query_as(ESCROW_BALANCE_QUERY)
+            .bind(&trade.buyer_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or((0.0, 0.0));
+        let now = self.now();
+        sqlx::query(ESCROW_UPDATE_QUERY)
+            .bind(0.0)
+            .bind(-release_amount(trade.total_price, reserved))
+            .bind(now)
+            .bind(&trade.buyer_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .execute(&mut **tx)
+            .await?;
+        sqlx::query(CREDIT_BALANCE_QUERY)
+            .bind(&trade.seller_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .bind(trade.total_price)
+            .bind(now)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    async fn settle_fill_sqlite(&self, tx: &mut Transaction<'_, Sqlite>, trade: &Trade, freed: f64) -> Result<(), DatabaseError> {
+        let (available, reserved): (f64, f64) = sqlx::query_as(ESCROW_BALANCE_QUERY)
+            .bind(&trade.buyer_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or((0.0, 0.0));
+        let (spendable_delta, mut reserved_delta) = settlement_adjustment(freed, trade.total_price, available, reserved)?;
+        // A trade settling later keeps its payment reserved until then
+        let pending = trade.status == "pending";
+        if pending {
+            reserved_delta += trade.total_price;
+        }
+        let now = self.now();
+        sqlx::query(ESCROW_UPDATE_QUERY)
+            .bind(spendable_delta)
+            .bind(reserved_delta)
+            .bind(now)
+            .bind(&trade.buyer_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .execute(&mut **tx)
+            .await?;
+        if !pending {
+            sqlx::query(CREDIT_BALANCE_QUERY)
+                .bind(&trade.seller_address)
+                .bind(ESCROW_TOKEN_TYPE)
+                .bind(trade.total_price)
+                .bind(now)
+                .execute(&mut **tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Pays the seller of a delayed trade out of the payment reserved from the buyer at execution
+    async fn release_settlement_sqlite(&self, tx: &mut Transaction<'_, Sqlite>, trade: &Trade) -> Result<(), DatabaseError> {
+        let (_, reserved): (f64, f64) = sqlx::query_as(ESCROW_BALANCE_QUERY)
+            .bind(&trade.buyer_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or((0.0, 0.0));
+        let now = self.now();
+        sqlx::query(ESCROW_UPDATE_QUERY)
+            .bind(0.0)
+            .bind(-release_amount(trade.total_price, reserved))
+            .bind(now)
+            .bind(&trade.buyer_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .execute(&mut **tx)
+            .await?;
+        sqlx::query(CREDIT_BALANCE_QUERY)
+            .bind(&trade.seller_address)
+            .bind(ESCROW_TOKEN_TYPE)
+            .bind(trade.total_price)
+            .bind(now)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_order(&self, id: Uuid) -> Result<Order, DatabaseError> {
+        let _timer = self.time_query("get_order");
+        let query = "SELECT * FROM orders WHERE id = $1";
+        
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let row = sqlx::query_as::<_, OrderRow>(query)
+                    .bind(id)
+                    .fetch_optional(pool)
+                    .await?;
+                match row {
+                    Some(row) => Ok(row.into()),
+                    None => Err(DatabaseError::NotFound(format!("Order '{}' not found", id))),
+                }
+            }
+            DatabasePool::Sqlite(pool) => {
+                let row = sqlx::query_as::<_, OrderRow>(query)
+                    .bind(id)
+                    .fetch_optional(pool)
+                    .await?;
+                match row {
+                    Some(row) => Ok(row.into()),
+                    None => Err(DatabaseError::NotFound(format!("Order '{}' not found", id))),
+                }
+            }
+        }
+    }
+
+    pub async fn order_exists(&self, id: Uuid) -> Result<bool, DatabaseError> {
+        let _timer = self.time_query("order_exists");
+        let query = "SELECT COUNT(*) FROM orders WHERE id = $1";
+
+        let count: i64 = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query_scalar(query)
+                    .bind(id)
+                    .fetch_one(pool)
+                    .await?
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query_scalar(query)
+                    .bind(id)
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+        Ok(count > 0)
+    }
+
+    // Number of orders matching the filters that are set, as listed by get_orders
+    pub async fn count_orders(&self, status: Option<&str>, order_type: Option<&str>, prosumer_address: Option<&str>) -> Result<i64, DatabaseError> {
+        let _timer = self.time_query("count_orders");
+        let mut query = "SELECT COUNT(*) FROM orders WHERE 1=1".to_string();
+        let filters = [("status", status), ("order_type", order_type), ("prosumer_address", prosumer_address)];
+        let values: Vec<&str> = filters.iter().filter_map(|(_, value)| *value).collect();
+        for (i, (column, _)) in filters.iter().filter(|(_, value)| value.is_some()).enumerate() {
+            query.push_str(&format!(" AND {} = ${}", column, i + 1));
+        }
+
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query_scalar(&query);
+                for &value in &values {
+                    q = q.bind(value);
+                }
+                Ok(q.fetch_one(pool).await?)
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut q = sqlx::query_scalar(&query);
+                for &value in &values {
+                    q = q.bind(value);
+                }
+                Ok(q.fetch_one(pool).await?)
+            }
+        }
+    }
+
+    pub async fn get_orders(&self, page: u32, limit: u32, status: Option<String>, order_type: Option<String>, prosumer_address: Option<String>, sort: SortOrder) -> Result<Vec<Order>, DatabaseError> {
+        let _timer = self.time_query("get_orders");
+        let offset = page.saturating_sub(1).saturating_mul(limit);
+        let mut query = "SELECT * FROM orders WHERE 1=1".to_string();
+        let mut bind_count = 1;
+        
+        if status.is_some() {
+            query.push_str(&format!(" AND status = ${}", bind_count));
+            bind_count += 1;
+        }
+        if order_type.is_some() {
+            query.push_str(&format!(" AND order_type = ${}", bind_count));
+            bind_count += 1;
+        }
+        if prosumer_address.is_some() {
+            query.push_str(&format!(" AND prosumer_address = ${}", bind_count));
+            bind_count += 1;
+        }
+        
+        query.push_str(&format!(" ORDER BY {} LIMIT ${} OFFSET ${}", sort.to_sql(""), bind_count, bind_count + 1));
+        
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query_as::<_, OrderRow>(&query);
+                if let Some(ref s) = status {
+                    q = q.bind(s);
+                }
+                if let Some(ref ot) = order_type {
+                    q = q.bind(ot);
+                }
+                if let Some(ref pa) = prosumer_address {
+                    q = q.bind(pa);
+                }
+                q = q.bind(limit as i64).bind(offset as i64);
+                let rows = q.fetch_all(pool).await?;
+                Ok(rows.into_iter().map(|row| row.into()).collect())
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut q = sqlx::query_as::<_, OrderRow>(&query);
+                if let Some(ref s) = status {
+                    q = q.bind(s);
+                }
+                if let Some(ref ot) = order_type {
+                    q = q.bind(ot);
+                }
+                if let Some(ref pa) = prosumer_address {
+                    q = q.bind(pa);
+                }
+                q = q.bind(limit as i64).bind(offset as i64);
+                let rows = q.fetch_all(pool).await?;
+                Ok(rows.into_iter().map(|row| row.into()).collect())
+            }
+        }
+    }
+
+    // `price_per_unit` is interpreted in the order's quote currency and normalized before storing
+    pub async fn update_order(&self, id: Uuid, energy_amount: Option<f64>, price_per_unit: Option<f64>) -> Result<Order, DatabaseError> {
+        let _timer = self.time_query("update_order");
+        let query = r#"
+            UPDATE orders 
+            SET energy_amount = COALESCE($2, energy_amount),
+                price_per_unit = COALESCE($3, price_per_unit),
+                total_price = COALESCE($2, energy_amount) * COALESCE($3, price_per_unit),
+                updated_at = $4,
+                quoted_price_per_unit = COALESCE($5, quoted_price_per_unit)
+            WHERE id = $1
+            RETURNING *
+        "#;
+
+        let energy_amount = match energy_amount {
+            Some(energy_amount) => Some(self.market_config.normalize_energy_amount(energy_amount).map_err(DatabaseError::Validation)?),
+            None => None,
+        };
+        if let Some(price) = price_per_unit {
+            self.market_config.validate_price(price).map_err(DatabaseError::Validation)?;
+        }
+
+        let quoted_price = price_per_unit;
+        let price_per_unit = match quoted_price {
+            Some(price) => {
+                let order = self.get_order(id).await?;
+                Some(self.normalize_price(&order.currency, price)?)
+            }
+            None => None,
+        };
+        
+        // The buy-order escrow follows any change to amount or price
+        match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1 FOR UPDATE")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from)
+                    .ok_or_else(|| DatabaseError::NotFound(format!("Order '{}' not found", id)))?;
+                check_amendable(&before, energy_amount)?;
+                let after = Order::from(
+                    sqlx::query_as::<_, OrderRow>(query)
+                        .bind(id)
+                        .bind(energy_amount)
+                        .bind(price_per_unit)
+                        .bind(self.now())
+                        .bind(quoted_price)
+                        .fetch_one(&mut *tx)
+                        .await?,
+                );
+                self.adjust_escrow_postgres(&mut tx, &after.prosumer_address, buy_reservation(&after) - buy_reservation(&before)).await?;
+                tx.commit().await?;
+                Ok(after)
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from)
+                    .ok_or_else(|| DatabaseError::NotFound(format!("Order '{}' not found", id)))?;
+                check_amendable(&before, energy_amount)?;
+                let row = if self.sqlite_returning {
+                    sqlx::query_as::<_, OrderRow>(query)
+                        .bind(id)
+                        .bind(energy_amount)
+                        .bind(price_per_unit)
+                        .bind(self.now())
+                        .bind(quoted_price)
+                        .fetch_one(&mut *tx)
+                        .await?
+                } else {
+                    sqlx::query(&without_returning(query))
+                        .bind(id)
+                        .bind(energy_amount)
+                        .bind(price_per_unit)
+                        .bind(self.now())
+                        .bind(quoted_price)
+                        .execute(&mut *tx)
+                        .await?;
+                    sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                        .bind(id)
+                        .fetch_one(&mut *tx)
+                        .await?
+                };
+                let after = Order::from(row);
+                self.adjust_escrow_sqlite(&mut tx, &after.prosumer_address, buy_reservation(&after) - buy_reservation(&before)).await?;
+                tx.commit().await?;
+                Ok(after)
+            }
+        }
+    }
+
+    // Admin override: cancels any open order regardless of owner and records who did it and why
+    pub async fn force_cancel_order(&self, id: Uuid, admin_id: &str, reason: &str) -> Result<Order, DatabaseError> {
+        let _timer = self.time_query("force_cancel_order");
+        let query = r#"
+            UPDATE orders
+            SET status = 'cancelled',
+                updated_at = $2
+            WHERE id = $1 AND status IN ('pending', 'active')
+            RETURNING *
+        "#;
+        let audit_query = r#"
+            INSERT INTO admin_audit_log (id, admin_id, action, target_id, reason, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+        "#;
+
+        let row = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1 FOR UPDATE")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from);
+                let row = sqlx::query_as::<_, OrderRow>(query)
+                    .bind(id)
+                    .bind(self.now())
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                if let (Some(_), Some(before)) = (&row, &before) {
+                    self.adjust_escrow_postgres(&mut tx, &before.prosumer_address, -buy_reservation(before)).await?;
+                }
+                if row.is_some() {
+                    sqlx::query(audit_query)
+                        .bind(Uuid::new_v4())
+                        .bind(admin_id)
+                        .bind("force_cancel_order")
+                        .bind(id.to_string())
+                        .bind(reason)
+                        .bind(self.now())
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+                row
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from);
+                let row = sqlx::query_as::<_, OrderRow>(query)
+                    .bind(id)
+                    .bind(self.now())
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                if let (Some(_), Some(before)) = (&row, &before) {
+                    self.adjust_escrow_sqlite(&mut tx, &before.prosumer_address, -buy_reservation(before)).await?;
+                }
+                if row.is_some() {
+                    sqlx::query(audit_query)
+                        .bind(Uuid::new_v4())
+                        .bind(admin_id)
+                        .bind("force_cancel_order")
+                        .bind(id.to_string())
+                        .bind(reason)
+                        .bind(self.now())
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+                row
+            }
+        };
+
+        match row {
+            Some(row) => Ok(row.into()),
+            None if self.order_exists(id).await? => {
+                Err(DatabaseError::Conflict(format!("Order '{}' is already closed", id)))
+            }
+            None => Err(DatabaseError::NotFound(format!("Order '{}' not found", id))),
+        }
+    }
+
+    pub async fn cancel_order(&self, id: Uuid) -> Result<Order, DatabaseError> {
+        let _timer = self.time_query("cancel_order");
+        let query = r#"
+            UPDATE orders 
+            SET status = 'cancelled',
+                updated_at = $2
+            WHERE id = $1 AND status IN ('pending', 'active')
+            RETURNING *
+        "#;
+        
+        // Cancelling a buy order returns its escrowed tokens. Completed, expired and already
+        // cancelled orders stay as they are.
+        match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1 FOR UPDATE")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from)
+                    .ok_or_else(|| DatabaseError::NotFound(format!("Order '{}' not found", id)))?;
+                let row = sqlx::query_as::<_, OrderRow>(query)
+                    .bind(id)
+                    .bind(self.now())
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .ok_or_else(|| DatabaseError::Conflict(format!("Order '{}' is {}; only open orders can be cancelled", id, before.status)))?;
+                self.adjust_escrow_postgres(&mut tx, &before.prosumer_address, -buy_reservation(&before)).await?;
+                tx.commit().await?;
+                Ok(row.into())
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from)
+                    .ok_or_else(|| DatabaseError::NotFound(format!("Order '{}' not found", id)))?;
+                let row = sqlx::query_as::<_, OrderRow>(query)
+                    .bind(id)
+                    .bind(self.now())
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .ok_or_else(|| DatabaseError::Conflict(format!("Order '{}' is {}; only open orders can be cancelled", id, before.status)))?;
+                self.adjust_escrow_sqlite(&mut tx, &before.prosumer_address, -buy_reservation(&before)).await?;
+                tx.commit().await?;
+                Ok(row.into())
+            }
+        }
+    }
+
+    // Puts a cancelled order back on the book under the same id. It gets a fresh created_at, so
+    // it queues behind orders placed in the meantime, and goes through the same prosumer,
+    // energy backing and escrow checks as a new order.
+    pub async fn reactivate_order(&self, id: Uuid) -> Result<Order, DatabaseError> {
+        let _timer = self.time_query("reactivate_order");
+        self.check_market_open().await?;
+        let query = r#"
+            UPDATE orders
+            SET status = 'active',
+                created_at = $2,
+                updated_at = $2
+            WHERE id = $1 AND status = 'cancelled'
+            RETURNING *
+        "#;
+
+        match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1 FOR UPDATE")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from)
+                    .ok_or_else(|| DatabaseError::NotFound(format!("Order '{}' not found", id)))?;
+                let now = self.now();
+                check_reactivatable(&before, now)?;
+                let is_active: bool = sqlx::query_scalar("SELECT is_active FROM prosumers WHERE address = $1")
+                    .bind(&before.prosumer_address)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .unwrap_or(false);
+                if !is_active {
+                    return Err(DatabaseError::Conflict(format!("Prosumer '{}' is not active", before.prosumer_address)));
+                }
+                let open_orders = sqlx::query_as(&format!("{} FOR UPDATE OF p", OPEN_ORDERS_QUERY))
+                    .bind(&before.prosumer_address)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                self.check_open_order_limit(&before.prosumer_address, open_orders)?;
+                if self.market_config.enforce_energy_backing && before.order_type == "sell" {
+                    let (net_energy, committed): (f64, f64) = sqlx::query_as(ENERGY_BACKING_QUERY)
+                        .bind(&before.prosumer_address)
+                        .fetch_one(&mut *tx)
+                        .await?;
+                    check_energy_backing(before.remaining_amount, net_energy, committed)?;
+                }
+                let order = Order::from(
+                    sqlx::query_as::<_, OrderRow>(query)
+                        .bind(id)
+                        .bind(now)
+                        .fetch_one(&mut *tx)
+                        .await?,
+                );
+                self.adjust_escrow_postgres(&mut tx, &order.prosumer_address, buy_reservation(&order)).await?;
+                tx.commit().await?;
+                Ok(order)
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from)
+                    .ok_or_else(|| DatabaseError::NotFound(format!("Order '{}' not found", id)))?;
+                let now = self.now();
+                check_reactivatable(&before, now)?;
+                let is_active: bool = sqlx::query_scalar("SELECT is_active FROM prosumers WHERE address = $1")
+                    .bind(&before.prosumer_address)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .unwrap_or(false);
+                if !is_active {
+                    return Err(DatabaseError::Conflict(format!("Prosumer '{}' is not active", before.prosumer_address)));
+                }
+                let open_orders = sqlx::query_as(OPEN_ORDERS_QUERY)
+                    .bind(&before.prosumer_address)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                self.check_open_order_limit(&before.prosumer_address, open_orders)?;
+                if self.market_config.enforce_energy_backing && before.order_type == "sell" {
+                    let (net_energy, committed): (f64, f64) = sqlx::query_as(ENERGY_BACKING_QUERY)
+                        .bind(&before.prosumer_address)
+                        .fetch_one(&mut *tx)
+                        .await?;
+                    check_energy_backing(before.remaining_amount, net_energy, committed)?;
+                }
+                let order = Order::from(
+                    sqlx::query_as::<_, OrderRow>(query)
+                        .bind(id)
+                        .bind(now)
+                        .fetch_one(&mut *tx)
+                        .await?,
+                );
+                self.adjust_escrow_sqlite(&mut tx, &order.prosumer_address, buy_reservation(&order)).await?;
+                tx.commit().await?;
+                Ok(order)
+            }
+        }
+    }
+
+    // Expires up to `limit` open orders whose expires_at has passed, earliest first. Each
+    // order expires in its own transaction and gives back what a buy order still held in
+    // escrow; one that fails is logged and retried on the next run.
+    pub async fn expire_orders(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<Order>, DatabaseError> {
+        let _timer = self.time_query("expire_orders");
+        let query = "SELECT id FROM orders WHERE status IN ('pending', 'active') AND expires_at <= $1 ORDER BY expires_at LIMIT $2";
+        let due: Vec<Uuid> = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_scalar(query).bind(now).bind(limit).fetch_all(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_scalar(query).bind(now).bind(limit).fetch_all(pool).await?,
+        };
+
+        let mut expired = Vec::with_capacity(due.len());
+        for id in due {
+            match self.expire_order(id, now).await {
+                Ok(Some(order)) => expired.push(order),
+                Ok(None) => {}
+                Err(e) => log::error!("Expiry of order {} failed: {}", id, e),
+            }
+        }
+        Ok(expired)
+    }
+
+    // Returns None if the order was filled or cancelled in the meantime
+    async fn expire_order(&self, id: Uuid, now: DateTime<Utc>) -> Result<Option<Order>, DatabaseError> {
+        let query = r#"
+            UPDATE orders
+            SET status = 'expired',
+                updated_at = $2
+            WHERE id = $1 AND status IN ('pending', 'active') AND expires_at <= $2
             RETURNING *
         "#;
-        
-        match &self.pool {
+
+        match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let Some(before) = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1 FOR UPDATE")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from)
+                else {
+                    return Ok(None);
+                };
+                let Some(row) = sqlx::query_as::<_, OrderRow>(query).bind(id).bind(now).fetch_optional(&mut *tx).await? else {
+                    return Ok(None);
+                };
+                self.adjust_escrow_postgres(&mut tx, &before.prosumer_address, -buy_reservation(&before)).await?;
+                tx.commit().await?;
+                Ok(Some(row.into()))
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let Some(before) = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Order::from)
+                else {
+                    return Ok(None);
+                };
+                let Some(row) = sqlx::query_as::<_, OrderRow>(query).bind(id).bind(now).fetch_optional(&mut *tx).await? else {
+                    return Ok(None);
+                };
+                self.adjust_escrow_sqlite(&mut tx, &before.prosumer_address, -buy_reservation(&before)).await?;
+                tx.commit().await?;
+                Ok(Some(row.into()))
+            }
+        }
+    }
+
+    // Settles up to `limit` pending trades whose settles_at has passed, earliest first. Each
+    // trade settles in its own transaction; one that fails is logged and retried on the next run.
+    pub async fn settle_due_trades(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<Trade>, DatabaseError> {
+        let _timer = self.time_query("settle_due_trades");
+        let query = "SELECT id FROM trades WHERE status = 'pending' AND settles_at <= $1 ORDER BY settles_at LIMIT $2";
+        let due: Vec<Uuid> = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_scalar(query).bind(now).bind(limit).fetch_all(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_scalar(query).bind(now).bind(limit).fetch_all(pool).await?,
+        };
+
+        let mut settled = Vec::with_capacity(due.len());
+        for id in due {
+            match self.settle_trade(id, "pending").await {
+                Ok(Some(trade)) => settled.push(trade),
+                Ok(None) => {}
+                Err(e) => log::error!("Settlement of trade {} failed: {}", id, e),
+            }
+        }
+        Ok(settled)
+    }
+
+    // Admin resolution of a dispute in the seller's favour: pays the held payment out now
+    pub async fn settle_disputed_trade(&self, id: Uuid) -> Result<Trade, DatabaseError> {
+        let _timer = self.time_query("settle_disputed_trade");
+        match self.settle_trade(id, "disputed").await? {
+            Some(trade) => Ok(trade),
+            None => {
+                let trade = self.get_trade(id).await?;
+                Err(DatabaseError::Conflict(format!(
+                    "Trade '{}' is {}; only disputed trades can be settled",
+                    trade.id, trade.status
+                )))
+            }
+        }
+    }
+
+    // Completes a delayed trade currently in `from_status`. Returns None if it has moved on in
+    // the meantime (disputed, or settled by someone else).
+    async fn settle_trade(&self, id: Uuid, from_status: &str) -> Result<Option<Trade>, DatabaseError> {
+        let query = "UPDATE trades SET status = 'completed' WHERE id = $1 AND status = $2 RETURNING *";
+
+        match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let Some(row) = sqlx::query_as::<_, TradeRow>(query).bind(id).bind(from_status).fetch_optional(&mut *tx).await? else {
+                    return Ok(None);
+                };
+                let trade = Trade::from(row);
+                self.release_settlement_postgres(&mut tx, &trade).await?;
+                tx.commit().await?;
+                self.invalidate_market_stats();
+                Ok(Some(trade))
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let Some(row) = sqlx::query_as::<_, TradeRow>(query).bind(id).bind(from_status).fetch_optional(&mut *tx).await? else {
+                    return Ok(None);
+                };
+                let trade = Trade::from(row);
+                self.release_settlement_sqlite(&mut tx, &trade).await?;
+                tx.commit().await?;
+                self.invalidate_market_stats();
+                Ok(Some(trade))
+            }
+        }
+    }
+
+    // Holds a pending trade's payment for review. Only the buyer or seller can dispute, and
+    // only before the trade's settles_at.
+    pub async fn dispute_trade(&self, id: Uuid, address: &str, now: DateTime<Utc>) -> Result<Trade, DatabaseError> {
+        let _timer = self.time_query("dispute_trade");
+        let query = "UPDATE trades SET status = 'disputed' WHERE id = $1 AND status = 'pending' AND settles_at > $2 RETURNING *";
+
+        let row = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, TradeRow>("SELECT * FROM trades WHERE id = $1 FOR UPDATE")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Trade::from)
+                    .ok_or_else(|| DatabaseError::NotFound(format!("Trade '{}' not found", id)))?;
+                check_disputable(&before, address, now)?;
+                let row = sqlx::query_as::<_, TradeRow>(query).bind(id).bind(now).fetch_one(&mut *tx).await?;
+                tx.commit().await?;
+                row
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let before = sqlx::query_as::<_, TradeRow>("SELECT * FROM trades WHERE id = $1")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(Trade::from)
+                    .ok_or_else(|| DatabaseError::NotFound(format!("Trade '{}' not found", id)))?;
+                check_disputable(&before, address, now)?;
+                let row = sqlx::query_as::<_, TradeRow>(query).bind(id).bind(now).fetch_one(&mut *tx).await?;
+                tx.commit().await?;
+                row
+            }
+        };
+        Ok(row.into())
+    }
+
+    pub async fn create_trade(&self, trade: Trade) -> Result<Trade, DatabaseError> {
+        let _timer = self.time_query("create_trade");
+        let query = INSERT_TRADE_QUERY;
+        
+        match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let row = sqlx::query_as::<_, TradeRow>(query)
+                    .bind(trade.id)
+                    .bind(trade.buy_order_id)
+                    .bind(trade.sell_order_id)
+                    .bind(&trade.buyer_address)
+                    .bind(&trade.seller_address)
+                    .bind(trade.energy_amount)
+                    .bind(trade.price_per_unit)
+                    .bind(trade.total_price)
+                    .bind(&trade.status)
+                    .bind(trade.executed_at)
+                    .bind(trade.created_at)
+                    .bind(trade.grid_fee)
+                    .bind(trade.settles_at)
+                    .fetch_one(pool)
+                    .await?;
+                Ok(row.into())
+            }
+            DatabasePool::Sqlite(pool) => {
+                let row = sqlx::query_as::<_, TradeRow>(query)
+                    .bind(trade.id)
+                    .bind(trade.buy_order_id)
+                    .bind(trade.sell_order_id)
+                    .bind(&trade.buyer_address)
+                    .bind(&trade.seller_address)
+                    .bind(trade.energy_amount)
+                    .bind(trade.price_per_unit)
+                    .bind(trade.total_price)
+                    .bind(&trade.status)
+                    .bind(trade.executed_at)
+                    .bind(trade.created_at)
+                    .bind(trade.grid_fee)
+                    .bind(trade.settles_at)
+                    .fetch_one(pool)
+                    .await?;
+                Ok(row.into())
+            }
+        }
+    }
+
+    pub async fn get_trade(&self, id: Uuid) -> Result<Trade, DatabaseError> {
+        let _timer = self.time_query("get_trade");
+        let query = "SELECT * FROM trades WHERE id = $1";
+        
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let row = sqlx::query_as::<_, TradeRow>(query)
+                    .bind(id)
+                    .fetch_optional(pool)
+                    .await?;
+                match row {
+                    Some(row) => Ok(row.into()),
+                    None => Err(DatabaseError::NotFound(format!("Trade '{}' not found", id))),
+                }
+            }
+            DatabasePool::Sqlite(pool) => {
+                let row = sqlx::query_as::<_, TradeRow>(query)
+                    .bind(id)
+                    .fetch_optional(pool)
+                    .await?;
+                match row {
+                    Some(row) => Ok(row.into()),
+                    None => Err(DatabaseError::NotFound(format!("Trade '{}' not found", id))),
+                }
+            }
+        }
+    }
+
+    // Trades newest first, optionally limited to `from <= created_at < to`. Archived trades
+    // are searched whenever the range reaches back to the newest archived trade, which
+    // includes any range without a `from`.
+    pub async fn get_trades(&self, page: u32, limit: u32, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>, sort: SortOrder) -> Result<Vec<Trade>, DatabaseError> {
+        let _timer = self.time_query("get_trades");
+        let offset = page.saturating_sub(1).saturating_mul(limit);
+
+        let include_archive = range_reaches_archive(from, self.latest_archived_trade().await?);
+
+        let mut filter = String::new();
+        let mut bind_count = 1;
+        if from.is_some() {
+            filter.push_str(&format!(" AND created_at >= ${}", bind_count));
+            bind_count += 1;
+        }
+        if to.is_some() {
+            filter.push_str(&format!(" AND created_at < ${}", bind_count));
+            bind_count += 1;
+        }
+        let source = if include_archive {
+            format!(
+                "(SELECT * FROM trades WHERE 1=1{0} UNION ALL SELECT * FROM trades_archive WHERE 1=1{0}) t",
+                filter
+            )
+        } else {
+            format!("(SELECT * FROM trades WHERE 1=1{}) t", filter)
+        };
+        let query = format!(
+            "SELECT * FROM {} ORDER BY {} LIMIT ${} OFFSET ${}",
+            source,
+            sort.to_sql(""),
+            bind_count,
+            bind_count + 1
+        );
+
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut query = sqlx::query_as::<_, TradeRow>(&query);
+                if let Some(from) = from {
+                    query = query.bind(from);
+                }
+                if let Some(to) = to {
+                    query = query.bind(to);
+                }
+                let rows = query
+                    .bind(limit as i64)
+                    .bind(offset as i64)
+                    .fetch_all(pool)
+                    .await?;
+                Ok(rows.into_iter().map(|row| row.into()).collect())
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut query = sqlx::query_as::<_, TradeRow>(&query);
+                if let Some(from) = from {
+                    query = query.bind(from);
+                }
+                if let Some(to) = to {
+                    query = query.bind(to);
+                }
+                let rows = query
+                    .bind(limit as i64)
+                    .bind(offset as i64)
+                    .fetch_all(pool)
+                    .await?;
+                Ok(rows.into_iter().map(|row| row.into()).collect())
+            }
+        }
+    }
+
+    // Creation time of the newest archived trade, if anything has been archived
+    async fn latest_archived_trade(&self) -> Result<Option<DateTime<Utc>>, DatabaseError> {
+        let query = "SELECT MAX(created_at) FROM trades_archive";
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => Ok(sqlx::query_scalar(query).fetch_one(pool).await?),
+            DatabasePool::Sqlite(pool) => Ok(sqlx::query_scalar(query).fetch_one(pool).await?),
+        }
+    }
+
+    // Moves trades created before `cutoff` into trades_archive in one transaction and
+    // returns how many were moved
+    pub async fn archive_trades(&self, cutoff: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        let _timer = self.time_query("archive_trades");
+        let copy_query = "INSERT INTO trades_archive SELECT * FROM trades WHERE created_at < $1";
+        let delete_query = "DELETE FROM trades WHERE created_at < $1";
+
+        match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let copied = sqlx::query(copy_query).bind(cutoff).execute(&mut *tx).await?.rows_affected();
+                let deleted = sqlx::query(delete_query).bind(cutoff).execute(&mut *tx).await?.rows_affected();
+                if copied != deleted {
+                    return Err(DatabaseError::Validation(format!(
+                        "Archived {} trades but removed {}; rolled back",
+                        copied, deleted
+                    )));
+                }
+                tx.commit().await?;
+                Ok(deleted)
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let copied = sqlx::query(copy_query).bind(cutoff).execute(&mut *tx).await?.rows_affected();
+                let deleted = sqlx::query(delete_query).bind(cutoff).execute(&mut *tx).await?.rows_affected();
+                if copied != deleted {
+                    return Err(DatabaseError::Validation(format!(
+                        "Archived {} trades but removed {}; rolled back",
+                        copied, deleted
+                    )));
+                }
+                tx.commit().await?;
+                Ok(deleted)
+            }
+        }
+    }
+
+    pub async fn get_prosumer_balance(&self, address: &str) -> Result<TokenBalance, DatabaseError> {
+        let _timer = self.time_query("get_prosumer_balance");
+        let prosumer = match self.get_prosumer(address).await {
+            Ok(prosumer) => prosumer,
+            Err(DatabaseError::NotFound(_)) => return Err(prosumer_not_found(address)),
+            Err(e) => return Err(e),
+        };
+
+        let query = "SELECT address, token_type, amount, reserved_balance FROM balances WHERE address = $1 AND (amount > 0 OR reserved_balance > 0)";
+        let rows: Vec<BalanceRow> = match self.read_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_as(query).bind(address).fetch_all(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_as(query).bind(address).fetch_all(pool).await?,
+        };
+
+        let mut balance = TokenBalance {
+            address: prosumer.address,
+            grid_tokens: prosumer.grid_tokens,
+            watt_tokens: prosumer.watt_tokens,
+            staked: 0.0,
+            reserved_grid_tokens: 0.0,
+            tokens: BTreeMap::new(),
+        };
+        add_balance_rows(&mut balance, rows);
+        Ok(balance)
+    }
+
+    // Balances for many prosumers in one query; unknown addresses are listed in `not_found`
+    pub async fn get_prosumer_balances(&self, addresses: &[String]) -> Result<BulkBalances, DatabaseError> {
+        let _timer = self.time_query("get_prosumer_balances");
+        if addresses.len() > MAX_BULK_BALANCE_ADDRESSES {
+            return Err(DatabaseError::Validation(format!(
+                "At most {} addresses may be queried at once",
+                MAX_BULK_BALANCE_ADDRESSES
+            )));
+        }
+        if addresses.is_empty() {
+            return Ok(BulkBalances { balances: Vec::new(), not_found: Vec::new() });
+        }
+
+        let balances_query = "SELECT address, token_type, amount, reserved_balance FROM balances WHERE (amount > 0 OR reserved_balance > 0) AND address";
+        let (rows, token_rows): (Vec<ProsumerRow>, Vec<BalanceRow>) = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let rows = sqlx::query_as::<_, ProsumerRow>(&format!("{} WHERE p.address = ANY($1)", PROSUMER_SELECT))
+                    .bind(addresses)
+                    .fetch_all(pool)
+                    .await?;
+                let token_rows = sqlx::query_as(&format!("{} = ANY($1)", balances_query))
+                    .bind(addresses)
+                    .fetch_all(pool)
+                    .await?;
+                (rows, token_rows)
+            }
+            DatabasePool::Sqlite(pool) => {
+                let placeholders = (1..=addresses.len()).map(|i| format!("${}", i)).collect::<Vec<_>>().join(", ");
+                let query = format!("{} WHERE p.address IN ({})", PROSUMER_SELECT, placeholders);
+                let mut query = sqlx::query_as::<_, ProsumerRow>(&query);
+                for address in addresses {
+                    query = query.bind(address);
+                }
+                let rows = query.fetch_all(pool).await?;
+
+                let token_query = format!("{} IN ({})", balances_query, placeholders);
+                let mut token_query = sqlx::query_as(&token_query);
+                for address in addresses {
+                    token_query = token_query.bind(address);
+                }
+                (rows, token_query.fetch_all(pool).await?)
+            }
+        };
+
+        let mut tokens: HashMap<String, Vec<BalanceRow>> = HashMap::new();
+        for row in token_rows {
+            tokens.entry(row.address.clone()).or_default().push(row);
+        }
+
+        // Preserve the caller's ordering
+        let mut found: HashMap<String, ProsumerRow> = rows.into_iter().map(|row| (row.address.clone(), row)).collect();
+        let mut result = BulkBalances { balances: Vec::new(), not_found: Vec::new() };
+        for address in addresses {
+            match found.remove(address) {
+                Some(row) => {
+                    let token_rows = tokens.remove(&row.address).unwrap_or_default();
+                    let mut balance = TokenBalance {
+                        address: row.address,
+                        grid_tokens: row.grid_tokens,
+                        watt_tokens: row.watt_tokens,
+                        staked: 0.0,
+                        reserved_grid_tokens: 0.0,
+                        tokens: BTreeMap::new(),
+                    };
+                    add_balance_rows(&mut balance, token_rows);
+                    result.balances.push(balance);
+                }
+                None => result.not_found.push(address.clone()),
+            }
+        }
+        Ok(result)
+    }
+
+    // Trades where the prosumer was either the buyer or the seller, newest first
+    pub async fn get_prosumer_trades(&self, address: &str, page: u32, limit: u32) -> Result<Vec<ProsumerTrade>, DatabaseError> {
+        let _timer = self.time_query("get_prosumer_trades");
+        if !self.prosumer_exists(address).await? {
+            return Err(prosumer_not_found(address));
+        }
+
+        let offset = page.saturating_sub(1).saturating_mul(limit);
+        let query = "SELECT * FROM trades WHERE buyer_address = $1 OR seller_address = $1 ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3";
+
+        let rows = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query_as::<_, TradeRow>(query)
+                    .bind(address)
+                    .bind(limit as i64)
+                    .bind(offset as i64)
+                    .fetch_all(pool)
+                    .await?
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query_as::<_, TradeRow>(query)
+                    .bind(address)
+                    .bind(limit as i64)
+                    .bind(offset as i64)
+                    .fetch_all(pool)
+                    .await?
+            }
+        };
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let trade: Trade = row.into();
+                let role = if trade.buyer_address == address { "buyer" } else { "seller" };
+                ProsumerTrade { trade, role: role.to_string() }
+            })
+            .collect())
+    }
+
+    // Fills a buy order against a sell order for the largest amount both still have open.
+    // The trade and both order fills are written in one transaction; an order stays active
+    // until it is fully filled.
+    pub async fn execute_trade(&self, buy_order_id: Uuid, sell_order_id: Uuid, price_per_unit: Option<f64>) -> Result<Trade, DatabaseError> {
+        self.execute_fill(buy_order_id, sell_order_id, price_per_unit, None).await
+    }
+
+    // Executes a fill proposed by the matching engine for exactly its amount and price
+    pub async fn execute_proposed_trade(&self, proposal: &ProposedTrade) -> Result<Trade, DatabaseError> {
+        self.execute_fill(proposal.buy_order_id, proposal.sell_order_id, Some(proposal.price_per_unit), Some(proposal.energy_amount)).await
+    }
+
+    async fn execute_fill(&self, buy_order_id: Uuid, sell_order_id: Uuid, price_per_unit: Option<f64>, energy_amount: Option<f64>) -> Result<Trade, DatabaseError> {
+        let _timer = self.time_query("execute_fill");
+        self.check_market_open().await?;
+        let trade = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let trade = self.execute_trade_postgres(&mut tx, buy_order_id, sell_order_id, price_per_unit, energy_amount).await?;
+                tx.commit().await?;
+                trade
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let trade = self.execute_trade_sqlite(&mut tx, buy_order_id, sell_order_id, price_per_unit, energy_amount).await?;
+                tx.commit().await?;
+                trade
+            }
+        };
+        telemetry::record_trade_executed(trade.energy_amount);
+        self.invalidate_market_stats();
+        Ok(trade)
+    }
+
+    async fn execute_trade_postgres(&self, tx: &mut Transaction<'_, Postgres>, buy_order_id: Uuid, sell_order_id: Uuid, price_per_unit: Option<f64>, energy_amount: Option<f64>) -> Result<Trade, DatabaseError> {
+        // Both orders are locked in id order, so concurrent fills of the same order queue here
+        // instead of validating against a stale row, and two fills can't deadlock on the pair
+        let mut ids = [buy_order_id, sell_order_id];
+        ids.sort();
+        let mut locked = HashMap::with_capacity(2);
+        for id in ids {
+            let row = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1 FOR UPDATE")
+                .bind(id)
+                .fetch_optional(&mut **tx)
+                .await?
+                .ok_or_else(|| DatabaseError::NotFound(format!("Order '{}' not found", id)))?;
+            locked.insert(id, Order::from(row));
+        }
+
+        let orders = [&locked[&buy_order_id], &locked[&sell_order_id]];
+
+        let trade = build_fill(orders[0], orders[1], price_per_unit, energy_amount, &self.market_config, self.now())?;
+        self.record_daily_usage_postgres(tx, &trade).await?;
+        let row = sqlx::query_as::<_, TradeRow>(INSERT_TRADE_QUERY)
+            .bind(trade.id)
+            .bind(trade.buy_order_id)
+            .bind(trade.sell_order_id)
+            .bind(&trade.buyer_address)
+            .bind(&trade.seller_address)
+            .bind(trade.energy_amount)
+            .bind(trade.price_per_unit)
+            .bind(trade.total_price)
+            .bind(&trade.status)
+            .bind(trade.executed_at)
+            .bind(trade.created_at)
+            .bind(trade.grid_fee)
+            .bind(trade.settles_at)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        for id in [buy_order_id, sell_order_id] {
+            let filled = sqlx::query(FILL_ORDER_QUERY)
+                .bind(trade.energy_amount)
+                .bind(self.now())
+                .bind(id)
+                .execute(&mut **tx)
+                .await?
+                .rows_affected();
+            check_fill_applied(id, filled)?;
+        }
+
+        let filled_buy = Order::from(
+            sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                .bind(buy_order_id)
+                .fetch_one(&mut **tx)
+                .await?,
+        );
+        let freed = buy_reservation(orders[0]) - buy_reservation(&filled_buy);
+        self.settle_fill_postgres(tx, &trade, freed).await?;
+
+        Ok(row.into())
+    }
+
+    async fn execute_trade_sqlite(&self, tx: &mut Transaction<'_, Sqlite>, buy_order_id: Uuid, sell_order_id: Uuid, price_per_unit: Option<f64>, energy_amount: Option<f64>) -> Result<Trade, DatabaseError> {
+        let mut orders = Vec::with_capacity(2);
+        for id in [buy_order_id, sell_order_id] {
+            let row = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&mut **tx)
+                .await?
+                .ok_or_else(|| DatabaseError::NotFound(format!("Order '{}' not found", id)))?;
+            orders.push(Order::from(row));
+        }
+
+        let trade = build_fill(&orders[0], &orders[1], price_per_unit, energy_amount, &self.market_config, self.now())?;
+        self.record_daily_usage_sqlite(tx, &trade).await?;
+        let row = sqlx::query_as::<_, TradeRow>(INSERT_TRADE_QUERY)
+            .bind(trade.id)
+            .bind(trade.buy_order_id)
+            .bind(trade.sell_order_id)
+            .bind(&trade.buyer_address)
+            .bind(&trade.seller_address)
+            .bind(trade.energy_amount)
+            .bind(trade.price_per_unit)
+            .bind(trade.total_price)
+            .bind(&trade.status)
+            .bind(trade.executed_at)
+            .bind(trade.created_at)
+            .bind(trade.grid_fee)
+            .bind(trade.settles_at)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        for id in [buy_order_id, sell_order_id] {
+            let filled = sqlx::query(FILL_ORDER_QUERY)
+                .bind(trade.energy_amount)
+                .bind(self.now())
+                .bind(id)
+                .execute(&mut **tx)
+                .await?
+                .rows_affected();
+            check_fill_applied(id, filled)?;
+        }
+
+        let filled_buy = Order::from(
+            sqlx::query_as::<_, OrderRow>("SELECT * FROM orders WHERE id = $1")
+                .bind(buy_order_id)
+                .fetch_one(&mut **tx)
+                .await?,
+        );
+        let freed = buy_reservation(&orders[0]) - buy_reservation(&filled_buy);
+        self.settle_fill_sqlite(tx, &trade, freed).await?;
+
+        Ok(row.into())
+    }
+
+    // All trades the order took part in, on either side, oldest first; empty for an order that
+    // hasn't traded, NotFound for one that doesn't exist
+    pub async fn get_trades_for_order(&self, id: Uuid) -> Result<Vec<Trade>, DatabaseError> {
+        let _timer = self.time_query("get_trades_for_order");
+        if !self.order_exists(id).await? {
+            return Err(DatabaseError::NotFound(format!("Order '{}' not found", id)));
+        }
+        self.fetch_order_trades(id).await
+    }
+
+    // Execution report: the trades that filled an order, oldest first
+    pub async fn get_order_fills(&self, id: Uuid) -> Result<OrderFills, DatabaseError> {
+        let _timer = self.time_query("get_order_fills");
+        let order = self.get_order(id).await?;
+        let fills = self.fetch_order_trades(id).await?;
+
+        Ok(OrderFills {
+            order_id: order.id,
+            energy_amount: order.energy_amount,
+            filled_amount: order.filled_amount,
+            remaining_amount: order.remaining_amount,
+            fills,
+        })
+    }
+
+    async fn fetch_order_trades(&self, id: Uuid) -> Result<Vec<Trade>, DatabaseError> {
+        let rows = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query_as::<_, TradeRow>(ORDER_TRADES_QUERY)
+                    .bind(id)
+                    .fetch_all(pool)
+                    .await?
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query_as::<_, TradeRow>(ORDER_TRADES_QUERY)
+                    .bind(id)
+                    .fetch_all(pool)
+                    .await?
+            }
+        };
+
+        Ok(rows.into_iter().map(|row| row.into()).collect())
+    }
+
+    // With tag filters, every figure covers only the matching prosumers: their orders, and
+    // trades where they are the buyer or the seller
+    // Served from memory for up to `stats_cache_secs` after it was computed; executed trades,
+    // settlements and market halts drop the cached figures straight away.
+    pub async fn get_market_stats(&self, tags: &[TagFilter]) -> Result<MarketStats, DatabaseError> {
+        let now = self.now();
+        let max_age = chrono::Duration::seconds(self.stats_cache_secs.min(i64::MAX as u64) as i64);
+        if self.stats_cache_secs > 0 {
+            let cache = self.market_stats_cache.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((computed_at, stats)) = cache.get(tags) {
+                if now - *computed_at < max_age {
+                    return Ok(stats.clone());
+                }
+            }
+        }
+
+        let stats = self.query_market_stats(tags).await?;
+        if self.stats_cache_secs > 0 {
+            let mut cache = self.market_stats_cache.lock().unwrap_or_else(|e| e.into_inner());
+            cache.retain(|_, (computed_at, _)| now - *computed_at < max_age);
+            cache.insert(tags.to_vec(), (now, stats.clone()));
+        }
+        Ok(stats)
+    }
+
+    async fn query_market_stats(&self, tags: &[TagFilter]) -> Result<MarketStats, DatabaseError> {
+        let _timer = self.time_query("get_market_stats");
+        let scope = format!("SELECT p.address FROM prosumers p WHERE {}", TagFilter::to_sql(tags, self.read_pool(), 1));
+        let order_scope = format!("prosumer_address IN ({})", scope);
+        let trade_scope = format!("(buyer_address IN ({0}) OR seller_address IN ({0}))", scope);
+        let query = &format!(r#"
+            SELECT 
+                (SELECT COUNT(*) FROM ({scope}) scoped) as total_prosumers,
+                (SELECT COUNT(*) FROM orders WHERE {order_scope}) as total_orders,
+                (SELECT COUNT(*) FROM trades WHERE {trade_scope}) as total_trades,
+                (SELECT COALESCE(SUM(energy_amount), 0.0) FROM trades WHERE status = 'completed' AND {trade_scope}) as total_energy_traded,
+                (SELECT COALESCE(SUM(total_price), 0.0) FROM trades WHERE status = 'completed' AND {trade_scope}) as total_volume,
+                (SELECT COALESCE(SUM(grid_fee), 0.0) FROM trades WHERE status = 'completed' AND {trade_scope}) as total_fees_collected,
+                (SELECT COALESCE(AVG(price_per_unit), 0.0) FROM trades WHERE status = 'completed' AND {trade_scope}) as average_price,
+                (SELECT COUNT(*) FROM orders WHERE status = 'active' AND order_type = 'buy' AND {order_scope}) as active_buy_orders,
+                (SELECT COUNT(*) FROM orders WHERE status = 'active' AND order_type = 'sell' AND {order_scope}) as active_sell_orders
+        "#);
+        let mut book = self.get_order_book().await?;
+        if !tags.is_empty() {
+            let addresses = self.get_tagged_addresses(tags).await?;
+            book.bids.retain(|order| addresses.contains(&order.prosumer_address));
+            book.asks.retain(|order| addresses.contains(&order.prosumer_address));
+        }
+        let imbalance = book.aggregate().imbalance();
+        let halt = self.get_market_halt().await?;
+        
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query(query);
+                for tag in tags {
+                    q = q.bind(&tag.key).bind(&tag.value);
+                }
+                let row = q.fetch_one(pool).await?;
+                let gross_volume = row.get::<f64, _>("total_volume");
+                let total_fees_collected = row.get::<f64, _>("total_fees_collected");
+                Ok(MarketStats {
+                    total_prosumers: row.get::<i64, _>("total_prosumers"),
+                    total_orders: row.get::<i64, _>("total_orders"),
+                    total_trades: row.get::<i64, _>("total_trades"),
+                    total_energy_traded: row.get::<f64, _>("total_energy_traded"),
+                    total_volume: gross_volume,
+                    gross_volume,
+                    net_volume: gross_volume - total_fees_collected,
+                    total_fees_collected,
+                    average_price: row.get::<f64, _>("average_price"),
+                    active_buy_orders: row.get::<i64, _>("active_buy_orders"),
+                    active_sell_orders: row.get::<i64, _>("active_sell_orders"),
+                    imbalance,
+                    min_order_energy: self.market_config.min_order_energy,
+                    price_tick: self.market_config.price_tick,
+                    fee_schedule: self.market_config.fee_schedule.clone(),
+                    match_price_policy: self.market_config.match_price_policy,
+                    energy_decimals: self.market_config.energy_decimals,
+                    daily_energy_limit: self.market_config.daily_energy_limit,
+                    daily_notional_limit: self.market_config.daily_notional_limit,
+                    halted: halt.halted,
+                    halt_reason: halt.reason.clone(),
+                })
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut q = sqlx::query(query);
+                for tag in tags {
+                    q = q.bind(&tag.key).bind(&tag.value);
+                }
+                let row = q.fetch_one(pool).await?;
+                let gross_volume = row.get::<f64, _>("total_volume");
+                let total_fees_collected = row.get::<f64, _>("total_fees_collected");
+                Ok(MarketStats {
+                    total_prosumers: row.get::<i64, _>("total_prosumers"),
+                    total_orders: row.get::<i64, _>("total_orders"),
+                    total_trades: row.get::<i64, _>("total_trades"),
+                    total_energy_traded: row.get::<f64, _>("total_energy_traded"),
+                    total_volume: gross_volume,
+                    gross_volume,
+                    net_volume: gross_volume - total_fees_collected,
+                    total_fees_collected,
+                    average_price: row.get::<f64, _>("average_price"),
+                    active_buy_orders: row.get::<i64, _>("active_buy_orders"),
+                    active_sell_orders: row.get::<i64, _>("active_sell_orders"),
+                    imbalance,
+                    min_order_energy: self.market_config.min_order_energy,
+                    price_tick: self.market_config.price_tick,
+                    fee_schedule: self.market_config.fee_schedule.clone(),
+                    match_price_policy: self.market_config.match_price_policy,
+                    energy_decimals: self.market_config.energy_decimals,
+                    daily_energy_limit: self.market_config.daily_energy_limit,
+                    daily_notional_limit: self.market_config.daily_notional_limit,
+                    halted: halt.halted,
+                    halt_reason: halt.reason.clone(),
+                })
+            }
+        }
+    }
+
+    // Per-bucket trade count, energy, volume, average price and open/high/low/close of completed
+    // trades in [from, to), aggregated by the database. Buckets without trades are included with
+    // zeros so charts have no gaps.
+    pub async fn get_market_timeseries(&self, interval_secs: i64, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<MarketTimeseriesPoint>, DatabaseError> {
+        let _timer = self.time_query("get_market_timeseries");
+        let bucket_count = timeseries_bucket_count(interval_secs, from, to).map_err(DatabaseError::Validation)?;
+        let first_bucket = bucket_start(from, interval_secs);
+
+        let rows: Vec<TimeseriesBucketRow> = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query_as(&TIMESERIES_AGGREGATE.replace("{bucket}", TIMESERIES_BUCKET_POSTGRES))
+                    .bind(from)
+                    .bind(to)
+                    .bind(interval_secs)
+                    .fetch_all(pool)
+                    .await?
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query_as(&TIMESERIES_AGGREGATE.replace("{bucket}", TIMESERIES_BUCKET_SQLITE))
+                    .bind(from)
+                    .bind(to)
+                    .bind(interval_secs)
+                    .fetch_all(pool)
+                    .await?
+            }
+        };
+
+        let mut points: Vec<MarketTimeseriesPoint> = (0..bucket_count)
+            .map(|i| MarketTimeseriesPoint {
+                bucket_start: first_bucket + chrono::Duration::seconds(i * interval_secs),
+                trade_count: 0,
+                energy_traded: 0.0,
+                volume: 0.0,
+                average_price: 0.0,
+                open: 0.0,
+                high: 0.0,
+                low: 0.0,
+                close: 0.0,
+            })
+            .collect();
+
+        for row in rows {
+            let index = (row.bucket * interval_secs - first_bucket.timestamp()) / interval_secs;
+            if let Some(point) = usize::try_from(index).ok().and_then(|index| points.get_mut(index)) {
+                point.trade_count = row.trade_count;
+                point.energy_traded = row.energy_traded;
+                point.volume = row.volume;
+                point.average_price = row.average_price;
+                point.open = row.open;
+                point.high = row.high;
+                point.low = row.low;
+                point.close = row.close;
+            }
+        }
+
+        Ok(points)
+    }
+
+    // Same activity subqueries as `get_prosumer_stats`, without the volume aggregates
+    pub async fn get_prosumer_with_counts(&self, address: &str) -> Result<ProsumerWithCounts, DatabaseError> {
+        let _timer = self.time_query("get_prosumer_with_counts");
+        let prosumer = self.get_prosumer(address).await?;
+        let query = r#"
+            SELECT
+                (SELECT COUNT(*) FROM orders WHERE prosumer_address = p.address) as orders_count,
+                (SELECT COUNT(*) FROM trades WHERE buyer_address = p.address OR seller_address = p.address) as trades_count
+            FROM prosumers p
+            WHERE p.address = $1
+        "#;
+
+        let (orders_count, trades_count) = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let row = sqlx::query(query).bind(address).fetch_one(pool).await?;
+                (row.get::<i64, _>("orders_count"), row.get::<i64, _>("trades_count"))
+            }
+            DatabasePool::Sqlite(pool) => {
+                let row = sqlx::query(query).bind(address).fetch_one(pool).await?;
+                (row.get::<i64, _>("orders_count"), row.get::<i64, _>("trades_count"))
+            }
+        };
+
+        Ok(ProsumerWithCounts { prosumer, orders_count, trades_count })
+    }
+
+    pub async fn get_prosumer_stats(&self, address: &str) -> Result<ProsumerStats, DatabaseError> {
+        let _timer = self.time_query("get_prosumer_stats");
+        let query = r#"
+            SELECT 
+                p.address,
+                p.name,
+                p.energy_generated,
+                p.energy_consumed,
+                (p.energy_generated - p.energy_consumed) as net_energy,
+                CAST(COALESCE((SELECT b.amount FROM balances b WHERE b.address = p.address AND b.token_type = 'grid_tokens'), 0) AS DOUBLE PRECISION) as grid_tokens,
+                CAST(COALESCE((SELECT b.amount FROM balances b WHERE b.address = p.address AND b.token_type = 'watt_tokens'), 0) AS DOUBLE PRECISION) as watt_tokens,
+                (SELECT COUNT(*) FROM orders WHERE prosumer_address = p.address) as orders_count,
+                (SELECT COUNT(*) FROM trades WHERE buyer_address = p.address OR seller_address = p.address) as trades_count,
+                (SELECT COALESCE(SUM(energy_amount), 0) FROM trades WHERE (buyer_address = p.address OR seller_address = p.address) AND status = 'completed') as total_energy_traded,
+                (SELECT COALESCE(SUM(total_price), 0) FROM trades WHERE (buyer_address = p.address OR seller_address = p.address) AND status = 'completed') as total_volume
+            FROM prosumers p
+            WHERE p.address = $1
+        "#;
+        
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let row = sqlx::query(query)
+                    .bind(address)
+                    .fetch_optional(pool)
+                    .await?;
+                match row {
+                    Some(row) => Ok(ProsumerStats {
+                        address: row.get::<String, _>("address"),
+                        name: row.get::<String, _>("name"),
+                        energy_generated: row.get::<f64, _>("energy_generated"),
+                        energy_consumed: row.get::<f64, _>("energy_consumed"),
+                        net_energy: row.get::<f64, _>("net_energy"),
+                        grid_tokens: row.get::<f64, _>("grid_tokens"),
+                        watt_tokens: row.get::<f64, _>("watt_tokens"),
+                        orders_count: row.get::<i64, _>("orders_count"),
+                        trades_count: row.get::<i64, _>("trades_count"),
+                        total_energy_traded: row.get::<f64, _>("total_energy_traded"),
+                        total_volume: row.get::<f64, _>("total_volume"),
+                    }),
+                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
+                }
+            }
+            DatabasePool::Sqlite(pool) => {
+                let row = sqlx::query(query)
+                    .bind(address)
+                    .fetch_optional(pool)
+                    .await?;
+                match row {
+                    Some(row) => Ok(ProsumerStats {
+                        address: row.get::<String, _>("address"),
+                        name: row.get::<String, _>("name"),
+                        energy_generated: row.get::<f64, _>("energy_generated"),
+                        energy_consumed: row.get::<f64, _>("energy_consumed"),
+                        net_energy: row.get::<f64, _>("net_energy"),
+                        grid_tokens: row.get::<f64, _>("grid_tokens"),
+                        watt_tokens: row.get::<f64, _>("watt_tokens"),
+                        orders_count: row.get::<i64, _>("orders_count"),
+                        trades_count: row.get::<i64, _>("trades_count"),
+                        total_energy_traded: row.get::<f64, _>("total_energy_traded"),
+                        total_volume: row.get::<f64, _>("total_volume"),
+                    }),
+                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
+                }
+            }
+        }
+    }
+
+    // Energy committed across a prosumer's active orders, per side, in one grouped query
+    // Per-prosumer stats for a whole page in one query. Orders and trades are aggregated once
+    // per address and joined, instead of running the correlated subqueries of
+    // `get_prosumer_stats` for every row. A self-trade counts once, as it does there.
+    pub async fn get_all_prosumer_stats(&self, page: u32, limit: u32, sort_by: ProsumerStatsSort) -> Result<Vec<ProsumerStats>, DatabaseError> {
+        let _timer = self.time_query("get_all_prosumer_stats");
+        let offset = page.saturating_sub(1).saturating_mul(limit);
+        let query = format!(
+            r#"
+            SELECT
+                p.address,
+                p.name,
+                p.energy_generated,
+                p.energy_consumed,
+                (p.energy_generated - p.energy_consumed) as net_energy,
+                CAST(COALESCE(g.amount, 0) AS DOUBLE PRECISION) as grid_tokens,
+                CAST(COALESCE(w.amount, 0) AS DOUBLE PRECISION) as watt_tokens,
+                COALESCE(o.orders_count, 0) as orders_count,
+                COALESCE(t.trades_count, 0) as trades_count,
+                CAST(COALESCE(t.total_energy_traded, 0) AS DOUBLE PRECISION) as total_energy_traded,
+                CAST(COALESCE(t.total_volume, 0) AS DOUBLE PRECISION) as total_volume
+            FROM prosumers p
+            LEFT JOIN balances g ON g.address = p.address AND g.token_type = 'grid_tokens'
+            LEFT JOIN balances w ON w.address = p.address AND w.token_type = 'watt_tokens'
+            LEFT JOIN (
+                SELECT prosumer_address, COUNT(*) as orders_count
+                FROM orders
+                GROUP BY prosumer_address
+            ) o ON o.prosumer_address = p.address
+            LEFT JOIN (
+                SELECT
+                    address,
+                    COUNT(*) as trades_count,
+                    SUM(CASE WHEN status = 'completed' THEN energy_amount ELSE 0 END) as total_energy_traded,
+                    SUM(CASE WHEN status = 'completed' THEN total_price ELSE 0 END) as total_volume
+                FROM (
+                    SELECT id, buyer_address as address, energy_amount, total_price, status FROM trades
+                    UNION
+                    SELECT id, seller_address as address, energy_amount, total_price, status FROM trades
+                ) sides
+                GROUP BY address
+            ) t ON t.address = p.address
+            ORDER BY {} DESC, p.address ASC
+            LIMIT $1 OFFSET $2
+        "#,
+            sort_by.column()
+        );
+
+        let rows = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query(&query)
+                    .bind(limit as i64)
+                    .bind(offset as i64)
+                    .fetch_all(pool)
+                    .await?
+                    .into_iter()
+                    .map(|row| ProsumerStats {
+                        address: row.get::<String, _>("address"),
+                        name: row.get::<String, _>("name"),
+                        energy_generated: row.get::<f64, _>("energy_generated"),
+                        energy_consumed: row.get::<f64, _>("energy_consumed"),
+                        net_energy: row.get::<f64, _>("net_energy"),
+                        grid_tokens: row.get::<f64, _>("grid_tokens"),
+                        watt_tokens: row.get::<f64, _>("watt_tokens"),
+                        orders_count: row.get::<i64, _>("orders_count"),
+                        trades_count: row.get::<i64, _>("trades_count"),
+                        total_energy_traded: row.get::<f64, _>("total_energy_traded"),
+                        total_volume: row.get::<f64, _>("total_volume"),
+                    })
+                    .collect()
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query(&query)
+                    .bind(limit as i64)
+                    .bind(offset as i64)
+                    .fetch_all(pool)
+                    .await?
+                    .into_iter()
+                    .map(|row| ProsumerStats {
+                        address: row.get::<String, _>("address"),
+                        name: row.get::<String, _>("name"),
+                        energy_generated: row.get::<f64, _>("energy_generated"),
+                        energy_consumed: row.get::<f64, _>("energy_consumed"),
+                        net_energy: row.get::<f64, _>("net_energy"),
+                        grid_tokens: row.get::<f64, _>("grid_tokens"),
+                        watt_tokens: row.get::<f64, _>("watt_tokens"),
+                        orders_count: row.get::<i64, _>("orders_count"),
+                        trades_count: row.get::<i64, _>("trades_count"),
+                        total_energy_traded: row.get::<f64, _>("total_energy_traded"),
+                        total_volume: row.get::<f64, _>("total_volume"),
+                    })
+                    .collect()
+            }
+        };
+
+        Ok(rows)
+    }
+
+    pub async fn get_prosumer_exposure(&self, address: &str) -> Result<ProsumerExposure, DatabaseError> {
+        let _timer = self.time_query("get_prosumer_exposure");
+        if !self.prosumer_exists(address).await? {
+            return Err(prosumer_not_found(address));
+        }
+
+        let query = r#"
+            SELECT order_type,
+                   COUNT(*) as order_count,
+                   CAST(COALESCE(SUM(energy_amount - filled_amount), 0) AS DOUBLE PRECISION) as energy_committed
+            FROM orders
+            WHERE prosumer_address = $1 AND status = 'active'
+            GROUP BY order_type
+        "#;
+
+        let rows: Vec<(String, i64, f64)> = match self.read_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query_as::<_, ProsumerRow>(query)
+                sqlx::query_as(query)
                     .bind(address)
-                    .bind(name.as_deref())
-                    .bind(energy_generated)
-                    .bind(energy_consumed)
-                    .bind(Utc::now())
-                    .fetch_optional(pool)
-                    .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
+                    .fetch_all(pool)
+                    .await?
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query_as(query)
+                    .bind(address)
+                    .fetch_all(pool)
+                    .await?
+            }
+        };
+
+        let mut exposure = ProsumerExposure {
+            address: address.to_string(),
+            active_buy_orders: 0,
+            active_sell_orders: 0,
+            buy_energy_committed: 0.0,
+            sell_energy_committed: 0.0,
+            net_position: 0.0,
+        };
+        for (order_type, order_count, energy_committed) in rows {
+            match order_type.as_str() {
+                "buy" => {
+                    exposure.active_buy_orders = order_count;
+                    exposure.buy_energy_committed = energy_committed;
+                }
+                "sell" => {
+                    exposure.active_sell_orders = order_count;
+                    exposure.sell_energy_committed = energy_committed;
                 }
+                _ => {}
+            }
+        }
+        exposure.net_position = exposure.buy_energy_committed - exposure.sell_energy_committed;
+
+        Ok(exposure)
+    }
+
+    pub async fn get_open_order_summary(&self, address: &str) -> Result<OpenOrderSummary, DatabaseError> {
+        let _timer = self.time_query("get_open_order_summary");
+        if !self.prosumer_exists(address).await? {
+            return Err(prosumer_not_found(address));
+        }
+
+        let query = r#"
+            SELECT order_type,
+                   COUNT(*) as order_count,
+                   CAST(COALESCE(SUM(energy_amount - filled_amount), 0) AS DOUBLE PRECISION) as energy_open,
+                   CAST(MAX(price_per_unit) AS DOUBLE PRECISION) as max_price,
+                   CAST(MIN(price_per_unit) AS DOUBLE PRECISION) as min_price
+            FROM orders
+            WHERE prosumer_address = $1 AND status IN ('pending', 'active')
+            GROUP BY order_type
+        "#;
+
+        let rows = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query_as::<_, OpenOrderSideRow>(query)
+                    .bind(address)
+                    .fetch_all(pool)
+                    .await?
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query_as::<_, ProsumerRow>(query)
+                sqlx::query_as::<_, OpenOrderSideRow>(query)
                     .bind(address)
-                    .bind(name.as_deref())
-                    .bind(energy_generated)
-                    .bind(energy_consumed)
-                    .bind(Utc::now())
-                    .fetch_optional(pool)
-                    .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
+                    .fetch_all(pool)
+                    .await?
+            }
+        };
+
+        let mut summary = OpenOrderSummary {
+            address: address.to_string(),
+            open_buy_orders: 0,
+            open_sell_orders: 0,
+            buy_energy_open: 0.0,
+            sell_energy_open: 0.0,
+            best_bid: None,
+            best_ask: None,
+        };
+        for row in rows {
+            match row.order_type.as_str() {
+                "buy" => {
+                    summary.open_buy_orders = row.order_count;
+                    summary.buy_energy_open = row.energy_open;
+                    summary.best_bid = row.max_price;
+                }
+                "sell" => {
+                    summary.open_sell_orders = row.order_count;
+                    summary.sell_energy_open = row.energy_open;
+                    summary.best_ask = row.min_price;
                 }
+                _ => {}
             }
         }
+
+        Ok(summary)
     }
 
-    pub async fn create_order(&self, order: Order) -> Result<Order, DatabaseError> {
+    pub async fn get_stats(&self) -> Result<DatabaseStats, DatabaseError> {
+        let _timer = self.time_query("get_stats");
         let query = r#"
-            INSERT INTO orders (id, prosumer_address, order_type, energy_amount, price_per_unit, total_price, status, created_at, updated_at, expires_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
-            RETURNING *
+            SELECT 
+                (SELECT COUNT(*) FROM prosumers) as total_prosumers,
+                (SELECT COUNT(*) FROM orders) as total_orders,
+                (SELECT COUNT(*) FROM trades) as total_trades
         "#;
         
-        match &self.pool {
+        match self.read_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query_as::<_, OrderRow>(query)
-                    .bind(order.id)
-                    .bind(&order.prosumer_address)
-                    .bind(&order.order_type)
-                    .bind(order.energy_amount)
-                    .bind(order.price_per_unit)
-                    .bind(order.total_price)
-                    .bind(&order.status)
-                    .bind(order.created_at)
-                    .bind(order.updated_at)
-                    .bind(order.expires_at)
-                    .fetch_one(pool)
-                    .await?;
-                Ok(row.into())
+                let row = sqlx::query(query).fetch_one(pool).await?;
+                Ok(DatabaseStats {
+                    total_users: 0, // We don't have users table yet
+                    total_api_keys: 0, // We don't have api_keys table yet
+                    total_prosumers: row.get::<i64, _>("total_prosumers"),
+                    total_orders: row.get::<i64, _>("total_orders"),
+                    total_trades: row.get::<i64, _>("total_trades"),
+                    database_size: None, // Would need platform-specific queries
+                })
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query_as::<_, OrderRow>(query)
-                    .bind(order.id)
-                    .bind(&order.prosumer_address)
-                    .bind(&order.order_type)
-                    .bind(order.energy_amount)
-                    .bind(order.price_per_unit)
-                    .bind(order.total_price)
-                    .bind(&order.status)
-                    .bind(order.created_at)
-                    .bind(order.updated_at)
-                    .bind(order.expires_at)
-                    .fetch_one(pool)
-                    .await?;
-                Ok(row.into())
+                let row = sqlx::query(query).fetch_one(pool).await?;
+                Ok(DatabaseStats {
+                    total_users: 0, // We don't have users table yet
+                    total_api_keys: 0, // We don't have api_keys table yet
+                    total_prosumers: row.get::<i64, _>("total_prosumers"),
+                    total_orders: row.get::<i64, _>("total_orders"),
+                    total_trades: row.get::<i64, _>("total_trades"),
+                    database_size: None, // Would need platform-specific queries
+                })
             }
         }
     }
 
-    pub async fn get_order(&self, id: Uuid) -> Result<Order, DatabaseError> {
-        let query = "SELECT * FROM orders WHERE id = $1";
+    pub async fn transfer_tokens(&self, from_address: &str, to_address: &str, amount: f64, token_type: &str) -> Result<String, DatabaseError> {
+        let _timer = self.time_query("transfer_tokens");
+        // Start a transaction
+        let transaction_id = Uuid::new_v4();
         
-        match &self.pool {
+        match self.write_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query_as::<_, OrderRow>(query)
-                    .bind(id)
-                    .fetch_optional(pool)
-                    .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Order '{}' not found", id))),
+                let mut tx = pool.begin().await?;
+                self.transfer_tokens_postgres(&mut tx, transaction_id, from_address, to_address, amount, token_type).await?;
+                tx.commit().await?;
+                Ok(transaction_id.to_string())
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                self.transfer_tokens_sqlite(&mut tx, transaction_id, from_address, to_address, amount, token_type).await?;
+                tx.commit().await?;
+                Ok(transaction_id.to_string())
+            }
+        }
+    }
+
+    // Runs the full transfer inside a transaction that is always rolled back. Validation and
+    // missing-prosumer failures become a negative preview; database errors are still returned.
+    pub async fn preview_transfer(&self, from_address: &str, to_address: &str, amount: f64, token_type: &str) -> Result<TransferPreview, DatabaseError> {
+        let _timer = self.time_query("preview_transfer");
+        let (result, from_balance, to_balance) = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let result = self.transfer_tokens_postgres(&mut tx, Uuid::new_v4(), from_address, to_address, amount, token_type).await;
+                let mut balances = [0.0; 2];
+                for (balance, address) in balances.iter_mut().zip([from_address, to_address]) {
+                    *balance = sqlx::query_scalar(BALANCE_QUERY)
+                        .bind(address)
+                        .bind(token_type)
+                        .fetch_optional(&mut *tx)
+                        .await?
+                        .unwrap_or(0.0);
                 }
+                tx.rollback().await?;
+                (result, balances[0], balances[1])
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query_as::<_, OrderRow>(query)
-                    .bind(id)
-                    .fetch_optional(pool)
-                    .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Order '{}' not found", id))),
+                let mut tx = pool.begin().await?;
+                let result = self.transfer_tokens_sqlite(&mut tx, Uuid::new_v4(), from_address, to_address, amount, token_type).await;
+                let mut balances = [0.0; 2];
+                for (balance, address) in balances.iter_mut().zip([from_address, to_address]) {
+                    *balance = sqlx::query_scalar(BALANCE_QUERY)
+                        .bind(address)
+                        .bind(token_type)
+                        .fetch_optional(&mut *tx)
+                        .await?
+                        .unwrap_or(0.0);
                 }
+                tx.rollback().await?;
+                (result, balances[0], balances[1])
+            }
+        };
+
+        let reason = match result {
+            Ok(()) => None,
+            Err(DatabaseError::Validation(msg))
+            | Err(DatabaseError::NotFound(msg))
+            | Err(DatabaseError::Conflict(msg)) => Some(msg),
+            Err(e @ DatabaseError::InsufficientBalance { .. }) => Some(e.to_string()),
+            Err(e) => return Err(e),
+        };
+        Ok(TransferPreview {
+            would_succeed: reason.is_none(),
+            reason,
+            token_type: token_type.to_string(),
+            from_balance,
+            to_balance,
+        })
+    }
+
+    async fn transfer_tokens_postgres(&self, tx: &mut Transaction<'_, Postgres>, transfer_id: Uuid, from_address: &str, to_address: &str, amount: f64, token_type: &str) -> Result<(), DatabaseError> {
+        validate_transfer_amount(amount)?;
+
+        let known: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM token_types WHERE name = $1")
+            .bind(token_type)
+            .fetch_one(&mut **tx)
+            .await?;
+        if known == 0 {
+            return Err(DatabaseError::Validation(format!("Unknown token type '{}'", token_type)));
+        }
+
+        for address in [from_address, to_address] {
+            let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM prosumers WHERE address = $1")
+                .bind(address)
+                .fetch_one(&mut **tx)
+                .await?;
+            if exists == 0 {
+                return Err(prosumer_not_found(address));
             }
         }
+
+        // Check if sender has enough tokens
+        let current_balance: f64 = sqlx::query_scalar(BALANCE_QUERY)
+            .bind(from_address)
+            .bind(token_type)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or(0.0);
+        if current_balance < amount {
+            return Err(DatabaseError::InsufficientBalance {
+                token_type: token_type.to_string(),
+                required: amount,
+                available: current_balance,
+            });
+        }
+
+        let recipient_balance: f64 = sqlx::query_scalar(BALANCE_QUERY)
+            .bind(to_address)
+            .bind(token_type)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or(0.0);
+        check_balance_update(current_balance - amount)?;
+        check_balance_update(recipient_balance + amount)?;
+
+        let now = self.now();
+        sqlx::query(DEBIT_BALANCE_QUERY)
+            .bind(amount)
+            .bind(now)
+            .bind(from_address)
+            .bind(token_type)
+            .execute(&mut **tx)
+            .await?;
+        sqlx::query(CREDIT_BALANCE_QUERY)
+            .bind(to_address)
+            .bind(token_type)
+            .bind(amount)
+            .bind(now)
+            .execute(&mut **tx)
+            .await?;
+
+        sqlx::query("UPDATE prosumers SET updated_at = $1 WHERE address = $2 OR address = $3")
+            .bind(now)
+            .bind(from_address)
+            .bind(to_address)
+            .execute(&mut **tx)
+            .await?;
+        sqlx::query(INSERT_TRANSFER_QUERY)
+            .bind(transfer_id)
+            .bind(from_address)
+            .bind(to_address)
+            .bind(token_type)
+            .bind(amount)
+            .bind(now)
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(())
     }
 
-    pub async fn get_orders(&self, page: u32, limit: u32, status: Option<String>, order_type: Option<String>, prosumer_address: Option<String>) -> Result<Vec<Order>, DatabaseError> {
-        let offset = (page - 1) * limit;
-        let mut query = "SELECT * FROM orders WHERE 1=1".to_string();
-        let mut bind_count = 1;
-        
-        if status.is_some() {
-            query.push_str(&format!(" AND status = ${}", bind_count));
-            bind_count += 1;
+    async fn transfer_tokens_sqlite(&self, tx: &mut Transaction<'_, Sqlite>, transfer_id: Uuid, from_address: &str, to_address: &str, amount: f64, token_type: &str) -> Result<(), DatabaseError> {
+        validate_transfer_amount(amount)?;
+
+        let known: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM token_types WHERE name = $1")
+            .bind(token_type)
+            .fetch_one(&mut **tx)
+            .await?;
+        if known == 0 {
+            return Err(DatabaseError::Validation(format!("Unknown token type '{}'", token_type)));
         }
-        if order_type.is_some() {
-            query.push_str(&format!(" AND order_type = ${}", bind_count));
-            bind_count += 1;
+
+        for address in [from_address, to_address] {
+            let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM prosumers WHERE address = $1")
+                .bind(address)
+                .fetch_one(&mut **tx)
+                .await?;
+            if exists == 0 {
+                return Err(prosumer_not_found(address));
+            }
         }
-        if prosumer_address.is_some() {
-            query.push_str(&format!(" AND prosumer_address = ${}", bind_count));
-            bind_count += 1;
+
+        // Check if sender has enough tokens
+        let current_balance: f64 = sqlx::query_scalar(BALANCE_QUERY)
+            .bind(from_address)
+            .bind(token_type)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or(0.0);
+        if current_balance < amount {
+            return Err(DatabaseError::InsufficientBalance {
+                token_type: token_type.to_string(),
+                required: amount,
+                available: current_balance,
+            });
         }
-        
-        query.push_str(&format!(" ORDER BY created_at DESC LIMIT ${} OFFSET ${}", bind_count, bind_count + 1));
-        
-        match &self.pool {
+
+        let recipient_balance: f64 = sqlx::query_scalar(BALANCE_QUERY)
+            .bind(to_address)
+            .bind(token_type)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or(0.0);
+        check_balance_update(current_balance - amount)?;
+        check_balance_update(recipient_balance + amount)?;
+
+        let now = self.now();
+        sqlx::query(DEBIT_BALANCE_QUERY)
+            .bind(amount)
+            .bind(now)
+            .bind(from_address)
+            .bind(token_type)
+            .execute(&mut **tx)
+            .await?;
+        sqlx::query(CREDIT_BALANCE_QUERY)
+            .bind(to_address)
+            .bind(token_type)
+            .bind(amount)
+            .bind(now)
+            .execute(&mut **tx)
+            .await?;
+
+        sqlx::query("UPDATE prosumers SET updated_at = $1 WHERE address = $2 OR address = $3")
+            .bind(now)
+            .bind(from_address)
+            .bind(to_address)
+            .execute(&mut **tx)
+            .await?;
+        sqlx::query(INSERT_TRANSFER_QUERY)
+            .bind(transfer_id)
+            .bind(from_address)
+            .bind(to_address)
+            .bind(token_type)
+            .bind(amount)
+            .bind(now)
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(())
+    }
+
+    // Runs a mixed list of operations in a single transaction. Either every operation is
+    // applied or, on the first failure, none are and the failing index is reported.
+    pub async fn execute_batch(&self, operations: Vec<BatchOperation>) -> Result<Vec<BatchOperationResult>, DatabaseError> {
+        let _timer = self.time_query("execute_batch");
+        if operations.is_empty() {
+            return Err(DatabaseError::Validation("Batch must contain at least one operation".to_string()));
+        }
+        if operations.len() > MAX_BATCH_OPERATIONS {
+            return Err(DatabaseError::Validation(format!(
+                "Batch contains {} operations; maximum is {}",
+                operations.len(),
+                MAX_BATCH_OPERATIONS
+            )));
+        }
+        if operations
+            .iter()
+            .any(|op| matches!(op, BatchOperation::CreateOrder(_) | BatchOperation::ExecuteTrade { .. }))
+        {
+            self.check_market_open().await?;
+        }
+
+        let mut results = Vec::with_capacity(operations.len());
+
+        match self.write_pool() {
             DatabasePool::Postgres(pool) => {
-                let mut q = sqlx::query_as::<_, OrderRow>(&query);
-                if let Some(ref s) = status {
-                    q = q.bind(s);
-                }
-                if let Some(ref ot) = order_type {
-                    q = q.bind(ot);
-                }
-                if let Some(ref pa) = prosumer_address {
-                    q = q.bind(pa);
+                let mut tx = pool.begin().await?;
+                for (index, operation) in operations.into_iter().enumerate() {
+                    let result = match operation {
+                        BatchOperation::CreateProsumer(prosumer) => self
+                            .insert_prosumer_postgres(&mut tx, &prosumer)
+                            .await
+                            .map(|prosumer| BatchOperationResult::CreateProsumer { prosumer }),
+                        BatchOperation::Transfer { from_address, to_address, amount, token_type } => {
+                            let transfer_id = Uuid::new_v4();
+                            self.transfer_tokens_postgres(&mut tx, transfer_id, &from_address, &to_address, amount, &token_type)
+                                .await
+                                .map(|_| BatchOperationResult::Transfer { transfer_id: transfer_id.to_string() })
+                        }
+                        BatchOperation::CreateOrder(order) => self
+                            .insert_order_postgres(&mut tx, order)
+                            .await
+                            .map(|order| BatchOperationResult::CreateOrder { order }),
+                        BatchOperation::ExecuteTrade { buy_order_id, sell_order_id, price_per_unit } => self
+                            .execute_trade_postgres(&mut tx, buy_order_id, sell_order_id, price_per_unit, None)
+                            .await
+                            .map(|trade| BatchOperationResult::ExecuteTrade { trade }),
+                    };
+                    results.push(result.map_err(|e| DatabaseError::BatchFailed { index, source: Box::new(e) })?);
                 }
-                q = q.bind(limit as i64).bind(offset as i64);
-                let rows = q.fetch_all(pool).await?;
-                Ok(rows.into_iter().map(|row| row.into()).collect())
+                tx.commit().await?;
             }
             DatabasePool::Sqlite(pool) => {
-                let mut q = sqlx::query_as::<_, OrderRow>(&query);
-                if let Some(ref s) = status {
-                    q = q.bind(s);
+                let mut tx = pool.begin().await?;
+                for (index, operation) in operations.into_iter().enumerate() {
+                    let result = match operation {
+                        BatchOperation::CreateProsumer(prosumer) => self
+                            .insert_prosumer_sqlite(&mut tx, &prosumer)
+                            .await
+                            .map(|prosumer| BatchOperationResult::CreateProsumer { prosumer }),
+                        BatchOperation::Transfer { from_address, to_address, amount, token_type } => {
+                            let transfer_id = Uuid::new_v4();
+                            self.transfer_tokens_sqlite(&mut tx, transfer_id, &from_address, &to_address, amount, &token_type)
+                                .await
+                                .map(|_| BatchOperationResult::Transfer { transfer_id: transfer_id.to_string() })
+                        }
+                        BatchOperation::CreateOrder(order) => self
+                            .insert_order_sqlite(&mut tx, order)
+                            .await
+                            .map(|order| BatchOperationResult::CreateOrder { order }),
+                        BatchOperation::ExecuteTrade { buy_order_id, sell_order_id, price_per_unit } => self
+                            .execute_trade_sqlite(&mut tx, buy_order_id, sell_order_id, price_per_unit, None)
+                            .await
+                            .map(|trade| BatchOperationResult::ExecuteTrade { trade }),
+                    };
+                    results.push(result.map_err(|e| DatabaseError::BatchFailed { index, source: Box::new(e) })?);
                 }
-                if let Some(ref ot) = order_type {
-                    q = q.bind(ot);
+                tx.commit().await?;
+            }
+        }
+
+        // Counted only once the whole batch has committed
+        for result in &results {
+            match result {
+                BatchOperationResult::CreateOrder { order } => telemetry::record_order_created(&order.order_type),
+                BatchOperationResult::ExecuteTrade { trade } => {
+                    telemetry::record_trade_executed(trade.energy_amount);
+                    self.invalidate_market_stats();
                 }
-                if let Some(ref pa) = prosumer_address {
-                    q = q.bind(pa);
+                _ => {}
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub async fn get_token_types(&self) -> Result<Vec<TokenType>, DatabaseError> {
+        let _timer = self.time_query("get_token_types");
+        let query = "SELECT * FROM token_types ORDER BY created_at ASC, name ASC";
+
+        match self.read_pool() {
+            DatabasePool::Postgres(pool) => Ok(sqlx::query_as::<_, TokenType>(query).fetch_all(pool).await?),
+            DatabasePool::Sqlite(pool) => Ok(sqlx::query_as::<_, TokenType>(query).fetch_all(pool).await?),
+        }
+    }
+
+    // Credits several token balances of one prosumer in a single transaction. The tokens are
+    // newly issued, so each credit is recorded as a transfer from MINT_ADDRESS
+    pub async fn credit_tokens(&self, address: &str, credits: &[(&str, f64)]) -> Result<TokenBalance, DatabaseError> {
+        let _timer = self.time_query("credit_tokens");
+        if !self.prosumer_exists(address).await? {
+            return Err(prosumer_not_found(address));
+        }
+
+        let now = self.now();
+        match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                for (token_type, amount) in credits.iter().filter(|(_, amount)| *amount > 0.0) {
+                    sqlx::query(CREDIT_BALANCE_QUERY)
+                        .bind(address)
+                        .bind(token_type)
+                        .bind(amount)
+                        .bind(now)
+                        .execute(&mut *tx)
+                        .await?;
+                    sqlx::query(INSERT_TRANSFER_QUERY)
+                        .bind(Uuid::new_v4())
+                        .bind(MINT_ADDRESS)
+                        .bind(address)
+                        .bind(token_type)
+                        .bind(amount)
+                        .bind(now)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                for (token_type, amount) in credits.iter().filter(|(_, amount)| *amount > 0.0) {
+                    sqlx::query(CREDIT_BALANCE_QUERY)
+                        .bind(address)
+                        .bind(token_type)
+                        .bind(amount)
+                        .bind(now)
+                        .execute(&mut *tx)
+                        .await?;
+                    sqlx::query(INSERT_TRANSFER_QUERY)
+                        .bind(Uuid::new_v4())
+                        .bind(MINT_ADDRESS)
+                        .bind(address)
+                        .bind(token_type)
+                        .bind(amount)
+                        .bind(now)
+                        .execute(&mut *tx)
+                        .await?;
                 }
-                q = q.bind(limit as i64).bind(offset as i64);
-                let rows = q.fetch_all(pool).await?;
-                Ok(rows.into_iter().map(|row| row.into()).collect())
+                tx.commit().await?;
             }
         }
+
+        self.get_prosumer_balance(address).await
     }
 
-    pub async fn update_order(&self, id: Uuid, status: Option<String>, energy_amount: Option<f64>, price_per_unit: Option<f64>) -> Result<Order, DatabaseError> {
-        let query = r#"
-            UPDATE orders 
-            SET status = COALESCE($2, status),
-                energy_amount = COALESCE($3, energy_amount),
-                price_per_unit = COALESCE($4, price_per_unit),
-                total_price = COALESCE($3 * $4, total_price),
-                updated_at = $5
-            WHERE id = $1
-            RETURNING *
-        "#;
-        
-        match &self.pool {
+    // Makes a new token type transferable; balances start at zero for everyone
+    pub async fn register_token_type(&self, name: &str, description: &str) -> Result<TokenType, DatabaseError> {
+        let _timer = self.time_query("register_token_type");
+        let exists = "SELECT COUNT(*) FROM token_types WHERE name = $1";
+        let insert = "INSERT INTO token_types (name, description, created_at) VALUES ($1, $2, $3)";
+        let token_type = TokenType {
+            name: name.to_string(),
+            description: description.to_string(),
+            created_at: self.now(),
+        };
+
+        let count: i64 = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_scalar(exists).bind(name).fetch_one(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_scalar(exists).bind(name).fetch_one(pool).await?,
+        };
+        if count > 0 {
+            return Err(DatabaseError::Conflict(format!("Token type '{}' already exists", name)));
+        }
+
+        match self.write_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query_as::<_, OrderRow>(query)
-                    .bind(id)
-                    .bind(status.as_deref())
-                    .bind(energy_amount)
-                    .bind(price_per_unit)
-                    .bind(Utc::now())
-                    .fetch_optional(pool)
+                sqlx::query(insert)
+                    .bind(&token_type.name)
+                    .bind(&token_type.description)
+                    .bind(token_type.created_at)
+                    .execute(pool)
                     .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Order '{}' not found", id))),
-                }
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query_as::<_, OrderRow>(query)
-                    .bind(id)
-                    .bind(status.as_deref())
-                    .bind(energy_amount)
-                    .bind(price_per_unit)
-                    .bind(Utc::now())
-                    .fetch_optional(pool)
+                sqlx::query(insert)
+                    .bind(&token_type.name)
+                    .bind(&token_type.description)
+                    .bind(token_type.created_at)
+                    .execute(pool)
                     .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Order '{}' not found", id))),
-                }
             }
         }
+        Ok(token_type)
     }
 
-    pub async fn cancel_order(&self, id: Uuid) -> Result<Order, DatabaseError> {
+    pub async fn create_webhook(&self, url: &str, events: &[String], secret: &str, owner_id: &str) -> Result<Webhook, DatabaseError> {
+        let _timer = self.time_query("create_webhook");
         let query = r#"
-            UPDATE orders 
-            SET status = 'cancelled',
-                updated_at = $2
-            WHERE id = $1
+            INSERT INTO webhooks (id, url, events, secret, is_active, failure_count, created_at, owner_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
             RETURNING *
         "#;
-        
-        match &self.pool {
+
+        let row = match self.write_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query_as::<_, OrderRow>(query)
-                    .bind(id)
-                    .bind(Utc::now())
-                    .fetch_optional(pool)
-                    .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Order '{}' not found", id))),
+                sqlx::query_as::<_, WebhookRow>(query)
+                    .bind(Uuid::new_v4())
+                    .bind(url)
+                    .bind(events.join(","))
+                    .bind(secret)
+                    .bind(true)
+                    .bind(0i64)
+                    .bind(self.now())
+                    .bind(owner_id)
+                    .fetch_one(pool)
+                    .await?
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query_as::<_, WebhookRow>(query)
+                    .bind(Uuid::new_v4())
+                    .bind(url)
+                    .bind(events.join(","))
+                    .bind(secret)
+                    .bind(true)
+                    .bind(0i64)
+                    .bind(self.now())
+                    .bind(owner_id)
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+
+        Ok(row.into())
+    }
+
+    // Every webhook, or only those registered by `owner_id`
+    pub async fn get_webhooks(&self, owner_id: Option<&str>) -> Result<Vec<Webhook>, DatabaseError> {
+        let _timer = self.time_query("get_webhooks");
+        let query = match owner_id {
+            Some(_) => "SELECT * FROM webhooks WHERE owner_id = $1 ORDER BY created_at DESC",
+            None => "SELECT * FROM webhooks ORDER BY created_at DESC",
+        };
+
+        let rows = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query_as::<_, WebhookRow>(query);
+                if let Some(owner_id) = owner_id {
+                    q = q.bind(owner_id);
                 }
+                q.fetch_all(pool).await?
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query_as::<_, OrderRow>(query)
-                    .bind(id)
-                    .bind(Utc::now())
-                    .fetch_optional(pool)
-                    .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Order '{}' not found", id))),
+                let mut q = sqlx::query_as::<_, WebhookRow>(query);
+                if let Some(owner_id) = owner_id {
+                    q = q.bind(owner_id);
+                }
+                q.fetch_all(pool).await?
+            }
+        };
+
+        Ok(rows.into_iter().map(|row| row.into()).collect())
+    }
+
+    // Active webhooks subscribed to `event`, whoever registered them
+    pub async fn get_webhooks_for_event(&self, event: &str) -> Result<Vec<Webhook>, DatabaseError> {
+        let _timer = self.time_query("get_webhooks_for_event");
+        Ok(self
+            .get_webhooks(None)
+            .await?
+            .into_iter()
+            .filter(|hook| hook.is_active && hook.events.iter().any(|e| e == event))
+            .collect())
+    }
+
+    // Deletes the webhook if it belongs to `owner_id` (any webhook when None). Someone else's
+    // webhook is reported as not found, the same as a missing one.
+    pub async fn delete_webhook(&self, id: Uuid, owner_id: Option<&str>) -> Result<(), DatabaseError> {
+        let _timer = self.time_query("delete_webhook");
+        let query = match owner_id {
+            Some(_) => "DELETE FROM webhooks WHERE id = $1 AND owner_id = $2",
+            None => "DELETE FROM webhooks WHERE id = $1",
+        };
+
+        let rows_affected = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query(query).bind(id);
+                if let Some(owner_id) = owner_id {
+                    q = q.bind(owner_id);
+                }
+                q.execute(pool).await?.rows_affected()
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut q = sqlx::query(query).bind(id);
+                if let Some(owner_id) = owner_id {
+                    q = q.bind(owner_id);
                 }
+                q.execute(pool).await?.rows_affected()
             }
+        };
+
+        if rows_affected == 0 {
+            return Err(DatabaseError::NotFound(format!("Webhook '{}' not found", id)));
         }
+        Ok(())
     }
 
-    pub async fn create_trade(&self, trade: Trade) -> Result<Trade, DatabaseError> {
-        let query = r#"
-            INSERT INTO trades (id, buy_order_id, sell_order_id, buyer_address, seller_address, energy_amount, price_per_unit, total_price, status, executed_at, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
-            RETURNING *
-        "#;
-        
-        match &self.pool {
+    pub async fn record_webhook_success(&self, id: Uuid) -> Result<(), DatabaseError> {
+        let _timer = self.time_query("record_webhook_success");
+        let query = "UPDATE webhooks SET failure_count = 0 WHERE id = $1";
+
+        match self.write_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query_as::<_, TradeRow>(query)
-                    .bind(trade.id)
-                    .bind(trade.buy_order_id)
-                    .bind(trade.sell_order_id)
-                    .bind(&trade.buyer_address)
-                    .bind(&trade.seller_address)
-                    .bind(trade.energy_amount)
-                    .bind(trade.price_per_unit)
-                    .bind(trade.total_price)
-                    .bind(&trade.status)
-                    .bind(trade.executed_at)
-                    .bind(trade.created_at)
-                    .fetch_one(pool)
-                    .await?;
-                Ok(row.into())
+                sqlx::query(query).bind(id).execute(pool).await?;
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query_as::<_, TradeRow>(query)
-                    .bind(trade.id)
-                    .bind(trade.buy_order_id)
-                    .bind(trade.sell_order_id)
-                    .bind(&trade.buyer_address)
-                    .bind(&trade.seller_address)
-                    .bind(trade.energy_amount)
-                    .bind(trade.price_per_unit)
-                    .bind(trade.total_price)
-                    .bind(&trade.status)
-                    .bind(trade.executed_at)
-                    .bind(trade.created_at)
-                    .fetch_one(pool)
-                    .await?;
-                Ok(row.into())
+                sqlx::query(query).bind(id).execute(pool).await?;
             }
         }
+        Ok(())
     }
 
-    pub async fn get_trade(&self, id: Uuid) -> Result<Trade, DatabaseError> {
-        let query = "SELECT * FROM trades WHERE id = $1";
-        
-        match &self.pool {
+    // Moves an undeliverable event to the dead-letter log and counts the failure
+    pub async fn record_webhook_failure(&self, id: Uuid, event: &str, payload: &str, error: &str, attempts: u32) -> Result<(), DatabaseError> {
+        let _timer = self.time_query("record_webhook_failure");
+        let insert_query = r#"
+            INSERT INTO webhook_dead_letters (id, webhook_id, event, payload, error, attempts, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#;
+        let update_query = "UPDATE webhooks SET failure_count = failure_count + 1 WHERE id = $1";
+
+        match self.write_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query_as::<_, TradeRow>(query)
+                let mut tx = pool.begin().await?;
+                sqlx::query(insert_query)
+                    .bind(Uuid::new_v4())
                     .bind(id)
-                    .fetch_optional(pool)
+                    .bind(event)
+                    .bind(payload)
+                    .bind(error)
+                    .bind(attempts as i64)
+                    .bind(self.now())
+                    .execute(&mut *tx)
                     .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Trade '{}' not found", id))),
-                }
+                sqlx::query(update_query).bind(id).execute(&mut *tx).await?;
+                tx.commit().await?;
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query_as::<_, TradeRow>(query)
+                let mut tx = pool.begin().await?;
+                sqlx::query(insert_query)
+                    .bind(Uuid::new_v4())
                     .bind(id)
-                    .fetch_optional(pool)
+                    .bind(event)
+                    .bind(payload)
+                    .bind(error)
+                    .bind(attempts as i64)
+                    .bind(self.now())
+                    .execute(&mut *tx)
                     .await?;
-                match row {
-                    Some(row) => Ok(row.into()),
-                    None => Err(DatabaseError::NotFound(format!("Trade '{}' not found", id))),
-                }
+                sqlx::query(update_query).bind(id).execute(&mut *tx).await?;
+                tx.commit().await?;
             }
         }
+        Ok(())
     }
 
-    pub async fn get_trades(&self, page: u32, limit: u32) -> Result<Vec<Trade>, DatabaseError> {
-        let offset = (page - 1) * limit;
-        let query = "SELECT * FROM trades ORDER BY created_at DESC LIMIT $1 OFFSET $2";
-        
-        match &self.pool {
+    pub async fn record_audit_entry(&self, entry: &AuditEntry) -> Result<(), DatabaseError> {
+        let _timer = self.time_query("record_audit_entry");
+        let query = r#"
+            INSERT INTO audit_log (id, user_id, method, path, request_id, status_code, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#;
+
+        match self.write_pool() {
             DatabasePool::Postgres(pool) => {
-                let rows = sqlx::query_as::<_, TradeRow>(query)
-                    .bind(limit as i64)
-                    .bind(offset as i64)
-                    .fetch_all(pool)
+                sqlx::query(query)
+                    .bind(entry.id)
+                    .bind(&entry.user_id)
+                    .bind(&entry.method)
+                    .bind(&entry.path)
+                    .bind(&entry.request_id)
+                    .bind(entry.status_code)
+                    .bind(entry.created_at)
+                    .execute(pool)
                     .await?;
-                Ok(rows.into_iter().map(|row| row.into()).collect())
             }
             DatabasePool::Sqlite(pool) => {
-                let rows = sqlx::query_as::<_, TradeRow>(query)
-                    .bind(limit as i64)
-                    .bind(offset as i64)
-                    .fetch_all(pool)
+                sqlx::query(query)
+                    .bind(entry.id)
+                    .bind(&entry.user_id)
+                    .bind(&entry.method)
+                    .bind(&entry.path)
+                    .bind(&entry.request_id)
+                    .bind(entry.status_code)
+                    .bind(entry.created_at)
+                    .execute(pool)
                     .await?;
-                Ok(rows.into_iter().map(|row| row.into()).collect())
             }
         }
+        Ok(())
     }
 
-    pub async fn execute_trade(&self, trade: Trade) -> Result<Trade, DatabaseError> {
-        // First create the trade
-        let created_trade = self.create_trade(trade).await?;
-        
-        // Then update the associated orders to completed
-        let _buy_order = self.update_order(created_trade.buy_order_id, Some("completed".to_string()), None, None).await?;
-        let _sell_order = self.update_order(created_trade.sell_order_id, Some("completed".to_string()), None, None).await?;
-        
-        Ok(created_trade)
-    }
+    pub async fn get_audit_log(&self, page: u32, limit: u32, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>, user_id: Option<String>) -> Result<Vec<AuditEntry>, DatabaseError> {
+        let _timer = self.time_query("get_audit_log");
+        let offset = page.saturating_sub(1).saturating_mul(limit);
+        let mut query = "SELECT * FROM audit_log WHERE 1=1".to_string();
+        let mut bind_count = 1;
 
-    pub async fn get_market_stats(&self) -> Result<MarketStats, DatabaseError> {
-        let query = r#"
-            SELECT 
-                (SELECT COUNT(*) FROM prosumers) as total_prosumers,
-                (SELECT COUNT(*) FROM orders) as total_orders,
-                (SELECT COUNT(*) FROM trades) as total_trades,
-                (SELECT COALESCE(SUM(CAST(energy_amount AS REAL)), 0.0) FROM trades WHERE status = 'completed') as total_energy_traded,
-                (SELECT COALESCE(SUM(CAST(total_price AS REAL)), 0.0) FROM trades WHERE status = 'completed') as total_volume,
-                (SELECT COALESCE(AVG(CAST(price_per_unit AS REAL)), 0.0) FROM trades WHERE status = 'completed') as average_price,
-                (SELECT COUNT(*) FROM orders WHERE status = 'active' AND order_type = 'buy') as active_buy_orders,
-                (SELECT COUNT(*) FROM orders WHERE status = 'active' AND order_type = 'sell') as active_sell_orders
-        "#;
-        
-        match &self.pool {
+        if from.is_some() {
+            query.push_str(&format!(" AND created_at >= ${}", bind_count));
+            bind_count += 1;
+        }
+        if to.is_some() {
+            query.push_str(&format!(" AND created_at < ${}", bind_count));
+            bind_count += 1;
+        }
+        if user_id.is_some() {
+            query.push_str(&format!(" AND user_id = ${}", bind_count));
+            bind_count += 1;
+        }
+
+        query.push_str(&format!(" ORDER BY created_at DESC LIMIT ${} OFFSET ${}", bind_count, bind_count + 1));
+
+        match self.read_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query(query).fetch_one(pool).await?;
-                Ok(MarketStats {
-                    total_prosumers: row.get::<i64, _>("total_prosumers"),
-                    total_orders: row.get::<i64, _>("total_orders"),
-                    total_trades: row.get::<i64, _>("total_trades"),
-                    total_energy_traded: row.get::<f64, _>("total_energy_traded"),
-                    total_volume: row.get::<f64, _>("total_volume"),
-                    average_price: row.get::<f64, _>("average_price"),
-                    active_buy_orders: row.get::<i64, _>("active_buy_orders"),
-                    active_sell_orders: row.get::<i64, _>("active_sell_orders"),
-                })
+                let mut q = sqlx::query_as::<_, AuditEntry>(&query);
+                if let Some(from) = from {
+                    q = q.bind(from);
+                }
+                if let Some(to) = to {
+                    q = q.bind(to);
+                }
+                if let Some(ref user_id) = user_id {
+                    q = q.bind(user_id);
+                }
+                q = q.bind(limit as i64).bind(offset as i64);
+                Ok(q.fetch_all(pool).await?)
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query(query).fetch_one(pool).await?;
-                Ok(MarketStats {
-                    total_prosumers: row.get::<i64, _>("total_prosumers"),
-                    total_orders: row.get::<i64, _>("total_orders"),
-                    total_trades: row.get::<i64, _>("total_trades"),
-                    total_energy_traded: row.get::<f64, _>("total_energy_traded"),
-                    total_volume: row.get::<f64, _>("total_volume"),
-                    average_price: row.get::<f64, _>("average_price"),
-                    active_buy_orders: row.get::<i64, _>("active_buy_orders"),
-                    active_sell_orders: row.get::<i64, _>("active_sell_orders"),
-                })
+                let mut q = sqlx::query_as::<_, AuditEntry>(&query);
+                if let Some(from) = from {
+                    q = q.bind(from);
+                }
+                if let Some(to) = to {
+                    q = q.bind(to);
+                }
+                if let Some(ref user_id) = user_id {
+                    q = q.bind(user_id);
+                }
+                q = q.bind(limit as i64).bind(offset as i64);
+                Ok(q.fetch_all(pool).await?)
             }
         }
     }
 
-    pub async fn get_prosumer_stats(&self, address: &str) -> Result<ProsumerStats, DatabaseError> {
+    // Active, unexpired orders with energy left to fill, split into bids and asks
+    pub async fn get_order_book(&self) -> Result<OrderBook, DatabaseError> {
+        let _timer = self.time_query("get_order_book");
+        let query = "SELECT * FROM orders WHERE status = 'active' AND energy_amount > filled_amount AND (expires_at IS NULL OR expires_at > $1)";
+
+        let now = self.now();
+        let rows = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_as::<_, OrderRow>(query).bind(now).fetch_all(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_as::<_, OrderRow>(query).bind(now).fetch_all(pool).await?,
+        };
+
+        let (bids, asks) = rows
+            .into_iter()
+            .map(Order::from)
+            .partition(|order| order.order_type == "buy");
+        Ok(OrderBook { bids, asks })
+    }
+
+    // Highest active buy price and lowest active sell price among unexpired orders with
+    // energy left to fill
+    pub async fn get_best_prices(&self) -> Result<(Option<f64>, Option<f64>), DatabaseError> {
+        let _timer = self.time_query("get_best_prices");
         let query = r#"
-            SELECT 
-                p.address,
-                p.name,
-                p.energy_generated,
-                p.energy_consumed,
-                (p.energy_generated - p.energy_consumed) as net_energy,
-                p.grid_tokens,
-                p.watt_tokens,
-                (SELECT COUNT(*) FROM orders WHERE prosumer_address = p.address) as orders_count,
-                (SELECT COUNT(*) FROM trades WHERE buyer_address = p.address OR seller_address = p.address) as trades_count,
-                (SELECT COALESCE(SUM(energy_amount), 0) FROM trades WHERE (buyer_address = p.address OR seller_address = p.address) AND status = 'completed') as total_energy_traded,
-                (SELECT COALESCE(SUM(total_price), 0) FROM trades WHERE (buyer_address = p.address OR seller_address = p.address) AND status = 'completed') as total_volume
-            FROM prosumers p
-            WHERE p.address = $1
+            SELECT
+                (SELECT MAX(price_per_unit) FROM orders WHERE status = 'active' AND order_type = 'buy' AND energy_amount > filled_amount AND (expires_at IS NULL OR expires_at > $1)),
+                (SELECT MIN(price_per_unit) FROM orders WHERE status = 'active' AND order_type = 'sell' AND energy_amount > filled_amount AND (expires_at IS NULL OR expires_at > $1))
         "#;
-        
-        match &self.pool {
+
+        let now = self.now();
+        let prices = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_as::<_, (Option<f64>, Option<f64>)>(query).bind(now).fetch_one(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_as::<_, (Option<f64>, Option<f64>)>(query).bind(now).fetch_one(pool).await?,
+        };
+        Ok(prices)
+    }
+
+    pub async fn create_price_alert(&self, address: &str, side: &str, direction: &str, price: f64) -> Result<PriceAlert, DatabaseError> {
+        let _timer = self.time_query("create_price_alert");
+        if !self.prosumer_exists(address).await? {
+            return Err(prosumer_not_found(address));
+        }
+
+        let alert = PriceAlert {
+            id: Uuid::new_v4(),
+            address: address.to_string(),
+            side: side.to_string(),
+            direction: direction.to_string(),
+            price,
+            triggered: false,
+            triggered_price: None,
+            triggered_at: None,
+            created_at: self.now(),
+        };
+        let query = r#"
+            INSERT INTO price_alerts (id, address, side, direction, price, triggered, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#;
+
+        match self.write_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query(query)
-                    .bind(address)
-                    .fetch_optional(pool)
+                sqlx::query(query)
+                    .bind(alert.id)
+                    .bind(&alert.address)
+                    .bind(&alert.side)
+                    .bind(&alert.direction)
+                    .bind(alert.price)
+                    .bind(alert.triggered)
+                    .bind(alert.created_at)
+                    .execute(pool)
                     .await?;
-                match row {
-                    Some(row) => Ok(ProsumerStats {
-                        address: row.get::<String, _>("address"),
-                        name: row.get::<String, _>("name"),
-                        energy_generated: row.get::<f64, _>("energy_generated"),
-                        energy_consumed: row.get::<f64, _>("energy_consumed"),
-                        net_energy: row.get::<f64, _>("net_energy"),
-                        grid_tokens: row.get::<f64, _>("grid_tokens"),
-                        watt_tokens: row.get::<f64, _>("watt_tokens"),
-                        orders_count: row.get::<i64, _>("orders_count"),
-                        trades_count: row.get::<i64, _>("trades_count"),
-                        total_energy_traded: row.get::<f64, _>("total_energy_traded"),
-                        total_volume: row.get::<f64, _>("total_volume"),
-                    }),
-                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query(query)
+                    .bind(alert.id)
+                    .bind(&alert.address)
+                    .bind(&alert.side)
+                    .bind(&alert.direction)
+                    .bind(alert.price)
+                    .bind(alert.triggered)
+                    .bind(alert.created_at)
+                    .execute(pool)
+                    .await?;
+            }
+        }
+        Ok(alert)
+    }
+
+    // Alerts for one prosumer, or all alerts when `address` is None, newest first. With
+    // `owner_id` only alerts on prosumers owned by that user are listed.
+    pub async fn get_price_alerts(&self, address: Option<&str>, owner_id: Option<&str>) -> Result<Vec<PriceAlert>, DatabaseError> {
+        let _timer = self.time_query("get_price_alerts");
+        let mut conditions = Vec::new();
+        if address.is_some() {
+            conditions.push(format!("address = ${}", conditions.len() + 1));
+        }
+        if owner_id.is_some() {
+            conditions.push(format!("address IN (SELECT address FROM prosumers WHERE owner_id = ${})", conditions.len() + 1));
+        }
+        let filter = if conditions.is_empty() { String::new() } else { format!(" WHERE {}", conditions.join(" AND ")) };
+        let query = format!("SELECT * FROM price_alerts{} ORDER BY created_at DESC", filter);
+
+        let alerts = match self.read_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query_as::<_, PriceAlert>(&query);
+                for value in [address, owner_id].into_iter().flatten() {
+                    q = q.bind(value);
+                }
+                q.fetch_all(pool).await?
+            }
+            DatabasePool::Sqlite(pool) => {
+                let mut q = sqlx::query_as::<_, PriceAlert>(&query);
+                for value in [address, owner_id].into_iter().flatten() {
+                    q = q.bind(value);
+                }
+                q.fetch_all(pool).await?
+            }
+        };
+        Ok(alerts)
+    }
+
+    // With `owner_id` the alert is only deleted when its prosumer belongs to that user; anyone
+    // else's alert is reported as not found
+    pub async fn delete_price_alert(&self, id: Uuid, owner_id: Option<&str>) -> Result<(), DatabaseError> {
+        let _timer = self.time_query("delete_price_alert");
+        let query = match owner_id {
+            Some(_) => "DELETE FROM price_alerts WHERE id = $1 AND address IN (SELECT address FROM prosumers WHERE owner_id = $2)",
+            None => "DELETE FROM price_alerts WHERE id = $1",
+        };
+
+        let rows_affected = match self.write_pool() {
+            DatabasePool::Postgres(pool) => {
+                let mut q = sqlx::query(query).bind(id);
+                if let Some(owner_id) = owner_id {
+                    q = q.bind(owner_id);
                 }
+                q.execute(pool).await?.rows_affected()
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query(query)
-                    .bind(address)
-                    .fetch_optional(pool)
-                    .await?;
-                match row {
-                    Some(row) => Ok(ProsumerStats {
-                        address: row.get::<String, _>("address"),
-                        name: row.get::<String, _>("name"),
-                        energy_generated: row.get::<f64, _>("energy_generated"),
-                        energy_consumed: row.get::<f64, _>("energy_consumed"),
-                        net_energy: row.get::<f64, _>("net_energy"),
-                        grid_tokens: row.get::<f64, _>("grid_tokens"),
-                        watt_tokens: row.get::<f64, _>("watt_tokens"),
-                        orders_count: row.get::<i64, _>("orders_count"),
-                        trades_count: row.get::<i64, _>("trades_count"),
-                        total_energy_traded: row.get::<f64, _>("total_energy_traded"),
-                        total_volume: row.get::<f64, _>("total_volume"),
-                    }),
-                    None => Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", address))),
+                let mut q = sqlx::query(query).bind(id);
+                if let Some(owner_id) = owner_id {
+                    q = q.bind(owner_id);
                 }
+                q.execute(pool).await?.rows_affected()
             }
+        };
+
+        if rows_affected == 0 {
+            return Err(DatabaseError::NotFound(format!("Price alert '{}' not found", id)));
         }
+        Ok(())
     }
 
-    pub async fn get_stats(&self) -> Result<DatabaseStats, DatabaseError> {
-        let query = r#"
-            SELECT 
-                (SELECT COUNT(*) FROM prosumers) as total_prosumers,
-                (SELECT COUNT(*) FROM orders) as total_orders,
-                (SELECT COUNT(*) FROM trades) as total_trades
+    // Marks every pending alert crossed by the current best bid/ask as triggered and returns
+    // them. Each alert is claimed with a conditional update, so concurrent checks fire it once.
+    pub async fn trigger_price_alerts(&self) -> Result<Vec<PriceAlert>, DatabaseError> {
+        let _timer = self.time_query("trigger_price_alerts");
+        let (best_bid, best_ask) = self.get_best_prices().await?;
+        if best_bid.is_none() && best_ask.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let pending_query = "SELECT * FROM price_alerts WHERE triggered = FALSE";
+        let claim_query = r#"
+            UPDATE price_alerts SET triggered = TRUE, triggered_price = $1, triggered_at = $2
+            WHERE id = $3 AND triggered = FALSE
         "#;
-        
-        match &self.pool {
+
+        let pending = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_as::<_, PriceAlert>(pending_query).fetch_all(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_as::<_, PriceAlert>(pending_query).fetch_all(pool).await?,
+        };
+
+        let now = self.now();
+        let mut fired = Vec::new();
+        for mut alert in pending {
+            let best = if alert.side == "bid" { best_bid } else { best_ask };
+            let Some(best) = best.filter(|best| alert.is_crossed(*best)) else {
+                continue;
+            };
+
+            let rows_affected = match self.write_pool() {
+                DatabasePool::Postgres(pool) => {
+                    sqlx::query(claim_query).bind(best).bind(now).bind(alert.id).execute(pool).await?.rows_affected()
+                }
+                DatabasePool::Sqlite(pool) => {
+                    sqlx::query(claim_query).bind(best).bind(now).bind(alert.id).execute(pool).await?.rows_affected()
+                }
+            };
+            if rows_affected == 1 {
+                alert.triggered = true;
+                alert.triggered_price = Some(best);
+                alert.triggered_at = Some(now);
+                fired.push(alert);
+            }
+        }
+        Ok(fired)
+    }
+
+    // Stores the current aggregated book and its checksum
+    pub async fn snapshot_order_book(&self) -> Result<OrderBookSnapshot, DatabaseError> {
+        let _timer = self.time_query("snapshot_order_book");
+        let book = self.get_order_book().await?.aggregate();
+        let snapshot = OrderBookSnapshot {
+            id: Uuid::new_v4(),
+            checksum: book.checksum(),
+            book,
+            created_at: self.now(),
+        };
+        let encoded = serde_json::to_string(&snapshot.book)
+            .map_err(|e| DatabaseError::Validation(format!("Failed to encode order book: {}", e)))?;
+        let query = "INSERT INTO order_book_snapshots (id, book, checksum, created_at) VALUES ($1, $2, $3, $4)";
+
+        match self.write_pool() {
             DatabasePool::Postgres(pool) => {
-                let row = sqlx::query(query).fetch_one(pool).await?;
-                Ok(DatabaseStats {
-                    total_users: 0, // We don't have users table yet
-                    total_api_keys: 0, // We don't have api_keys table yet
-                    total_prosumers: row.get::<i64, _>("total_prosumers"),
-                    total_orders: row.get::<i64, _>("total_orders"),
-                    total_trades: row.get::<i64, _>("total_trades"),
-                    database_size: None, // Would need platform-specific queries
-                })
+                sqlx::query(query)
+                    .bind(snapshot.id)
+                    .bind(&encoded)
+                    .bind(&snapshot.checksum)
+                    .bind(snapshot.created_at)
+                    .execute(pool)
+                    .await?;
             }
             DatabasePool::Sqlite(pool) => {
-                let row = sqlx::query(query).fetch_one(pool).await?;
-                Ok(DatabaseStats {
-                    total_users: 0, // We don't have users table yet
-                    total_api_keys: 0, // We don't have api_keys table yet
-                    total_prosumers: row.get::<i64, _>("total_prosumers"),
-                    total_orders: row.get::<i64, _>("total_orders"),
-                    total_trades: row.get::<i64, _>("total_trades"),
-                    database_size: None, // Would need platform-specific queries
-                })
+                sqlx::query(query)
+                    .bind(snapshot.id)
+                    .bind(&encoded)
+                    .bind(&snapshot.checksum)
+                    .bind(snapshot.created_at)
+                    .execute(pool)
+                    .await?;
             }
         }
+        Ok(snapshot)
     }
 
-    pub async fn transfer_tokens(&self, from_address: &str, to_address: &str, amount: f64, token_type: &str) -> Result<String, DatabaseError> {
-        // Start a transaction
-        let transaction_id = Uuid::new_v4();
-        
-        match &self.pool {
+    // Compares a freshly aggregated book against the most recent snapshot
+    pub async fn verify_order_book(&self) -> Result<OrderBookVerification, DatabaseError> {
+        let _timer = self.time_query("verify_order_book");
+        let query = "SELECT * FROM order_book_snapshots ORDER BY created_at DESC LIMIT 1";
+
+        let row = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_as::<_, OrderBookSnapshotRow>(query).fetch_optional(pool).await?,
+            DatabasePool::Sqlite(pool) => sqlx::query_as::<_, OrderBookSnapshotRow>(query).fetch_optional(pool).await?,
+        };
+        let Some(row) = row else {
+            return Err(DatabaseError::NotFound("No order book snapshot has been taken".to_string()));
+        };
+        let snapshot_book: AggregatedBook = serde_json::from_str(&row.book)
+            .map_err(|e| DatabaseError::Validation(format!("Snapshot {} is unreadable: {}", row.id, e)))?;
+
+        let current = self.get_order_book().await?.aggregate();
+        let current_checksum = current.checksum();
+        let changed_levels = diff_order_books(&snapshot_book, &current);
+
+        Ok(OrderBookVerification {
+            snapshot_id: row.id,
+            snapshot_at: row.created_at,
+            drifted: current_checksum != row.checksum || !changed_levels.is_empty(),
+            snapshot_checksum: row.checksum,
+            current_checksum,
+            changed_levels,
+        })
+    }
+
+    // Runs the configured matching engine over the current book and executes each proposed
+    // fill in its own transaction. A proposal invalidated by a concurrent change is skipped.
+    // Work is done in passes of up to MATCH_BATCH_SIZE fills, re-planning from the book after
+    // each pass, until nothing more executes or MATCH_MAX_TRADES_PER_RUN is reached.
+    // Runs are serialized so manual and scheduled matching never overlap.
+    // Read from the primary so a halt takes effect immediately; without the row the market is open
+    pub async fn get_market_halt(&self) -> Result<MarketHalt, DatabaseError> {
+        let _timer = self.time_query("get_market_halt");
+        let halt = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query(MARKET_HALT_QUERY).fetch_optional(pool).await?.map(|row| MarketHalt {
+                halted: row.get("halted"),
+                reason: row.get("reason"),
+                updated_at: row.get("updated_at"),
+            }),
+            DatabasePool::Sqlite(pool) => sqlx::query(MARKET_HALT_QUERY).fetch_optional(pool).await?.map(|row| MarketHalt {
+                halted: row.get("halted"),
+                reason: row.get("reason"),
+                updated_at: row.get("updated_at"),
+            }),
+        };
+        Ok(halt.unwrap_or_default())
+    }
+
+    // Halts or resumes trading. The state is persisted, so a halt survives a restart, and
+    // the change is recorded in the admin audit log.
+    pub async fn set_market_halt(&self, halted: bool, reason: Option<&str>, admin_id: &str) -> Result<MarketHalt, DatabaseError> {
+        let _timer = self.time_query("set_market_halt");
+        let halt = MarketHalt {
+            halted,
+            reason: reason.map(str::to_string),
+            updated_at: Some(self.now()),
+        };
+        let audit_query = r#"
+            INSERT INTO admin_audit_log (id, admin_id, action, target_id, reason, created_at)
+            VALUES ($1, $2, $3, 'market', $4, $5)
+        "#;
+        let action = if halted { "halt_market" } else { "resume_market" };
+
+        match self.write_pool() {
             DatabasePool::Postgres(pool) => {
                 let mut tx = pool.begin().await?;
-                
-                // Check if sender has enough tokens
-                let sender = sqlx::query_as::<_, ProsumerRow>("SELECT * FROM prosumers WHERE address = $1")
-                    .bind(from_address)
-                    .fetch_optional(&mut *tx)
+                sqlx::query(SET_MARKET_HALT_QUERY)
+                    .bind(halt.halted)
+                    .bind(&halt.reason)
+                    .bind(halt.updated_at)
+                    .execute(&mut *tx)
                     .await?;
-                
-                if let Some(sender) = sender {
-                    let current_balance = match token_type {
-                        "grid_tokens" => sender.grid_tokens,
-                        "watt_tokens" => sender.watt_tokens,
-                        _ => return Err(DatabaseError::Validation("Invalid token type".to_string())),
-                    };
-                    
-                    if current_balance < amount {
-                        return Err(DatabaseError::Validation("Insufficient tokens".to_string()));
-                    }
-                    
-                    // Deduct from sender
-                    let query = match token_type {
-                        "grid_tokens" => "UPDATE prosumers SET grid_tokens = grid_tokens - $1, updated_at = $2 WHERE address = $3",
-                        "watt_tokens" => "UPDATE prosumers SET watt_tokens = watt_tokens - $1, updated_at = $2 WHERE address = $3",
-                        _ => unreachable!(),
-                    };
-                    
-                    sqlx::query(query)
-                        .bind(amount)
-                        .bind(Utc::now())
-                        .bind(from_address)
-                        .execute(&mut *tx)
-                        .await?;
-                    
-                    // Add to recipient
-                    let query = match token_type {
-                        "grid_tokens" => "UPDATE prosumers SET grid_tokens = grid_tokens + $1, updated_at = $2 WHERE address = $3",
-                        "watt_tokens" => "UPDATE prosumers SET watt_tokens = watt_tokens + $1, updated_at = $2 WHERE address = $3",
-                        _ => unreachable!(),
-                    };
-                    
-                    sqlx::query(query)
-                        .bind(amount)
-                        .bind(Utc::now())
-                        .bind(to_address)
-                        .execute(&mut *tx)
-                        .await?;
-                    
-                    tx.commit().await?;
-                    Ok(transaction_id.to_string())
-                } else {
-                    Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", from_address)))
-                }
+                sqlx::query(audit_query)
+                    .bind(Uuid::new_v4())
+                    .bind(admin_id)
+                    .bind(action)
+                    .bind(reason.unwrap_or_default())
+                    .bind(halt.updated_at)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
             }
             DatabasePool::Sqlite(pool) => {
                 let mut tx = pool.begin().await?;
-                
-                // Check if sender has enough tokens
-                let sender = sqlx::query_as::<_, ProsumerRow>("SELECT * FROM prosumers WHERE address = $1")
-                    .bind(from_address)
-                    .fetch_optional(&mut *tx)
+                sqlx::query(SET_MARKET_HALT_QUERY)
+                    .bind(halt.halted)
+                    .bind(&halt.reason)
+                    .bind(halt.updated_at)
+                    .execute(&mut *tx)
                     .await?;
-                
-                if let Some(sender) = sender {
-                    let current_balance = match token_type {
-                        "grid_tokens" => sender.grid_tokens,
-                        "watt_tokens" => sender.watt_tokens,
-                        _ => return Err(DatabaseError::Validation("Invalid token type".to_string())),
-                    };
-                    
-                    if current_balance < amount {
-                        return Err(DatabaseError::Validation("Insufficient tokens".to_string()));
-                    }
-                    
-                    // Deduct from sender
-                    let query = match token_type {
-                        "grid_tokens" => "UPDATE prosumers SET grid_tokens = grid_tokens - $1, updated_at = $2 WHERE address = $3",
-                        "watt_tokens" => "UPDATE prosumers SET watt_tokens = watt_tokens - $1, updated_at = $2 WHERE address = $3",
-                        _ => unreachable!(),
-                    };
-                    
-                    sqlx::query(query)
-                        .bind(amount)
-                        .bind(Utc::now())
-                        .bind(from_address)
-                        .execute(&mut *tx)
-                        .await?;
-                    
-                    // Add to recipient
-                    let query = match token_type {
-                        "grid_tokens" => "UPDATE prosumers SET grid_tokens = grid_tokens + $1, updated_at = $2 WHERE address = $3",
-                        "watt_tokens" => "UPDATE prosumers SET watt_tokens = watt_tokens + $1, updated_at = $2 WHERE address = $3",
-                        _ => unreachable!(),
-                    };
-                    
-                    sqlx::query(query)
-                        .bind(amount)
-                        .bind(Utc::now())
-                        .bind(to_address)
-                        .execute(&mut *tx)
-                        .await?;
-                    
-                    tx.commit().await?;
-                    Ok(transaction_id.to_string())
-                } else {
-                    Err(DatabaseError::NotFound(format!("Prosumer '{}' not found", from_address)))
+                sqlx::query(audit_query)
+                    .bind(Uuid::new_v4())
+                    .bind(admin_id)
+                    .bind(action)
+                    .bind(reason.unwrap_or_default())
+                    .bind(halt.updated_at)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+            }
+        }
+        self.invalidate_market_stats();
+        Ok(halt)
+    }
+
+    async fn check_market_open(&self) -> Result<(), DatabaseError> {
+        let halt = self.get_market_halt().await?;
+        if halt.halted {
+            return Err(DatabaseError::MarketHalted(
+                halt.reason.unwrap_or_else(|| "trading is paused by an operator".to_string()),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn match_orders(&self) -> Result<MatchResult, DatabaseError> {
+        let _timer = self.time_query("match_orders");
+        self.check_market_open().await?;
+        let _guard = self.match_lock.lock().await;
+
+        let mut result = MatchResult::default();
+        // Pairs that failed this run are not retried, so they are recorded once and a pass
+        // that only re-proposes them ends the run
+        let mut failed: HashSet<(Uuid, Uuid)> = HashSet::new();
+        let mut first_pass = true;
+        while result.trades.len() < self.match_batch.max_trades_per_run {
+            let book = self.get_order_book().await?;
+            let mut plan = self.matching_engine.find_matches(&book, &self.market_config);
+            plan.reject_trade_throughs(&book);
+            if first_pass {
+                for skip in plan.skipped {
+                    self.match_diagnostics.record(skip);
                 }
+                first_pass = false;
+            }
+
+            let budget = self.match_batch.batch_size.min(self.match_batch.max_trades_per_run - result.trades.len());
+            let executed = self.execute_match_batch(&plan.proposals, budget, &mut failed, &mut result).await?;
+            if executed == 0 {
+                break;
+            }
+        }
+
+        *self.last_match_at.lock().unwrap_or_else(|e| e.into_inner()) = Some(self.now());
+        Ok(result)
+    }
+
+    // Executes up to `budget` proposals not in `failed`, adding the trades to `result`.
+    // Returns how many executed.
+    async fn execute_match_batch(&self, proposals: &[ProposedTrade], budget: usize, failed: &mut HashSet<(Uuid, Uuid)>, result: &mut MatchResult) -> Result<usize, DatabaseError> {
+        let mut executed = 0;
+        for proposal in proposals {
+            if executed >= budget {
+                break;
+            }
+            if failed.contains(&(proposal.buy_order_id, proposal.sell_order_id)) {
+                continue;
             }
+            let (reason, msg) = match self.execute_proposed_trade(proposal).await {
+                Ok(trade) => {
+                    result.matched_energy += trade.energy_amount;
+                    result.total_value += trade.total_price;
+                    result.trades.push(trade);
+                    executed += 1;
+                    continue;
+                }
+                Err(DatabaseError::Validation(msg)) | Err(DatabaseError::Conflict(msg)) => (SkipReason::Validation, msg),
+                Err(e @ DatabaseError::InsufficientBalance { .. }) => (SkipReason::Validation, e.to_string()),
+                Err(DatabaseError::LimitExceeded(msg)) => (SkipReason::DailyLimit, msg),
+                Err(e) => return Err(e),
+            };
+            log::warn!(
+                "Skipping proposed match {} -> {}: {}",
+                proposal.buy_order_id, proposal.sell_order_id, msg
+            );
+            self.match_diagnostics.record(MatchSkip::new(proposal.buy_order_id, proposal.sell_order_id, reason, msg));
+            failed.insert((proposal.buy_order_id, proposal.sell_order_id));
         }
+        Ok(executed)
     }
 
-    pub async fn match_orders(&self) -> Result<Vec<Trade>, DatabaseError> {
-        // Simple order matching algorithm
+    // A handful of cheap aggregates plus a latency probe; backs GET /admin/summary
+    pub async fn get_system_summary(&self) -> Result<SystemSummary, DatabaseError> {
+        let _timer = self.time_query("get_system_summary");
         let query = r#"
-            SELECT b.id as buy_id, b.prosumer_address as buyer_address, b.energy_amount as buy_amount, b.price_per_unit as buy_price,
-                   s.id as sell_id, s.prosumer_address as seller_address, s.energy_amount as sell_amount, s.price_per_unit as sell_price
-            FROM orders b
-            JOIN orders s ON b.order_type = 'buy' AND s.order_type = 'sell' 
-                          AND b.price_per_unit >= s.price_per_unit
-                          AND b.status = 'active' AND s.status = 'active'
-            ORDER BY b.created_at, s.created_at
-            LIMIT 10
+            SELECT
+                (SELECT COUNT(*) FROM orders WHERE status = 'active') as active_orders,
+                (SELECT COUNT(*) FROM trades WHERE status = 'pending') as pending_trades,
+                (SELECT MAX(executed_at) FROM trades) as last_trade_at
         "#;
-        
-        let mut trades = Vec::new();
-        
-        match &self.pool {
+
+        let started = std::time::Instant::now();
+        let (latency, row) = match self.read_pool() {
             DatabasePool::Postgres(pool) => {
-                let rows = sqlx::query(query).fetch_all(pool).await?;
-                
-                for row in rows {
-                    let buy_id: Uuid = row.get("buy_id");
-                    let sell_id: Uuid = row.get("sell_id");
-                    let buyer_address: String = row.get("buyer_address");
-                    let seller_address: String = row.get("seller_address");
-                    let buy_amount: f64 = row.get("buy_amount");
-                    let sell_amount: f64 = row.get("sell_amount");
-                    let buy_price: f64 = row.get("buy_price");
-                    let sell_price: f64 = row.get("sell_price");
-                    
-                    // Match at the lower price (seller's price)
-                    let trade_price = sell_price;
-                    let trade_amount = buy_amount.min(sell_amount);
-                    
-                    let trade = Trade {
-                        id: Uuid::new_v4(),
-                        buy_order_id: buy_id,
-                        sell_order_id: sell_id,
-                        buyer_address,
-                        seller_address,
-                        energy_amount: trade_amount,
-                        price_per_unit: trade_price,
-                        total_price: trade_amount * trade_price,
-                        status: "pending".to_string(),
-                        executed_at: Utc::now(),
-                        created_at: Utc::now(),
-                    };
-                    
-                    trades.push(trade);
-                }
+                sqlx::query("SELECT 1").execute(pool).await?;
+                let latency = started.elapsed();
+                let row = sqlx::query(query).fetch_one(pool).await?;
+                (latency, (row.get::<i64, _>("active_orders"), row.get::<i64, _>("pending_trades"), row.get::<Option<DateTime<Utc>>, _>("last_trade_at")))
             }
             DatabasePool::Sqlite(pool) => {
-                let rows = sqlx::query(query).fetch_all(pool).await?;
-                
-                for row in rows {
-                    let buy_id: Uuid = row.get("buy_id");
-                    let sell_id: Uuid = row.get("sell_id");
-                    let buyer_address: String = row.get("buyer_address");
-                    let seller_address: String = row.get("seller_address");
-                    let buy_amount: f64 = row.get("buy_amount");
-                    let sell_amount: f64 = row.get("sell_amount");
-                    let buy_price: f64 = row.get("buy_price");
-                    let sell_price: f64 = row.get("sell_price");
-                    
-                    // Match at the lower price (seller's price)
-                    let trade_price = sell_price;
-                    let trade_amount = buy_amount.min(sell_amount);
-                    
-                    let trade = Trade {
-                        id: Uuid::new_v4(),
-                        buy_order_id: buy_id,
-                        sell_order_id: sell_id,
-                        buyer_address,
-                        seller_address,
-                        energy_amount: trade_amount,
-                        price_per_unit: trade_price,
-                        total_price: trade_amount * trade_price,
-                        status: "pending".to_string(),
-                        executed_at: Utc::now(),
-                        created_at: Utc::now(),
-                    };
-                    
-                    trades.push(trade);
-                }
+                sqlx::query("SELECT 1").execute(pool).await?;
+                let latency = started.elapsed();
+                let row = sqlx::query(query).fetch_one(pool).await?;
+                (latency, (row.get::<i64, _>("active_orders"), row.get::<i64, _>("pending_trades"), row.get::<Option<DateTime<Utc>>, _>("last_trade_at")))
+            }
+        };
+        let (active_orders, pending_trades, last_trade_at) = row;
+
+        Ok(SystemSummary {
+            active_orders,
+            pending_trades,
+            last_match_at: *self.last_match_at.lock().unwrap_or_else(|e| e.into_inner()),
+            last_trade_at,
+            db_latency_ms: latency.as_secs_f64() * 1000.0,
+            generated_at: self.now(),
+        })
+    }
+
+    // Never fails: problems reaching the database or reading migration state are reported
+    // in the result instead
+    pub async fn get_health_details(&self, migrations: &MigrationConfig) -> HealthDetails {
+        let _timer = self.time_query("get_health_details");
+        let status_query = format!("SELECT version, success FROM {}", migrations.table);
+
+        let database = pool_health(self.write_pool()).await;
+        let replica = match &self.replica {
+            Some(replica) => Some(pool_health(replica).await),
+            None => None,
+        };
+        let rows = match self.write_pool() {
+            DatabasePool::Postgres(pool) => sqlx::query_as::<_, (i64, bool)>(&status_query).fetch_all(pool).await,
+            DatabasePool::Sqlite(pool) => sqlx::query_as::<_, (i64, bool)>(&status_query).fetch_all(pool).await,
+        };
+        let (migrations, migrations_error) = match rows {
+            Ok(rows) => {
+                let applied: HashMap<i64, bool> = rows.into_iter().collect();
+                let pending = sqlx::migrate!("./migrations")
+                    .iter()
+                    .filter(|m| !m.migration_type.is_down_migration() && !applied.contains_key(&m.version))
+                    .map(|m| m.version)
+                    .collect();
+                let mut failed: Vec<i64> = applied.iter().filter(|(_, ok)| !**ok).map(|(version, _)| *version).collect();
+                failed.sort_unstable();
+                let status = MigrationStatus {
+                    latest_applied: applied.iter().filter(|(_, ok)| **ok).map(|(version, _)| *version).max(),
+                    applied: applied.values().filter(|ok| **ok).count(),
+                    pending,
+                    failed,
+                };
+                (Some(status), None)
             }
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        let healthy = database.reachable
+            && replica.as_ref().is_none_or(|replica| replica.reachable)
+            && migrations.as_ref().is_some_and(|m| m.pending.is_empty() && m.failed.is_empty());
+        HealthDetails {
+            status: if healthy { "ok" } else { "degraded" }.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            database,
+            replica,
+            migrations,
+            migrations_error,
         }
-        
-        Ok(trades)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(order_type: &str, status: &str, energy_amount: f64, filled_amount: f64, price_per_unit: f64) -> Order {
+        let now = Utc::now();
+        Order {
+            id: Uuid::new_v4(),
+            prosumer_address: "0xbuyer".to_string(),
+            order_type: order_type.to_string(),
+            energy_amount,
+            price_per_unit,
+            total_price: energy_amount * price_per_unit,
+            currency: "USD".to_string(),
+            quoted_price_per_unit: price_per_unit,
+            filled_amount,
+            remaining_amount: energy_amount - filled_amount,
+            status: status.to_string(),
+            created_at: now,
+            updated_at: now,
+            expires_at: None,
+        }
+    }
+
+    // A buy at 0.30 crossing a sell at 0.20, with `resting` placed a minute before the other
+    fn crossing_pair(resting: &str) -> (Order, Order) {
+        let mut buy = order("buy", "active", 5.0, 0.0, 0.30);
+        let mut sell = order("sell", "active", 5.0, 0.0, 0.20);
+        sell.prosumer_address = "0xseller".to_string();
+        let (earlier, later) = if resting == "buy" { (&mut buy, &mut sell) } else { (&mut sell, &mut buy) };
+        earlier.created_at = later.created_at - chrono::Duration::minutes(1);
+        (buy, sell)
+    }
+
+    fn executed_price(policy: MatchPricePolicy, price_tick: f64, resting: &str) -> f64 {
+        let market = MarketConfig { match_price_policy: policy, price_tick, ..MarketConfig::default() };
+        let (buy, sell) = crossing_pair(resting);
+        let trade = build_fill(&buy, &sell, None, None, &market, Utc::now()).unwrap();
+        assert!((trade.total_price - trade.energy_amount * trade.price_per_unit).abs() < 1e-9);
+        trade.price_per_unit
+    }
+
+    #[test]
+    fn fill_executes_at_the_price_the_policy_picks() {
+        for resting in ["buy", "sell"] {
+            assert_eq!(executed_price(MatchPricePolicy::Seller, 0.0, resting), 0.20);
+            assert_eq!(executed_price(MatchPricePolicy::Buyer, 0.0, resting), 0.30);
+            assert!((executed_price(MatchPricePolicy::Midpoint, 0.0, resting) - 0.25).abs() < 1e-9);
+        }
+        // Resting is the maker's price: whichever order was in the book first, not the
+        // incoming taker's
+        assert_eq!(executed_price(MatchPricePolicy::Resting, 0.0, "buy"), 0.30);
+        assert_eq!(executed_price(MatchPricePolicy::Resting, 0.0, "sell"), 0.20);
+    }
+
+    #[test]
+    fn midpoint_is_rounded_to_the_tick_within_the_crossing_range() {
+        // 0.25 lies between ticks of 0.1 and rounds half away from zero
+        assert!((executed_price(MatchPricePolicy::Midpoint, 0.1, "buy") - 0.3).abs() < 1e-9);
+        // A tick coarser than the spread rounds to 0.5, outside it, so the price is clamped to
+        // the buy price
+        assert!((executed_price(MatchPricePolicy::Midpoint, 0.5, "buy") - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn explicit_price_overrides_the_policy_but_must_cross() {
+        let market = MarketConfig { match_price_policy: MatchPricePolicy::Buyer, ..MarketConfig::default() };
+        let (buy, sell) = crossing_pair("buy");
+        assert_eq!(build_fill(&buy, &sell, Some(0.22), None, &market, Utc::now()).unwrap().price_per_unit, 0.22);
+        assert!(matches!(
+            build_fill(&buy, &sell, Some(0.35), None, &market, Utc::now()),
+            Err(DatabaseError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn buy_reservation_covers_the_open_remainder_at_the_limit_price() {
+        assert_eq!(buy_reservation(&order("buy", "active", 10.0, 4.0, 0.5)), 3.0);
+        assert_eq!(buy_reservation(&order("buy", "pending", 10.0, 0.0, 0.5)), 5.0);
+    }
+
+    #[test]
+    fn only_open_buy_orders_hold_a_reservation() {
+        assert_eq!(buy_reservation(&order("sell", "active", 10.0, 0.0, 0.5)), 0.0);
+        for status in ["completed", "cancelled", "expired"] {
+            assert_eq!(buy_reservation(&order("buy", status, 10.0, 4.0, 0.5)), 0.0);
+        }
+    }
+
+    #[test]
+    fn escrow_adjustment_reserves_from_the_spendable_balance() {
+        assert_eq!(escrow_adjustment(3.0, 10.0, 1.0).unwrap(), (-3.0, 3.0));
+        // Reserving exactly what is spendable is allowed
+        assert_eq!(escrow_adjustment(10.0, 10.0, 0.0).unwrap(), (-10.0, 10.0));
+    }
+
+    #[test]
+    fn escrow_adjustment_rejects_reserving_more_than_is_spendable() {
+        match escrow_adjustment(3.0, 2.0, 5.0) {
+            Err(DatabaseError::InsufficientBalance { required, available, .. }) => {
+                assert_eq!((required, available), (3.0, 2.0));
+            }
+            other => panic!("expected InsufficientBalance, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn escrow_adjustment_releases_at_most_what_is_reserved() {
+        assert_eq!(escrow_adjustment(-3.0, 0.0, 5.0).unwrap(), (3.0, -3.0));
+        assert_eq!(escrow_adjustment(-8.0, 0.0, 5.0).unwrap(), (5.0, -5.0));
+    }
+
+    #[test]
+    fn release_that_leaves_only_rounding_noise_frees_everything() {
+        let reserved = 12.5 * 0.123 - 12.25 * 0.123;
+        let (spendable, reserved_delta) = escrow_adjustment(-(0.25 * 0.123), 0.0, reserved).unwrap();
+        assert_eq!(reserved + reserved_delta, 0.0);
+        assert_eq!(spendable, reserved);
+    }
+
+    #[test]
+    fn range_reaches_the_archive_unless_it_starts_after_it() {
+        let latest = Utc::now();
+        let hour = chrono::Duration::hours(1);
+        assert!(!range_reaches_archive(None, None));
+        assert!(range_reaches_archive(None, Some(latest)));
+        assert!(range_reaches_archive(Some(latest), Some(latest)));
+        assert!(range_reaches_archive(Some(latest - hour), Some(latest)));
+        assert!(!range_reaches_archive(Some(latest + hour), Some(latest)));
+    }
+
+    #[test]
+    fn escrow_round_trip_across_two_partial_fills_ends_at_zero() {
+        // Reserve on placement, then release what each fill frees from the order
+        let placed = order("buy", "active", 12.5, 0.0, 0.123);
+        let (_, mut reserved) = escrow_adjustment(buy_reservation(&placed), 10.0, 0.0).unwrap();
+
+        let after_first = order("buy", "active", 12.5, 12.25, 0.123);
+        let (_, delta) = settlement_adjustment(buy_reservation(&placed) - buy_reservation(&after_first), 12.25 * 0.123, 10.0, reserved).unwrap();
+        reserved += delta;
+        assert!(reserved > 0.0);
+
+        let after_second = order("buy", "completed", 12.5, 12.5, 0.123);
+        let (_, delta) = settlement_adjustment(buy_reservation(&after_first) - buy_reservation(&after_second), 0.25 * 0.123, 10.0, reserved).unwrap();
+        reserved += delta;
+        assert_eq!(reserved, 0.0);
+    }
+
+    #[test]
+    fn cancelled_order_with_energy_left_can_be_reactivated() {
+        let now = Utc::now();
+        assert!(check_reactivatable(&order("buy", "cancelled", 10.0, 4.0, 0.5), now).is_ok());
+
+        let mut not_yet_expired = order("sell", "cancelled", 10.0, 0.0, 0.5);
+        not_yet_expired.expires_at = Some(now + chrono::Duration::minutes(5));
+        assert!(check_reactivatable(&not_yet_expired, now).is_ok());
+    }
+
+    #[test]
+    fn only_cancelled_orders_can_be_reactivated() {
+        for status in ["completed", "expired", "active", "pending"] {
+            assert!(
+                matches!(check_reactivatable(&order("buy", status, 10.0, 0.0, 0.5), Utc::now()), Err(DatabaseError::Conflict(_))),
+                "{} order was reactivated",
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn fully_filled_or_expired_cancelled_order_stays_cancelled() {
+        let now = Utc::now();
+        assert!(matches!(check_reactivatable(&order("buy", "cancelled", 10.0, 10.0, 0.5), now), Err(DatabaseError::Conflict(_))));
+
+        let mut expired = order("buy", "cancelled", 10.0, 0.0, 0.5);
+        expired.expires_at = Some(now);
+        assert!(matches!(check_reactivatable(&expired, now), Err(DatabaseError::Conflict(_))));
+    }
+
+    fn trade(buyer: &str, seller: &str, energy_amount: f64, price_per_unit: f64, executed_at: DateTime<Utc>) -> Trade {
+        Trade {
+            id: Uuid::new_v4(),
+            buy_order_id: Uuid::new_v4(),
+            sell_order_id: Uuid::new_v4(),
+            buyer_address: buyer.to_string(),
+            seller_address: seller.to_string(),
+            energy_amount,
+            price_per_unit,
+            total_price: energy_amount * price_per_unit,
+            grid_fee: 0.0,
+            status: "completed".to_string(),
+            executed_at,
+            created_at: executed_at,
+            settles_at: None,
+        }
+    }
+
+    #[test]
+    fn fifo_and_average_cost_differ_on_a_partial_sale() {
+        let day = DateTime::from_timestamp(1_750_000_000, 0).unwrap();
+        // Buy 10 @ 0.10 and 10 @ 0.30, then sell 10 @ 0.40
+        let trades = [
+            trade("0xme", "0xa", 10.0, 0.1, day),
+            trade("0xme", "0xa", 10.0, 0.3, day + chrono::Duration::minutes(1)),
+            trade("0xb", "0xme", 10.0, 0.4, day + chrono::Duration::minutes(2)),
+        ];
+        let window = (day - chrono::Duration::days(1), day + chrono::Duration::days(1));
+
+        let fifo = realized_pnl("0xme", &trades, PnlMethod::Fifo, window.0, window.1);
+        assert_eq!(fifo.len(), 1);
+        assert!((fifo[0].cost_basis - 1.0).abs() < 1e-9);
+        assert!((fifo[0].realized_pnl - 3.0).abs() < 1e-9);
+
+        let average = realized_pnl("0xme", &trades, PnlMethod::AverageCost, window.0, window.1);
+        assert!((average[0].cost_basis - 2.0).abs() < 1e-9);
+        assert!((average[0].realized_pnl - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn energy_sold_beyond_purchases_has_no_cost() {
+        let day = DateTime::from_timestamp(1_750_000_000, 0).unwrap();
+        let trades = [
+            trade("0xme", "0xa", 5.0, 0.2, day),
+            trade("0xb", "0xme", 8.0, 0.3, day + chrono::Duration::minutes(1)),
+        ];
+        for method in [PnlMethod::Fifo, PnlMethod::AverageCost] {
+            let periods = realized_pnl("0xme", &trades, method, day, day + chrono::Duration::days(1));
+            assert!((periods[0].cost_basis - 1.0).abs() < 1e-9);
+            assert!((periods[0].realized_pnl - 1.4).abs() < 1e-9);
+        }
+    }
+
+    // The initial schema plus the later order and prosumer columns and the market_state table
+    // order writes touch, since the later migrations only run on Postgres
+    const SQLITE_ORDER_SCHEMA: &str = r#"
+        ALTER TABLE orders ADD COLUMN currency TEXT NOT NULL DEFAULT 'GRID';
+        ALTER TABLE orders ADD COLUMN quoted_price_per_unit REAL NOT NULL DEFAULT 0;
+        ALTER TABLE orders ADD COLUMN filled_amount REAL NOT NULL DEFAULT 0;
+        ALTER TABLE prosumers ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
+        CREATE TABLE market_state (
+            id INTEGER PRIMARY KEY,
+            halted BOOLEAN NOT NULL DEFAULT false,
+            reason TEXT,
+            updated_at TEXT
+        );
+        INSERT INTO prosumers (address, name) VALUES ('0xseller', 'Seller');
+    "#;
+
+    #[test]
+    fn returning_is_used_from_sqlite_3_35() {
+        assert!(!sqlite_supports_returning("3.34.1"));
+        assert!(sqlite_supports_returning("3.35.0"));
+        assert!(sqlite_supports_returning("3.45.1"));
+        assert_eq!(without_returning("UPDATE orders SET status = $2 WHERE id = $1 RETURNING *").trim_end(), "UPDATE orders SET status = $2 WHERE id = $1");
+    }
+
+    // Runs order creation, lookup, filtered listing and amendment on SQLite, both through
+    // RETURNING and through the write-then-select fallback for older versions
+    #[tokio::test]
+    async fn order_crud_runs_on_sqlite_with_and_without_returning() {
+        for returning in [true, false] {
+            let path = std::env::temp_dir().join(format!("orders-{}.db", Uuid::new_v4()));
+            let mut db = DatabaseService::new(&format!("sqlite://{}", path.display())).await.unwrap();
+            db.sqlite_returning = returning;
+            let DatabasePool::Sqlite(pool) = &db.pool else { unreachable!() };
+            let mut conn = pool.acquire().await.unwrap();
+            sqlx::Executor::execute(&mut *conn, include_str!("../migrations/20250710000001_initial_schema.sql")).await.unwrap();
+            sqlx::Executor::execute(&mut *conn, SQLITE_ORDER_SCHEMA).await.unwrap();
+            drop(conn);
+
+            let sell = |status: &str, energy_amount: f64, price_per_unit: f64| Order {
+                prosumer_address: "0xseller".to_string(),
+                currency: db.base_currency().to_string(),
+                ..order("sell", status, energy_amount, 0.0, price_per_unit)
+            };
+            let cheap = db.create_order(sell("active", 4.0, 0.20)).await.unwrap();
+            let dear = db.create_order(sell("pending", 2.0, 0.40)).await.unwrap();
+            assert_eq!(db.get_order(cheap.id).await.unwrap().energy_amount, 4.0);
+
+            let list = |status: Option<&str>, order_type: Option<&str>, address: Option<&str>| {
+                db.get_orders(1, 10, status.map(str::to_string), order_type.map(str::to_string), address.map(str::to_string), SortOrder::default())
+            };
+            assert_eq!(list(None, None, None).await.unwrap().len(), 2);
+            let active = list(Some("active"), Some("sell"), Some("0xseller")).await.unwrap();
+            assert_eq!(active.iter().map(|o| o.id).collect::<Vec<_>>(), vec![cheap.id]);
+            assert!(list(Some("pending"), Some("buy"), None).await.unwrap().is_empty());
+            assert!(list(None, None, Some("0xnobody")).await.unwrap().is_empty());
+
+            let amended = db.update_order(dear.id, Some(3.0), Some(0.30)).await.unwrap();
+            assert_eq!((amended.energy_amount, amended.price_per_unit), (3.0, 0.30));
+            assert!((amended.total_price - 0.90).abs() < 1e-9);
+            assert_eq!(db.get_order(dear.id).await.unwrap().energy_amount, 3.0);
+
+            drop(db);
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    fn migration(version: i64, sql: &'static str) -> Migration {
+        Migration::new(version, format!("step {}", version).into(), sqlx::migrate::MigrationType::Simple, sql.into())
+    }
+
+    #[test]
+    fn applied_migrations_must_have_succeeded_and_be_unchanged() {
+        let first = migration(1, "CREATE TABLE a (id INTEGER)");
+        let second = migration(2, "CREATE TABLE b (id INTEGER)");
+        let migrations = [&first, &second];
+        let mut applied = HashMap::from([(1, (true, first.checksum.to_vec()))]);
+        assert!(check_applied_migrations(&applied, &migrations).is_ok());
+
+        // An empty checksum is a row written without one, which is not compared
+        applied.insert(2, (true, Vec::new()));
+        assert!(check_applied_migrations(&applied, &migrations).is_ok());
+
+        applied.insert(2, (false, second.checksum.to_vec()));
+        let Err(DatabaseError::Validation(message)) = check_applied_migrations(&applied, &migrations) else { panic!() };
+        assert!(message.contains("2 previously failed"), "{}", message);
+
+        applied.insert(2, (true, first.checksum.to_vec()));
+        let Err(DatabaseError::Validation(message)) = check_applied_migrations(&applied, &migrations) else { panic!() };
+        assert!(message.contains("2 was modified"), "{}", message);
+    }
+
+    // The runner records into the configured table, skips baselined versions and refuses to
+    // go on once a recorded migration no longer matches its file
+    #[tokio::test]
+    async fn migrations_run_once_into_the_configured_table_after_the_baseline() {
+        let mut conn = sqlx::SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        let first = migration(1, "CREATE TABLE a (id INTEGER)");
+        let second = migration(2, "CREATE TABLE b (id INTEGER)");
+        let migrations = [&first, &second];
+
+        let ran = migrate::<Sqlite>(&mut conn, SQLITE_MIGRATIONS_TABLE, "schema_history", Some(1), &migrations).await.unwrap();
+        assert_eq!(ran, vec![2]);
+        let recorded: Vec<(i64, i64)> = sqlx::query_as("SELECT version, execution_time FROM schema_history ORDER BY version")
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0], (1, 0));
+        // The baselined migration was recorded without running, the other one ran
+        assert!(sqlx::query("SELECT * FROM a").fetch_all(&mut conn).await.is_err());
+        assert!(sqlx::query("SELECT * FROM b").fetch_all(&mut conn).await.is_ok());
+        let default_table: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE name = '_sqlx_migrations'")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(default_table, 0);
+
+        let ran = migrate::<Sqlite>(&mut conn, SQLITE_MIGRATIONS_TABLE, "schema_history", None, &migrations).await.unwrap();
+        assert!(ran.is_empty());
+
+        let edited = migration(2, "CREATE TABLE b (id INTEGER, name TEXT)");
+        let result = migrate::<Sqlite>(&mut conn, SQLITE_MIGRATIONS_TABLE, "schema_history", None, &[&first, &edited]).await;
+        assert!(matches!(result, Err(DatabaseError::Validation(_))), "{:?}", result);
+    }
+
+    fn level(price: f64, energy: f64, order_count: i64) -> PriceLevel {
+        PriceLevel { price, energy, order_count }
+    }
+
+    #[test]
+    fn order_book_diff_reports_changed_added_and_removed_levels() {
+        let snapshot = AggregatedBook {
+            bids: vec![level(0.3, 5.0, 1), level(0.2, 4.0, 2)],
+            asks: vec![level(0.4, 3.0, 1)],
+        };
+        // A snapshot survives being stored as JSON and read back
+        let stored: AggregatedBook = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+        assert_eq!(stored.checksum(), snapshot.checksum());
+        assert!(diff_order_books(&snapshot, &stored).is_empty());
+
+        let current = AggregatedBook {
+            bids: vec![level(0.3, 5.0 + 1e-9, 1), level(0.2, 1.5, 2)],
+            asks: vec![level(0.45, 2.0, 1)],
+        };
+        let drift: Vec<(String, f64, f64, f64, i64, i64)> = diff_order_books(&snapshot, &current)
+            .into_iter()
+            .map(|d| (d.side, d.price, d.snapshot_energy, d.current_energy, d.snapshot_orders, d.current_orders))
+            .collect();
+        assert_eq!(
+            drift,
+            vec![
+                ("bid".to_string(), 0.2, 4.0, 1.5, 2, 2),
+                ("ask".to_string(), 0.4, 3.0, 0.0, 1, 0),
+                ("ask".to_string(), 0.45, 0.0, 2.0, 0, 1),
+            ]
+        );
+    }
+
+    // Messages of the WARN events emitted while it is the default subscriber
+    #[derive(Clone, Default)]
+    struct Warnings(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Warnings {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+            if *event.metadata().level() != tracing::Level::WARN {
+                return;
+            }
+            let mut message = String::new();
+            event.record(&mut |field: &tracing::field::Field, value: &dyn std::fmt::Debug| {
+                if field.name() == "message" {
+                    message = format!("{:?}", value);
+                }
+            });
+            self.0.lock().unwrap().push(message);
+        }
+    }
+
+    // A query timer from `db` backdated as if its query had run for `elapsed_ms`
+    fn timer(db: &DatabaseService, operation: &'static str, elapsed_ms: u64) -> QueryTimer {
+        let mut timer = db.time_query(operation);
+        timer.started -= std::time::Duration::from_millis(elapsed_ms);
+        timer
+    }
+
+    #[tokio::test]
+    async fn only_queries_reaching_the_threshold_are_logged_as_slow() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let warnings = Warnings::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(warnings.clone()));
+        let db = DatabaseService::new("sqlite::memory:").await.unwrap().with_slow_query_ms(100);
+        drop(timer(&db, "get_order", 0));
+        drop(timer(&db, "get_orders", 250));
+        // A zero threshold switches the log off
+        let db = db.with_slow_query_ms(0);
+        drop(timer(&db, "match_orders", 250));
+
+        let logged = warnings.0.lock().unwrap().clone();
+        assert_eq!(logged.len(), 1, "{:?}", logged);
+        assert!(logged[0].starts_with("Slow query: get_orders took "), "{}", logged[0]);
+        assert!(logged[0].ends_with("(threshold 100 ms)"), "{}", logged[0]);
+    }
+
+    #[test]
+    fn sort_order_accepts_allowlisted_columns_in_either_direction() {
+        let parse = |value: &str| SortOrder::parse(value, &ORDER_SORT_COLUMNS);
+        assert_eq!(parse("price_per_unit:asc"), Ok(SortOrder { column: "price_per_unit", descending: false }));
+        assert_eq!(parse(" price_per_unit : DESC "), Ok(SortOrder { column: "price_per_unit", descending: true }));
+        assert_eq!(parse("energy_amount"), Ok(SortOrder { column: "energy_amount", descending: false }));
+        assert_eq!(parse("price_per_unit:desc").unwrap().to_sql("o."), "o.price_per_unit DESC");
+        assert_eq!(SortOrder::default().to_sql(""), "created_at DESC");
+    }
+
+    #[test]
+    fn sort_order_rejects_unknown_columns_and_directions() {
+        let parse = |value: &str| SortOrder::parse(value, &ORDER_SORT_COLUMNS);
+        assert!(parse("password").unwrap_err().starts_with("cannot sort by 'password'"));
+        assert!(parse("created_at; DROP TABLE orders").is_err());
+        // A column allowed for another list is not allowed here
+        assert!(parse("name").is_err());
+        assert!(parse("price_per_unit:sideways").unwrap_err().contains("'sideways'"));
     }
 }
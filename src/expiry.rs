@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use ntex::time::{sleep, Millis};
+
+use crate::database::DatabaseService;
+use crate::webhooks;
+
+// Most orders expired per run, so a large backlog is worked off over several runs
+const EXPIRY_BATCH_SIZE: i64 = 500;
+
+// Order expiry
+//
+// Orders past their `expires_at` already drop out of the book and can't be filled. Every
+// `interval_secs` this marks them `expired` and releases what buy orders still hold in
+// escrow back to the buyer's spendable balance.
+pub async fn run_order_expiry(db: Arc<DatabaseService>, interval_secs: u64) {
+    loop {
+        sleep(Millis(interval_secs.saturating_mul(1000).min(u32::MAX as u64) as u32)).await;
+
+        match db.expire_orders(db.now(), EXPIRY_BATCH_SIZE).await {
+            Ok(orders) if orders.is_empty() => {}
+            Ok(orders) => {
+                log::info!("Expired {} orders", orders.len());
+                for order in &orders {
+                    webhooks::notify(&db, webhooks::ORDER_EXPIRED, order);
+                }
+                webhooks::check_price_alerts(&db);
+            }
+            Err(e) => log::error!("Order expiry failed: {}", e),
+        }
+    }
+}
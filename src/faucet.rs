@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::FaucetConfig;
+
+// Test token faucet
+//
+// Tracks when each address last received tokens so drips can be rate limited. The
+// cooldown is in-memory and per process, which is enough for the test environments
+// the faucet is meant for.
+#[derive(Debug)]
+pub struct Faucet {
+    config: FaucetConfig,
+    last_drip: Mutex<HashMap<String, Instant>>,
+}
+
+impl Faucet {
+    pub fn new(config: FaucetConfig) -> Self {
+        Self {
+            config,
+            last_drip: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &FaucetConfig {
+        &self.config
+    }
+
+    // Claims a drip for `address`, or returns how long until it may drip again. The claim
+    // is recorded immediately so concurrent requests can't both pass the check.
+    pub fn claim(&self, address: &str) -> Result<(), Duration> {
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        let mut last_drip = self.last_drip.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if let Some(last) = last_drip.get(address) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < cooldown {
+                return Err(cooldown - elapsed);
+            }
+        }
+        last_drip.retain(|_, last| now.duration_since(*last) < cooldown);
+        last_drip.insert(address.to_string(), now);
+        Ok(())
+    }
+
+    // Gives back a claim whose credit failed
+    pub fn release(&self, address: &str) {
+        self.last_drip.lock().unwrap_or_else(|e| e.into_inner()).remove(address);
+    }
+}
@@ -1,13 +1,102 @@
 use std::sync::Arc;
 
+use ntex::http::{header, StatusCode};
 use ntex::web::{self, HttpResponse};
 use ntex::web::types::State;
+use serde::Serialize;
 use serde_json::json;
 use uuid::Uuid;
-use chrono::Utc;
 
-use crate::database::{DatabaseService, Prosumer, Order, Trade};
+use crate::database::{
+    BatchOperation, BatchOperationResult, DatabaseError, DatabaseService, Prosumer, ProsumerStatsSort, Order, OrderWithTrades,
+    SortOrder, TagFilter, prosumer_not_found, timeseries_bucket_count, ORDER_SORT_COLUMNS, PROSUMER_SORT_COLUMNS, TRADE_SORT_COLUMNS,
+};
+use crate::config::MAX_OPEN_ORDERS_TAG;
+use crate::faucet::Faucet;
+use crate::matching;
+use crate::auth::AuthError;
+use crate::middleware::AuthContext;
 use crate::models::*;
+use crate::webhooks;
+
+// Number of items a paginated listing matches across all its pages
+const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+// HTTP status for a failed database operation: 400 validation, 404 not found, 409 conflict,
+// 422 insufficient balance or daily limit reached, 503 market halted or query timed out, 500 for
+// anything else
+fn error_status(e: &DatabaseError) -> StatusCode {
+    match e {
+        DatabaseError::Validation(_) => StatusCode::BAD_REQUEST,
+        DatabaseError::NotFound(_) | DatabaseError::SqlxError(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+        DatabaseError::Conflict(_) => StatusCode::CONFLICT,
+        DatabaseError::SqlxError(sqlx::Error::Database(db)) if db.is_unique_violation() => StatusCode::CONFLICT,
+        DatabaseError::InsufficientBalance { .. } | DatabaseError::LimitExceeded(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        DatabaseError::BatchFailed { source, .. } => error_status(source),
+        DatabaseError::MarketHalted(_) | DatabaseError::Timeout(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// Response for a batch rolled back by its operation at `index`
+pub(crate) fn batch_failed(index: usize, source: &DatabaseError) -> HttpResponse {
+    HttpResponse::build(error_status(source)).json(&json!({
+        "error": format!("Batch rolled back: {}", source),
+        "failed_index": index
+    }))
+}
+
+// `{"error": ...}` response for a failed database operation. Domain errors carry their own
+// message; anything else is reported as "Failed to <action>: <error>". A balance shortfall
+// also reports `token_type`, `required` and `available` so clients can offer to top up.
+pub(crate) fn database_error(action: &str, e: DatabaseError) -> HttpResponse {
+    let status = error_status(&e);
+    if let DatabaseError::InsufficientBalance { token_type, required, available } = &e {
+        return HttpResponse::build(status).json(&json!({
+            "error": e.to_string(),
+            "token_type": token_type,
+            "required": required,
+            "available": available
+        }));
+    }
+    let message = match e {
+        DatabaseError::Validation(msg)
+        | DatabaseError::NotFound(msg)
+        | DatabaseError::Conflict(msg)
+        | DatabaseError::LimitExceeded(msg) => msg,
+        e @ DatabaseError::MarketHalted(_) => e.to_string(),
+        e => format!("Failed to {}: {}", action, e),
+    };
+    HttpResponse::build(status).json(&json!({
+        "error": message
+    }))
+}
+
+// Adds a "did you mean" hint to a missing-prosumer error when the caller is authenticated and
+// one of `addresses` is the missing one. Anonymous callers only learn that the address doesn't
+// exist, so the hint can't be used to discover registered addresses.
+async fn hint_missing_address(state: &DatabaseService, auth: Option<&AuthContext>, addresses: &[&str], e: DatabaseError) -> DatabaseError {
+    let (Some(_), DatabaseError::NotFound(message)) = (auth, &e) else { return e };
+    for address in addresses {
+        if e.to_string() != prosumer_not_found(address).to_string() {
+            continue;
+        }
+        if let Some(suggestion) = state.closest_address(address).await {
+            return DatabaseError::NotFound(format!("{} (did you mean '{}'?)", message, suggestion));
+        }
+    }
+    e
+}
+
+// 200 with the `?fields=` selection of `value`, or 400 when a requested field doesn't exist
+fn json_fields<T: Serialize>(value: &T, fields: Option<&str>) -> HttpResponse {
+    match select_fields(value, fields) {
+        Ok(json) => HttpResponse::Ok().json(&json),
+        Err(msg) => HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })),
+    }
+}
 
 // Root handler - returns API information
 pub async fn root() -> Result<HttpResponse, ntex::web::Error> {
@@ -21,118 +110,216 @@ pub async fn root() -> Result<HttpResponse, ntex::web::Error> {
 // Health check endpoint
 pub async fn health_check() -> Result<HttpResponse, ntex::web::Error> {
     Ok(HttpResponse::Ok().json(&json!({
-        "status": "healthy",
+        "status": "ok",
     })))
 }
 
+fn new_prosumer(state: &DatabaseService, request: &CreateProsumerRequest) -> Prosumer {
+    Prosumer {
+        address: request.address.clone(),
+        name: request.name.clone(),
+        energy_generated: 0.0,
+        energy_consumed: 0.0,
+        grid_tokens: state.initial_balances().grid_tokens,
+        watt_tokens: state.initial_balances().watt_tokens,
+        is_active: true,
+        created_at: state.now(),
+        updated_at: state.now(),
+    }
+}
+
+fn new_order(state: &DatabaseService, request: &CreateOrderRequest) -> Order {
+    Order {
+        id: Uuid::new_v4(),
+        prosumer_address: request.prosumer_address.clone(),
+        order_type: request.order_type.clone(),
+        energy_amount: request.energy_amount,
+        price_per_unit: request.price_per_unit, // normalized by the database service
+        total_price: request.energy_amount * request.price_per_unit,
+        currency: request.currency.clone().unwrap_or_else(|| state.base_currency().to_string()),
+        quoted_price_per_unit: request.price_per_unit,
+        filled_amount: 0.0,
+        remaining_amount: request.energy_amount,
+        status: "active".to_string(),
+        created_at: state.now(),
+        updated_at: state.now(),
+        expires_at: request.expires_at,
+    }
+}
+
 // Prosumer handlers
+// An authenticated caller becomes the prosumer's owner
 pub async fn create_prosumer(
     state: State<Arc<DatabaseService>>,
+    auth: Option<AuthContext>,
     body: web::types::Json<CreateProsumerRequest>,
 ) -> Result<HttpResponse, ntex::web::Error> {
-    let prosumer = Prosumer {
-        address: body.address.clone(),
-        name: body.name.clone(),
-        energy_generated: 0.0,
-        energy_consumed: 0.0,
-        grid_tokens: 0.0,
-        watt_tokens: 0.0,
-        is_active: true,
-        created_at: Utc::now(),
-        updated_at: Utc::now(),
-    };
-    
-    match state.create_prosumer(prosumer).await {
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+    let prosumer = new_prosumer(&state, &request);
+    let owner_id = auth.as_ref().map(|auth| auth.user_id.as_str());
+
+    match state.create_prosumer_for(prosumer, owner_id).await {
         Ok(prosumer) => Ok(HttpResponse::Created().json(&prosumer)),
-        Err(e) => Ok(HttpResponse::BadRequest().json(&json!({
-            "error": format!("Failed to create prosumer: {}", e)
-        })))
+        Err(e) => Ok(database_error("create prosumer", e))
     }
 }
 
 pub async fn get_prosumer(
     state: State<Arc<DatabaseService>>,
     address: web::types::Path<String>,
+    query: web::types::Query<ProsumerQuery>,
 ) -> Result<HttpResponse, ntex::web::Error> {
     let address = address.into_inner();
-    match state.get_prosumer(&address).await {
-        Ok(prosumer) => Ok(HttpResponse::Ok().json(&prosumer)),
-        Err(e) => {
-            if e.to_string().contains("not found") {
-                Ok(HttpResponse::NotFound().json(&json!({
-                    "error": format!("Prosumer with address {} not found", address)
-                })))
-            } else {
-                Ok(HttpResponse::InternalServerError().json(&json!({
-                    "error": format!("Failed to get prosumer: {}", e)
-                })))
-            }
-        }
+    let fields = query.fields.as_deref();
+    let result = if query.include_counts.unwrap_or(false) {
+        state.get_prosumer_with_counts(&address).await.map(|prosumer| json_fields(&prosumer, fields))
+    } else {
+        state.get_prosumer(&address).await.map(|prosumer| json_fields(&prosumer, fields))
+    };
+    match result {
+        Ok(response) => Ok(response),
+        Err(e) => Ok(database_error("get prosumer", e))
     }
 }
 
 pub async fn get_all_prosumers(
     state: State<Arc<DatabaseService>>,
+    query: web::types::Query<ProsumerListQuery>,
 ) -> Result<HttpResponse, ntex::web::Error> {
-    match state.get_prosumers(0, 100).await {
-        Ok(prosumers) => Ok(HttpResponse::Ok().json(&prosumers)),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(&json!({
-            "error": format!("Failed to get prosumers: {}", e)
-        })))
+    let sort = match query.sort.as_deref().map(|sort| SortOrder::parse(sort, &PROSUMER_SORT_COLUMNS)).transpose() {
+        Ok(sort) => sort.unwrap_or_default(),
+        Err(msg) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        }))),
+    };
+    let tags = match TagFilter::from_query(query.tag.as_deref(), query.region.as_deref()) {
+        Ok(tags) => tags,
+        Err(msg) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        }))),
+    };
+    let (page, limit) = state.pagination().resolve(query.page, query.limit);
+    let prosumers = match state.get_prosumers(page, limit, sort, &tags).await {
+        Ok(prosumers) => prosumers,
+        Err(e) => return Ok(database_error("get prosumers", e)),
+    };
+    match state.count_prosumers(&tags).await {
+        Ok(total) => Ok(HttpResponse::Ok()
+            .header(TOTAL_COUNT_HEADER, total.to_string())
+            .json(&PaginatedResponse { items: prosumers, page, limit })),
+        Err(e) => Ok(database_error("count prosumers", e))
+    }
+}
+
+// HEAD handlers answer existence checks without serializing a body
+pub async fn head_prosumer(
+    state: State<Arc<DatabaseService>>,
+    address: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    match state.prosumer_exists(&address.into_inner()).await {
+        Ok(true) => Ok(HttpResponse::Ok().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().finish()),
+        Err(_) => Ok(HttpResponse::InternalServerError().finish()),
     }
 }
 
+// HEAD on a listing builds the same response as GET, so its Content-Length and X-Total-Count
+// match; the server sends the headers and drops the body
+pub async fn head_all_prosumers(
+    state: State<Arc<DatabaseService>>,
+    query: web::types::Query<ProsumerListQuery>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    get_all_prosumers(state, query).await
+}
+
 pub async fn update_prosumer(
     state: State<Arc<DatabaseService>>,
     address: web::types::Path<String>,
     body: web::types::Json<UpdateProsumerRequest>,
 ) -> Result<HttpResponse, ntex::web::Error> {
     let address = address.into_inner();
-    match state.update_prosumer(&address, body.name.clone(), body.energy_generated, body.energy_consumed).await {
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+    let (name, energy_generated, energy_consumed) = request.changes();
+    match state.update_prosumer(&address, name, energy_generated, energy_consumed).await {
         Ok(prosumer) => Ok(HttpResponse::Ok().json(&prosumer)),
-        Err(e) => {
-            if e.to_string().contains("not found") {
-                Ok(HttpResponse::NotFound().json(&json!({
-                    "error": format!("Prosumer with address {} not found", address)
-                })))
-            } else {
-                Ok(HttpResponse::InternalServerError().json(&json!({
-                    "error": format!("Failed to update prosumer: {}", e)
-                })))
+        Err(e) => Ok(database_error("update prosumer", e))
+    }
+}
+
+// Deregisters a prosumer, sweeping what it still holds to `destination_address`. Only the
+// prosumer's owner or an admin may close it.
+pub async fn close_prosumer(
+    state: State<Arc<DatabaseService>>,
+    auth: AuthContext,
+    address: web::types::Path<String>,
+    body: web::types::Json<CloseProsumerRequest>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let address = address.into_inner();
+    if !auth.is_admin() {
+        match state.get_prosumer_owner(&address).await {
+            Ok(Some(owner_id)) if owner_id == auth.user_id => {}
+            Ok(_) => return Err(AuthError::InsufficientPermissions.into()),
+            Err(e) => return Ok(database_error("close prosumer", e)),
+        }
+    }
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+    match state.close_prosumer(&address, &request.destination_address).await {
+        Ok(closure) => {
+            for order in &closure.cancelled_orders {
+                webhooks::notify(state.get_ref(), webhooks::ORDER_CANCELLED, order);
             }
+            Ok(HttpResponse::Ok().json(&closure))
         }
+        Err(e) => Ok(database_error("close prosumer", e))
     }
 }
 
 // Energy order handlers
 pub async fn create_energy_order(
     state: State<Arc<DatabaseService>>,
+    auth: Option<AuthContext>,
     body: web::types::Json<CreateOrderRequest>,
 ) -> Result<HttpResponse, ntex::web::Error> {
-    let order = Order {
-        id: Uuid::new_v4(),
-        prosumer_address: body.prosumer_address.clone(),
-        order_type: body.order_type.clone(),
-        energy_amount: body.energy_amount,
-        price_per_unit: body.price_per_unit,
-        total_price: body.energy_amount * body.price_per_unit,
-        status: "active".to_string(),
-        created_at: Utc::now(),
-        updated_at: Utc::now(),
-        expires_at: body.expires_at,
-    };
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+    let order = new_order(&state, &request);
     
     match state.create_order(order).await {
-        Ok(order) => Ok(HttpResponse::Created().json(&order)),
-        Err(e) => Ok(HttpResponse::BadRequest().json(&json!({
-            "error": format!("Failed to create order: {}", e)
-        })))
+        Ok(order) => {
+            webhooks::notify(state.get_ref(), webhooks::ORDER_CREATED, &order);
+            webhooks::check_price_alerts(state.get_ref());
+            Ok(HttpResponse::Created().json(&order))
+        }
+        Err(e) => {
+            let e = hint_missing_address(&state, auth.as_ref(), &[&request.prosumer_address], e).await;
+            Ok(database_error("create order", e))
+        }
     }
 }
 
 pub async fn get_energy_order(
     state: State<Arc<DatabaseService>>,
     order_id: web::types::Path<String>,
+    query: web::types::Query<OrderQuery>,
 ) -> Result<HttpResponse, ntex::web::Error> {
     let order_id_str = order_id.into_inner();
     let order_id = match Uuid::parse_str(&order_id_str) {
@@ -143,29 +330,101 @@ pub async fn get_energy_order(
     };
     
     match state.get_order(order_id).await {
-        Ok(order) => Ok(HttpResponse::Ok().json(&order)),
-        Err(e) => {
-            if e.to_string().contains("not found") {
-                Ok(HttpResponse::NotFound().json(&json!({
-                    "error": format!("Order with ID {} not found", order_id)
-                })))
-            } else {
-                Ok(HttpResponse::InternalServerError().json(&json!({
-                    "error": format!("Failed to get order: {}", e)
-                })))
-            }
-        }
+        Ok(order) if query.include_trades.unwrap_or(false) => match state.get_trades_for_order(order_id).await {
+            Ok(trades) => Ok(json_fields(&OrderWithTrades { order, trades }, query.fields.as_deref())),
+            Err(e) => Ok(database_error("get order trades", e))
+        },
+        Ok(order) => Ok(json_fields(&order, query.fields.as_deref())),
+        Err(e) => Ok(database_error("get order", e))
     }
 }
 
+pub async fn head_energy_order(
+    state: State<Arc<DatabaseService>>,
+    order_id: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let order_id = match Uuid::parse_str(&order_id.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().finish()),
+    };
+
+    match state.order_exists(order_id).await {
+        Ok(true) => Ok(HttpResponse::Ok().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().finish()),
+        Err(_) => Ok(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+pub async fn head_all_energy_orders(
+    state: State<Arc<DatabaseService>>,
+    query: web::types::Query<OrderListQuery>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    get_all_energy_orders(state, query).await
+}
+
 pub async fn get_all_energy_orders(
     state: State<Arc<DatabaseService>>,
+    query: web::types::Query<OrderListQuery>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let mut query = query.into_inner();
+    if let Err(msg) = query.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+    let sort = match query.sort.as_deref().map(|sort| SortOrder::parse(sort, &ORDER_SORT_COLUMNS)).transpose() {
+        Ok(sort) => sort.unwrap_or_default(),
+        Err(msg) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        }))),
+    };
+    let (page, limit) = state.pagination().resolve(query.page, query.limit);
+    let total = match state
+        .count_orders(query.status.as_deref(), query.order_type.as_deref(), query.prosumer_address.as_deref())
+        .await
+    {
+        Ok(total) => total,
+        Err(e) => return Ok(database_error("count orders", e)),
+    };
+    match state.get_orders(page, limit, query.status, query.order_type, query.prosumer_address, sort).await {
+        Ok(orders) => Ok(HttpResponse::Ok()
+            .header(TOTAL_COUNT_HEADER, total.to_string())
+            .json(&PaginatedResponse { items: orders, page, limit })),
+        Err(e) => Ok(database_error("get orders", e))
+    }
+}
+
+pub async fn get_order_fills(
+    state: State<Arc<DatabaseService>>,
+    order_id: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let order_id = match Uuid::parse_str(&order_id.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "Invalid order ID format"
+        })))
+    };
+
+    match state.get_order_fills(order_id).await {
+        Ok(fills) => Ok(HttpResponse::Ok().json(&fills)),
+        Err(e) => Ok(database_error("get order fills", e))
+    }
+}
+
+pub async fn get_order_trades(
+    state: State<Arc<DatabaseService>>,
+    order_id: web::types::Path<String>,
 ) -> Result<HttpResponse, ntex::web::Error> {
-    match state.get_orders(0, 100, None, None, None).await {
-        Ok(orders) => Ok(HttpResponse::Ok().json(&orders)),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(&json!({
-            "error": format!("Failed to get orders: {}", e)
+    let order_id = match Uuid::parse_str(&order_id.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "Invalid order ID format"
         })))
+    };
+
+    match state.get_trades_for_order(order_id).await {
+        Ok(trades) => Ok(HttpResponse::Ok().json(&trades)),
+        Err(e) => Ok(database_error("get order trades", e))
     }
 }
 
@@ -182,19 +441,12 @@ pub async fn update_energy_order(
         })))
     };
     
-    match state.update_order(order_id, body.status.clone(), body.energy_amount, body.price_per_unit).await {
-        Ok(order) => Ok(HttpResponse::Ok().json(&order)),
-        Err(e) => {
-            if e.to_string().contains("not found") {
-                Ok(HttpResponse::NotFound().json(&json!({
-                    "error": format!("Order with ID {} not found", order_id)
-                })))
-            } else {
-                Ok(HttpResponse::InternalServerError().json(&json!({
-                    "error": format!("Failed to update order: {}", e)
-                })))
-            }
+    match state.update_order(order_id, body.energy_amount, body.price_per_unit).await {
+        Ok(order) => {
+            webhooks::check_price_alerts(state.get_ref());
+            Ok(HttpResponse::Ok().json(&order))
         }
+        Err(e) => Ok(database_error("update order", e))
     }
 }
 
@@ -211,21 +463,39 @@ pub async fn cancel_energy_order(
     };
     
     match state.cancel_order(order_id).await {
-        Ok(order) => Ok(HttpResponse::Ok().json(&json!({
-            "message": "Order cancelled successfully",
-            "order": order
-        }))),
-        Err(e) => {
-            if e.to_string().contains("not found") {
-                Ok(HttpResponse::NotFound().json(&json!({
-                    "error": format!("Order with ID {} not found", order_id)
-                })))
-            } else {
-                Ok(HttpResponse::InternalServerError().json(&json!({
-                    "error": format!("Failed to cancel order: {}", e)
-                })))
-            }
+        Ok(order) => {
+            webhooks::notify(state.get_ref(), webhooks::ORDER_CANCELLED, &order);
+            webhooks::check_price_alerts(state.get_ref());
+            Ok(HttpResponse::Ok().json(&json!({
+                "message": "Order cancelled successfully",
+                "order": order
+            })))
+        }
+        Err(e) => Ok(database_error("cancel order", e))
+    }
+}
+
+pub async fn reactivate_energy_order(
+    state: State<Arc<DatabaseService>>,
+    order_id: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let order_id = match Uuid::parse_str(&order_id.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "Invalid order ID format"
+        })))
+    };
+
+    match state.reactivate_order(order_id).await {
+        Ok(order) => {
+            webhooks::notify(state.get_ref(), webhooks::ORDER_REACTIVATED, &order);
+            webhooks::check_price_alerts(state.get_ref());
+            Ok(HttpResponse::Ok().json(&json!({
+                "message": "Order reactivated successfully",
+                "order": order
+            })))
         }
+        Err(e) => Ok(database_error("reactivate order", e))
     }
 }
 
@@ -234,25 +504,58 @@ pub async fn execute_trade(
     state: State<Arc<DatabaseService>>,
     body: web::types::Json<ExecuteTradeRequest>,
 ) -> Result<HttpResponse, ntex::web::Error> {
-    let trade = Trade {
-        id: Uuid::new_v4(),
-        buy_order_id: body.buy_order_id,
-        sell_order_id: body.sell_order_id,
-        buyer_address: "".to_string(), // Will be populated by the database
-        seller_address: "".to_string(), // Will be populated by the database
-        energy_amount: 0.0, // Will be calculated by the database
-        price_per_unit: body.price_per_unit.unwrap_or(0.0),
-        total_price: 0.0, // Will be calculated by the database
-        status: "pending".to_string(),
-        executed_at: Utc::now(),
-        created_at: Utc::now(),
-    };
-    
-    match state.execute_trade(trade).await {
-        Ok(trade) => Ok(HttpResponse::Created().json(&trade)),
-        Err(e) => Ok(HttpResponse::BadRequest().json(&json!({
-            "error": format!("Failed to execute trade: {}", e)
+    match state.execute_trade(body.buy_order_id, body.sell_order_id, body.price_per_unit).await {
+        Ok(trade) => {
+            webhooks::notify(state.get_ref(), webhooks::TRADE_EXECUTED, &trade);
+            webhooks::check_price_alerts(state.get_ref());
+            Ok(HttpResponse::Created().json(&trade))
+        }
+        Err(e) => Ok(database_error("execute trade", e))
+    }
+}
+
+// A party to a trade awaiting settlement holds its payment for review. The caller must own the
+// trade's buyer or seller, and that prosumer is the one raising the dispute.
+pub async fn dispute_trade(
+    state: State<Arc<DatabaseService>>,
+    auth: AuthContext,
+    trade_id: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let trade_id = match Uuid::parse_str(&trade_id.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "Invalid trade ID format"
         })))
+    };
+
+    let trade = match state.get_trade(trade_id).await {
+        Ok(trade) => trade,
+        Err(e) => return Ok(database_error("dispute trade", e)),
+    };
+    let mut party = None;
+    for address in [&trade.buyer_address, &trade.seller_address] {
+        match state.get_prosumer_owner(address).await {
+            Ok(Some(owner_id)) if owner_id == auth.user_id => {
+                party = Some(address.clone());
+                break;
+            }
+            Ok(_) => {}
+            Err(e) => return Ok(database_error("dispute trade", e)),
+        }
+    }
+    let Some(address) = party else {
+        return Err(AuthError::InsufficientPermissions.into());
+    };
+
+    match state.dispute_trade(trade_id, &address, state.now()).await {
+        Ok(trade) => {
+            webhooks::notify(state.get_ref(), webhooks::TRADE_DISPUTED, &trade);
+            Ok(HttpResponse::Ok().json(&json!({
+                "message": "Trade disputed; settlement is on hold",
+                "trade": trade
+            })))
+        }
+        Err(e) => Ok(database_error("dispute trade", e))
     }
 }
 
@@ -270,56 +573,293 @@ pub async fn get_trade(
     
     match state.get_trade(trade_id).await {
         Ok(trade) => Ok(HttpResponse::Ok().json(&trade)),
-        Err(e) => {
-            if e.to_string().contains("not found") {
-                Ok(HttpResponse::NotFound().json(&json!({
-                    "error": format!("Trade with ID {} not found", trade_id)
-                })))
-            } else {
-                Ok(HttpResponse::InternalServerError().json(&json!({
-                    "error": format!("Failed to get trade: {}", e)
-                })))
-            }
-        }
+        Err(e) => Ok(database_error("get trade", e))
     }
 }
 
 pub async fn get_all_trades(
     state: State<Arc<DatabaseService>>,
+    query: web::types::Query<TradeListQuery>,
 ) -> Result<HttpResponse, ntex::web::Error> {
-    match state.get_trades(0, 100).await {
-        Ok(trades) => Ok(HttpResponse::Ok().json(&trades)),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(&json!({
-            "error": format!("Failed to get trades: {}", e)
-        })))
+    let sort = match query.sort.as_deref().map(|sort| SortOrder::parse(sort, &TRADE_SORT_COLUMNS)).transpose() {
+        Ok(sort) => sort.unwrap_or_default(),
+        Err(msg) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        }))),
+    };
+    let (page, limit) = state.pagination().resolve(query.page, query.limit);
+    match state.get_trades(page, limit, query.from, query.to, sort).await {
+        Ok(trades) => Ok(HttpResponse::Ok().json(&PaginatedResponse { items: trades, page, limit })),
+        Err(e) => Ok(database_error("get trades", e))
     }
 }
 
 // Token transfer handlers
 pub async fn transfer_tokens(
     state: State<Arc<DatabaseService>>,
+    auth: Option<AuthContext>,
     body: web::types::Json<TransferTokensRequest>,
 ) -> Result<HttpResponse, ntex::web::Error> {
-    match state.transfer_tokens(&body.from_address, &body.to_address, body.amount, &body.token_type).await {
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+    match state.transfer_tokens(&request.from_address, &request.to_address, request.amount, &request.token_type).await {
         Ok(transfer_id) => Ok(HttpResponse::Ok().json(&json!({
             "message": "Tokens transferred successfully",
             "transfer_id": transfer_id
         }))),
-        Err(e) => Ok(HttpResponse::BadRequest().json(&json!({
-            "error": format!("Failed to transfer tokens: {}", e)
-        })))
+        Err(e) => {
+            let addresses = [request.from_address.as_str(), request.to_address.as_str()];
+            let e = hint_missing_address(&state, auth.as_ref(), &addresses, e).await;
+            Ok(database_error("transfer tokens", e))
+        }
+    }
+}
+
+// Dry-run transfer: reports whether `POST /transfer` would succeed without applying it
+pub async fn validate_transfer(
+    state: State<Arc<DatabaseService>>,
+    body: web::types::Json<TransferTokensRequest>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+    match state.preview_transfer(&request.from_address, &request.to_address, request.amount, &request.token_type).await {
+        Ok(preview) => Ok(HttpResponse::Ok().json(&preview)),
+        Err(e) => Ok(database_error("validate transfer", e))
+    }
+}
+
+// Test-environment faucet; answers 404 when disabled so its existence isn't advertised
+pub async fn faucet(
+    state: State<Arc<DatabaseService>>,
+    faucet: State<Arc<Faucet>>,
+    body: web::types::Json<FaucetRequest>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    if !faucet.config().enabled {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+    if let Err(wait) = faucet.claim(&request.address) {
+        let retry_after = wait.as_secs().max(1);
+        return Ok(HttpResponse::TooManyRequests()
+            .header("Retry-After", retry_after.to_string())
+            .json(&json!({
+                "error": format!("Faucet cooldown active for {}; retry in {}s", request.address, retry_after)
+            })));
+    }
+
+    let credits = [
+        ("grid_tokens", faucet.config().grid_amount),
+        ("watt_tokens", faucet.config().watt_amount),
+    ];
+    match state.credit_tokens(&request.address, &credits).await {
+        Ok(balance) => Ok(HttpResponse::Ok().json(&json!({
+            "message": "Tokens credited",
+            "credited": { "grid_tokens": credits[0].1, "watt_tokens": credits[1].1 },
+            "balance": balance
+        }))),
+        Err(e) => {
+            faucet.release(&request.address);
+            Ok(database_error("credit tokens", e))
+        }
+    }
+}
+
+// Atomic multi-operation batch
+pub async fn execute_batch(
+    state: State<Arc<DatabaseService>>,
+    body: web::types::Json<BatchRequest>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let mut request = body.into_inner();
+    for (index, operation) in request.operations.iter_mut().enumerate() {
+        if let Err(msg) = operation.sanitize(state.field_limits()) {
+            return Ok(HttpResponse::BadRequest().json(&json!({
+                "error": msg,
+                "failed_index": index
+            })));
+        }
+    }
+
+    let operations = request
+        .operations
+        .iter()
+        .map(|operation| match operation {
+            BatchOperationRequest::CreateProsumer(request) => BatchOperation::CreateProsumer(new_prosumer(&state, request)),
+            BatchOperationRequest::Transfer(request) => BatchOperation::Transfer {
+                from_address: request.from_address.clone(),
+                to_address: request.to_address.clone(),
+                amount: request.amount,
+                token_type: request.token_type.clone(),
+            },
+            BatchOperationRequest::CreateOrder(request) => BatchOperation::CreateOrder(new_order(&state, request)),
+        })
+        .collect();
+
+    match state.execute_batch(operations).await {
+        Ok(results) => {
+            for result in &results {
+                if let BatchOperationResult::CreateOrder { order } = result {
+                    webhooks::notify(state.get_ref(), webhooks::ORDER_CREATED, order);
+                }
+            }
+            webhooks::check_price_alerts(state.get_ref());
+            Ok(HttpResponse::Ok().json(&json!({
+                "message": "Batch executed successfully",
+                "results": results
+            })))
+        }
+        Err(DatabaseError::BatchFailed { index, source }) => Ok(batch_failed(index, &source)),
+        Err(e) => Ok(database_error("execute batch", e))
     }
 }
 
 // Statistics handlers
 pub async fn get_market_stats(
     state: State<Arc<DatabaseService>>,
+    query: web::types::Query<MarketStatsQuery>,
 ) -> Result<HttpResponse, ntex::web::Error> {
-    match state.get_market_stats().await {
+    let tags = match TagFilter::from_query(query.tag.as_deref(), query.region.as_deref()) {
+        Ok(tags) => tags,
+        Err(msg) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        }))),
+    };
+    match state.get_market_stats(&tags).await {
+        // Clients may reuse the figures for as long as the server itself does
+        Ok(stats) if state.stats_cache_secs() > 0 => Ok(HttpResponse::Ok()
+            .header(header::CACHE_CONTROL, format!("max-age={}", state.stats_cache_secs()))
+            .json(&stats)),
         Ok(stats) => Ok(HttpResponse::Ok().json(&stats)),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(&json!({
-            "error": format!("Failed to get market stats: {}", e)
-        })))
+        Err(e) => Ok(database_error("get market stats", e))
+    }
+}
+
+pub async fn get_market_timeseries(
+    state: State<Arc<DatabaseService>>,
+    query: web::types::Query<TimeseriesQuery>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let to = query.to.unwrap_or_else(|| state.now());
+    let from = query.from.unwrap_or(to - chrono::Duration::hours(24));
+    let interval_secs = query.interval_secs.unwrap_or(3600);
+    if let Err(msg) = timeseries_bucket_count(interval_secs, from, to) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+
+    match state.get_market_timeseries(interval_secs, from, to).await {
+        Ok(points) => Ok(HttpResponse::Ok().json(&points)),
+        Err(e) => Ok(database_error("get market timeseries", e))
+    }
+}
+
+pub async fn get_account_statement(
+    state: State<Arc<DatabaseService>>,
+    address: web::types::Path<String>,
+    query: web::types::Query<StatementQuery>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let csv = match query.format.as_deref() {
+        Some("csv") => true,
+        Some("json") => false,
+        Some(other) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": format!("format must be 'json' or 'csv', got '{}'", other)
+        }))),
+        None => req
+            .headers()
+            .get("Accept")
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/csv")),
+    };
+    let to = query.to.unwrap_or_else(|| state.now());
+    let from = query.from.unwrap_or(to - chrono::Duration::days(30));
+
+    match state.get_account_statement(&address.into_inner(), from, to).await {
+        Ok(statement) if csv => Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .header("Content-Disposition", "attachment; filename=\"statement.csv\"")
+            .body(statement.to_csv())),
+        Ok(statement) => Ok(HttpResponse::Ok().json(&statement)),
+        Err(e) => Ok(database_error("get account statement", e))
+    }
+}
+
+pub async fn get_prosumer_pnl(
+    state: State<Arc<DatabaseService>>,
+    address: web::types::Path<String>,
+    query: web::types::Query<PnlQuery>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let method = match query.method.as_deref() {
+        Some(method) => match method.parse() {
+            Ok(method) => method,
+            Err(msg) => return Ok(HttpResponse::BadRequest().json(&json!({
+                "error": msg
+            }))),
+        },
+        None => state.market_config().pnl_method,
+    };
+    let to = query.to.unwrap_or_else(|| state.now());
+    let from = query.from.unwrap_or(to - chrono::Duration::days(30));
+
+    match state.get_prosumer_pnl(&address.into_inner(), from, to, method).await {
+        Ok(pnl) => Ok(HttpResponse::Ok().json(&pnl)),
+        Err(e) => Ok(database_error("get prosumer P&L", e))
+    }
+}
+
+pub async fn get_prosumer_tags(
+    state: State<Arc<DatabaseService>>,
+    address: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    match state.get_prosumer_tags(&address.into_inner()).await {
+        Ok(tags) => Ok(HttpResponse::Ok().json(&json!({ "tags": tags }))),
+        Err(e) => Ok(database_error("get prosumer tags", e))
+    }
+}
+
+// Replaces a prosumer's tags. The `max_open_orders` tag overrides the open order cap, so only
+// an admin may set or change it; anyone else's replacement keeps the stored value.
+pub async fn set_prosumer_tags(
+    state: State<Arc<DatabaseService>>,
+    auth: Option<AuthContext>,
+    address: web::types::Path<String>,
+    body: web::types::Json<SetProsumerTagsRequest>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let address = address.into_inner();
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+    if !auth.as_ref().is_some_and(AuthContext::is_admin) {
+        let stored = match state.get_prosumer_tags(&address).await {
+            Ok(tags) => tags.get(MAX_OPEN_ORDERS_TAG).cloned(),
+            Err(e) => return Ok(database_error("set prosumer tags", e)),
+        };
+        let requested = request.tags.remove(MAX_OPEN_ORDERS_TAG);
+        if requested.is_some() && requested != stored {
+            return Err(AuthError::InsufficientPermissions.into());
+        }
+        if let Some(limit) = stored {
+            request.tags.insert(MAX_OPEN_ORDERS_TAG.to_string(), limit);
+        }
+    }
+    match state.set_prosumer_tags(&address, &request.tags).await {
+        Ok(tags) => Ok(HttpResponse::Ok().json(&json!({ "tags": tags }))),
+        Err(e) => Ok(database_error("set prosumer tags", e))
     }
 }
 
@@ -330,17 +870,87 @@ pub async fn get_prosumer_stats(
     let address = address.into_inner();
     match state.get_prosumer_stats(&address).await {
         Ok(stats) => Ok(HttpResponse::Ok().json(&stats)),
-        Err(e) => {
-            if e.to_string().contains("not found") {
-                Ok(HttpResponse::NotFound().json(&json!({
-                    "error": format!("Prosumer with address {} not found", address)
-                })))
-            } else {
-                Ok(HttpResponse::InternalServerError().json(&json!({
-                    "error": format!("Failed to get prosumer stats: {}", e)
-                })))
-            }
-        }
+        Err(e) => Ok(database_error("get prosumer stats", e))
+    }
+}
+
+pub async fn get_all_prosumer_stats(
+    state: State<Arc<DatabaseService>>,
+    query: web::types::Query<ProsumerStatsQuery>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let sort_by = match query.sort_by.as_deref().map(str::parse::<ProsumerStatsSort>) {
+        Some(Ok(sort_by)) => sort_by,
+        Some(Err(msg)) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        }))),
+        None => ProsumerStatsSort::default(),
+    };
+    let (page, limit) = state.pagination().resolve(query.page, query.limit);
+    match state.get_all_prosumer_stats(page, limit, sort_by).await {
+        Ok(stats) => Ok(HttpResponse::Ok().json(&PaginatedResponse { items: stats, page, limit })),
+        Err(e) => Ok(database_error("get prosumer stats", e))
+    }
+}
+
+pub async fn get_prosumer_exposure(
+    state: State<Arc<DatabaseService>>,
+    address: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let address = address.into_inner();
+    match state.get_prosumer_exposure(&address).await {
+        Ok(exposure) => Ok(HttpResponse::Ok().json(&exposure)),
+        Err(e) => Ok(database_error("get prosumer exposure", e))
+    }
+}
+
+pub async fn get_open_order_summary(
+    state: State<Arc<DatabaseService>>,
+    address: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    match state.get_open_order_summary(&address.into_inner()).await {
+        Ok(summary) => Ok(HttpResponse::Ok().json(&summary)),
+        Err(e) => Ok(database_error("get open order summary", e))
+    }
+}
+
+pub async fn get_prosumer_balance(
+    state: State<Arc<DatabaseService>>,
+    address: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    match state.get_prosumer_balance(&address.into_inner()).await {
+        Ok(balance) => Ok(HttpResponse::Ok().json(&balance)),
+        Err(e) => Ok(database_error("get prosumer balance", e))
+    }
+}
+
+pub async fn get_prosumer_balances(
+    state: State<Arc<DatabaseService>>,
+    body: web::types::Json<BulkBalanceRequest>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+
+    match state.get_prosumer_balances(&request.addresses).await {
+        Ok(balances) => Ok(HttpResponse::Ok().json(&balances)),
+        Err(e) => Ok(database_error("get prosumer balances", e))
+    }
+}
+
+pub async fn get_prosumer_trades(
+    state: State<Arc<DatabaseService>>,
+    address: web::types::Path<String>,
+    query: web::types::Query<PaginationQuery>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let address = address.into_inner();
+    let (page, limit) = state.pagination().resolve(query.page, query.limit);
+
+    match state.get_prosumer_trades(&address, page, limit).await {
+        Ok(trades) => Ok(HttpResponse::Ok().json(&PaginatedResponse { items: trades, page, limit })),
+        Err(e) => Ok(database_error("get prosumer trades", e))
     }
 }
 
@@ -349,9 +959,7 @@ pub async fn get_database_stats(
 ) -> Result<HttpResponse, ntex::web::Error> {
     match state.get_stats().await {
         Ok(stats) => Ok(HttpResponse::Ok().json(&stats)),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(&json!({
-            "error": format!("Failed to get database stats: {}", e)
-        })))
+        Err(e) => Ok(database_error("get database stats", e))
     }
 }
 
@@ -360,12 +968,266 @@ pub async fn match_orders(
     state: State<Arc<DatabaseService>>,
 ) -> Result<HttpResponse, ntex::web::Error> {
     match state.match_orders().await {
-        Ok(trades) => Ok(HttpResponse::Ok().json(&json!({
-            "message": "Order matching completed",
-            "trades": trades
+        Ok(result) => {
+            for trade in &result.trades {
+                webhooks::notify(state.get_ref(), webhooks::TRADE_EXECUTED, trade);
+            }
+            webhooks::check_price_alerts(state.get_ref());
+            Ok(HttpResponse::Ok().json(&json!({
+                "message": "Order matching completed",
+                "trades": result.trades,
+                "matched_energy": result.matched_energy,
+                "total_value": result.total_value
+            })))
+        }
+        Err(e) => Ok(database_error("match orders", e))
+    }
+}
+
+// Token type handlers
+pub async fn get_token_types(
+    state: State<Arc<DatabaseService>>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    match state.get_token_types().await {
+        Ok(token_types) => Ok(HttpResponse::Ok().json(&token_types)),
+        Err(e) => Ok(database_error("get token types", e))
+    }
+}
+
+// Webhook subscriptions. Each belongs to the user who registered it; admins see and delete all.
+pub async fn create_webhook(
+    auth: AuthContext,
+    state: State<Arc<DatabaseService>>,
+    body: web::types::Json<CreateWebhookRequest>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let url = body.url.trim();
+    if url.len() > MAX_WEBHOOK_URL_LENGTH {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": format!("url must be at most {} characters", MAX_WEBHOOK_URL_LENGTH)
+        })));
+    }
+    if let Err(msg) = webhooks::validate_target(url).await {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+
+    let mut events: Vec<String> = body.events.iter().map(|e| e.trim().to_lowercase()).collect();
+    events.sort();
+    events.dedup();
+    if events.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "At least one event is required",
+            "supported_events": webhooks::WEBHOOK_EVENTS
+        })));
+    }
+    if let Some(unknown) = events.iter().find(|e| !webhooks::WEBHOOK_EVENTS.contains(&e.as_str())) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": format!("Unknown event '{}'", unknown),
+            "supported_events": webhooks::WEBHOOK_EVENTS
+        })));
+    }
+
+    let secret = match body.secret.as_deref().map(str::trim) {
+        Some(secret) if !secret.is_empty() => secret.to_string(),
+        _ => hex::encode(rand::random::<[u8; 32]>()),
+    };
+
+    match state.create_webhook(url, &events, &secret, &auth.user_id).await {
+        Ok(webhook) => Ok(HttpResponse::Created().json(&json!({
+            "webhook": webhook,
+            "secret": secret
         }))),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(&json!({
-            "error": format!("Failed to match orders: {}", e)
+        Err(e) => Ok(database_error("create webhook", e))
+    }
+}
+
+pub async fn get_webhooks(
+    auth: AuthContext,
+    state: State<Arc<DatabaseService>>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let owner_id = (!auth.is_admin()).then_some(auth.user_id.as_str());
+    match state.get_webhooks(owner_id).await {
+        Ok(webhooks) => Ok(HttpResponse::Ok().json(&webhooks)),
+        Err(e) => Ok(database_error("get webhooks", e))
+    }
+}
+
+pub async fn delete_webhook(
+    auth: AuthContext,
+    state: State<Arc<DatabaseService>>,
+    webhook_id: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let webhook_id = match Uuid::parse_str(&webhook_id.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "Invalid webhook ID format"
         })))
+    };
+
+    let owner_id = (!auth.is_admin()).then_some(auth.user_id.as_str());
+    match state.delete_webhook(webhook_id, owner_id).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(database_error("delete webhook", e))
+    }
+}
+
+// Price alert handlers
+// Alerts are scoped to the prosumers the caller owns; admins see and manage all of them
+pub async fn create_price_alert(
+    auth: AuthContext,
+    state: State<Arc<DatabaseService>>,
+    body: web::types::Json<CreatePriceAlertRequest>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits()) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
     }
-}
\ No newline at end of file
+    if !auth.is_admin() {
+        match state.get_prosumer_owner(&request.address).await {
+            Ok(Some(owner_id)) if owner_id == auth.user_id => {}
+            Ok(_) => return Err(AuthError::InsufficientPermissions.into()),
+            Err(e) => return Ok(database_error("create price alert", e)),
+        }
+    }
+
+    match state.create_price_alert(&request.address, &request.side, &request.direction, request.price).await {
+        Ok(alert) => {
+            // The book may already be past the threshold
+            webhooks::check_price_alerts(state.get_ref());
+            Ok(HttpResponse::Created().json(&alert))
+        }
+        Err(e) => Ok(database_error("create price alert", e))
+    }
+}
+
+pub async fn get_price_alerts(
+    auth: AuthContext,
+    state: State<Arc<DatabaseService>>,
+    query: web::types::Query<PriceAlertQuery>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let owner_id = (!auth.is_admin()).then_some(auth.user_id.as_str());
+    match state.get_price_alerts(query.address.as_deref(), owner_id).await {
+        Ok(alerts) => Ok(HttpResponse::Ok().json(&alerts)),
+        Err(e) => Ok(database_error("get price alerts", e))
+    }
+}
+
+pub async fn delete_price_alert(
+    auth: AuthContext,
+    state: State<Arc<DatabaseService>>,
+    alert_id: web::types::Path<String>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let alert_id = match Uuid::parse_str(&alert_id.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": "Invalid price alert ID format"
+        })))
+    };
+
+    let owner_id = (!auth.is_admin()).then_some(auth.user_id.as_str());
+    match state.delete_price_alert(alert_id, owner_id).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(database_error("delete price alert", e))
+    }
+}
+
+// Backtesting: runs the matching engine over the submitted orders in memory only
+pub async fn simulate_match(
+    state: State<Arc<DatabaseService>>,
+    body: web::types::Json<SimulateMatchRequest>,
+) -> Result<HttpResponse, ntex::web::Error> {
+    let mut request = body.into_inner();
+    if let Err(msg) = request.sanitize(state.field_limits(), matching::MAX_SIMULATION_ORDERS) {
+        return Ok(HttpResponse::BadRequest().json(&json!({
+            "error": msg
+        })));
+    }
+
+    let market = state.market_config();
+    let submitted_at = state.now();
+    let mut orders = Vec::with_capacity(request.orders.len());
+    for (index, order) in request.orders.into_iter().enumerate() {
+        let energy_amount = match market
+            .normalize_energy_amount(order.energy_amount)
+            .and_then(|amount| market.validate_price(order.price_per_unit).map(|_| amount))
+        {
+            Ok(amount) => amount,
+            Err(msg) => {
+                return Ok(HttpResponse::BadRequest().json(&json!({
+                    "error": msg,
+                    "failed_index": index
+                })));
+            }
+        };
+        // Orders without a timestamp keep their submission order for time priority
+        let created_at = order
+            .created_at
+            .unwrap_or(submitted_at + chrono::Duration::microseconds(index as i64));
+        orders.push(Order {
+            id: order.id.unwrap_or_else(Uuid::new_v4),
+            prosumer_address: order.prosumer_address,
+            order_type: order.order_type,
+            energy_amount,
+            price_per_unit: order.price_per_unit,
+            total_price: energy_amount * order.price_per_unit,
+            currency: state.base_currency().to_string(),
+            quoted_price_per_unit: order.price_per_unit,
+            filled_amount: 0.0,
+            remaining_amount: energy_amount,
+            status: "active".to_string(),
+            created_at,
+            updated_at: created_at,
+            expires_at: None,
+        });
+    }
+
+    let result = matching::simulate(state.matching_engine(), orders, market);
+    Ok(HttpResponse::Ok().json(&result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_database_error_maps_to_its_status() {
+        let insufficient = || DatabaseError::InsufficientBalance {
+            token_type: "grid_tokens".to_string(),
+            required: 2.0,
+            available: 1.0,
+        };
+        let cases = [
+            (DatabaseError::Validation("bad".into()), StatusCode::BAD_REQUEST),
+            (DatabaseError::NotFound("missing".into()), StatusCode::NOT_FOUND),
+            (DatabaseError::SqlxError(sqlx::Error::RowNotFound), StatusCode::NOT_FOUND),
+            (DatabaseError::Conflict("taken".into()), StatusCode::CONFLICT),
+            (insufficient(), StatusCode::UNPROCESSABLE_ENTITY),
+            (DatabaseError::LimitExceeded("daily".into()), StatusCode::UNPROCESSABLE_ENTITY),
+            (DatabaseError::MarketHalted("maintenance".into()), StatusCode::SERVICE_UNAVAILABLE),
+            (DatabaseError::Timeout("canceled".into()), StatusCode::SERVICE_UNAVAILABLE),
+            (DatabaseError::SqlxError(sqlx::Error::PoolTimedOut), StatusCode::INTERNAL_SERVER_ERROR),
+            (
+                DatabaseError::MigrateError(sqlx::migrate::MigrateError::VersionMissing(1)),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            // A failed batch takes the status of the operation that failed it
+            (
+                DatabaseError::BatchFailed { index: 2, source: Box::new(insufficient()) },
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                DatabaseError::BatchFailed { index: 0, source: Box::new(DatabaseError::NotFound("missing".into())) },
+                StatusCode::NOT_FOUND,
+            ),
+        ];
+
+        for (error, status) in cases {
+            let described = error.to_string();
+            assert_eq!(error_status(&error), status, "{}", described);
+            assert_eq!(database_error("test", error).status(), status, "{}", described);
+        }
+    }
+}
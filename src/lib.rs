@@ -1,3 +1,5 @@
+#![recursion_limit = "512"]
+
 pub mod handlers;
 pub mod middleware;
 pub mod models;
@@ -5,3 +7,14 @@ pub mod server;
 pub mod auth;
 pub mod auth_handlers;
 pub mod database;
+pub mod currency;
+pub mod config;
+pub mod matching;
+pub mod webhooks;
+pub mod seed;
+pub mod retention;
+pub mod expiry;
+pub mod settlement;
+pub mod faucet;
+pub mod telemetry;
+pub mod clock;
@@ -1,11 +1,18 @@
 // Import the server module directly 
 use std::io;
 
+use energy_trading_api::server::ServerOptions;
+
 #[ntex::main]
 async fn main() -> io::Result<()> {
     println!("🌟 Energy Trading API Server 🌟");
     println!("================================");
-    
+
+    // `--seed` inserts demo prosumers, orders and trades before serving
+    let options = ServerOptions {
+        seed: std::env::args().skip(1).any(|arg| arg == "--seed"),
+    };
+
     // Start the API server on port 3000
-    energy_trading_api::server::start_server(3000).await
+    energy_trading_api::server::start_server_with_options(3000, options).await
 }
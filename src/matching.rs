@@ -0,0 +1,728 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+use ntex::time::{sleep, Millis};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::{AutoMatchConfig, MarketConfig};
+use crate::database::{DatabaseError, DatabaseService, Order};
+use crate::webhooks;
+
+// Snapshot of the open orders a matching engine works on
+#[derive(Debug, Clone, Default)]
+pub struct OrderBook {
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+}
+
+impl OrderBook {
+    // Collapses open orders into price levels: bids best (highest) first, asks lowest first
+    pub fn aggregate(&self) -> AggregatedBook {
+        AggregatedBook {
+            bids: price_levels(sorted_bids(self)),
+            asks: price_levels(sorted_asks(self)),
+        }
+    }
+}
+
+// Remaining energy and order count resting at one price
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceLevel {
+    pub price: f64,
+    pub energy: f64,
+    pub order_count: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AggregatedBook {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+impl AggregatedBook {
+    // (bid energy - ask energy) / (bid energy + ask energy), from -1 (all asks) to 1 (all
+    // bids); None when both sides are empty
+    pub fn imbalance(&self) -> Option<f64> {
+        let bid_volume: f64 = self.bids.iter().map(|level| level.energy).sum();
+        let ask_volume: f64 = self.asks.iter().map(|level| level.energy).sum();
+        let total = bid_volume + ask_volume;
+        (total > 0.0).then(|| (bid_volume - ask_volume) / total)
+    }
+
+    // SHA-256 over a canonical rendering of every level. Amounts are rounded to 1e-6 so
+    // float noise from summing fills doesn't register as drift.
+    pub fn checksum(&self) -> String {
+        let mut hasher = Sha256::new();
+        for (side, levels) in [("bid", &self.bids), ("ask", &self.asks)] {
+            for level in levels {
+                hasher.update(format!("{}:{:.6}:{:.6}:{};", side, level.price, level.energy, level.order_count));
+            }
+        }
+        hex::encode(hasher.finalize())
+    }
+}
+
+// Expects orders already sorted by price
+fn price_levels(orders: Vec<&Order>) -> Vec<PriceLevel> {
+    let mut levels: Vec<PriceLevel> = Vec::new();
+    for order in orders {
+        match levels.last_mut() {
+            Some(level) if level.price.total_cmp(&order.price_per_unit) == Ordering::Equal => {
+                level.energy += order.remaining_amount;
+                level.order_count += 1;
+            }
+            _ => levels.push(PriceLevel {
+                price: order.price_per_unit,
+                energy: order.remaining_amount,
+                order_count: 1,
+            }),
+        }
+    }
+    levels
+}
+
+// A fill the engine wants executed; the database re-validates it before writing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProposedTrade {
+    pub buy_order_id: Uuid,
+    pub sell_order_id: Uuid,
+    pub energy_amount: f64,
+    pub price_per_unit: f64,
+}
+
+// Why a crossing bid/ask pair did not trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    SelfTrade,
+    Dust,
+    Validation,
+    DailyLimit,
+    TradeThrough,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchSkip {
+    pub buy_order_id: Uuid,
+    pub sell_order_id: Uuid,
+    pub reason: SkipReason,
+    pub detail: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl MatchSkip {
+    pub fn new(buy_order_id: Uuid, sell_order_id: Uuid, reason: SkipReason, detail: impl Into<String>) -> Self {
+        Self {
+            buy_order_id,
+            sell_order_id,
+            reason,
+            detail: detail.into(),
+            recorded_at: Utc::now(),
+        }
+    }
+}
+
+// What an engine returns for one pass over the book
+#[derive(Debug, Clone, Default)]
+pub struct MatchPlan {
+    pub proposals: Vec<ProposedTrade>,
+    pub skipped: Vec<MatchSkip>,
+}
+
+impl MatchPlan {
+    // Trade-through protection: moves any proposal priced above the buyer's limit or below
+    // the seller's into `skipped`, whatever the engine or price policy computed. A skip here
+    // means the pricing logic is wrong, so it is logged as well as recorded.
+    pub fn reject_trade_throughs(&mut self, book: &OrderBook) {
+        let limits = |proposal: &ProposedTrade| {
+            let bid = book.bids.iter().find(|order| order.id == proposal.buy_order_id)?;
+            let ask = book.asks.iter().find(|order| order.id == proposal.sell_order_id)?;
+            Some((bid.price_per_unit, ask.price_per_unit))
+        };
+        let skipped = &mut self.skipped;
+        self.proposals.retain(|proposal| {
+            let Some((bid_price, ask_price)) = limits(proposal) else {
+                return true;
+            };
+            let price = proposal.price_per_unit;
+            if price.is_finite() && price >= ask_price && price <= bid_price {
+                return true;
+            }
+            let detail = format!("Price {} is outside the limits [{}, {}]", price, ask_price, bid_price);
+            log::warn!(
+                "Trade-through protection skipped match {} -> {}: {}",
+                proposal.buy_order_id, proposal.sell_order_id, detail
+            );
+            skipped.push(MatchSkip::new(proposal.buy_order_id, proposal.sell_order_id, SkipReason::TradeThrough, detail));
+            false
+        });
+    }
+}
+
+// Order-matching strategy. Engines are pure: they only see the book snapshot and market
+// rules and return proposed fills, so they can be swapped without touching SQL.
+pub trait MatchingEngine: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn find_matches(&self, book: &OrderBook, market: &MarketConfig) -> MatchPlan;
+}
+
+pub const DEFAULT_MATCH_DIAGNOSTICS_CAPACITY: usize = 200;
+
+// Ring buffer of the most recent skipped matches, newest last. Once full, the oldest
+// entry is dropped for every new one so memory stays bounded.
+#[derive(Debug)]
+pub struct MatchDiagnostics {
+    capacity: usize,
+    entries: Mutex<VecDeque<MatchSkip>>,
+}
+
+impl MatchDiagnostics {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    // Capacity from MATCH_DIAGNOSTICS_CAPACITY
+    pub fn from_env() -> Self {
+        Self::new(crate::config::env_parse("MATCH_DIAGNOSTICS_CAPACITY", DEFAULT_MATCH_DIAGNOSTICS_CAPACITY))
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn record(&self, skip: MatchSkip) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        while entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(skip);
+    }
+
+    // Most recent first
+    pub fn recent(&self) -> Vec<MatchSkip> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().rev().cloned().collect()
+    }
+}
+
+impl Default for MatchDiagnostics {
+    fn default() -> Self {
+        Self::new(DEFAULT_MATCH_DIAGNOSTICS_CAPACITY)
+    }
+}
+
+// Selected by MATCHING_ENGINE (price_time | pro_rata), defaulting to price-time priority
+pub fn engine_from_env() -> Arc<dyn MatchingEngine> {
+    match std::env::var("MATCHING_ENGINE").as_deref().map(str::trim) {
+        Ok("pro_rata") => Arc::new(ProRataEngine),
+        Ok("price_time") | Err(_) => Arc::new(PriceTimePriorityEngine),
+        Ok(other) => {
+            log::warn!("Unknown MATCHING_ENGINE '{}', using price_time", other);
+            Arc::new(PriceTimePriorityEngine)
+        }
+    }
+}
+
+// Remaining amounts at or below this are treated as filled, absorbing float residue
+const FILL_EPSILON: f64 = 1e-9;
+
+// Best price first, then oldest first. Orders with the same price and timestamp are ordered by
+// id so a given book always produces the same plan.
+fn sorted_bids(book: &OrderBook) -> Vec<&Order> {
+    let mut bids: Vec<&Order> = book.bids.iter().collect();
+    bids.sort_by(|a, b| {
+        b.price_per_unit
+            .total_cmp(&a.price_per_unit)
+            .then(a.created_at.cmp(&b.created_at))
+            .then(a.id.cmp(&b.id))
+    });
+    bids
+}
+
+fn sorted_asks(book: &OrderBook) -> Vec<&Order> {
+    let mut asks: Vec<&Order> = book.asks.iter().collect();
+    asks.sort_by(|a, b| {
+        a.price_per_unit
+            .total_cmp(&b.price_per_unit)
+            .then(a.created_at.cmp(&b.created_at))
+            .then(a.id.cmp(&b.id))
+    });
+    asks
+}
+
+fn propose(bid: &Order, ask: &Order, energy_amount: f64, market: &MarketConfig) -> ProposedTrade {
+    ProposedTrade {
+        buy_order_id: bid.id,
+        sell_order_id: ask.id,
+        energy_amount,
+        price_per_unit: market.execution_price(bid.price_per_unit, ask.price_per_unit, bid.created_at <= ask.created_at),
+    }
+}
+
+fn self_trade_skip(bid: &Order, ask: &Order) -> MatchSkip {
+    MatchSkip::new(
+        bid.id,
+        ask.id,
+        SkipReason::SelfTrade,
+        format!("Both orders belong to {}", bid.prosumer_address),
+    )
+}
+
+// Classic continuous matching: each bid, best first, takes the cheapest and then oldest asks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PriceTimePriorityEngine;
+
+impl MatchingEngine for PriceTimePriorityEngine {
+    fn name(&self) -> &'static str {
+        "price_time"
+    }
+
+    fn find_matches(&self, book: &OrderBook, market: &MarketConfig) -> MatchPlan {
+        let asks = sorted_asks(book);
+        let mut ask_remaining: HashMap<Uuid, f64> = asks.iter().map(|a| (a.id, a.remaining_amount)).collect();
+        let mut plan = MatchPlan::default();
+
+        for bid in sorted_bids(book) {
+            let mut bid_remaining = bid.remaining_amount;
+
+            for ask in &asks {
+                if bid_remaining <= FILL_EPSILON || ask.price_per_unit > bid.price_per_unit {
+                    break;
+                }
+                let remaining = ask_remaining.get_mut(&ask.id).expect("every ask is tracked");
+                if *remaining <= FILL_EPSILON {
+                    continue;
+                }
+                if ask.prosumer_address == bid.prosumer_address {
+                    plan.skipped.push(self_trade_skip(bid, ask));
+                    continue;
+                }
+
+                let amount = bid_remaining.min(*remaining);
+                bid_remaining -= amount;
+                *remaining -= amount;
+                plan.proposals.push(propose(bid, ask, amount, market));
+            }
+        }
+
+        plan
+    }
+}
+
+// Each bid, best first, is split across all asks at the best crossing price level in
+// proportion to their remaining size, then moves on to the next level
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProRataEngine;
+
+impl MatchingEngine for ProRataEngine {
+    fn name(&self) -> &'static str {
+        "pro_rata"
+    }
+
+    fn find_matches(&self, book: &OrderBook, market: &MarketConfig) -> MatchPlan {
+        let asks = sorted_asks(book);
+        let mut ask_remaining: HashMap<Uuid, f64> = asks.iter().map(|a| (a.id, a.remaining_amount)).collect();
+        let mut plan = MatchPlan::default();
+
+        for bid in sorted_bids(book) {
+            let mut bid_remaining = bid.remaining_amount;
+            plan.skipped.extend(
+                asks.iter()
+                    .filter(|ask| {
+                        ask.price_per_unit <= bid.price_per_unit
+                            && ask.prosumer_address == bid.prosumer_address
+                            && ask_remaining[&ask.id] > FILL_EPSILON
+                    })
+                    .map(|ask| self_trade_skip(bid, ask)),
+            );
+
+            while bid_remaining > FILL_EPSILON {
+                let eligible: Vec<&Order> = asks
+                    .iter()
+                    .copied()
+                    .filter(|ask| {
+                        ask.price_per_unit <= bid.price_per_unit
+                            && ask.prosumer_address != bid.prosumer_address
+                            && ask_remaining[&ask.id] > FILL_EPSILON
+                    })
+                    .collect();
+                let Some(level_price) = eligible.first().map(|ask| ask.price_per_unit) else {
+                    break;
+                };
+                let level: Vec<&Order> = eligible
+                    .into_iter()
+                    .filter(|ask| ask.price_per_unit.total_cmp(&level_price) == Ordering::Equal)
+                    .collect();
+
+                let level_total: f64 = level.iter().map(|ask| ask_remaining[&ask.id]).sum();
+                let take = bid_remaining.min(level_total);
+                for ask in level {
+                    let remaining = ask_remaining.get_mut(&ask.id).expect("every ask is tracked");
+                    let amount = (take * *remaining / level_total).min(*remaining);
+                    *remaining -= amount;
+                    if amount <= FILL_EPSILON {
+                        plan.skipped.push(MatchSkip::new(
+                            bid.id,
+                            ask.id,
+                            SkipReason::Dust,
+                            format!("Pro-rata share of {} kWh is below the fill threshold", amount),
+                        ));
+                        continue;
+                    }
+                    plan.proposals.push(propose(bid, ask, amount, market));
+                }
+                bid_remaining -= take;
+            }
+        }
+
+        plan
+    }
+}
+
+// Largest order set a single simulation accepts
+pub const MAX_SIMULATION_ORDERS: usize = 1_000;
+
+// A fill the engine would make in a simulation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulatedTrade {
+    pub buy_order_id: Uuid,
+    pub sell_order_id: Uuid,
+    pub buyer_address: String,
+    pub seller_address: String,
+    pub energy_amount: f64,
+    pub price_per_unit: f64,
+    pub total_price: f64,
+    pub grid_fee: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulationResult {
+    pub engine: String,
+    pub trades: Vec<SimulatedTrade>,
+    pub skipped: Vec<MatchSkip>,
+    pub matched_energy: f64,
+    pub total_value: f64,
+    pub book: AggregatedBook, // what is left resting after the fills
+}
+
+// Runs one matching pass of `engine` over `orders` entirely in memory, the same pass
+// `match_orders` makes against the live book, and applies the resulting fills.
+pub fn simulate(engine: &dyn MatchingEngine, orders: Vec<Order>, market: &MarketConfig) -> SimulationResult {
+    let (bids, asks) = orders
+        .into_iter()
+        .filter(|order| order.status == "active" && order.remaining_amount > FILL_EPSILON)
+        .partition(|order| order.order_type == "buy");
+    let mut book = OrderBook { bids, asks };
+    let mut plan = engine.find_matches(&book, market);
+    plan.reject_trade_throughs(&book);
+
+    let mut result = SimulationResult {
+        engine: engine.name().to_string(),
+        trades: Vec::with_capacity(plan.proposals.len()),
+        skipped: plan.skipped,
+        matched_energy: 0.0,
+        total_value: 0.0,
+        book: AggregatedBook::default(),
+    };
+    for proposal in plan.proposals {
+        let (Some(bid), Some(ask)) = (
+            book.bids.iter().position(|order| order.id == proposal.buy_order_id),
+            book.asks.iter().position(|order| order.id == proposal.sell_order_id),
+        ) else {
+            continue;
+        };
+        for order in [&mut book.bids[bid], &mut book.asks[ask]] {
+            order.filled_amount += proposal.energy_amount;
+            order.remaining_amount = (order.remaining_amount - proposal.energy_amount).max(0.0);
+        }
+
+        let total_price = proposal.energy_amount * proposal.price_per_unit;
+        result.matched_energy += proposal.energy_amount;
+        result.total_value += total_price;
+        result.trades.push(SimulatedTrade {
+            buy_order_id: proposal.buy_order_id,
+            sell_order_id: proposal.sell_order_id,
+            buyer_address: book.bids[bid].prosumer_address.clone(),
+            seller_address: book.asks[ask].prosumer_address.clone(),
+            energy_amount: proposal.energy_amount,
+            price_per_unit: proposal.price_per_unit,
+            total_price,
+            grid_fee: market.fee_schedule.fee_for(total_price),
+        });
+    }
+
+    book.bids.retain(|order| order.remaining_amount > FILL_EPSILON);
+    book.asks.retain(|order| order.remaining_amount > FILL_EPSILON);
+    result.book = book.aggregate();
+    result
+}
+
+// Scheduled order matching
+//
+// Calls `match_orders` every `interval_ms`. Cycles that produce no trades (including
+// failed ones) double the wait, up to `max_backoff_factor` times the interval, so an
+// idle book is not polled at full rate; the first productive cycle resets it.
+pub async fn run_auto_match(db: Arc<DatabaseService>, config: AutoMatchConfig) {
+    let mut backoff: u32 = 1;
+
+    loop {
+        sleep(interval(config.interval_ms, backoff)).await;
+
+        match db.match_orders().await {
+            Ok(result) if !result.trades.is_empty() => {
+                log::info!(
+                    "Auto-match executed {} trades ({} kWh, {} total value)",
+                    result.trades.len(),
+                    result.matched_energy,
+                    result.total_value
+                );
+                for trade in &result.trades {
+                    webhooks::notify(&db, webhooks::TRADE_EXECUTED, trade);
+                }
+                backoff = 1;
+            }
+            Ok(_) => {
+                log::debug!("Auto-match found no crossing orders");
+                backoff = (backoff * 2).min(config.max_backoff_factor.max(1));
+            }
+            Err(DatabaseError::MarketHalted(_)) => {
+                log::debug!("Auto-match skipped while the market is halted");
+                backoff = (backoff * 2).min(config.max_backoff_factor.max(1));
+            }
+            Err(e) => {
+                log::error!("Auto-match failed: {}", e);
+                backoff = (backoff * 2).min(config.max_backoff_factor.max(1));
+            }
+        }
+    }
+}
+
+// Scheduled order book snapshots, taken every `interval_secs` so drift can be checked later
+pub async fn run_order_book_snapshots(db: Arc<DatabaseService>, interval_secs: u64) {
+    loop {
+        sleep(Millis(interval_secs.saturating_mul(1000).min(u32::MAX as u64) as u32)).await;
+
+        match db.snapshot_order_book().await {
+            Ok(snapshot) => log::debug!("Stored order book snapshot {} ({})", snapshot.id, snapshot.checksum),
+            Err(e) => log::error!("Order book snapshot failed: {}", e),
+        }
+    }
+}
+
+fn interval(interval_ms: u64, backoff: u32) -> Millis {
+    Millis(interval_ms.saturating_mul(backoff as u64).min(u32::MAX as u64) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: u128, address: &str, order_type: &str, energy_amount: f64, price_per_unit: f64, created_at_secs: i64) -> Order {
+        let created_at = DateTime::from_timestamp(1_750_000_000 + created_at_secs, 0).unwrap();
+        Order {
+            id: Uuid::from_u128(id),
+            prosumer_address: address.to_string(),
+            order_type: order_type.to_string(),
+            energy_amount,
+            price_per_unit,
+            total_price: energy_amount * price_per_unit,
+            currency: "USD".to_string(),
+            quoted_price_per_unit: price_per_unit,
+            filled_amount: 0.0,
+            remaining_amount: energy_amount,
+            status: "active".to_string(),
+            created_at,
+            updated_at: created_at,
+            expires_at: None,
+        }
+    }
+
+    fn fills(plan: &MatchPlan) -> Vec<(u128, f64)> {
+        plan.proposals.iter().map(|p| (p.sell_order_id.as_u128(), p.energy_amount)).collect()
+    }
+
+    // A 6 kWh bid against two asks at the best price (6 and 3 kWh, oldest first) and a
+    // worse-priced one
+    fn contested_book() -> OrderBook {
+        OrderBook {
+            bids: vec![order(1, "buyer", "buy", 6.0, 0.2, 0)],
+            asks: vec![
+                order(10, "seller-a", "sell", 6.0, 0.1, 1),
+                order(11, "seller-b", "sell", 3.0, 0.1, 2),
+                order(12, "seller-c", "sell", 5.0, 0.15, 0),
+            ],
+        }
+    }
+
+    #[test]
+    fn price_time_fills_the_oldest_ask_at_the_best_price_first() {
+        let plan = PriceTimePriorityEngine.find_matches(&contested_book(), &MarketConfig::default());
+        assert_eq!(fills(&plan), vec![(10, 6.0)]);
+        assert!(plan.skipped.is_empty());
+    }
+
+    #[test]
+    fn pro_rata_splits_the_bid_across_the_best_price_level() {
+        let plan = ProRataEngine.find_matches(&contested_book(), &MarketConfig::default());
+        assert_eq!(fills(&plan), vec![(10, 4.0), (11, 2.0)]);
+        assert!(plan.skipped.is_empty());
+    }
+
+    #[test]
+    fn engines_agree_when_the_bid_takes_whole_levels() {
+        let mut book = contested_book();
+        book.bids[0].energy_amount = 12.0;
+        book.bids[0].remaining_amount = 12.0;
+
+        let price_time = PriceTimePriorityEngine.find_matches(&book, &MarketConfig::default());
+        let pro_rata = ProRataEngine.find_matches(&book, &MarketConfig::default());
+        assert_eq!(fills(&price_time), vec![(10, 6.0), (11, 3.0), (12, 3.0)]);
+        assert_eq!(price_time.proposals, pro_rata.proposals);
+    }
+
+    #[test]
+    fn both_engines_skip_self_trades_and_use_the_policy_price() {
+        let book = OrderBook {
+            bids: vec![order(1, "alice", "buy", 5.0, 0.2, 0)],
+            asks: vec![order(10, "alice", "sell", 5.0, 0.1, 0), order(11, "bob", "sell", 5.0, 0.12, 0)],
+        };
+        for engine in [&PriceTimePriorityEngine as &dyn MatchingEngine, &ProRataEngine] {
+            let plan = engine.find_matches(&book, &MarketConfig::default());
+            assert_eq!(fills(&plan), vec![(11, 5.0)], "{}", engine.name());
+            assert_eq!(plan.proposals[0].price_per_unit, 0.12, "{}", engine.name());
+            assert_eq!(plan.skipped.len(), 1, "{}", engine.name());
+            assert_eq!(plan.skipped[0].reason, SkipReason::SelfTrade, "{}", engine.name());
+        }
+    }
+
+    #[test]
+    fn simulation_applies_the_fills_and_returns_what_is_left_resting() {
+        let book = contested_book();
+        let orders = book.bids.into_iter().chain(book.asks).collect();
+        let result = simulate(&PriceTimePriorityEngine, orders, &MarketConfig::default());
+
+        assert_eq!(result.engine, PriceTimePriorityEngine.name());
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!((result.trades[0].sell_order_id, result.trades[0].energy_amount), (Uuid::from_u128(10), 6.0));
+        assert_eq!(result.matched_energy, 6.0);
+        // The bid and the first ask are used up; the other two asks still rest
+        assert!(result.book.bids.is_empty());
+        let asks: Vec<(f64, f64, i64)> = result.book.asks.iter().map(|l| (l.price, l.energy, l.order_count)).collect();
+        assert_eq!(asks, vec![(0.1, 3.0, 1), (0.15, 5.0, 1)]);
+    }
+
+    // A buggy price policy: a cent above the 0.2 bid
+    struct OverpricingEngine;
+
+    impl MatchingEngine for OverpricingEngine {
+        fn name(&self) -> &'static str {
+            "overpricing"
+        }
+
+        fn find_matches(&self, book: &OrderBook, market: &MarketConfig) -> MatchPlan {
+            let mut plan = PriceTimePriorityEngine.find_matches(book, market);
+            for proposal in &mut plan.proposals {
+                proposal.price_per_unit = 0.21;
+            }
+            plan
+        }
+    }
+
+    #[test]
+    fn fills_priced_beyond_an_order_limit_are_skipped() {
+        let book = contested_book();
+        let orders = book.bids.into_iter().chain(book.asks).collect();
+        let result = simulate(&OverpricingEngine, orders, &MarketConfig::default());
+
+        assert!(result.trades.is_empty());
+        assert_eq!(result.skipped.len(), 1);
+        assert_eq!(result.skipped[0].reason, SkipReason::TradeThrough);
+        assert_eq!((result.skipped[0].buy_order_id, result.skipped[0].sell_order_id), (Uuid::from_u128(1), Uuid::from_u128(10)));
+        assert_eq!(result.book.bids[0].energy, 6.0);
+    }
+
+    #[test]
+    fn imbalance_compares_bid_and_ask_energy() {
+        let level = |energy| PriceLevel { price: 0.1, energy, order_count: 1 };
+        assert_eq!(AggregatedBook::default().imbalance(), None);
+        let book = AggregatedBook { bids: vec![level(6.0), level(2.0)], asks: vec![level(2.0)] };
+        assert_eq!(book.imbalance(), Some(0.6));
+        let asks_only = AggregatedBook { bids: vec![], asks: vec![level(5.0)] };
+        assert_eq!(asks_only.imbalance(), Some(-1.0));
+    }
+
+    fn ids(orders: Vec<&Order>) -> Vec<u128> {
+        orders.into_iter().map(|o| o.id.as_u128()).collect()
+    }
+
+    #[test]
+    fn same_price_same_time_orders_are_ordered_by_id() {
+        let book = OrderBook {
+            bids: vec![
+                order(3, "b3", "buy", 1.0, 0.2, 0),
+                order(1, "b1", "buy", 1.0, 0.2, 0),
+                order(4, "b4", "buy", 1.0, 0.3, 5),
+                order(2, "b2", "buy", 1.0, 0.2, 0),
+            ],
+            asks: vec![
+                order(12, "s2", "sell", 1.0, 0.1, 0),
+                order(13, "s3", "sell", 1.0, 0.1, 0),
+                order(10, "s0", "sell", 1.0, 0.05, 9),
+                order(11, "s1", "sell", 1.0, 0.1, 0),
+            ],
+        };
+        // Price first, then time, then id
+        assert_eq!(ids(sorted_bids(&book)), vec![4, 1, 2, 3]);
+        assert_eq!(ids(sorted_asks(&book)), vec![10, 11, 12, 13]);
+    }
+
+    #[test]
+    fn tied_orders_match_the_same_way_whatever_order_they_arrive_in() {
+        let bids: Vec<Order> = (1..=3).map(|id| order(id, &format!("buyer-{}", id), "buy", 2.0, 0.2, 0)).collect();
+        let asks: Vec<Order> = (10..=13).map(|id| order(id, &format!("seller-{}", id), "sell", 1.5, 0.2, 0)).collect();
+        let expected = PriceTimePriorityEngine.find_matches(&OrderBook { bids: bids.clone(), asks: asks.clone() }, &MarketConfig::default());
+        assert_eq!(
+            expected.proposals.iter().map(|p| (p.buy_order_id.as_u128(), p.sell_order_id.as_u128(), p.energy_amount)).collect::<Vec<_>>(),
+            vec![(1, 10, 1.5), (1, 11, 0.5), (2, 11, 1.0), (2, 12, 1.0), (3, 12, 0.5), (3, 13, 1.5)]
+        );
+
+        let pro_rata = ProRataEngine.find_matches(&OrderBook { bids: bids.clone(), asks: asks.clone() }, &MarketConfig::default());
+        for rotation in 1..3 {
+            let mut bids = bids.clone();
+            let mut asks = asks.clone();
+            bids.rotate_left(rotation);
+            asks.reverse();
+            asks.rotate_left(rotation);
+            let book = OrderBook { bids, asks };
+            assert_eq!(PriceTimePriorityEngine.find_matches(&book, &MarketConfig::default()).proposals, expected.proposals);
+            assert_eq!(ProRataEngine.find_matches(&book, &MarketConfig::default()).proposals, pro_rata.proposals);
+        }
+    }
+
+    #[test]
+    fn diagnostics_keep_only_the_most_recent_skips() {
+        let skip = |n: u128| MatchSkip::new(Uuid::from_u128(n), Uuid::from_u128(n + 100), SkipReason::Dust, "dust");
+        let diagnostics = MatchDiagnostics::new(2);
+        for n in 1..=3 {
+            diagnostics.record(skip(n));
+        }
+        let recent: Vec<u128> = diagnostics.recent().iter().map(|s| s.buy_order_id.as_u128()).collect();
+        assert_eq!(recent, vec![3, 2]);
+
+        let disabled = MatchDiagnostics::new(0);
+        disabled.record(skip(1));
+        assert!(disabled.recent().is_empty());
+    }
+}
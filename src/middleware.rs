@@ -0,0 +1,979 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use ntex::http::body::{Body, ResponseBody};
+use ntex::http::header::HeaderValue;
+use ntex::http::error::ResponseError;
+use ntex::http::{header, HeaderMap, Method, Payload, Request, StatusCode};
+use ntex::service::{chain_factory, IntoServiceFactory, Middleware, Service, ServiceCtx, ServiceFactory};
+use ntex::time::{timeout, Millis};
+use tracing::Instrument;
+use ntex_cors::{Cors, CorsFactory};
+use ntex::web::dev::AppConfig;
+use ntex::web::{DefaultError, FromRequest, HttpRequest, HttpResponse, WebRequest, WebResponse, WebResponseError};
+use uuid::Uuid;
+
+use crate::auth::{AuthError, AuthStore};
+use crate::config::{env_parse, CompressionConfig, CorsConfig, RateLimitConfig};
+use crate::database::{AuditEntry, DatabaseService};
+use crate::models::ApiResponse;
+
+// Request timeout middleware
+//
+// Aborts any request that takes longer than the configured duration and answers
+// with 504 Gateway Timeout. The handler future is dropped on timeout; database
+// work is cancel-safe because an un-committed `sqlx::Transaction` rolls back
+// when it is dropped, so a timed-out request never leaves a half-applied write.
+#[derive(Clone, Debug)]
+pub struct RequestTimeout {
+    timeout: Rc<Millis>,
+}
+
+impl RequestTimeout {
+    pub fn new(timeout_ms: u64) -> Self {
+        Self {
+            timeout: Rc::new(Millis(timeout_ms.min(u32::MAX as u64) as u32)),
+        }
+    }
+
+    // Reads REQUEST_TIMEOUT_MS from the environment, defaulting to 30 seconds
+    pub fn from_env() -> Self {
+        Self::new(env_parse("REQUEST_TIMEOUT_MS", 30_000))
+    }
+}
+
+impl<S> Middleware<S> for RequestTimeout {
+    type Service = RequestTimeoutMiddleware<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        RequestTimeoutMiddleware {
+            service,
+            timeout: self.timeout.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RequestTimeoutMiddleware<S> {
+    service: S,
+    timeout: Rc<Millis>,
+}
+
+impl<S> Service<WebRequest<DefaultError>> for RequestTimeoutMiddleware<S>
+where
+    S: Service<WebRequest<DefaultError>, Response = WebResponse, Error = ntex::web::Error>,
+{
+    type Response = WebResponse;
+    type Error = ntex::web::Error;
+
+    ntex::forward_poll!(service);
+    ntex::forward_ready!(service);
+    ntex::forward_shutdown!(service);
+
+    async fn call(
+        &self,
+        req: WebRequest<DefaultError>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        let method = req.method().clone();
+        let path = req.path().to_string();
+
+        match timeout(*self.timeout, ctx.call(&self.service, req)).await {
+            Ok(res) => res,
+            Err(()) => {
+                log::warn!("Request {} {} timed out after {}ms", method, path, self.timeout.0);
+                Err(RequestTimedOut.into())
+            }
+        }
+    }
+}
+
+// Rendered as a 504 with the standard ApiResponse error envelope
+#[derive(Debug)]
+pub struct RequestTimedOut;
+
+impl fmt::Display for RequestTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request timed out")
+    }
+}
+
+impl WebResponseError<DefaultError> for RequestTimedOut {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::GATEWAY_TIMEOUT
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(&ApiResponse::<()>::error(self.to_string()))
+    }
+}
+
+// Errors returned by middleware, like the timeout above, reach the server without their
+// request and would be written out as plain text. Wrapping the app renders them in the
+// standard ApiResponse error envelope instead.
+pub fn envelope_errors<F, T>(
+    app: F,
+) -> impl ServiceFactory<Request, AppConfig, Response = WebResponse, Error = EnvelopedError, InitError = ()>
+where
+    F: IntoServiceFactory<T, Request, AppConfig>,
+    T: ServiceFactory<Request, AppConfig, Response = WebResponse, Error = ntex::web::Error, InitError = ()>,
+{
+    chain_factory(app).map_err(EnvelopedError)
+}
+
+#[derive(Debug)]
+pub struct EnvelopedError(pub ntex::web::Error);
+
+impl fmt::Display for EnvelopedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl ResponseError for EnvelopedError {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.0.as_response_error().status_code())
+            .json(&ApiResponse::<()>::error(self.0.to_string()))
+    }
+}
+
+// Request body size middleware
+//
+// Rejects requests whose Content-Length exceeds the limit for their path with 413 before
+// the handler runs. Bodies sent without a length are capped by the `JsonConfig` limit
+// registered alongside, which fails with the same status while the body is read.
+#[derive(Clone, Debug)]
+pub struct BodyLimit {
+    default_limit: usize,
+    path_limits: Rc<Vec<(String, usize)>>,
+}
+
+impl BodyLimit {
+    pub fn new(default_limit: usize) -> Self {
+        Self {
+            default_limit,
+            path_limits: Rc::new(Vec::new()),
+        }
+    }
+
+    // Overrides the limit for requests to exactly `path`
+    pub fn with_path_limit(mut self, path: &str, limit: usize) -> Self {
+        Rc::make_mut(&mut self.path_limits).push((path.to_string(), limit));
+        self
+    }
+}
+
+impl<S> Middleware<S> for BodyLimit {
+    type Service = BodyLimitMiddleware<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        BodyLimitMiddleware {
+            service,
+            default_limit: self.default_limit,
+            path_limits: self.path_limits.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BodyLimitMiddleware<S> {
+    service: S,
+    default_limit: usize,
+    path_limits: Rc<Vec<(String, usize)>>,
+}
+
+impl<S> Service<WebRequest<DefaultError>> for BodyLimitMiddleware<S>
+where
+    S: Service<WebRequest<DefaultError>, Response = WebResponse, Error = ntex::web::Error>,
+{
+    type Response = WebResponse;
+    type Error = ntex::web::Error;
+
+    ntex::forward_poll!(service);
+    ntex::forward_ready!(service);
+    ntex::forward_shutdown!(service);
+
+    async fn call(
+        &self,
+        req: WebRequest<DefaultError>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        let limit = self
+            .path_limits
+            .iter()
+            .find(|(path, _)| path == req.path())
+            .map(|(_, limit)| *limit)
+            .unwrap_or(self.default_limit);
+
+        let length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<usize>().ok());
+        if let Some(length) = length {
+            if length > limit {
+                log::warn!("Rejected {} {}: body of {} bytes exceeds {}", req.method(), req.path(), length, limit);
+                return Ok(req.render_error(PayloadTooLarge { limit }));
+            }
+        }
+
+        ctx.call(&self.service, req).await
+    }
+}
+
+// Rendered as a 413 with the standard ApiResponse error envelope
+#[derive(Debug)]
+pub struct PayloadTooLarge {
+    pub limit: usize,
+}
+
+impl fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request body exceeds the {} byte limit", self.limit)
+    }
+}
+
+impl WebResponseError<DefaultError> for PayloadTooLarge {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::PAYLOAD_TOO_LARGE
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(&ApiResponse::<()>::error(self.to_string()))
+    }
+}
+
+// Response compression middleware
+//
+// Compresses buffered response bodies with brotli or gzip, whichever the client's
+// Accept-Encoding prefers (brotli on a tie). Bodies below the configured threshold,
+// streamed bodies and `text/event-stream` responses are passed through untouched, as
+// are responses that already carry a Content-Encoding. The compressed body stays a
+// sized buffer: any Content-Length set for the original body is dropped as stale, and the
+// server emits one for the compressed size.
+#[derive(Clone, Debug)]
+pub struct Compress {
+    config: Rc<CompressionConfig>,
+}
+
+impl Compress {
+    pub fn new(config: CompressionConfig) -> Self {
+        Self { config: Rc::new(config) }
+    }
+}
+
+impl<S> Middleware<S> for Compress {
+    type Service = CompressMiddleware<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        CompressMiddleware {
+            service,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CompressMiddleware<S> {
+    service: S,
+    config: Rc<CompressionConfig>,
+}
+
+impl<S> Service<WebRequest<DefaultError>> for CompressMiddleware<S>
+where
+    S: Service<WebRequest<DefaultError>, Response = WebResponse, Error = ntex::web::Error>,
+{
+    type Response = WebResponse;
+    type Error = ntex::web::Error;
+
+    ntex::forward_poll!(service);
+    ntex::forward_ready!(service);
+    ntex::forward_shutdown!(service);
+
+    async fn call(
+        &self,
+        req: WebRequest<DefaultError>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        let encoding = if self.config.enabled {
+            req.headers()
+                .get(header::ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok())
+                .and_then(negotiate_encoding)
+        } else {
+            None
+        };
+
+        let res = ctx.call(&self.service, req).await?;
+        let Some(encoding) = encoding else {
+            return Ok(res);
+        };
+
+        let min_size = self.config.min_size;
+        Ok(res.map_body(move |head, body| {
+            let skip = head.headers().contains_key(header::CONTENT_ENCODING)
+                || head.status == StatusCode::NO_CONTENT
+                || head.status == StatusCode::SWITCHING_PROTOCOLS
+                || head
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|ct| ct.starts_with("text/event-stream"));
+            if skip {
+                return body;
+            }
+
+            let bytes = match &body {
+                ResponseBody::Body(Body::Bytes(bytes)) | ResponseBody::Other(Body::Bytes(bytes)) => bytes,
+                _ => return body,
+            };
+            if bytes.len() < min_size {
+                return body;
+            }
+
+            match encoding.compress(bytes) {
+                Ok(compressed) => {
+                    let headers = head.headers_mut();
+                    headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+                    headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+                    headers.remove(header::CONTENT_LENGTH);
+                    ResponseBody::Body(Body::Bytes(compressed.into()))
+                }
+                Err(e) => {
+                    log::warn!("Failed to {} response body: {}", encoding.as_str(), e);
+                    body
+                }
+            }
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                writer.write_all(data)?;
+                writer.flush()?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+// Picks the supported encoding with the highest q-value; `*` covers whichever of the two
+// the header does not name explicitly
+pub fn negotiate_encoding(accept_encoding: &str) -> Option<Encoding> {
+    let mut brotli = None;
+    let mut gzip = None;
+    let mut wildcard = 0.0_f32;
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match name.as_str() {
+            "br" => brotli = Some(quality),
+            "gzip" => gzip = Some(quality),
+            "*" => wildcard = quality,
+            _ => {}
+        }
+    }
+
+    let brotli = brotli.unwrap_or(wildcard);
+    let gzip = gzip.unwrap_or(wildcard);
+    if brotli > 0.0 && brotli >= gzip {
+        Some(Encoding::Brotli)
+    } else if gzip > 0.0 {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    }
+}
+
+// CORS middleware
+//
+// Built from `CorsConfig`. With a `*` allowlist the wildcard is sent as-is; otherwise only
+// listed origins are accepted and the matched origin is reflected back. Credentialed CORS
+// additionally emits `Access-Control-Allow-Credentials: true`, and `CorsConfig::validate`
+// keeps it from ever being paired with the wildcard. `Access-Control-Max-Age` is only sent
+// on preflight (OPTIONS) responses.
+pub fn cors(config: &CorsConfig) -> CorsFactory<DefaultError> {
+    let mut cors = Cors::new()
+        .allowed_headers(vec![header::AUTHORIZATION, header::CONTENT_TYPE, header::ACCEPT])
+        .allowed_header("X-API-Key")
+        .expose_headers(vec!["X-Total-Count", "X-Version"]);
+    if config.allows_any_origin() {
+        cors = cors.send_wildcard();
+    } else {
+        for origin in &config.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+    if config.allow_credentials {
+        cors = cors.supports_credentials();
+    }
+    if config.max_age_secs > 0 {
+        cors = cors.max_age(config.max_age_secs.min(usize::MAX as u64) as usize);
+    }
+    cors.finish()
+}
+
+// Authentication context
+//
+// Resolved from either an `Authorization: Bearer <jwt>` header or an `X-API-Key` header
+// against the `Arc<AuthStore>` registered as app state. Use `AuthContext` as a handler
+// argument to require authentication, or `AdminContext` to require the admin role.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthMethod {
+    Jwt,
+    ApiKey,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    pub user_id: String,
+    pub username: String,
+    pub role: String,
+    pub permissions: Vec<String>,
+    pub method: AuthMethod,
+}
+
+impl AuthContext {
+    pub fn is_admin(&self) -> bool {
+        self.role == "admin"
+    }
+}
+
+pub fn extract_auth_context(req: &HttpRequest) -> Result<AuthContext, AuthError> {
+    // Already resolved earlier in this request (audit middleware or another extractor)
+    if let Some(context) = req.extensions().get::<AuthContext>() {
+        return Ok(context.clone());
+    }
+
+    let store = req
+        .app_state::<Arc<AuthStore>>()
+        .ok_or_else(|| AuthError::Internal("Authentication is not configured".to_string()))?;
+    let context = resolve_auth_context(store, req.headers())?;
+    // Cached so later extractors and the tracing middleware see the caller
+    req.extensions_mut().insert(context.clone());
+    Ok(context)
+}
+
+fn has_credentials(headers: &HeaderMap) -> bool {
+    headers.contains_key(header::AUTHORIZATION) || headers.contains_key("X-API-Key")
+}
+
+fn resolve_auth_context(store: &AuthStore, headers: &HeaderMap) -> Result<AuthContext, AuthError> {
+    if let Some(token) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        let claims = store.validate_jwt(token.trim())?;
+        // Re-load the user so deactivation takes effect before the token expires
+        let user = store.get_user_by_id(&claims.sub)?;
+        if !user.is_active {
+            return Err(AuthError::AccountDisabled);
+        }
+
+        return Ok(AuthContext {
+            user_id: user.id,
+            username: user.username,
+            role: user.role,
+            permissions: Vec::new(),
+            method: AuthMethod::Jwt,
+        });
+    }
+
+    if let Some(key) = headers.get("X-API-Key").and_then(|v| v.to_str().ok()) {
+        let api_key = store.validate_api_key(key.trim())?;
+        if !store.get_user_by_id(&api_key.user_id)?.is_active {
+            return Err(AuthError::AccountDisabled);
+        }
+        return Ok(AuthContext {
+            user_id: api_key.user_id,
+            username: api_key.name,
+            role: api_key.role,
+            permissions: api_key.permissions,
+            method: AuthMethod::ApiKey,
+        });
+    }
+
+    Err(AuthError::InvalidToken)
+}
+
+impl FromRequest<DefaultError> for AuthContext {
+    type Error = AuthError;
+
+    async fn from_request(req: &HttpRequest, _: &mut Payload) -> Result<Self, Self::Error> {
+        extract_auth_context(req)
+    }
+}
+
+// Authenticated caller holding the admin role
+#[derive(Debug, Clone)]
+pub struct AdminContext(pub AuthContext);
+
+impl FromRequest<DefaultError> for AdminContext {
+    type Error = AuthError;
+
+    async fn from_request(req: &HttpRequest, _: &mut Payload) -> Result<Self, Self::Error> {
+        let context = extract_auth_context(req)?;
+        if context.is_admin() {
+            Ok(AdminContext(context))
+        } else {
+            Err(AuthError::InsufficientPermissions)
+        }
+    }
+}
+
+impl WebResponseError<DefaultError> for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::InvalidCredentials
+            | AuthError::TokenExpired
+            | AuthError::InvalidToken
+            | AuthError::UserNotFound
+            | AuthError::ApiKeyNotFound
+            | AuthError::AccountDisabled => StatusCode::UNAUTHORIZED,
+            AuthError::InsufficientPermissions => StatusCode::FORBIDDEN,
+            AuthError::UserAlreadyExists => StatusCode::CONFLICT,
+            AuthError::WeakPassword(_) => StatusCode::BAD_REQUEST,
+            AuthError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(&ApiResponse::<()>::error(self.to_string()))
+    }
+}
+
+// Audit log middleware
+//
+// Records every mutating request (POST/PUT/PATCH/DELETE), successful or not, with the
+// authenticated user (if any), method, path, request id and response status. Rows are
+// written in the background so auditing never delays the response. The resolved
+// `AuthContext` is cached in request extensions for the handler's extractors.
+#[derive(Clone, Debug, Default)]
+pub struct AuditLog;
+
+impl<S> Middleware<S> for AuditLog {
+    type Service = AuditLogMiddleware<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        AuditLogMiddleware { service }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditLogMiddleware<S> {
+    service: S,
+}
+
+impl<S> Service<WebRequest<DefaultError>> for AuditLogMiddleware<S>
+where
+    S: Service<WebRequest<DefaultError>, Response = WebResponse, Error = ntex::web::Error>,
+{
+    type Response = WebResponse;
+    type Error = ntex::web::Error;
+
+    ntex::forward_poll!(service);
+    ntex::forward_ready!(service);
+    ntex::forward_shutdown!(service);
+
+    async fn call(
+        &self,
+        req: WebRequest<DefaultError>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        let method = req.method().clone();
+        if !matches!(method, Method::POST | Method::PUT | Method::PATCH | Method::DELETE) {
+            return ctx.call(&self.service, req).await;
+        }
+
+        let db = req.app_state::<Arc<DatabaseService>>().cloned();
+        let mut user_id = None;
+        if has_credentials(req.headers()) {
+            if let Some(store) = req.app_state::<Arc<AuthStore>>() {
+                if let Ok(context) = resolve_auth_context(store, req.headers()) {
+                    user_id = Some(context.user_id.clone());
+                    req.extensions_mut().insert(context);
+                }
+            }
+        }
+        let request_id = req
+            .headers()
+            .get("X-Request-ID")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let path = req.path().to_string();
+
+        let result = ctx.call(&self.service, req).await;
+        let status = match &result {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+
+        if let Some(db) = db {
+            let entry = AuditEntry {
+                id: Uuid::new_v4(),
+                user_id,
+                method: method.to_string(),
+                path,
+                request_id,
+                status_code: status.as_u16() as i64,
+                created_at: Utc::now(),
+            };
+            ntex::rt::spawn(async move {
+                if let Err(e) = db.record_audit_entry(&entry).await {
+                    log::error!("Failed to write audit entry for {} {}: {}", entry.method, entry.path, e);
+                }
+            });
+        }
+
+        result
+    }
+}
+
+// Request tracing middleware
+//
+// Opens a `request` span (method, path, request id, user) around each request so events
+// and database spans emitted while handling it are attributed to it. A request id is
+// assigned when the client didn't send `X-Request-ID`, so traces and audit entries agree.
+#[derive(Clone, Debug, Default)]
+pub struct RequestTracing;
+
+impl<S> Middleware<S> for RequestTracing {
+    type Service = RequestTracingMiddleware<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        RequestTracingMiddleware { service }
+    }
+}
+
+#[derive(Debug)]
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S> Service<WebRequest<DefaultError>> for RequestTracingMiddleware<S>
+where
+    S: Service<WebRequest<DefaultError>, Response = WebResponse, Error = ntex::web::Error>,
+{
+    type Response = WebResponse;
+    type Error = ntex::web::Error;
+
+    ntex::forward_poll!(service);
+    ntex::forward_ready!(service);
+    ntex::forward_shutdown!(service);
+
+    async fn call(
+        &self,
+        mut req: WebRequest<DefaultError>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        let request_id = match req.headers().get("X-Request-ID").and_then(|v| v.to_str().ok()) {
+            Some(id) => id.to_string(),
+            None => {
+                let id = Uuid::new_v4().to_string();
+                if let Ok(value) = HeaderValue::from_str(&id) {
+                    req.headers_mut().insert(header::HeaderName::from_static("x-request-id"), value);
+                }
+                id
+            }
+        };
+        let span = tracing::info_span!(
+            "request",
+            method = %req.method(),
+            path = %req.path(),
+            request_id = %request_id,
+            user = tracing::field::Empty,
+            status = tracing::field::Empty,
+        );
+
+        let result = ctx.call(&self.service, req).instrument(span.clone()).await;
+        let status = match &result {
+            Ok(res) => {
+                // The caller is only known once an extractor (or the audit log) resolved it
+                if let Some(context) = res.request().extensions().get::<AuthContext>() {
+                    span.record("user", context.user_id.as_str());
+                }
+                res.status()
+            }
+            Err(e) => e.as_response_error().status_code(),
+        };
+        span.record("status", status.as_u16());
+
+        result
+    }
+}
+
+// Rate-limit headers middleware
+//
+// Counts the requests of each authenticated caller in fixed windows and reports their
+// standing on every authenticated response: `X-RateLimit-Limit`, `X-RateLimit-Remaining`
+// and `X-RateLimit-Reset` (seconds until the window starts over). Requests are never
+// rejected here. Unauthenticated responses carry none of the headers.
+#[derive(Clone, Debug)]
+pub struct RateLimitHeaders {
+    quota: Arc<QuotaCounter>,
+}
+
+impl RateLimitHeaders {
+    // The counter is shared so every worker reports against the same quota
+    pub fn new(quota: Arc<QuotaCounter>) -> Self {
+        Self { quota }
+    }
+}
+
+impl<S> Middleware<S> for RateLimitHeaders {
+    type Service = RateLimitHeadersMiddleware<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        RateLimitHeadersMiddleware {
+            service,
+            quota: self.quota.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimitHeadersMiddleware<S> {
+    service: S,
+    quota: Arc<QuotaCounter>,
+}
+
+impl<S> Service<WebRequest<DefaultError>> for RateLimitHeadersMiddleware<S>
+where
+    S: Service<WebRequest<DefaultError>, Response = WebResponse, Error = ntex::web::Error>,
+{
+    type Response = WebResponse;
+    type Error = ntex::web::Error;
+
+    ntex::forward_poll!(service);
+    ntex::forward_ready!(service);
+    ntex::forward_shutdown!(service);
+
+    async fn call(
+        &self,
+        req: WebRequest<DefaultError>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        // Reuse the caller the audit log already resolved. Otherwise resolve it once here and
+        // cache it, so a handler taking an `AuthContext` doesn't verify the credentials again.
+        let cached = req.extensions().get::<AuthContext>().map(|context| context.user_id.clone());
+        let caller = match cached {
+            Some(user_id) => Some(user_id),
+            None if has_credentials(req.headers()) => {
+                let context = req
+                    .app_state::<Arc<AuthStore>>()
+                    .and_then(|store| resolve_auth_context(store, req.headers()).ok());
+                context.map(|context| {
+                    let user_id = context.user_id.clone();
+                    req.extensions_mut().insert(context);
+                    user_id
+                })
+            }
+            None => None,
+        };
+
+        let mut res = ctx.call(&self.service, req).await?;
+        let Some(caller) = caller else {
+            return Ok(res);
+        };
+
+        let state = self.quota.record(&caller, Instant::now());
+        let headers = res.headers_mut();
+        for (name, value) in [
+            ("x-ratelimit-limit", u64::from(state.limit)),
+            ("x-ratelimit-remaining", u64::from(state.remaining)),
+            ("x-ratelimit-reset", state.reset_secs),
+        ] {
+            if let Ok(value) = HeaderValue::from_str(&value.to_string()) {
+                headers.insert(header::HeaderName::from_static(name), value);
+            }
+        }
+        Ok(res)
+    }
+}
+
+// Fixed-window request counts per caller. Windows that have run out are dropped at most once
+// per window length, so callers who stop sending requests aren't tracked forever.
+#[derive(Debug)]
+pub struct QuotaCounter {
+    limit: u32,
+    window: Duration,
+    windows: Mutex<QuotaWindows>,
+}
+
+#[derive(Debug, Default)]
+struct QuotaWindows {
+    by_caller: HashMap<String, (Instant, u32)>,
+    swept_at: Option<Instant>,
+}
+
+// A caller's standing after their latest request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaState {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_secs: u64,
+}
+
+impl QuotaCounter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            limit: config.requests_per_window,
+            window: Duration::from_secs(config.window_secs.max(1)),
+            windows: Mutex::new(QuotaWindows::default()),
+        }
+    }
+
+    // Counts one request by `caller` at `now`, starting a new window if theirs has passed
+    pub fn record(&self, caller: &str, now: Instant) -> QuotaState {
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        let swept_at = *windows.swept_at.get_or_insert(now);
+        if now.saturating_duration_since(swept_at) >= self.window {
+            let window = self.window;
+            windows.by_caller.retain(|_, (started, _)| now.saturating_duration_since(*started) < window);
+            windows.swept_at = Some(now);
+        }
+
+        let (started, count) = windows.by_caller.entry(caller.to_string()).or_insert((now, 0));
+        if now.saturating_duration_since(*started) >= self.window {
+            *started = now;
+            *count = 0;
+        }
+        *count = count.saturating_add(1);
+
+        let left = self.window.saturating_sub(now.saturating_duration_since(*started));
+        QuotaState {
+            limit: self.limit,
+            remaining: self.limit.saturating_sub(*count),
+            reset_secs: left.as_millis().div_ceil(1000) as u64,
+        }
+    }
+
+    // Callers with a window still being tracked
+    pub fn tracked_callers(&self) -> usize {
+        self.windows.lock().unwrap_or_else(|e| e.into_inner()).by_caller.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brotli_wins_a_tie() {
+        assert_eq!(negotiate_encoding("gzip, deflate, br"), Some(Encoding::Brotli));
+    }
+
+    #[test]
+    fn higher_q_value_wins() {
+        assert_eq!(negotiate_encoding("br;q=0.5, gzip;q=0.8"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_encoding("GZIP;q=0.2, BR ; q=0.9"), Some(Encoding::Brotli));
+    }
+
+    #[test]
+    fn zero_q_value_refuses_an_encoding() {
+        assert_eq!(negotiate_encoding("br;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_encoding("br;q=0, gzip;q=0"), None);
+    }
+
+    #[test]
+    fn wildcard_covers_only_unnamed_encodings() {
+        assert_eq!(negotiate_encoding("*"), Some(Encoding::Brotli));
+        assert_eq!(negotiate_encoding("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_encoding("*, br;q=0, gzip;q=0"), None);
+        assert_eq!(negotiate_encoding("*;q=0"), None);
+    }
+
+    #[test]
+    fn unsupported_encodings_are_ignored() {
+        assert_eq!(negotiate_encoding("deflate, identity"), None);
+        assert_eq!(negotiate_encoding(""), None);
+    }
+
+    fn quota(requests_per_window: u32) -> QuotaCounter {
+        QuotaCounter::new(&RateLimitConfig {
+            requests_per_window,
+            window_secs: 60,
+        })
+    }
+
+    #[test]
+    fn remaining_quota_decrements_across_requests() {
+        let quota = quota(3);
+        let now = Instant::now();
+        let first = quota.record("user-1", now);
+        let second = quota.record("user-1", now + Duration::from_secs(1));
+        assert_eq!((first.limit, first.remaining), (3, 2));
+        assert_eq!((second.limit, second.remaining), (3, 1));
+        assert_eq!((first.reset_secs, second.reset_secs), (60, 59));
+    }
+
+    #[test]
+    fn callers_have_separate_quotas() {
+        let quota = quota(3);
+        let now = Instant::now();
+        quota.record("user-1", now);
+        assert_eq!(quota.record("user-2", now).remaining, 2);
+    }
+
+    #[test]
+    fn remaining_quota_stops_at_zero_and_resets_with_the_window() {
+        let quota = quota(1);
+        let now = Instant::now();
+        quota.record("user-1", now);
+        assert_eq!(quota.record("user-1", now).remaining, 0);
+        let next_window = quota.record("user-1", now + Duration::from_secs(60));
+        assert_eq!((next_window.remaining, next_window.reset_secs), (0, 60));
+    }
+
+    #[test]
+    fn expired_windows_are_dropped() {
+        let quota = quota(3);
+        let now = Instant::now();
+        quota.record("user-1", now);
+        quota.record("user-2", now + Duration::from_secs(30));
+        assert_eq!(quota.tracked_callers(), 2);
+
+        // A minute on, user-1's window has run out and goes; user-2's is still open
+        quota.record("user-3", now + Duration::from_secs(60));
+        assert_eq!(quota.tracked_callers(), 2);
+        assert_eq!(quota.record("user-2", now + Duration::from_secs(61)).remaining, 1);
+
+        quota.record("user-3", now + Duration::from_secs(200));
+        assert_eq!(quota.tracked_callers(), 1);
+    }
+}
@@ -1,7 +1,10 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 
+use crate::config::{sanitize_tag_key, sanitize_text, FieldLimits};
+
 // API Request/Response Models
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,11 +42,53 @@ pub struct CreateProsumerRequest {
     pub name: String,
 }
 
+impl CreateProsumerRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        self.address = sanitize_text("address", &self.address, limits.max_address_length)?;
+        self.name = sanitize_text("name", &self.name, limits.max_name_length)?;
+        Ok(())
+    }
+}
+
+// Deserializes a field that may be omitted (None) or sent as an explicit null (Some(None)).
+// Use with `#[serde(default, deserialize_with = "nullable")]`.
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// Partial update: an omitted field is left unchanged, an explicit null clears it (name to "",
+// energy totals to 0), and a value replaces it
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateProsumerRequest {
-    pub name: Option<String>,
-    pub energy_generated: Option<f64>,
-    pub energy_consumed: Option<f64>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub energy_generated: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub energy_consumed: Option<Option<f64>>,
+}
+
+impl UpdateProsumerRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        if let Some(Some(name)) = &self.name {
+            self.name = Some(Some(sanitize_text("name", name, limits.max_name_length)?));
+        }
+        Ok(())
+    }
+
+    // Values to write, with None meaning "leave unchanged" and cleared fields resolved to
+    // their empty value
+    pub fn changes(&self) -> (Option<String>, Option<f64>, Option<f64>) {
+        (
+            self.name.clone().map(Option::unwrap_or_default),
+            self.energy_generated.map(|value| value.unwrap_or(0.0)),
+            self.energy_consumed.map(|value| value.unwrap_or(0.0)),
+        )
+    }
 }
 
 // Order API Models
@@ -53,16 +98,63 @@ pub struct CreateOrderRequest {
     pub order_type: String, // "buy" or "sell"
     pub energy_amount: f64,
     pub price_per_unit: f64,
+    pub currency: Option<String>, // defaults to the base currency (GRID)
     pub expires_at: Option<DateTime<Utc>>,
 }
 
+impl CreateOrderRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        self.prosumer_address = sanitize_text("prosumer_address", &self.prosumer_address, limits.max_address_length)?;
+        if let Some(currency) = &self.currency {
+            // Matches the width of orders.currency
+            self.currency = Some(sanitize_text("currency", currency, 10)?);
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateOrderRequest {
-    pub status: Option<String>,
     pub energy_amount: Option<f64>,
     pub price_per_unit: Option<f64>,
 }
 
+// Trades executed together: all of them or none
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExecuteTradeBatchRequest {
+    pub trades: Vec<ExecuteTradeRequest>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ForceCancelOrderRequest {
+    pub reason: String, // recorded in the admin audit log
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HaltMarketRequest {
+    pub reason: String, // reported in /stats/market and recorded in the admin audit log
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkBalanceRequest {
+    pub addresses: Vec<String>,
+}
+
+impl BulkBalanceRequest {
+    // Trims each address and drops duplicates, keeping first-seen order
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        let mut addresses: Vec<String> = Vec::with_capacity(self.addresses.len());
+        for address in &self.addresses {
+            let address = sanitize_text("address", address, limits.max_address_length)?;
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        self.addresses = addresses;
+        Ok(())
+    }
+}
+
 // Trade API Models
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ExecuteTradeRequest {
@@ -71,14 +163,362 @@ pub struct ExecuteTradeRequest {
     pub price_per_unit: Option<f64>,
 }
 
+// Order lookup options; trades are only embedded when asked for
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderQuery {
+    pub include_trades: Option<bool>,
+    pub fields: Option<String>, // comma-separated, see select_fields
+}
+
+pub const ORDER_STATUSES: [&str; 5] = ["pending", "active", "completed", "cancelled", "expired"];
+pub const ORDER_TYPES: [&str; 2] = ["buy", "sell"];
+
+// Order listing filters; every field is optional and absent ones don't filter
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderListQuery {
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+    pub status: Option<String>,
+    pub order_type: Option<String>,
+    pub prosumer_address: Option<String>,
+    pub sort: Option<String>, // `column[:asc|desc]`, defaults to created_at:desc
+}
+
+impl OrderListQuery {
+    // Rejects unknown statuses and order types instead of silently matching nothing
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        if let Some(status) = &self.status {
+            let status = status.trim().to_lowercase();
+            if !ORDER_STATUSES.contains(&status.as_str()) {
+                return Err(format!("status must be one of: {}", ORDER_STATUSES.join(", ")));
+            }
+            self.status = Some(status);
+        }
+        if let Some(order_type) = &self.order_type {
+            let order_type = order_type.trim().to_lowercase();
+            if !ORDER_TYPES.contains(&order_type.as_str()) {
+                return Err(format!("order_type must be one of: {}", ORDER_TYPES.join(", ")));
+            }
+            self.order_type = Some(order_type);
+        }
+        if let Some(address) = &self.prosumer_address {
+            self.prosumer_address = Some(sanitize_text("prosumer_address", address, limits.max_address_length)?);
+        }
+        Ok(())
+    }
+}
+
+// Prosumer lookup options; activity counts cost two extra subqueries, so they are opt-in
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProsumerQuery {
+    pub include_counts: Option<bool>,
+    pub fields: Option<String>, // comma-separated, see select_fields
+}
+
+// Partial responses: keeps only the top-level fields named in a `fields=a,b` query, as `value`
+// would serialize them. Without a selection the whole value is returned. Naming a field the
+// response does not have is an error rather than silently dropped.
+pub fn select_fields<T: Serialize>(value: &T, fields: Option<&str>) -> Result<serde_json::Value, String> {
+    let value = serde_json::to_value(value).map_err(|e| format!("cannot serialize response: {}", e))?;
+    let Some(fields) = fields else {
+        return Ok(value);
+    };
+    let serde_json::Value::Object(mut object) = value else {
+        return Err("fields can only be selected on an object response".to_string());
+    };
+    let requested: Vec<&str> = fields.split(',').map(str::trim).filter(|f| !f.is_empty()).collect();
+    if requested.is_empty() {
+        return Err("fields must name at least one field".to_string());
+    }
+    if let Some(unknown) = requested.iter().find(|field| !object.contains_key(**field)) {
+        let known: Vec<&str> = object.keys().map(String::as_str).collect();
+        return Err(format!("unknown field '{}'; expected any of: {}", unknown, known.join(", ")));
+    }
+    object.retain(|key, _| requested.contains(&key.as_str()));
+    Ok(serde_json::Value::Object(object))
+}
+
+// Pagination API Models
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaginationQuery {
+    pub page: Option<u32>,  // 1-based, defaults to 1
+    pub limit: Option<u32>, // defaults to DEFAULT_PAGE_SIZE, clamped to MAX_PAGE_SIZE
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProsumerListQuery {
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+    pub sort: Option<String>, // `column[:asc|desc]`, defaults to created_at:desc
+    pub tag: Option<String>, // `key:value`
+    pub region: Option<String>, // shorthand for tag=region:<value>
+}
+
+// Scopes market stats to prosumers carrying a tag; same syntax as ProsumerListQuery
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarketStatsQuery {
+    pub tag: Option<String>,
+    pub region: Option<String>,
+}
+
+// Most tags a single prosumer can carry
+pub const MAX_PROSUMER_TAGS: usize = 32;
+
+// Replaces all of a prosumer's tags
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetProsumerTagsRequest {
+    pub tags: BTreeMap<String, String>,
+}
+
+impl SetProsumerTagsRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        if self.tags.len() > MAX_PROSUMER_TAGS {
+            return Err(format!("a prosumer can have at most {} tags", MAX_PROSUMER_TAGS));
+        }
+        let mut tags = BTreeMap::new();
+        for (key, value) in &self.tags {
+            let key = sanitize_tag_key(key)?;
+            let value = sanitize_text(&format!("tag '{}'", key), value, limits.max_name_length)?;
+            tags.insert(key, value);
+        }
+        self.tags = tags;
+        Ok(())
+    }
+}
+
+// Paginated per-prosumer stats; `sort_by` names a ProsumerStats metric (default total_volume)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProsumerStatsQuery {
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+    pub sort_by: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaginatedResponse<T> {
+    pub items: Vec<T>,
+    pub page: u32,
+    pub limit: u32, // effective limit after clamping
+}
+
 // Token transfer API Models
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TransferTokensRequest {
     pub from_address: String,
     pub to_address: String,
     pub amount: f64,
-    pub token_type: String, // "grid_tokens" or "watt_tokens"
+    pub token_type: String, // a registered token type, e.g. "grid_tokens" or "watt_tokens"
+}
+
+impl TransferTokensRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        self.from_address = sanitize_text("from_address", &self.from_address, limits.max_address_length)?;
+        self.to_address = sanitize_text("to_address", &self.to_address, limits.max_address_length)?;
+        Ok(())
+    }
 }
+
+// Closing a prosumer sweeps its balances to another, still active prosumer
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CloseProsumerRequest {
+    pub destination_address: String,
+}
+
+impl CloseProsumerRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        self.destination_address = sanitize_text("destination_address", &self.destination_address, limits.max_address_length)?;
+        Ok(())
+    }
+}
+
+// Faucet API Models
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FaucetRequest {
+    pub address: String,
+}
+
+impl FaucetRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        self.address = sanitize_text("address", &self.address, limits.max_address_length)?;
+        Ok(())
+    }
+}
+
+// Token type API Models
+pub const MAX_TOKEN_TYPE_LENGTH: usize = 64;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateTokenTypeRequest {
+    pub name: String, // lowercase letters, digits and underscores, e.g. "carbon_credits"
+    pub description: Option<String>,
+}
+
+impl CreateTokenTypeRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        self.name = sanitize_text("name", &self.name, MAX_TOKEN_TYPE_LENGTH)?;
+        if !self.name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+            return Err("name may only contain lowercase letters, digits and underscores".to_string());
+        }
+        if let Some(description) = &self.description {
+            self.description = Some(sanitize_text("description", description, limits.max_description_length)?);
+        }
+        Ok(())
+    }
+}
+// Audit API Models
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub user: Option<String>, // user id
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+}
+
+// Webhook API Models
+pub const MAX_WEBHOOK_URL_LENGTH: usize = 2048;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub events: Vec<String>,    // e.g. ["order_created", "trade_executed"]
+    pub secret: Option<String>, // generated when omitted
+}
+
+// Price alert API Models
+pub const PRICE_ALERT_SIDES: [&str; 2] = ["bid", "ask"];
+pub const PRICE_ALERT_DIRECTIONS: [&str; 2] = ["above", "below"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatePriceAlertRequest {
+    pub address: String,
+    pub side: String,      // "bid" (best buy price) or "ask" (best sell price)
+    pub direction: String, // "above" or "below"
+    pub price: f64,
+}
+
+impl CreatePriceAlertRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        self.address = sanitize_text("address", &self.address, limits.max_address_length)?;
+        self.side = self.side.trim().to_lowercase();
+        if !PRICE_ALERT_SIDES.contains(&self.side.as_str()) {
+            return Err(format!("side must be one of {:?}", PRICE_ALERT_SIDES));
+        }
+        self.direction = self.direction.trim().to_lowercase();
+        if !PRICE_ALERT_DIRECTIONS.contains(&self.direction.as_str()) {
+            return Err(format!("direction must be one of {:?}", PRICE_ALERT_DIRECTIONS));
+        }
+        if !self.price.is_finite() || self.price <= 0.0 {
+            return Err("price must be a positive number".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PriceAlertQuery {
+    pub address: Option<String>,
+}
+
+// Statistics API Models
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimeseriesQuery {
+    pub interval_secs: Option<i64>,       // defaults to 3600 (hourly buckets)
+    pub from: Option<DateTime<Utc>>,      // defaults to 24 hours before `to`
+    pub to: Option<DateTime<Utc>>,        // defaults to now
+}
+
+// Account statement window and output format
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatementQuery {
+    pub from: Option<DateTime<Utc>>, // defaults to 30 days before `to`
+    pub to: Option<DateTime<Utc>>,   // defaults to now
+    pub format: Option<String>,      // "json" (default) or "csv"; `Accept: text/csv` also selects CSV
+}
+
+// Realized P&L window and cost basis method
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PnlQuery {
+    pub from: Option<DateTime<Utc>>, // defaults to 30 days before `to`
+    pub to: Option<DateTime<Utc>>,   // defaults to now
+    pub method: Option<String>,      // "average_cost" or "fifo"; defaults to PNL_METHOD
+}
+
+// Trade listing; a `from` older than the retention window also searches archived trades
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TradeListQuery {
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+    pub from: Option<DateTime<Utc>>, // inclusive
+    pub to: Option<DateTime<Utc>>,   // exclusive
+    pub sort: Option<String>,        // `column[:asc|desc]`, defaults to created_at:desc
+}
+
+// Batch API Models
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperationRequest {
+    CreateProsumer(CreateProsumerRequest),
+    Transfer(TransferTokensRequest),
+    CreateOrder(CreateOrderRequest),
+}
+
+impl BatchOperationRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits) -> Result<(), String> {
+        match self {
+            BatchOperationRequest::CreateProsumer(request) => request.sanitize(limits),
+            BatchOperationRequest::Transfer(request) => request.sanitize(limits),
+            BatchOperationRequest::CreateOrder(request) => request.sanitize(limits),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperationRequest>,
+}
+
+// Simulation API Models
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimulatedOrderRequest {
+    pub id: Option<Uuid>, // generated when omitted
+    pub prosumer_address: String,
+    pub order_type: String, // "buy" or "sell"
+    pub energy_amount: f64,
+    pub price_per_unit: f64, // in the base currency
+    pub created_at: Option<DateTime<Utc>>, // defaults to submission order
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimulateMatchRequest {
+    pub orders: Vec<SimulatedOrderRequest>,
+}
+
+impl SimulateMatchRequest {
+    pub fn sanitize(&mut self, limits: &FieldLimits, max_orders: usize) -> Result<(), String> {
+        if self.orders.is_empty() {
+            return Err("orders must contain at least one order".to_string());
+        }
+        if self.orders.len() > max_orders {
+            return Err(format!("At most {} orders may be simulated at once", max_orders));
+        }
+        let mut ids = std::collections::HashSet::new();
+        for order in &mut self.orders {
+            order.prosumer_address = sanitize_text("prosumer_address", &order.prosumer_address, limits.max_address_length)?;
+            order.order_type = order.order_type.trim().to_lowercase();
+            if !ORDER_TYPES.contains(&order.order_type.as_str()) {
+                return Err(format!("order_type must be one of: {}", ORDER_TYPES.join(", ")));
+            }
+            if let Some(id) = order.id {
+                if !ids.insert(id) {
+                    return Err(format!("Duplicate order id {}", id));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 // Legacy API Models (for backward compatibility)
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateAccountRequest {
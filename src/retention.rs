@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use chrono::Duration;
+use ntex::time::{sleep, Millis};
+
+use crate::config::TradeArchiveConfig;
+use crate::database::DatabaseService;
+
+// Trade history retention
+//
+// Every `interval_secs` moves trades older than the retention window into
+// `trades_archive`, keeping the hot `trades` table small. Archived trades stay
+// reachable through GET /trades with a `from` date that reaches back far enough.
+pub async fn run_trade_archival(db: Arc<DatabaseService>, config: TradeArchiveConfig) {
+    loop {
+        let cutoff = db.now() - Duration::days(config.retention_days);
+        match db.archive_trades(cutoff).await {
+            Ok(0) => {}
+            Ok(archived) => log::info!("Archived {} trades created before {}", archived, cutoff),
+            Err(e) => log::error!("Trade archival failed: {}", e),
+        }
+
+        sleep(Millis(config.interval_secs.saturating_mul(1000).min(u32::MAX as u64) as u32)).await;
+    }
+}
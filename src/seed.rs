@@ -0,0 +1,112 @@
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::config::is_production_env;
+use crate::database::{BatchOperation, BatchOperationResult, DatabaseError, DatabaseService, Order, Prosumer};
+
+// Demo data for local development
+//
+// Populates an empty database with a few prosumers, open buy/sell orders and two
+// executed trades so the API and Swagger UI have something to show. Seeding is
+// idempotent: if the first demo prosumer already exists nothing is written.
+
+// (address, name, energy_generated, energy_consumed, grid_tokens)
+const DEMO_PROSUMERS: [(&str, &str, f64, f64, f64); 4] = [
+    ("0xdemo0000000000000000000000000000000000a1", "Demo Solar Farm", 1_200.0, 150.0, 500.0),
+    ("0xdemo0000000000000000000000000000000000b2", "Demo Wind Co-op", 900.0, 200.0, 350.0),
+    ("0xdemo0000000000000000000000000000000000c3", "Demo Household", 40.0, 320.0, 1_000.0),
+    ("0xdemo0000000000000000000000000000000000d4", "Demo Office Block", 0.0, 780.0, 2_500.0),
+];
+
+// (prosumer index, order_type, energy_amount, price_per_unit)
+const DEMO_ORDERS: [(usize, &str, f64, f64); 6] = [
+    (0, "sell", 100.0, 0.12),
+    (1, "sell", 80.0, 0.14),
+    (2, "buy", 50.0, 0.13),
+    (3, "buy", 120.0, 0.15),
+    (0, "sell", 60.0, 0.18),
+    (2, "buy", 30.0, 0.10),
+];
+
+// (buy order index, sell order index) pairs executed after the orders are placed
+const DEMO_TRADES: [(usize, usize); 2] = [(2, 0), (3, 1)];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeedSummary {
+    pub prosumers: usize,
+    pub orders: usize,
+    pub trades: usize,
+}
+
+// Refuses to seed when APP_ENV names a production environment
+pub fn ensure_seed_allowed() -> Result<(), String> {
+    if is_production_env() {
+        let env = std::env::var("APP_ENV").unwrap_or_default();
+        return Err(format!("Refusing to seed demo data with APP_ENV={}", env.trim()));
+    }
+    Ok(())
+}
+
+// Returns `None` when the demo data is already present. Everything is written in one batch
+// transaction, so a failed or concurrent seed leaves no partial demo data behind.
+pub async fn seed_demo_data(db: &DatabaseService) -> Result<Option<SeedSummary>, DatabaseError> {
+    if db.prosumer_exists(DEMO_PROSUMERS[0].0).await? {
+        return Ok(None);
+    }
+
+    let now = Utc::now();
+    let mut operations = Vec::with_capacity(DEMO_PROSUMERS.len() + DEMO_ORDERS.len() + DEMO_TRADES.len());
+    for (address, name, generated, consumed, grid_tokens) in DEMO_PROSUMERS {
+        operations.push(BatchOperation::CreateProsumer(Prosumer {
+            address: address.to_string(),
+            name: name.to_string(),
+            energy_generated: generated,
+            energy_consumed: consumed,
+            grid_tokens,
+            watt_tokens: 0.0,
+            is_active: true,
+            created_at: now,
+            updated_at: now,
+        }));
+    }
+
+    // Ids are assigned up front so the trades can refer to orders of the same batch
+    let order_ids: Vec<Uuid> = DEMO_ORDERS.iter().map(|_| Uuid::new_v4()).collect();
+    for (id, (prosumer, order_type, energy_amount, price_per_unit)) in order_ids.iter().zip(DEMO_ORDERS) {
+        operations.push(BatchOperation::CreateOrder(Order {
+            id: *id,
+            prosumer_address: DEMO_PROSUMERS[prosumer].0.to_string(),
+            order_type: order_type.to_string(),
+            energy_amount,
+            price_per_unit,
+            total_price: energy_amount * price_per_unit,
+            currency: db.base_currency().to_string(),
+            quoted_price_per_unit: price_per_unit,
+            filled_amount: 0.0,
+            remaining_amount: energy_amount,
+            status: "active".to_string(),
+            created_at: now,
+            updated_at: now,
+            expires_at: None,
+        }));
+    }
+
+    for (buy, sell) in DEMO_TRADES {
+        operations.push(BatchOperation::ExecuteTrade {
+            buy_order_id: order_ids[buy],
+            sell_order_id: order_ids[sell],
+            price_per_unit: None,
+        });
+    }
+
+    let mut summary = SeedSummary::default();
+    for result in db.execute_batch(operations).await? {
+        match result {
+            BatchOperationResult::CreateProsumer { .. } => summary.prosumers += 1,
+            BatchOperationResult::CreateOrder { .. } => summary.orders += 1,
+            BatchOperationResult::ExecuteTrade { .. } => summary.trades += 1,
+            BatchOperationResult::Transfer { .. } => {}
+        }
+    }
+    Ok(Some(summary))
+}
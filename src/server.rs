@@ -3,11 +3,61 @@ use std::io;
 
 use ntex::web::{self, middleware, App, HttpServer};
 
+use crate::auth::{AdminBootstrap, AuthStore};
+use crate::auth_handlers;
+use crate::config::{env_parse, AutoMatchConfig, BindAddress, BodyLimitConfig, CompressionConfig, CorsConfig, FaucetConfig, MigrationConfig, RateLimitConfig, TradeArchiveConfig};
 use crate::database::DatabaseService;
+use crate::expiry;
+use crate::faucet::Faucet;
 use crate::handlers;
+use crate::matching;
+use crate::retention;
+use crate::telemetry;
+use crate::middleware::{cors, envelope_errors, AuditLog, BodyLimit, Compress, QuotaCounter, RateLimitHeaders, RequestTimeout, RequestTracing};
+use crate::seed;
+use crate::settlement;
+
+// Startup switches parsed from the command line in main.rs
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    // Insert demo data before serving (refused when APP_ENV is production)
+    pub seed: bool,
+}
+
+// Endpoints that take many operations in one body. They are allowed MAX_BATCH_BODY_BYTES
+// rather than MAX_BODY_BYTES, both by `body_limit` and by their JSON extractor; a new batch
+// endpoint is listed here and registered in `batch_routes`.
+pub const BATCH_ROUTES: [&str; 2] = ["/batch", "/trades/batch"];
+
+// Body size limits for the whole app, with the batch allowance on every batch route
+pub fn body_limit(config: &BodyLimitConfig) -> BodyLimit {
+    BATCH_ROUTES
+        .iter()
+        .fold(BodyLimit::new(config.max_body_bytes), |limit, path| limit.with_path_limit(path, config.max_batch_body_bytes))
+}
+
+// The batch endpoints, each reading bodies up to `max_batch_body_bytes`
+pub fn batch_routes(cfg: &mut web::ServiceConfig, max_batch_body_bytes: usize) {
+    let json = web::types::JsonConfig::default().limit(max_batch_body_bytes);
+    let [batch, trades_batch] = BATCH_ROUTES;
+    cfg.service(
+        web::resource(batch)
+            .state(json.clone())
+            .route(web::post().to(handlers::execute_batch))
+    )
+    .service(
+        web::resource(trades_batch)
+            .state(json)
+            .route(web::post().to(auth_handlers::execute_trade_batch))
+    );
+}
 
 pub async fn start_server(port: u16) -> io::Result<()> {
-    env_logger::init();
+    start_server_with_options(port, ServerOptions::default()).await
+}
+
+pub async fn start_server_with_options(port: u16, options: ServerOptions) -> io::Result<()> {
+    let _telemetry = telemetry::init();
 
     // Load environment variables from .env file if it exists
     dotenv::dotenv().ok();
@@ -30,22 +80,143 @@ pub async fn start_server(port: u16) -> io::Result<()> {
         }
     };
 
-    // Run migrations
-    if let Err(e) = db_service.run_migrations().await {
-        log::error!("Failed to run migrations: {}", e);
-        std::process::exit(1);
+    // Optional read replica; reads stay on the primary without one
+    let db_service = match std::env::var("DATABASE_REPLICA_URL").ok().filter(|url| !url.trim().is_empty()) {
+        Some(replica_url) => match db_service.with_replica(replica_url.trim()).await {
+            Ok(service) => {
+                log::info!("Routing reads to the database replica");
+                service
+            }
+            Err(e) => {
+                log::error!("Failed to connect to the database replica: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => db_service,
+    };
+
+    // Run migrations (skipped on read replicas with RUN_MIGRATIONS=false)
+    let migrations = Arc::new(MigrationConfig::from_env());
+    match db_service.run_migrations(&migrations).await {
+        Ok(applied) if migrations.run => {
+            log::info!("Database migrations completed ({} applied)", applied.len())
+        }
+        Ok(_) => {}
+        Err(e) => {
+            log::error!("Failed to run migrations: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if options.seed {
+        if let Err(msg) = seed::ensure_seed_allowed() {
+            log::error!("{}", msg);
+            std::process::exit(1);
+        }
+        match seed::seed_demo_data(&db_service).await {
+            Ok(Some(summary)) => log::info!(
+                "Seeded demo data: {} prosumers, {} orders, {} trades",
+                summary.prosumers, summary.orders, summary.trades
+            ),
+            Ok(None) => log::info!("Demo data already present, skipping seed"),
+            Err(e) => {
+                log::error!("Failed to seed demo data: {}", e);
+                std::process::exit(1);
+            }
+        }
     }
-    log::info!("Database migrations completed");
 
     let db_service = Arc::new(db_service);
+    let admin_bootstrap = AdminBootstrap::from_env();
+    if let Err(e) = admin_bootstrap.validate() {
+        log::error!("Refusing to start: {}", e);
+        std::process::exit(1);
+    }
+    let auth_store = AuthStore::new();
+    if let Err(e) = auth_store.password_hasher.validate() {
+        log::error!("Refusing to start: {}", e);
+        std::process::exit(1);
+    }
+    match auth_store.bootstrap_admin(&admin_bootstrap) {
+        Ok(true) => log::info!("Created bootstrap admin '{}'", admin_bootstrap.username),
+        Ok(false) => {}
+        Err(e) => {
+            log::error!("Failed to create bootstrap admin '{}': {}", admin_bootstrap.username, e);
+            std::process::exit(1);
+        }
+    }
+    let auth_store = Arc::new(auth_store);
+
+    let auto_match = AutoMatchConfig::from_env();
+    if auto_match.enabled {
+        log::info!("Auto-matching enabled every {}ms", auto_match.interval_ms);
+        ntex::rt::spawn(matching::run_auto_match(db_service.clone(), auto_match));
+    }
 
-    log::info!("Starting Energy Trading API server on port {}", port);
+    // Trade history archival; off unless TRADE_ARCHIVE_ENABLED is set
+    let trade_archive = TradeArchiveConfig::from_env();
+    if trade_archive.enabled {
+        log::info!(
+            "Archiving trades older than {} days every {}s",
+            trade_archive.retention_days, trade_archive.interval_secs
+        );
+        ntex::rt::spawn(retention::run_trade_archival(db_service.clone(), trade_archive));
+    }
+
+    // Expiry sweep; orders past expires_at are already out of the book, this releases their escrow
+    let expiry_interval_secs = env_parse::<u64>("ORDER_EXPIRY_INTERVAL_SECS", 30).max(1);
+    ntex::rt::spawn(expiry::run_order_expiry(db_service.clone(), expiry_interval_secs));
+
+    // Settlement of delayed (T+N) trades; only needed when SETTLEMENT_DELAY_SECS is set
+    if db_service.market_config().settlement_delay_secs > 0 {
+        let settlement_interval_secs = env_parse::<u64>("SETTLEMENT_INTERVAL_SECS", 5).max(1);
+        log::info!(
+            "Trades settle {}s after execution, checked every {}s",
+            db_service.market_config().settlement_delay_secs, settlement_interval_secs
+        );
+        ntex::rt::spawn(settlement::run_trade_settlement(db_service.clone(), settlement_interval_secs));
+    }
 
-    HttpServer::new(move || {
-        App::new()
+    // Periodic order book snapshots for drift checks; off unless an interval is set
+    let snapshot_interval_secs = env_parse::<u64>("ORDER_BOOK_SNAPSHOT_INTERVAL_SECS", 0);
+    if snapshot_interval_secs > 0 {
+        log::info!("Order book snapshots enabled every {}s", snapshot_interval_secs);
+        ntex::rt::spawn(matching::run_order_book_snapshots(db_service.clone(), snapshot_interval_secs));
+    }
+
+    let quota = Arc::new(QuotaCounter::new(&RateLimitConfig::from_env()));
+
+    let compression = CompressionConfig::from_env();
+    let body_limits = BodyLimitConfig::from_env();
+    let faucet = Arc::new(Faucet::new(FaucetConfig::from_env()));
+    if faucet.config().enabled {
+        log::warn!("Token faucet enabled at POST /faucet; do not use outside test environments");
+    }
+    let cors_config = CorsConfig::from_env();
+    if let Err(e) = cors_config.validate() {
+        log::error!("Invalid CORS configuration: {}", e);
+        std::process::exit(1);
+    }
+    let bind_address = BindAddress::from_env(port).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    log::info!("Starting Energy Trading API server on {}", bind_address);
+
+    let server = HttpServer::new(move || {
+        envelope_errors(App::new()
             .state(db_service.clone())
+            .state(auth_store.clone())
+            .state(faucet.clone())
+            .state(migrations.clone())
+            .state(web::types::JsonConfig::default().limit(body_limits.max_body_bytes))
+            .wrap(Compress::new(compression.clone()))
+            .wrap(RequestTimeout::from_env())
+            .wrap(body_limit(&body_limits))
+            .wrap(RateLimitHeaders::new(quota.clone()))
+            .wrap(AuditLog)
+            .wrap(RequestTracing)
             .wrap(middleware::Logger::default())
             .wrap(middleware::DefaultHeaders::new().header("X-Version", "1.0.0"))
+            .wrap(cors(&cors_config))
             .service(
                 web::resource("/")
                     .route(web::get().to(handlers::root))
@@ -54,64 +225,321 @@ pub async fn start_server(port: u16) -> io::Result<()> {
                 web::resource("/health")
                     .route(web::get().to(handlers::health_check))
             )
+            .service(
+                web::resource("/health/detailed")
+                    .route(web::get().to(auth_handlers::health_details))
+            )
+            // Authentication endpoints
+            .service(
+                web::resource("/auth/login")
+                    .route(web::post().to(auth_handlers::login))
+            )
+            // Admin endpoints
+            .service(
+                web::resource("/admin/users/{user_id}")
+                    .route(web::delete().to(auth_handlers::deactivate_user))
+            )
+            .service(
+                web::resource("/admin/audit")
+                    .route(web::get().to(auth_handlers::get_audit_log))
+            )
+            .service(
+                web::resource("/admin/summary")
+                    .route(web::get().to(auth_handlers::get_system_summary))
+            )
+            .service(
+                web::resource("/admin/match-diagnostics")
+                    .route(web::get().to(auth_handlers::get_match_diagnostics))
+            )
+            .service(
+                web::resource("/admin/order-book/snapshots")
+                    .route(web::post().to(auth_handlers::snapshot_order_book))
+            )
+            .service(
+                web::resource("/admin/order-book/verify")
+                    .route(web::get().to(auth_handlers::verify_order_book))
+            )
+            .service(
+                web::resource("/admin/orders/{order_id}/cancel")
+                    .route(web::post().to(auth_handlers::force_cancel_order))
+            )
+            .service(
+                web::resource("/admin/trades/{trade_id}/settle")
+                    .route(web::post().to(auth_handlers::settle_disputed_trade))
+            )
+            .service(
+                web::resource("/admin/market/halt")
+                    .route(web::post().to(auth_handlers::halt_market))
+            )
+            .service(
+                web::resource("/admin/market/resume")
+                    .route(web::post().to(auth_handlers::resume_market))
+            )
             // Prosumer endpoints
             .service(
                 web::resource("/prosumers")
                     .route(web::post().to(handlers::create_prosumer))
                     .route(web::get().to(handlers::get_all_prosumers))
+                    .route(web::head().to(handlers::head_all_prosumers))
+            )
+            // Registered before /prosumers/{address} so "balances" is not taken as an address
+            .service(
+                web::resource("/prosumers/balances")
+                    .route(web::post().to(handlers::get_prosumer_balances))
             )
             .service(
                 web::resource("/prosumers/{address}")
                     .route(web::get().to(handlers::get_prosumer))
+                    .route(web::head().to(handlers::head_prosumer))
                     .route(web::put().to(handlers::update_prosumer))
             )
+            .service(
+                web::resource("/prosumers/{address}/statement")
+                    .route(web::get().to(handlers::get_account_statement))
+            )
+            .service(
+                web::resource("/prosumers/{address}/close")
+                    .route(web::post().to(handlers::close_prosumer))
+            )
+            .service(
+                web::resource("/prosumers/{address}/pnl")
+                    .route(web::get().to(handlers::get_prosumer_pnl))
+            )
+            .service(
+                web::resource("/prosumers/{address}/tags")
+                    .route(web::get().to(handlers::get_prosumer_tags))
+                    .route(web::put().to(handlers::set_prosumer_tags))
+            )
             .service(
                 web::resource("/prosumers/{address}/stats")
                     .route(web::get().to(handlers::get_prosumer_stats))
             )
+            .service(
+                web::resource("/prosumers/{address}/exposure")
+                    .route(web::get().to(handlers::get_prosumer_exposure))
+            )
+            .service(
+                web::resource("/prosumers/{address}/orders/summary")
+                    .route(web::get().to(handlers::get_open_order_summary))
+            )
+            .service(
+                web::resource("/prosumers/{address}/balance")
+                    .route(web::get().to(handlers::get_prosumer_balance))
+            )
+            .service(
+                web::resource("/prosumers/{address}/trades")
+                    .route(web::get().to(handlers::get_prosumer_trades))
+            )
             // Order endpoints
             .service(
                 web::resource("/orders")
                     .route(web::post().to(handlers::create_energy_order))
                     .route(web::get().to(handlers::get_all_energy_orders))
+                    .route(web::head().to(handlers::head_all_energy_orders))
             )
             .service(
                 web::resource("/orders/{order_id}")
                     .route(web::get().to(handlers::get_energy_order))
+                    .route(web::head().to(handlers::head_energy_order))
                     .route(web::put().to(handlers::update_energy_order))
                     .route(web::delete().to(handlers::cancel_energy_order))
             )
+            .service(
+                web::resource("/orders/{order_id}/reactivate")
+                    .route(web::post().to(handlers::reactivate_energy_order))
+            )
+            .service(
+                web::resource("/orders/{order_id}/fills")
+                    .route(web::get().to(handlers::get_order_fills))
+            )
+            .service(
+                web::resource("/orders/{order_id}/trades")
+                    .route(web::get().to(handlers::get_order_trades))
+            )
             // Trade endpoints
             .service(
                 web::resource("/trades")
                     .route(web::post().to(handlers::execute_trade))
                     .route(web::get().to(handlers::get_all_trades))
             )
+            // Registered before /trades/{trade_id} so "batch" is not taken as a trade id
+            .configure(|cfg| batch_routes(cfg, body_limits.max_batch_body_bytes))
             .service(
                 web::resource("/trades/{trade_id}")
                     .route(web::get().to(handlers::get_trade))
             )
+            .service(
+                web::resource("/trades/{trade_id}/dispute")
+                    .route(web::post().to(handlers::dispute_trade))
+            )
             // Token transfer endpoints
             .service(
                 web::resource("/transfer")
                     .route(web::post().to(handlers::transfer_tokens))
             )
+            .service(
+                web::resource("/transfer/validate")
+                    .route(web::post().to(handlers::validate_transfer))
+            )
+            .service(
+                web::resource("/tokens/{address}/balance")
+                    .route(web::get().to(handlers::get_prosumer_balance))
+            )
+            // Test token faucet (404 unless FAUCET_ENABLED)
+            .service(
+                web::resource("/faucet")
+                    .route(web::post().to(handlers::faucet))
+            )
             // Statistics endpoints
             .service(
                 web::resource("/stats/market")
                     .route(web::get().to(handlers::get_market_stats))
             )
+            .service(
+                web::resource("/stats/prosumers")
+                    .route(web::get().to(handlers::get_all_prosumer_stats))
+            )
+            .service(
+                web::resource("/stats/timeseries")
+                    .route(web::get().to(handlers::get_market_timeseries))
+            )
             .service(
                 web::resource("/stats/database")
                     .route(web::get().to(handlers::get_database_stats))
             )
+            // Token type endpoints
+            .service(
+                web::resource("/token-types")
+                    .route(web::get().to(handlers::get_token_types))
+                    .route(web::post().to(auth_handlers::create_token_type))
+            )
+            // Webhook subscriptions
+            .service(
+                web::resource("/webhooks")
+                    .route(web::post().to(handlers::create_webhook))
+                    .route(web::get().to(handlers::get_webhooks))
+            )
+            .service(
+                web::resource("/webhooks/{webhook_id}")
+                    .route(web::delete().to(handlers::delete_webhook))
+            )
+            // Price alerts
+            .service(
+                web::resource("/price-alerts")
+                    .route(web::post().to(handlers::create_price_alert))
+                    .route(web::get().to(handlers::get_price_alerts))
+            )
+            .service(
+                web::resource("/price-alerts/{alert_id}")
+                    .route(web::delete().to(handlers::delete_price_alert))
+            )
+            // Matching simulation against an in-memory book
+            .service(
+                web::resource("/simulate/match")
+                    .route(web::post().to(handlers::simulate_match))
+            )
             // Order matching
             .service(
                 web::resource("/match-orders")
                     .route(web::post().to(handlers::match_orders))
-            )
-    })
-    .bind(format!("127.0.0.1:{}", port))?
-    .run()
-    .await
+            ))
+    });
+
+    match bind_address {
+        BindAddress::Tcp(addr) => server.bind(addr)?.run().await,
+        #[cfg(unix)]
+        BindAddress::Unix(path) => {
+            // A socket file left behind by a crashed run would make the bind fail
+            remove_stale_socket(&path)?;
+            let result = server.bind_uds(&path)?.run().await;
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::warn!("Failed to remove socket {}: {}", path.display(), e);
+            }
+            result
+        }
+        #[cfg(not(unix))]
+        BindAddress::Unix(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix domain sockets are not supported on this platform",
+        )),
+    }
+}
+
+// Only removes the path when it is a socket, so a mistyped BIND_ADDRESS can't delete a regular file
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            log::info!("Removing stale socket {}", path.display());
+            std::fs::remove_file(path)
+        }
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    fn socket_path() -> PathBuf {
+        std::env::temp_dir().join(format!("energy-api-{}.sock", uuid::Uuid::new_v4().simple()))
+    }
+
+    // Binding a listener and dropping it leaves the socket file behind, as a crashed run would
+    fn leave_stale_socket(path: &std::path::Path) {
+        drop(UnixListener::bind(path).unwrap());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn only_a_leftover_socket_is_removed() {
+        let path = socket_path();
+        leave_stale_socket(&path);
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+        remove_stale_socket(&path).unwrap();
+
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[ntex::test]
+    async fn requests_are_served_over_a_unix_socket() {
+        let path = socket_path();
+        leave_stale_socket(&path);
+        remove_stale_socket(&path).unwrap();
+        let server = HttpServer::new(|| {
+            App::new().service(web::resource("/health").route(web::get().to(handlers::health_check)))
+        })
+        .workers(1)
+        .disable_signals()
+        .bind_uds(&path)
+        .unwrap()
+        .run();
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains(r#""status":"ok""#), "{}", response);
+
+        server.stop(true).await;
+    }
 }
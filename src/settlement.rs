@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use ntex::time::{sleep, Millis};
+
+use crate::database::DatabaseService;
+use crate::webhooks;
+
+// Most trades settled per run, so a large backlog is worked off over several runs
+const SETTLEMENT_BATCH_SIZE: i64 = 500;
+
+// Delayed trade settlement
+//
+// With SETTLEMENT_DELAY_SECS set, matched trades are created `pending` with their payment
+// held in the buyer's escrow. Every `interval_secs` this pays the seller for each pending
+// trade past its `settles_at` and marks it `completed`. Disputed trades are left alone.
+pub async fn run_trade_settlement(db: Arc<DatabaseService>, interval_secs: u64) {
+    loop {
+        sleep(Millis(interval_secs.saturating_mul(1000).min(u32::MAX as u64) as u32)).await;
+
+        match db.settle_due_trades(db.now(), SETTLEMENT_BATCH_SIZE).await {
+            Ok(trades) if trades.is_empty() => {}
+            Ok(trades) => {
+                log::info!("Settled {} trades", trades.len());
+                for trade in &trades {
+                    webhooks::notify(&db, webhooks::TRADE_SETTLED, trade);
+                }
+            }
+            Err(e) => log::error!("Trade settlement failed: {}", e),
+        }
+    }
+}
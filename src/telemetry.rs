@@ -0,0 +1,136 @@
+use std::sync::OnceLock;
+
+use opentelemetry::metrics::Counter;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+const SERVICE_NAME: &str = "energy-trading-api";
+
+// Keeps the OpenTelemetry pipeline alive; dropping it flushes buffered spans and metrics
+#[derive(Default)]
+pub struct Telemetry {
+    tracer_provider: Option<SdkTracerProvider>,
+    meter_provider: Option<SdkMeterProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.tracer_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush OpenTelemetry spans: {}", e);
+            }
+        }
+        if let Some(provider) = self.meter_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush OpenTelemetry metrics: {}", e);
+            }
+        }
+    }
+}
+
+// Installs the global tracing subscriber: spans and events are filtered by RUST_LOG and
+// written to stderr. `log` records from this crate and its dependencies are forwarded into
+// the same subscriber, so RUST_LOG covers both.
+//
+// When OTEL_EXPORTER_OTLP_ENDPOINT is set, spans and the order/trade counters are also
+// exported over OTLP to that collector. Without it no exporter is built and the counters
+// record into OpenTelemetry's no-op meter.
+pub fn init() -> Telemetry {
+    let telemetry = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().filter(|url| !url.trim().is_empty()) {
+        Some(_) => match otlp_pipeline() {
+            Ok(telemetry) => telemetry,
+            Err(e) => {
+                eprintln!("OpenTelemetry export disabled: {}", e);
+                Telemetry::default()
+            }
+        },
+        None => Telemetry::default(),
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt::layer())
+        .with(telemetry.tracer_provider.as_ref().map(trace_layer))
+        .init();
+
+    telemetry
+}
+
+// Bridges tracing spans (requests, database operations) into the given OpenTelemetry provider
+pub fn trace_layer<S>(provider: &SdkTracerProvider) -> OpenTelemetryLayer<S, Tracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME))
+}
+
+// The exporters read the endpoint (and headers, timeouts) from the standard OTEL_* variables
+fn otlp_pipeline() -> Result<Telemetry, Box<dyn std::error::Error>> {
+    let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+
+    let span_exporter = opentelemetry_otlp::SpanExporter::builder().with_http().build()?;
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(span_exporter)
+        .with_resource(resource.clone())
+        .build();
+
+    let metric_exporter = opentelemetry_otlp::MetricExporter::builder().with_http().build()?;
+    let meter_provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(metric_exporter)
+        .with_resource(resource)
+        .build();
+    global::set_meter_provider(meter_provider.clone());
+
+    Ok(Telemetry {
+        tracer_provider: Some(tracer_provider),
+        meter_provider: Some(meter_provider),
+    })
+}
+
+struct Metrics {
+    orders_created: Counter<u64>,
+    trades_executed: Counter<u64>,
+    energy_traded: Counter<f64>,
+}
+
+// Built on first use, which is after init() has installed the meter provider
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let meter = global::meter(SERVICE_NAME);
+        Metrics {
+            orders_created: meter
+                .u64_counter("orders_created")
+                .with_description("Orders accepted into the book")
+                .build(),
+            trades_executed: meter
+                .u64_counter("trades_executed")
+                .with_description("Trades executed between a buy and a sell order")
+                .build(),
+            energy_traded: meter
+                .f64_counter("energy_traded")
+                .with_description("Energy amount changing hands in executed trades")
+                .with_unit("kWh")
+                .build(),
+        }
+    })
+}
+
+pub fn record_order_created(order_type: &str) {
+    metrics().orders_created.add(1, &[KeyValue::new("order_type", order_type.to_string())]);
+}
+
+pub fn record_trade_executed(energy_amount: f64) {
+    let metrics = metrics();
+    metrics.trades_executed.add(1, &[]);
+    metrics.energy_traded.add(energy_amount, &[]);
+}
@@ -0,0 +1,205 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Once};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use ntex::http::client::Client;
+use ntex::time::{sleep, Millis};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use url::{Host, Url};
+
+use crate::database::{DatabaseService, Webhook};
+
+// Webhook delivery
+//
+// Events are POSTed as `{"event", "timestamp", "data"}` JSON. Each request carries the
+// event name in `X-Webhook-Event` and `X-Webhook-Signature: sha256=<hex>`, an HMAC-SHA256
+// of the raw body keyed with the webhook's secret. Failed deliveries are retried with
+// exponential backoff; once retries are exhausted the event goes to the dead-letter log.
+pub const ORDER_CREATED: &str = "order_created";
+pub const ORDER_CANCELLED: &str = "order_cancelled";
+pub const ORDER_REACTIVATED: &str = "order_reactivated";
+pub const ORDER_EXPIRED: &str = "order_expired";
+pub const TRADE_EXECUTED: &str = "trade_executed";
+pub const TRADE_SETTLED: &str = "trade_settled";
+pub const TRADE_DISPUTED: &str = "trade_disputed";
+pub const PRICE_ALERT_TRIGGERED: &str = "price_alert_triggered";
+
+pub const WEBHOOK_EVENTS: &[&str] = &[ORDER_CREATED, ORDER_CANCELLED, ORDER_REACTIVATED, ORDER_EXPIRED, TRADE_EXECUTED, TRADE_SETTLED, TRADE_DISPUTED, PRICE_ALERT_TRIGGERED];
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+const INITIAL_RETRY_DELAY_MS: u32 = 500;
+const DELIVERY_TIMEOUT_MS: u32 = 10_000;
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// Checks that `url` is somewhere webhooks may be delivered: an http(s) URL whose host is, or
+// resolves only to, public addresses. Loopback, private, link-local and other internal ranges
+// are refused so a webhook can't be used to reach services behind the API.
+pub async fn validate_target(url: &str) -> Result<(), String> {
+    let parsed = Url::parse(url).map_err(|e| format!("url is not a valid URL: {}", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("url scheme '{}' is not allowed; use http or https", parsed.scheme()));
+    }
+    let port = parsed.port_or_known_default().unwrap_or(80);
+
+    let addresses: Vec<IpAddr> = match parsed.host() {
+        Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| format!("url host '{}' could not be resolved: {}", domain, e))?
+            .map(|addr| addr.ip())
+            .collect(),
+        None => return Err("url must include a host".to_string()),
+    };
+
+    match addresses.iter().find(|ip| !is_public(ip)) {
+        Some(ip) => Err(format!("url host resolves to {}, which is not a public address", ip)),
+        None if addresses.is_empty() => Err("url host did not resolve to any address".to_string()),
+        None => Ok(()),
+    }
+}
+
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_v4(&mapped),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64))
+}
+
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80)
+}
+
+// Fires `event` to every subscribed webhook in the background
+pub fn notify<T: Serialize>(db: &Arc<DatabaseService>, event: &'static str, data: &T) {
+    let body = json!({
+        "event": event,
+        "timestamp": Utc::now(),
+        "data": data,
+    })
+    .to_string();
+    let db = db.clone();
+
+    ntex::rt::spawn(async move {
+        let hooks = match db.get_webhooks_for_event(event).await {
+            Ok(hooks) => hooks,
+            Err(e) => {
+                log::error!("Failed to load webhooks for {}: {}", event, e);
+                return;
+            }
+        };
+
+        for hook in hooks {
+            ntex::rt::spawn(deliver(db.clone(), hook, event, body.clone()));
+        }
+    });
+}
+
+// Re-evaluates price alerts in the background after the order book changed, delivering a
+// PRICE_ALERT_TRIGGERED event for each alert that fired
+pub fn check_price_alerts(db: &Arc<DatabaseService>) {
+    let db = db.clone();
+
+    ntex::rt::spawn(async move {
+        match db.trigger_price_alerts().await {
+            Ok(alerts) => {
+                for alert in &alerts {
+                    notify(&db, PRICE_ALERT_TRIGGERED, alert);
+                }
+            }
+            Err(e) => log::error!("Failed to evaluate price alerts: {}", e),
+        }
+    });
+}
+
+// ntex's client builds a rustls connector, which needs a process-wide crypto provider installed
+fn client() -> Client {
+    static PROVIDER: Once = Once::new();
+    PROVIDER.call_once(|| {
+        // Fails only if another provider was installed first, which serves just as well
+        let _ = rustls::crypto::ring::default_provider().install_default();
+    });
+    Client::new()
+}
+
+async fn deliver(db: Arc<DatabaseService>, hook: Webhook, event: &'static str, body: String) {
+    let client = client();
+    let signature = sign(&hook.secret, body.as_bytes());
+    let mut delay = INITIAL_RETRY_DELAY_MS;
+    let mut last_error = String::new();
+
+    for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+        let result = client
+            .post(&hook.url)
+            .header(SIGNATURE_HEADER, &signature)
+            .header(EVENT_HEADER, event)
+            .content_type("application/json")
+            .timeout(Millis(DELIVERY_TIMEOUT_MS))
+            .send_body(body.clone())
+            .await;
+
+        match result {
+            Ok(response) if response.status().is_success() => {
+                if hook.failure_count > 0 {
+                    if let Err(e) = db.record_webhook_success(hook.id).await {
+                        log::warn!("Failed to reset failure count for webhook {}: {}", hook.id, e);
+                    }
+                }
+                return;
+            }
+            Ok(response) => last_error = format!("HTTP {}", response.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+
+        log::warn!(
+            "Webhook {} delivery of {} failed (attempt {}/{}): {}",
+            hook.id, event, attempt, MAX_DELIVERY_ATTEMPTS, last_error
+        );
+        if attempt < MAX_DELIVERY_ATTEMPTS {
+            sleep(Millis(delay)).await;
+            delay = delay.saturating_mul(2);
+        }
+    }
+
+    if let Err(e) = db
+        .record_webhook_failure(hook.id, event, &body, &last_error, MAX_DELIVERY_ATTEMPTS)
+        .await
+    {
+        log::error!("Failed to dead-letter webhook {} event {}: {}", hook.id, event, e);
+    }
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{isolated_test_db, place, prosumer};
+use energy_trading_api::database::{DatabaseService, SortOrder, Trade};
+
+// Executes one trade and archives it. Archiving moves every trade in the database, so these
+// tests run on a database of their own.
+async fn archived_trade(db: &DatabaseService) -> Trade {
+    let buyer = prosumer(db, 100.0).await;
+    let seller = prosumer(db, 0.0).await;
+    let buy = place(db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(db, &seller, "sell", 5.0, 0.2).await;
+    let trade = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+
+    db.archive_trades(Utc::now() + Duration::minutes(1)).await.unwrap();
+    assert!(db.get_trade(trade.id).await.is_err(), "trade was archived");
+    trade
+}
+
+#[tokio::test]
+async fn range_without_a_from_date_finds_archived_trades() {
+    let Some(db) = isolated_test_db().await else { return };
+    let trade = archived_trade(&db).await;
+    let traded_at = trade.created_at;
+
+    let found = |trades: Vec<Trade>| trades.iter().any(|t| t.id == trade.id);
+    let to_only = db
+        .get_trades(1, 1_000, None, Some(traded_at + Duration::days(1)), SortOrder::default())
+        .await
+        .unwrap();
+    assert!(found(to_only));
+    let before = db
+        .get_trades(1, 1_000, None, Some(traded_at), SortOrder::default())
+        .await
+        .unwrap();
+    assert!(!found(before));
+    let from_after = db
+        .get_trades(1, 1_000, Some(traded_at + Duration::seconds(1)), None, SortOrder::default())
+        .await
+        .unwrap();
+    assert!(!found(from_after));
+}
+
+#[tokio::test]
+async fn order_fills_include_archived_trades() {
+    let Some(db) = isolated_test_db().await else { return };
+    let trade = archived_trade(&db).await;
+
+    let fills = db.get_order_fills(trade.buy_order_id).await.unwrap();
+    assert_eq!(fills.fills.iter().map(|t| t.id).collect::<Vec<_>>(), vec![trade.id]);
+    assert_eq!(fills.fills.iter().map(|t| t.energy_amount).sum::<f64>(), fills.filled_amount);
+
+    let trades = db.get_trades_for_order(trade.sell_order_id).await.unwrap();
+    assert_eq!(trades.iter().map(|t| t.id).collect::<Vec<_>>(), vec![trade.id]);
+}
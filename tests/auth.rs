@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use energy_trading_api::auth::{
+    get_endpoint_permission, AdminBootstrap, AuthError, AuthStore, CreateApiKeyRequest, CreateUserRequest, PermissionTable,
+};
+use energy_trading_api::clock::{Clock, MockClock};
+
+fn store() -> AuthStore {
+    let mut store = AuthStore::new();
+    store.jwt_secret = "test-secret".to_string();
+    store
+}
+
+fn trader(store: &AuthStore) -> energy_trading_api::auth::User {
+    store
+        .create_user(CreateUserRequest {
+            username: "trader".to_string(),
+            email: "trader@example.com".to_string(),
+            password: "Tr4der-password".to_string(),
+            role: "trader".to_string(),
+        })
+        .unwrap()
+}
+
+#[test]
+fn token_for_another_audience_is_rejected() {
+    let store = store();
+    let user = trader(&store);
+    let mut other_service = self::store();
+    other_service.jwt_audience = "another-service".to_string();
+
+    // Same secret and issuer, but minted for a different audience
+    let foreign = other_service.generate_jwt(&user).unwrap();
+    assert!(matches!(store.validate_jwt(&foreign), Err(AuthError::InvalidToken)));
+    let own = store.generate_jwt(&user).unwrap();
+    assert_eq!(store.validate_jwt(&own).unwrap().sub, user.id);
+}
+
+#[test]
+fn deactivation_revokes_the_users_api_keys() {
+    let store = store();
+    let user = trader(&store);
+    let key = store
+        .create_api_key(
+            &user.id,
+            CreateApiKeyRequest {
+                name: "bot".to_string(),
+                permissions: vec!["read".to_string()],
+                expires_in_days: None,
+            },
+        )
+        .unwrap();
+    assert!(store.validate_api_key(&key.key).is_ok());
+
+    let deactivated = store.deactivate_user(&user.id).unwrap();
+    assert!(!deactivated.is_active);
+    assert!(!store.get_user_by_id(&user.id).unwrap().is_active);
+    assert!(matches!(store.validate_api_key(&key.key), Err(AuthError::ApiKeyNotFound)));
+}
+
+#[test]
+fn bootstrap_admin_is_created_once_with_the_configured_password() {
+    let store = store();
+    let admin = AdminBootstrap {
+        username: "ops".to_string(),
+        email: "ops@example.com".to_string(),
+        password: Some("0ps-Admin-password".to_string()),
+    };
+    assert!(store.bootstrap_admin(&admin).unwrap());
+    let user = store.authenticate_user("ops", "0ps-Admin-password").unwrap();
+    assert_eq!(user.role, "admin");
+
+    // An admin already exists, so a second bootstrap leaves the accounts alone
+    assert!(!store.bootstrap_admin(&AdminBootstrap::default()).unwrap());
+    assert!(store.authenticate_user("admin", "admin123").is_err());
+}
+
+#[test]
+fn bootstrap_admin_password_must_pass_the_policy() {
+    let store = store();
+    let admin = AdminBootstrap {
+        password: Some("short".to_string()),
+        ..AdminBootstrap::default()
+    };
+    assert!(store.bootstrap_admin(&admin).is_err());
+    assert!(store.authenticate_user("admin", "short").is_err());
+}
+
+#[test]
+fn token_expires_after_a_day_on_the_stores_clock() {
+    let clock = Arc::new(MockClock::new(Utc::now()));
+    let mut store = store();
+    store.clock = clock.clone();
+    let user = trader(&store);
+    let token = store.generate_jwt(&user).unwrap();
+
+    clock.advance(Duration::hours(23));
+    assert!(store.validate_jwt(&token).is_ok());
+    clock.advance(Duration::hours(1));
+    assert!(matches!(store.validate_jwt(&token), Err(AuthError::InvalidToken)));
+}
+
+#[test]
+fn successful_logins_record_last_login_and_failed_ones_do_not() {
+    let clock = Arc::new(MockClock::new(Utc::now()));
+    let mut store = store();
+    store.clock = clock.clone();
+    let user = trader(&store);
+    assert_eq!(user.last_login, None);
+
+    assert!(matches!(store.authenticate_user("trader", "wrong-password"), Err(AuthError::InvalidCredentials)));
+    assert_eq!(store.get_user_by_id(&user.id).unwrap().last_login, None);
+
+    let first_login = clock.now();
+    let logged_in = store.authenticate_user("trader", "Tr4der-password").unwrap();
+    assert_eq!(logged_in.last_login, Some(first_login));
+    assert_eq!(store.get_user_by_id(&user.id).unwrap().last_login, Some(first_login));
+
+    clock.advance(Duration::hours(1));
+    assert!(store.authenticate_user("trader", "wrong-password").is_err());
+    assert_eq!(store.get_user_by_id(&user.id).unwrap().last_login, Some(first_login));
+
+    clock.advance(Duration::hours(1));
+    store.authenticate_user("trader", "Tr4der-password").unwrap();
+    assert_eq!(store.get_user_by_id(&user.id).unwrap().last_login, Some(first_login + Duration::hours(2)));
+}
+
+#[test]
+fn order_cancellation_needs_cancel_order_not_trade() {
+    assert_eq!(get_endpoint_permission("POST", "/orders/cancel"), "cancel_order");
+    assert_eq!(get_endpoint_permission("POST", "/api/energy/orders/cancel"), "cancel_order");
+    assert_eq!(get_endpoint_permission("DELETE", "/orders/7f1c"), "cancel_order");
+    assert_eq!(get_endpoint_permission("POST", "/orders"), "trade");
+    assert_eq!(get_endpoint_permission("POST", "/orders/7f1c/reactivate"), "trade");
+    assert_eq!(get_endpoint_permission("GET", "/orders/cancel"), "read");
+    assert_eq!(get_endpoint_permission("POST", "/webhooks"), "create");
+}
+
+#[test]
+fn registered_routes_apply_in_order_before_the_method_fallback() {
+    let table = PermissionTable::new()
+        .register("POST", "/trades/{trade_id}/dispute", "trade")
+        .register("POST", "/trades/*", "create_order");
+    assert_eq!(table.permission_for("POST", "/trades/42/dispute"), "trade");
+    assert_eq!(table.permission_for("POST", "/trades/42/dispute?x=1"), "trade");
+    assert_eq!(table.permission_for("POST", "/trades/42/settle"), "create_order");
+    assert_eq!(table.permission_for("PUT", "/trades/42"), "update");
+}
@@ -0,0 +1,185 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::Utc;
+use common::{auth_store, bearer, place, prosumer, test_db, user};
+use energy_trading_api::database::{BatchOperation, DatabaseError, Prosumer};
+use energy_trading_api::auth_handlers;
+use ntex::http::{header, StatusCode};
+use ntex::web::{self, test, App};
+use uuid::Uuid;
+
+fn transfer(from_address: &str, to_address: &str, amount: f64) -> BatchOperation {
+    BatchOperation::Transfer {
+        from_address: from_address.to_string(),
+        to_address: to_address.to_string(),
+        amount,
+        token_type: "grid_tokens".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn failed_operation_rolls_back_the_whole_batch() {
+    let Some(db) = test_db().await else { return };
+    let alice = prosumer(&db, 10.0).await;
+    let bob = prosumer(&db, 0.0).await;
+    let now = Utc::now();
+    let newcomer = Prosumer {
+        address: format!("0x{}", Uuid::new_v4().simple()),
+        name: "Newcomer".to_string(),
+        energy_generated: 0.0,
+        energy_consumed: 0.0,
+        grid_tokens: 0.0,
+        watt_tokens: 0.0,
+        is_active: true,
+        created_at: now,
+        updated_at: now,
+    };
+
+    // The third transfer overdraws alice after the first two operations succeeded
+    let result = db
+        .execute_batch(vec![
+            BatchOperation::CreateProsumer(newcomer.clone()),
+            transfer(&alice, &bob, 4.0),
+            transfer(&alice, &newcomer.address, 7.0),
+        ])
+        .await;
+    match result {
+        Err(DatabaseError::BatchFailed { index, .. }) => assert_eq!(index, 2),
+        other => panic!("expected BatchFailed, got {:?}", other),
+    }
+
+    assert!(db.get_prosumer_balance(&newcomer.address).await.is_err());
+    assert_eq!(db.get_prosumer_balance(&alice).await.unwrap().grid_tokens, 10.0);
+    assert_eq!(db.get_prosumer_balance(&bob).await.unwrap().grid_tokens, 0.0);
+}
+
+#[tokio::test]
+async fn successful_batch_applies_every_operation() {
+    let Some(db) = test_db().await else { return };
+    let alice = prosumer(&db, 10.0).await;
+    let bob = prosumer(&db, 0.0).await;
+
+    let results = db
+        .execute_batch(vec![transfer(&alice, &bob, 4.0), transfer(&bob, &alice, 1.0)])
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(db.get_prosumer_balance(&alice).await.unwrap().grid_tokens, 7.0);
+    assert_eq!(db.get_prosumer_balance(&bob).await.unwrap().grid_tokens, 3.0);
+}
+
+#[ntex::test]
+async fn trade_batch_with_an_inactive_order_rolls_back_every_trade() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let admin = user(&store, "admin");
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let cancelled = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 10.0, 0.2).await;
+    db.cancel_order(cancelled.id).await.unwrap();
+    let before = db.get_prosumer_balance(&buyer).await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(web::resource("/trades/batch").route(web::post().to(auth_handlers::execute_trade_batch))),
+    )
+    .await;
+
+    let request = test::TestRequest::post()
+        .uri("/trades/batch")
+        .header(header::AUTHORIZATION, bearer(&store, &admin))
+        .set_json(&serde_json::json!({
+            "trades": [
+                {"buy_order_id": buy.id, "sell_order_id": sell.id},
+                {"buy_order_id": cancelled.id, "sell_order_id": sell.id}
+            ]
+        }))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(body["failed_index"], 1);
+
+    // The first trade was valid on its own but is rolled back with the batch
+    assert!(db.get_trades_for_order(buy.id).await.unwrap().is_empty());
+    assert_eq!(db.get_order(buy.id).await.unwrap().filled_amount, 0.0);
+    assert_eq!(db.get_order(sell.id).await.unwrap().filled_amount, 0.0);
+    let after = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!((after.grid_tokens, after.reserved_grid_tokens), (before.grid_tokens, before.reserved_grid_tokens));
+    assert_eq!(db.get_prosumer_balance(&seller).await.unwrap().grid_tokens, 0.0);
+}
+
+#[ntex::test]
+async fn trade_batch_returns_each_trade_in_order() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let admin = user(&store, "admin");
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let first = place(&db, &buyer, "buy", 4.0, 0.2).await;
+    let second = place(&db, &buyer, "buy", 6.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 10.0, 0.2).await;
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(web::resource("/trades/batch").route(web::post().to(auth_handlers::execute_trade_batch))),
+    )
+    .await;
+
+    let request = test::TestRequest::post()
+        .uri("/trades/batch")
+        .header(header::AUTHORIZATION, bearer(&store, &admin))
+        .set_json(&serde_json::json!({
+            "trades": [
+                {"buy_order_id": first.id, "sell_order_id": sell.id},
+                {"buy_order_id": second.id, "sell_order_id": sell.id}
+            ]
+        }))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    let amounts: Vec<f64> = body["trades"].as_array().unwrap().iter().map(|t| t["energy_amount"].as_f64().unwrap()).collect();
+    assert_eq!(amounts, vec![4.0, 6.0]);
+    assert_eq!(db.get_order(sell.id).await.unwrap().status, "completed");
+}
+
+#[ntex::test]
+async fn trade_batch_is_refused_to_callers_who_are_not_admins() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let trader = user(&store, "trader");
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(web::resource("/trades/batch").route(web::post().to(auth_handlers::execute_trade_batch))),
+    )
+    .await;
+    let request = |auth: Option<String>| {
+        let mut request = test::TestRequest::post().uri("/trades/batch").set_json(&serde_json::json!({
+            "trades": [{"buy_order_id": buy.id, "sell_order_id": sell.id}]
+        }));
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+
+    assert_eq!(test::call_service(&app, request(None)).await.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(test::call_service(&app, request(Some(bearer(&store, &trader)))).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(db.get_order(buy.id).await.unwrap().filled_amount, 0.0);
+}
@@ -0,0 +1,141 @@
+// Shared setup for the database-backed integration tests
+//
+// These run against the Postgres database named by TEST_DATABASE_URL and are skipped when it
+// isn't set. Every test works on freshly generated prosumers and orders, so tests can share
+// one database and run in parallel.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use energy_trading_api::auth::{AuthStore, CreateUserRequest, User};
+use energy_trading_api::config::{MarketConfig, MigrationConfig};
+use energy_trading_api::database::{DatabaseService, Order, Prosumer};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// The first migration is the SQLite schema; Postgres starts from the one after it
+pub const SQLITE_SCHEMA_MIGRATION: i64 = 20250710000001;
+
+pub async fn test_db() -> Option<DatabaseService> {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set, skipping database test");
+        return None;
+    };
+    Some(connect(&url).await)
+}
+
+// A database of the test's own, for tests that act on the whole market (matching runs, halts,
+// seeding) and would otherwise pick up or disturb other tests' orders. Each call migrates a
+// fresh schema.
+pub async fn isolated_test_db() -> Option<DatabaseService> {
+    Some(connect(&isolated_test_url().await?).await)
+}
+
+// URL of a fresh, not yet migrated schema, for tests that also need a raw connection to it
+pub async fn isolated_test_url() -> Option<String> {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set, skipping database test");
+        return None;
+    };
+    let schema = format!("test_{}", Uuid::new_v4().simple());
+    let pool = PgPool::connect(&url).await.expect("connect to TEST_DATABASE_URL");
+    // The extension must live in public so every schema's migrations can see it. Tests
+    // starting together may race to create it; whichever wins, it exists afterwards.
+    let _ = sqlx::query(r#"CREATE EXTENSION IF NOT EXISTS "uuid-ossp" SCHEMA public"#)
+        .execute(&pool)
+        .await;
+    sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&pool).await.expect("create schema");
+    pool.close().await;
+
+    let separator = if url.contains('?') { '&' } else { '?' };
+    Some(format!("{}{}options[search_path]={},public", url, separator, schema))
+}
+
+// Connects to `url` and runs the migrations
+pub async fn connect(url: &str) -> DatabaseService {
+    let db = DatabaseService::new(url)
+        .await
+        .expect("connect to TEST_DATABASE_URL")
+        .with_market_config(MarketConfig::default());
+    let migrations = MigrationConfig {
+        baseline: Some(SQLITE_SCHEMA_MIGRATION),
+        ..MigrationConfig::default()
+    };
+    db.run_migrations(&migrations).await.expect("run migrations");
+    db
+}
+
+// Creates an active prosumer with a unique address and the given grid_tokens balance
+pub async fn prosumer(db: &DatabaseService, grid_tokens: f64) -> String {
+    owned_prosumer(db, grid_tokens, None).await
+}
+
+// Like `prosumer`, registered as belonging to the user `owner_id`
+pub async fn owned_prosumer(db: &DatabaseService, grid_tokens: f64, owner_id: Option<&str>) -> String {
+    let now = db.now();
+    let prosumer = Prosumer {
+        address: format!("0x{}", Uuid::new_v4().simple()),
+        name: "Test prosumer".to_string(),
+        energy_generated: 1_000.0,
+        energy_consumed: 0.0,
+        grid_tokens,
+        watt_tokens: 0.0,
+        is_active: true,
+        created_at: now,
+        updated_at: now,
+    };
+    let prosumer = db.create_prosumer_for(prosumer, owner_id).await.expect("create prosumer");
+    prosumer.address
+}
+
+// An active order, priced in the base currency, as a handler would build it
+pub fn order(db: &DatabaseService, address: &str, order_type: &str, energy_amount: f64, price_per_unit: f64) -> Order {
+    let now = db.now();
+    Order {
+        id: Uuid::new_v4(),
+        prosumer_address: address.to_string(),
+        order_type: order_type.to_string(),
+        energy_amount,
+        price_per_unit,
+        total_price: energy_amount * price_per_unit,
+        currency: db.base_currency().to_string(),
+        quoted_price_per_unit: price_per_unit,
+        filled_amount: 0.0,
+        remaining_amount: energy_amount,
+        status: "active".to_string(),
+        created_at: now,
+        updated_at: now,
+        expires_at: None,
+    }
+}
+
+pub async fn place(db: &DatabaseService, address: &str, order_type: &str, energy_amount: f64, price_per_unit: f64) -> Order {
+    db.create_order(order(db, address, order_type, energy_amount, price_per_unit))
+        .await
+        .expect("create order")
+}
+
+// An auth store with a fixed signing secret, for tests that send authenticated requests
+pub fn auth_store() -> Arc<AuthStore> {
+    let mut store = AuthStore::new();
+    store.jwt_secret = "test-secret".to_string();
+    Arc::new(store)
+}
+
+// A new user with the given role and a unique username
+pub fn user(store: &AuthStore, role: &str) -> User {
+    let username = format!("{}-{}", role, Uuid::new_v4().simple());
+    store
+        .create_user(CreateUserRequest {
+            email: format!("{}@example.com", username),
+            username,
+            password: "Us3r-password".to_string(),
+            role: role.to_string(),
+        })
+        .expect("create user")
+}
+
+// `Authorization` header value carrying a token for `user`
+pub fn bearer(store: &AuthStore, user: &User) -> String {
+    format!("Bearer {}", store.generate_jwt(user).expect("generate token"))
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{auth_store, bearer, place, prosumer, test_db, user};
+use energy_trading_api::auth_handlers;
+use energy_trading_api::database::DatabaseError;
+use ntex::http::{header, StatusCode};
+use ntex::web::{self, test, App};
+use serde_json::json;
+
+#[tokio::test]
+async fn buy_order_reserves_its_total() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 10.0).await;
+    place(&db, &buyer, "buy", 20.0, 0.15).await;
+
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!(balance.reserved_grid_tokens, 3.0);
+    assert_eq!(balance.grid_tokens, 7.0);
+}
+
+#[tokio::test]
+async fn buy_order_beyond_the_spendable_balance_is_rejected() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 1.0).await;
+    place(&db, &buyer, "buy", 5.0, 0.15).await;
+
+    // 0.25 left to spend; this order needs 0.3
+    let order = common::order(&db, &buyer, "buy", 2.0, 0.15);
+    match db.create_order(order).await {
+        Err(DatabaseError::InsufficientBalance { required, available, .. }) => {
+            assert_eq!((required, available), (0.3, 0.25));
+        }
+        other => panic!("expected InsufficientBalance, got {:?}", other),
+    }
+    assert_eq!(db.get_prosumer_balance(&buyer).await.unwrap().reserved_grid_tokens, 0.75);
+}
+
+#[tokio::test]
+async fn cancel_releases_the_reservation() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 10.0).await;
+    let buy = place(&db, &buyer, "buy", 20.0, 0.15).await;
+
+    db.cancel_order(buy.id).await.unwrap();
+
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!(balance.reserved_grid_tokens, 0.0);
+    assert_eq!(balance.grid_tokens, 10.0);
+}
+
+#[tokio::test]
+async fn partial_fills_consume_the_reservation_down_to_zero() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 10.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    // Amounts chosen so the two released parts don't add up to the reservation in f64
+    let buy = place(&db, &buyer, "buy", 12.5, 0.123).await;
+
+    let first = place(&db, &seller, "sell", 12.25, 0.123).await;
+    db.execute_trade(buy.id, first.id, None).await.unwrap();
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert!((balance.reserved_grid_tokens - 0.25 * 0.123).abs() < 1e-9);
+
+    let second = place(&db, &seller, "sell", 0.25, 0.123).await;
+    db.execute_trade(buy.id, second.id, None).await.unwrap();
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!(balance.reserved_grid_tokens, 0.0);
+    assert!((balance.grid_tokens - (10.0 - 12.5 * 0.123)).abs() < 1e-9);
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "completed");
+}
+
+#[tokio::test]
+async fn price_improvement_is_refunded_from_the_reservation() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 10.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 10.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 10.0, 0.1).await;
+
+    let trade = db.execute_trade(buy.id, sell.id, Some(0.1)).await.unwrap();
+
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!(trade.total_price, 1.0);
+    assert_eq!(balance.reserved_grid_tokens, 0.0);
+    assert_eq!(balance.grid_tokens, 9.0);
+    assert_eq!(db.get_prosumer_balance(&seller).await.unwrap().grid_tokens, 1.0);
+}
+
+#[ntex::test]
+async fn only_an_admin_can_force_cancel_and_the_reservation_is_released() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let trader = user(&store, "trader");
+    let admin = user(&store, "admin");
+    let buyer = prosumer(&db, 10.0).await;
+    let buy = place(&db, &buyer, "buy", 20.0, 0.15).await;
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(
+                web::resource("/admin/orders/{order_id}/cancel").route(web::post().to(auth_handlers::force_cancel_order)),
+            ),
+    )
+    .await;
+    let cancel = |auth: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/admin/orders/{}/cancel", buy.id))
+            .header(header::AUTHORIZATION, auth)
+            .set_json(&json!({ "reason": "stuck order" }))
+            .to_request()
+    };
+
+    let response = test::call_service(&app, cancel(&bearer(&store, &trader))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "active");
+    assert_eq!(db.get_prosumer_balance(&buyer).await.unwrap().reserved_grid_tokens, 3.0);
+
+    let response = test::call_service(&app, cancel(&bearer(&store, &admin))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "cancelled");
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!(balance.reserved_grid_tokens, 0.0);
+    assert_eq!(balance.grid_tokens, 10.0);
+
+    // Cancelling again releases nothing twice
+    let response = test::call_service(&app, cancel(&bearer(&store, &admin))).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(db.get_prosumer_balance(&buyer).await.unwrap().grid_tokens, 10.0);
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use common::{isolated_test_db, order, place, prosumer, test_db};
+use energy_trading_api::clock::MockClock;
+use energy_trading_api::database::DatabaseError;
+
+#[tokio::test]
+async fn sweep_expires_the_order_and_releases_its_escrow() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 100.0).await;
+    let mut buy = order(&db, &buyer, "buy", 10.0, 0.2);
+    buy.expires_at = Some(Utc::now() + Duration::minutes(5));
+    let buy = db.create_order(buy).await.unwrap();
+    assert_eq!(db.get_prosumer_balance(&buyer).await.unwrap().reserved_grid_tokens, 2.0);
+
+    // Not due yet
+    let expired = db.expire_orders(Utc::now(), 1_000).await.unwrap();
+    assert!(expired.iter().all(|o| o.id != buy.id));
+
+    let expired = db.expire_orders(Utc::now() + Duration::hours(1), 1_000).await.unwrap();
+    assert!(expired.iter().any(|o| o.id == buy.id));
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "expired");
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!((balance.grid_tokens, balance.reserved_grid_tokens), (100.0, 0.0));
+
+    // Swept once only
+    let expired = db.expire_orders(Utc::now() + Duration::hours(2), 1_000).await.unwrap();
+    assert!(expired.iter().all(|o| o.id != buy.id));
+}
+
+#[tokio::test]
+async fn order_expires_once_the_mock_clock_passes_its_deadline() {
+    // Sweeps every order in the database, so it needs its own schema
+    let Some(db) = isolated_test_db().await else { return };
+    let clock = Arc::new(MockClock::new(Utc::now()));
+    let db = db.with_clock(clock.clone());
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let mut buy = order(&db, &buyer, "buy", 10.0, 0.2);
+    buy.expires_at = Some(db.now() + Duration::hours(1));
+    let buy = db.create_order(buy).await.unwrap();
+    let sell = place(&db, &seller, "sell", 10.0, 0.2).await;
+
+    assert!(db.expire_orders(db.now(), 1_000).await.unwrap().is_empty());
+
+    // An hour and a second later the order can no longer fill, and the sweep picks it up
+    clock.advance(Duration::hours(1) + Duration::seconds(1));
+    assert!(matches!(db.execute_trade(buy.id, sell.id, None).await, Err(DatabaseError::Conflict(_))));
+    let expired = db.expire_orders(db.now(), 1_000).await.unwrap();
+    assert_eq!(expired.iter().map(|o| o.id).collect::<Vec<_>>(), vec![buy.id]);
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "expired");
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{auth_store, prosumer, test_db};
+use energy_trading_api::config::FieldLimits;
+use energy_trading_api::database::DatabaseService;
+use energy_trading_api::handlers;
+use ntex::http::{Method, StatusCode};
+use ntex::web::{self, test, App};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+// Limits small enough that an over-long value is easy to build, with room for the addresses
+// test prosumers are given
+const LIMITS: FieldLimits = FieldLimits {
+    max_address_length: 40,
+    max_name_length: 8,
+    max_description_length: 32,
+};
+
+// Sends `body(value)` for an over-long value and for one with a control character in it, and
+// checks each is refused with a 400 naming `field`
+async fn assert_field_is_checked(
+    db: &Arc<DatabaseService>,
+    method: Method,
+    uri: &str,
+    field: &str,
+    max_length: usize,
+    body: impl Fn(&str) -> Value,
+) {
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(auth_store())
+            .service(
+                web::resource("/prosumers")
+                    .route(web::post().to(handlers::create_prosumer)),
+            )
+            .service(
+                web::resource("/prosumers/{address}")
+                    .route(web::put().to(handlers::update_prosumer)),
+            )
+            .service(web::resource("/orders").route(web::post().to(handlers::create_energy_order)))
+            .service(web::resource("/transfer").route(web::post().to(handlers::transfer_tokens))),
+    )
+    .await;
+
+    let too_long = "x".repeat(max_length + 1);
+    for value in [too_long.as_str(), "ab\u{0}cd", "ab\ncd"] {
+        let request = test::TestRequest::with_uri(uri).method(method.clone()).set_json(&body(value)).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{} = {:?}", field, value);
+        let error: Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+        let message = error["error"].as_str().unwrap();
+        assert!(message.starts_with(field), "{} = {:?}: {}", field, value, message);
+    }
+
+    // Exactly at the limit, once surrounding whitespace is trimmed, is not a length error
+    let request = test::TestRequest::with_uri(uri)
+        .method(method)
+        .set_json(&body(&format!("  {}  ", "x".repeat(max_length))))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    let error: Value = serde_json::from_slice(&test::read_body(response).await).unwrap_or(Value::Null);
+    assert!(!error["error"].as_str().unwrap_or("").starts_with(field), "{}: {}", field, error);
+}
+
+async fn limited_db() -> Option<Arc<DatabaseService>> {
+    Some(Arc::new(test_db().await?.with_field_limits(LIMITS)))
+}
+
+#[ntex::test]
+async fn prosumer_address_is_checked() {
+    let Some(db) = limited_db().await else { return };
+    assert_field_is_checked(&db, Method::POST, "/prosumers", "address", LIMITS.max_address_length, |value| {
+        json!({ "address": value, "name": "Solar" })
+    })
+    .await;
+}
+
+#[ntex::test]
+async fn prosumer_name_is_checked() {
+    let Some(db) = limited_db().await else { return };
+    let address = format!("0x{}", Uuid::new_v4().simple());
+    assert_field_is_checked(&db, Method::POST, "/prosumers", "name", LIMITS.max_name_length, |value| {
+        json!({ "address": address, "name": value })
+    })
+    .await;
+}
+
+#[ntex::test]
+async fn updated_prosumer_name_is_checked() {
+    let Some(db) = limited_db().await else { return };
+    let address = prosumer(&db, 0.0).await;
+    let uri = format!("/prosumers/{}", address);
+    assert_field_is_checked(&db, Method::PUT, &uri, "name", LIMITS.max_name_length, |value| json!({ "name": value })).await;
+    // Only the value at the limit got through, trimmed
+    assert_eq!(db.get_prosumer(&address).await.unwrap().name, "x".repeat(LIMITS.max_name_length));
+}
+
+#[ntex::test]
+async fn order_prosumer_address_is_checked() {
+    let Some(db) = limited_db().await else { return };
+    assert_field_is_checked(&db, Method::POST, "/orders", "prosumer_address", LIMITS.max_address_length, |value| {
+        json!({ "prosumer_address": value, "order_type": "sell", "energy_amount": 1.0, "price_per_unit": 0.2 })
+    })
+    .await;
+}
+
+#[ntex::test]
+async fn order_currency_is_checked() {
+    let Some(db) = limited_db().await else { return };
+    let address = prosumer(&db, 0.0).await;
+    // Currency codes have a fixed limit of 10 characters
+    assert_field_is_checked(&db, Method::POST, "/orders", "currency", 10, |value| {
+        json!({ "prosumer_address": address, "order_type": "sell", "energy_amount": 1.0, "price_per_unit": 0.2, "currency": value })
+    })
+    .await;
+}
+
+#[ntex::test]
+async fn transfer_from_address_is_checked() {
+    let Some(db) = limited_db().await else { return };
+    assert_field_is_checked(&db, Method::POST, "/transfer", "from_address", LIMITS.max_address_length, |value| {
+        json!({ "from_address": value, "to_address": "0xabc", "amount": 1.0, "token_type": "grid_tokens" })
+    })
+    .await;
+}
+
+#[ntex::test]
+async fn transfer_to_address_is_checked() {
+    let Some(db) = limited_db().await else { return };
+    assert_field_is_checked(&db, Method::POST, "/transfer", "to_address", LIMITS.max_address_length, |value| {
+        json!({ "from_address": "0xabc", "to_address": value, "amount": 1.0, "token_type": "grid_tokens" })
+    })
+    .await;
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{auth_store, bearer, isolated_test_url, test_db, user, SQLITE_SCHEMA_MIGRATION};
+use energy_trading_api::database::DatabaseService;
+use energy_trading_api::{auth_handlers, handlers};
+use ntex::http::{header, StatusCode};
+use ntex::web::{self, test, App};
+use serde_json::Value;
+use sqlx::PgPool;
+use energy_trading_api::config::MigrationConfig;
+
+#[tokio::test]
+async fn migrated_database_reports_healthy() {
+    let Some(db) = test_db().await else { return };
+    let health = db.get_health_details(&MigrationConfig::default()).await;
+
+    assert_eq!(health.status, "ok");
+    assert!(health.database.reachable);
+    assert_eq!(health.database.backend, "postgres");
+    let migrations = health.migrations.expect("migration status");
+    assert!(migrations.pending.is_empty());
+    assert!(migrations.failed.is_empty());
+}
+
+#[tokio::test]
+async fn unreadable_migration_table_degrades_the_report() {
+    let Some(db) = test_db().await else { return };
+    let migrations = MigrationConfig {
+        table: "missing_migrations".to_string(),
+        ..MigrationConfig::default()
+    };
+    let health = db.get_health_details(&migrations).await;
+
+    assert_eq!(health.status, "degraded");
+    assert!(health.database.reachable);
+    assert!(health.migrations.is_none());
+    assert!(health.migrations_error.is_some());
+}
+
+#[tokio::test]
+async fn migrations_are_tracked_in_the_configured_table_from_the_baseline() {
+    let Some(url) = isolated_test_url().await else { return };
+    let db = DatabaseService::new(&url).await.unwrap();
+    let migrations = MigrationConfig {
+        table: "schema_history".to_string(),
+        baseline: Some(SQLITE_SCHEMA_MIGRATION),
+        ..MigrationConfig::default()
+    };
+
+    let ran = db.run_migrations(&migrations).await.unwrap();
+    assert!(!ran.is_empty());
+    assert!(ran.iter().all(|version| *version > SQLITE_SCHEMA_MIGRATION), "{:?}", ran);
+    assert!(db.run_migrations(&migrations).await.unwrap().is_empty());
+
+    let health = db.get_health_details(&migrations).await;
+    assert_eq!(health.status, "ok");
+    let status = health.migrations.expect("migration status");
+    assert!(status.pending.is_empty());
+    assert!(status.failed.is_empty());
+
+    // Nothing was recorded under the default table name in this schema
+    let pool = PgPool::connect(&url).await.unwrap();
+    let default_table: Option<String> = sqlx::query_scalar("SELECT to_regclass(current_schema() || '._sqlx_migrations')::text")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(default_table, None);
+}
+
+#[ntex::test]
+async fn only_admins_see_the_detailed_health_report() {
+    let Some(db) = test_db().await else { return };
+    let store = auth_store();
+    let trader = user(&store, "trader");
+    let admin = user(&store, "admin");
+    let app = test::init_service(
+        App::new()
+            .state(Arc::new(db))
+            .state(Arc::new(MigrationConfig::default()))
+            .state(store.clone())
+            .service(web::resource("/health").route(web::get().to(handlers::health_check)))
+            .service(web::resource("/health/detailed").route(web::get().to(auth_handlers::health_details))),
+    )
+    .await;
+    let get = |uri: &str, auth: Option<String>| {
+        let mut request = test::TestRequest::get().uri(uri);
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+
+    let response = test::call_service(&app, get("/health", None)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = test::call_service(&app, get("/health/detailed", None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = test::call_service(&app, get("/health/detailed", Some(bearer(&store, &trader)))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = test::call_service(&app, get("/health/detailed", Some(bearer(&store, &admin)))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(report["database"]["reachable"], true);
+}
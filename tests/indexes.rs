@@ -0,0 +1,63 @@
+mod common;
+
+use common::{connect, isolated_test_url};
+use sqlx::{Connection, Executor, PgConnection, Row};
+
+// A market history for the planner to weigh: 1000 prosumers, 5000 mostly finished orders of
+// both sides with a few still active or expired, and 5000 trades spread across the prosumers
+const SEED_HISTORY: &str = r#"
+    INSERT INTO prosumers (address, name)
+    SELECT '0x' || i, 'Prosumer ' || i FROM generate_series(1, 1000) AS i;
+
+    INSERT INTO orders (prosumer_address, order_type, energy_amount, price_per_unit, total_price, status)
+    SELECT '0x' || (1 + i % 100),
+           CASE WHEN i % 2 = 0 THEN 'buy' ELSE 'sell' END,
+           1.0, 0.1 + (i % 50) * 0.01, 0.1 + (i % 50) * 0.01,
+           CASE WHEN i % 20 = 0 THEN 'active' WHEN i % 50 = 1 THEN 'expired' WHEN i % 3 = 0 THEN 'cancelled' ELSE 'completed' END
+    FROM generate_series(1, 5000) AS i;
+
+    INSERT INTO trades (buy_order_id, sell_order_id, buyer_address, seller_address, energy_amount, price_per_unit, total_price, status)
+    SELECT (SELECT id FROM orders WHERE order_type = 'buy' LIMIT 1),
+           (SELECT id FROM orders WHERE order_type = 'sell' LIMIT 1),
+           '0x' || (1 + i % 1000), '0x' || (1 + (i * 7) % 1000),
+           1.0, 0.2, 0.2, 'completed'
+    FROM generate_series(1, 5000) AS i;
+
+    ANALYZE;
+"#;
+
+// The query plan for `query`, one line per plan node
+async fn plan(conn: &mut PgConnection, query: &str) -> String {
+    let rows = sqlx::query(&format!("EXPLAIN {}", query)).fetch_all(&mut *conn).await.unwrap();
+    rows.iter().map(|row| row.get::<String, _>(0)).collect::<Vec<_>>().join("\n")
+}
+
+// Even with a history behind it, a test database is too small for the planner to prefer an
+// index over a sequential scan, so sequential scans are switched off: this checks which index
+// each hot query is served by, not whether the planner would scan at this size
+#[tokio::test]
+async fn hot_queries_are_planned_on_their_indexes() {
+    let Some(url) = isolated_test_url().await else { return };
+    connect(&url).await;
+    let mut conn = PgConnection::connect(&url).await.unwrap();
+    conn.execute(SEED_HISTORY).await.unwrap();
+    conn.execute("SET enable_seqscan = off").await.unwrap();
+
+    let listing = plan(&mut conn, "SELECT * FROM orders WHERE status = 'expired' AND order_type = 'sell'").await;
+    assert!(listing.contains("idx_orders_status_type_price"), "{}", listing);
+
+    let best_ask = plan(
+        &mut conn,
+        "SELECT MIN(price_per_unit) FROM orders WHERE status = 'active' AND order_type = 'sell'",
+    )
+    .await;
+    assert!(best_ask.contains("idx_orders_active_book"), "{}", best_ask);
+
+    let history = plan(
+        &mut conn,
+        "SELECT * FROM trades WHERE buyer_address = '0x42' OR seller_address = '0x42' ORDER BY created_at DESC LIMIT 20",
+    )
+    .await;
+    assert!(history.contains("idx_trades_buyer_created_at"), "{}", history);
+    assert!(history.contains("idx_trades_seller_created_at"), "{}", history);
+}
@@ -0,0 +1,404 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{prosumer, test_db};
+use energy_trading_api::auth::{AuthStore, CreateApiKeyRequest, CreateUserRequest, User};
+use energy_trading_api::{handlers, server};
+use energy_trading_api::config::{BodyLimitConfig, CorsConfig, RateLimitConfig};
+use energy_trading_api::middleware::{
+    cors, envelope_errors, extract_auth_context, AuditLog, AuthContext, BodyLimit, QuotaCounter, RateLimitHeaders, RequestTimeout,
+    RequestTracing,
+};
+use energy_trading_api::telemetry;
+use ntex::http::{header, StatusCode};
+use ntex::time::{sleep, Millis};
+use ntex::web::{self, test, App, HttpResponse};
+use tracing::span::{Attributes, Id};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::Layer;
+
+fn auth_store() -> Arc<AuthStore> {
+    let mut store = AuthStore::new();
+    store.jwt_secret = "test-secret".to_string();
+    Arc::new(store)
+}
+
+fn trader(store: &AuthStore) -> User {
+    store
+        .create_user(CreateUserRequest {
+            username: format!("trader-{}", uuid::Uuid::new_v4().simple()),
+            email: "trader@example.com".to_string(),
+            password: "Tr4der-password".to_string(),
+            role: "trader".to_string(),
+        })
+        .unwrap()
+}
+
+async fn whoami(auth: AuthContext) -> HttpResponse {
+    HttpResponse::Ok().body(auth.user_id)
+}
+
+async fn accept(body: String) -> HttpResponse {
+    HttpResponse::Ok().body(body)
+}
+
+async fn request_id(req: web::HttpRequest) -> HttpResponse {
+    let id = req.headers().get("X-Request-ID").and_then(|v| v.to_str().ok()).unwrap_or_default();
+    HttpResponse::Ok().body(id.to_string())
+}
+
+// Records the name and fields of every span opened while it is the default subscriber
+#[derive(Clone, Default)]
+struct SpanRecorder(Arc<Mutex<Vec<String>>>);
+
+impl<S: tracing::Subscriber> Layer<S> for SpanRecorder {
+    fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+        let mut fields = String::new();
+        attrs.record(&mut |field: &tracing::field::Field, value: &dyn std::fmt::Debug| {
+            fields.push_str(&format!(" {}={:?}", field.name(), value));
+        });
+        self.0.lock().unwrap().push(format!("{}{}", attrs.metadata().name(), fields));
+    }
+}
+
+#[ntex::test]
+async fn deactivated_users_token_is_rejected_on_the_next_request() {
+    let store = auth_store();
+    let user = trader(&store);
+    let token = store.generate_jwt(&user).unwrap();
+    let app = test::init_service(
+        App::new()
+            .state(store.clone())
+            .service(web::resource("/whoami").route(web::get().to(whoami))),
+    )
+    .await;
+    let request = || {
+        test::TestRequest::get()
+            .uri("/whoami")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .to_request()
+    };
+
+    assert_eq!(test::call_service(&app, request()).await.status(), StatusCode::OK);
+    store.deactivate_user(&user.id).unwrap();
+    assert_eq!(test::call_service(&app, request()).await.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[ntex::test]
+async fn transfer_is_audited_with_its_user_and_path() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let user = trader(&store);
+    let token = store.generate_jwt(&user).unwrap();
+    let sender = prosumer(&db, 10.0).await;
+    let recipient = prosumer(&db, 0.0).await;
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .wrap(AuditLog)
+            .service(web::resource("/transfer").route(web::post().to(handlers::transfer_tokens))),
+    )
+    .await;
+
+    let request = test::TestRequest::post()
+        .uri("/transfer")
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .set_json(&serde_json::json!({
+            "from_address": sender,
+            "to_address": recipient,
+            "amount": 2.5,
+            "token_type": "grid_tokens"
+        }))
+        .to_request();
+    assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+    // The audit row is written in the background
+    let mut entries = Vec::new();
+    for _ in 0..100 {
+        entries = db.get_audit_log(1, 10, None, None, Some(user.id.clone())).await.unwrap();
+        if !entries.is_empty() {
+            break;
+        }
+        sleep(Millis(10)).await;
+    }
+    assert_eq!(entries.len(), 1);
+    assert_eq!((entries[0].method.as_str(), entries[0].path.as_str(), entries[0].status_code), ("POST", "/transfer", 200));
+}
+
+async fn slow() -> HttpResponse {
+    sleep(Millis(500)).await;
+    HttpResponse::Ok().finish()
+}
+
+// Served over HTTP: the timeout is an error returned by middleware, which only the server
+// renders, so the response is checked as the client receives it
+#[ntex::test]
+async fn slow_handler_times_out_with_504_in_the_api_envelope() {
+    let server = web::test::server(|| {
+        envelope_errors(
+            App::new()
+                .wrap(RequestTimeout::new(50))
+                .service(web::resource("/slow").route(web::get().to(slow)))
+                .service(web::resource("/echo").route(web::post().to(accept))),
+        )
+    });
+
+    let mut response = server.get("/slow").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let body: serde_json::Value = serde_json::from_slice(&response.body().await.unwrap()).unwrap();
+    assert_eq!(body["success"], false);
+    assert_eq!(body["message"], "Request timed out");
+    assert_eq!(body["data"], serde_json::Value::Null);
+    assert!(body["timestamp"].is_string());
+
+    // Handlers that answer in time are untouched
+    let mut response = server.post("/echo").send_body("quick").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().await.unwrap(), "quick".as_bytes());
+
+    server.stop().await;
+}
+
+#[ntex::test]
+async fn oversized_body_is_rejected_with_413() {
+    let app = test::init_service(
+        App::new()
+            .wrap(BodyLimit::new(16))
+            .service(web::resource("/echo").route(web::post().to(accept))),
+    )
+    .await;
+    let post = |body: &'static str| {
+        test::TestRequest::post()
+            .uri("/echo")
+            .header(header::CONTENT_LENGTH, body.len().to_string())
+            .set_payload(body)
+            .to_request()
+    };
+
+    assert_eq!(test::call_service(&app, post("small")).await.status(), StatusCode::OK);
+    let response = test::call_service(&app, post("a body well over sixteen bytes")).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[ntex::test]
+async fn every_batch_route_accepts_bodies_up_to_the_batch_limit() {
+    let Some(db) = test_db().await else { return };
+    let limits = BodyLimitConfig { max_body_bytes: 64, max_batch_body_bytes: 1024 };
+    let app = test::init_service(
+        App::new()
+            .state(Arc::new(db))
+            .state(auth_store())
+            .wrap(server::body_limit(&limits))
+            .configure(|cfg| server::batch_routes(cfg, limits.max_batch_body_bytes))
+            .service(web::resource("/echo").route(web::post().to(accept))),
+    )
+    .await;
+    let post = |path: &str, size: usize| {
+        let body = format!(r#"{{"operations": [], "trades": [], "padding": "{}"}}"#, "x".repeat(size));
+        test::TestRequest::post()
+            .uri(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, body.len().to_string())
+            .set_payload(body)
+            .to_request()
+    };
+
+    // Over the default limit but under the batch one: only routes outside BATCH_ROUTES refuse it
+    assert_eq!(test::call_service(&app, post("/echo", 500)).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    for path in server::BATCH_ROUTES {
+        let response = test::call_service(&app, post(path, 500)).await;
+        assert_ne!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{} refused a body under the batch limit", path);
+        let response = test::call_service(&app, post(path, 2000)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{} accepted a body over the batch limit", path);
+    }
+}
+
+#[ntex::test]
+async fn preflight_responses_carry_the_configured_max_age() {
+    let config = CorsConfig { max_age_secs: 3600, ..CorsConfig::default() };
+    let app = test::init_service(
+        App::new()
+            .wrap(cors(&config))
+            .service(web::resource("/echo").route(web::post().to(accept))),
+    )
+    .await;
+
+    let preflight = test::TestRequest::with_uri("/echo")
+        .method(ntex::http::Method::OPTIONS)
+        .header(header::ORIGIN, "https://app.example.com")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .to_request();
+    let response = test::call_service(&app, preflight).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "3600");
+
+    let actual = test::TestRequest::post().uri("/echo").header(header::ORIGIN, "https://app.example.com").to_request();
+    let response = test::call_service(&app, actual).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(header::ACCESS_CONTROL_MAX_AGE).is_none());
+}
+
+#[ntex::test]
+async fn credentials_are_only_allowed_for_allowlisted_origins() {
+    let config = CorsConfig {
+        allowed_origins: vec!["https://app.example.com".to_string()],
+        allow_credentials: true,
+        ..CorsConfig::default()
+    };
+    assert!(config.validate().is_ok());
+    let app = test::init_service(
+        App::new()
+            .wrap(cors(&config))
+            .service(web::resource("/echo").route(web::post().to(accept))),
+    )
+    .await;
+
+    let actual = test::TestRequest::post().uri("/echo").header(header::ORIGIN, "https://app.example.com").to_request();
+    let response = test::call_service(&app, actual).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://app.example.com");
+    assert_eq!(response.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+
+    let preflight = test::TestRequest::with_uri("/echo")
+        .method(ntex::http::Method::OPTIONS)
+        .header(header::ORIGIN, "https://app.example.com")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .to_request();
+    let response = test::call_service(&app, preflight).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+
+    let foreign = test::TestRequest::post().uri("/echo").header(header::ORIGIN, "https://evil.example.com").to_request();
+    let response = test::call_service(&app, foreign).await;
+    assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+    assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+}
+
+#[test]
+fn credentials_cannot_be_combined_with_a_wildcard_origin() {
+    let wildcard = CorsConfig { allow_credentials: true, ..CorsConfig::default() };
+    assert!(wildcard.validate().unwrap_err().contains("CORS_ALLOWED_ORIGINS"));
+    let mixed = CorsConfig {
+        allowed_origins: vec!["https://app.example.com".to_string(), "*".to_string()],
+        ..wildcard.clone()
+    };
+    assert!(mixed.validate().is_err());
+    // Without credentials the wildcard is fine
+    assert!(CorsConfig::default().validate().is_ok());
+}
+
+#[ntex::test]
+async fn request_without_an_id_gets_one_and_a_sent_id_is_kept() {
+    let app = test::init_service(
+        App::new()
+            .wrap(RequestTracing)
+            .service(web::resource("/id").route(web::get().to(request_id))),
+    )
+    .await;
+
+    let generated = test::read_body(test::call_service(&app, test::TestRequest::get().uri("/id").to_request()).await).await;
+    assert!(uuid::Uuid::parse_str(std::str::from_utf8(&generated).unwrap()).is_ok());
+
+    let sent = test::TestRequest::get().uri("/id").header("X-Request-ID", "abc-123").to_request();
+    assert_eq!(test::read_body(test::call_service(&app, sent).await).await, "abc-123");
+}
+
+#[ntex::test]
+async fn each_request_opens_a_span_with_method_path_and_request_id() {
+    let recorder = SpanRecorder::default();
+    let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+    let app = test::init_service(
+        App::new()
+            .wrap(RequestTracing)
+            .service(web::resource("/id").route(web::get().to(request_id))),
+    )
+    .await;
+
+    let sent = test::TestRequest::get().uri("/id").header("X-Request-ID", "abc-123").to_request();
+    assert_eq!(test::call_service(&app, sent).await.status(), StatusCode::OK);
+
+    let spans = recorder.0.lock().unwrap();
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0], "request method=GET path=/id request_id=abc-123");
+}
+
+#[ntex::test]
+async fn request_span_is_exported_to_opentelemetry() {
+    let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(telemetry::trace_layer(&provider)));
+    let app = test::init_service(
+        App::new()
+            .wrap(RequestTracing)
+            .service(web::resource("/id").route(web::get().to(request_id))),
+    )
+    .await;
+
+    let sent = test::TestRequest::get().uri("/id").header("X-Request-ID", "abc-123").to_request();
+    assert_eq!(test::call_service(&app, sent).await.status(), StatusCode::OK);
+
+    let spans = exporter.get_finished_spans().unwrap();
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].name, "request");
+    let request_id = spans[0].attributes.iter().find(|kv| kv.key.as_str() == "request_id").unwrap();
+    assert_eq!(request_id.value.as_str(), "abc-123");
+}
+
+// Deactivates the caller, then asks for their context: only a context resolved before the
+// handler ran can still be returned
+async fn deactivate_then_whoami(req: web::HttpRequest, store: web::types::State<Arc<AuthStore>>, user_id: web::types::Path<String>) -> HttpResponse {
+    store.deactivate_user(&user_id).unwrap();
+    match extract_auth_context(&req) {
+        Ok(auth) => HttpResponse::Ok().body(auth.user_id),
+        Err(_) => HttpResponse::Unauthorized().finish(),
+    }
+}
+
+#[ntex::test]
+async fn rate_limit_headers_reuse_the_callers_resolved_api_key() {
+    let store = auth_store();
+    let quota = Arc::new(QuotaCounter::new(&RateLimitConfig { requests_per_window: 5, window_secs: 60 }));
+    let app = test::init_service(
+        App::new()
+            .state(store.clone())
+            .wrap(RateLimitHeaders::new(quota))
+            .service(web::resource("/whoami").route(web::get().to(whoami)))
+            .service(web::resource("/deactivate/{user_id}").route(web::get().to(deactivate_then_whoami))),
+    )
+    .await;
+    let user = trader(&store);
+    let api_key = store
+        .create_api_key(&user.id, CreateApiKeyRequest { name: "integration".to_string(), permissions: Vec::new(), expires_in_days: None })
+        .unwrap()
+        .key;
+    let remaining = |response: &web::WebResponse| {
+        response.headers().get("x-ratelimit-remaining").map(|v| v.to_str().unwrap().to_string())
+    };
+
+    let response = test::call_service(&app, test::TestRequest::get().uri("/whoami").to_request()).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(remaining(&response), None);
+
+    for expected in ["4", "3"] {
+        let request = test::TestRequest::get().uri("/whoami").header("X-API-Key", api_key.as_str()).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(remaining(&response).as_deref(), Some(expected));
+    }
+
+    // The key stops working inside the handler, yet the caller is still known: the context the
+    // middleware resolved is reused rather than checked a second time
+    let request = test::TestRequest::get()
+        .uri(&format!("/deactivate/{}", user.id))
+        .header("X-API-Key", api_key.as_str())
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(remaining(&response).as_deref(), Some("2"));
+    assert_eq!(test::read_body(response).await, user.id.as_bytes());
+}
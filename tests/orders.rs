@@ -0,0 +1,395 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use common::{auth_store, bearer, isolated_test_db, order, owned_prosumer, place, prosumer, test_db, user};
+use energy_trading_api::config::{MarketConfig, MAX_OPEN_ORDERS_TAG};
+use energy_trading_api::currency::StaticRateTable;
+use energy_trading_api::database::{DatabaseError, DatabaseService, Order};
+use energy_trading_api::handlers;
+use ntex::http::{header, StatusCode};
+use ntex::web::{self, test, App};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn sell_beyond_the_available_energy_is_rejected() {
+    let Some(db) = test_db().await else { return };
+    let db = db.with_market_config(MarketConfig {
+        enforce_energy_backing: true,
+        ..MarketConfig::default()
+    });
+    // Test prosumers have generated 1000 kWh and consumed nothing
+    let seller = prosumer(&db, 0.0).await;
+    place(&db, &seller, "sell", 600.0, 0.2).await;
+
+    // Only 400 kWh is left uncommitted
+    assert!(matches!(
+        db.create_order(order(&db, &seller, "sell", 500.0, 0.2)).await,
+        Err(DatabaseError::Validation(_))
+    ));
+    place(&db, &seller, "sell", 400.0, 0.2).await;
+}
+
+#[tokio::test]
+async fn amendment_below_the_filled_amount_is_rejected() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 10.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 6.0, 0.2).await;
+    db.execute_trade(buy.id, sell.id, None).await.unwrap();
+
+    assert!(matches!(
+        db.update_order(buy.id, Some(5.0), None).await,
+        Err(DatabaseError::Validation(_))
+    ));
+    let amended = db.update_order(buy.id, Some(8.0), None).await.unwrap();
+    assert_eq!((amended.energy_amount, amended.filled_amount, amended.status.as_str()), (8.0, 6.0, "active"));
+}
+
+#[tokio::test]
+async fn only_open_orders_can_be_cancelled() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+    db.execute_trade(buy.id, sell.id, None).await.unwrap();
+
+    assert!(matches!(db.cancel_order(buy.id).await, Err(DatabaseError::Conflict(_))));
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "completed");
+
+    let open = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    assert_eq!(db.cancel_order(open.id).await.unwrap().status, "cancelled");
+    assert!(matches!(db.cancel_order(open.id).await, Err(DatabaseError::Conflict(_))));
+}
+
+#[tokio::test]
+async fn orders_beyond_the_open_order_cap_are_rejected() {
+    let Some(db) = test_db().await else { return };
+    let db = db.with_market_config(MarketConfig {
+        max_open_orders_per_prosumer: 2,
+        ..MarketConfig::default()
+    });
+    let seller = prosumer(&db, 0.0).await;
+    let first = place(&db, &seller, "sell", 1.0, 0.2).await;
+    place(&db, &seller, "sell", 1.0, 0.2).await;
+
+    assert!(matches!(
+        db.create_order(order(&db, &seller, "sell", 1.0, 0.2)).await,
+        Err(DatabaseError::LimitExceeded(_))
+    ));
+    // A cancelled order no longer counts, but reactivating it would exceed the cap again
+    db.cancel_order(first.id).await.unwrap();
+    let third = place(&db, &seller, "sell", 1.0, 0.2).await;
+    assert!(matches!(db.reactivate_order(first.id).await, Err(DatabaseError::LimitExceeded(_))));
+
+    // A market maker's own tag raises the cap
+    db.set_prosumer_tags(&seller, &[("max_open_orders".to_string(), "3".to_string())].into()).await.unwrap();
+    place(&db, &seller, "sell", 1.0, 0.2).await;
+    db.cancel_order(third.id).await.unwrap();
+    assert_eq!(db.reactivate_order(first.id).await.unwrap().status, "active");
+}
+
+#[ntex::test]
+async fn only_an_admin_can_set_the_open_order_cap_tag() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let owner = user(&store, "trader");
+    let admin = user(&store, "admin");
+    let address = owned_prosumer(&db, 0.0, Some(&owner.id)).await;
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(web::resource("/prosumers/{address}/tags").route(web::put().to(handlers::set_prosumer_tags))),
+    )
+    .await;
+    let set_tags = |tags: Value, auth: Option<String>| {
+        let mut request = test::TestRequest::put()
+            .uri(&format!("/prosumers/{}/tags", address))
+            .set_json(&json!({ "tags": tags }));
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+
+    for auth in [None, Some(bearer(&store, &owner))] {
+        let response = test::call_service(&app, set_tags(json!({ MAX_OPEN_ORDERS_TAG: "1000000" }), auth)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+    assert!(db.get_prosumer_tags(&address).await.unwrap().is_empty());
+
+    let response = test::call_service(&app, set_tags(json!({ MAX_OPEN_ORDERS_TAG: "5" }), Some(bearer(&store, &admin)))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The owner can still manage the other tags, and replacing them keeps the admin's cap
+    let response = test::call_service(&app, set_tags(json!({ "region": "north" }), Some(bearer(&store, &owner)))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let tags = db.get_prosumer_tags(&address).await.unwrap();
+    assert_eq!(tags.get(MAX_OPEN_ORDERS_TAG).map(String::as_str), Some("5"));
+    assert_eq!(tags.get("region").map(String::as_str), Some("north"));
+    let response = test::call_service(&app, set_tags(json!({ MAX_OPEN_ORDERS_TAG: "6" }), Some(bearer(&store, &owner)))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn orders_below_the_minimum_size_or_off_the_price_tick_are_rejected() {
+    let Some(db) = test_db().await else { return };
+    let db = db.with_market_config(MarketConfig {
+        min_order_energy: 1.0,
+        price_tick: 0.05,
+        ..MarketConfig::default()
+    });
+    let seller = prosumer(&db, 0.0).await;
+
+    for (energy_amount, price_per_unit) in [(0.5, 0.20), (0.999, 0.20), (2.0, 0.27), (2.0, 0.151)] {
+        assert!(
+            matches!(
+                db.create_order(order(&db, &seller, "sell", energy_amount, price_per_unit)).await,
+                Err(DatabaseError::Validation(_))
+            ),
+            "{} kWh at {}",
+            energy_amount,
+            price_per_unit
+        );
+    }
+
+    // Exactly the minimum, and prices on the tick even where division by it is inexact
+    for price_per_unit in [0.05, 0.15, 0.20, 1.35] {
+        place(&db, &seller, "sell", 1.0, price_per_unit).await;
+    }
+
+    // An amendment is held to the same rules
+    let open = place(&db, &seller, "sell", 2.0, 0.20).await;
+    assert!(matches!(db.update_order(open.id, Some(0.5), None).await, Err(DatabaseError::Validation(_))));
+    assert!(matches!(db.update_order(open.id, None, Some(0.22)).await, Err(DatabaseError::Validation(_))));
+    assert_eq!(db.update_order(open.id, Some(1.0), Some(0.25)).await.unwrap().price_per_unit, 0.25);
+}
+
+#[tokio::test]
+async fn orders_are_refused_while_the_market_is_halted() {
+    // The halt is market-wide, so it must not leak into tests sharing the database
+    let Some(db) = isolated_test_db().await else { return };
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+
+    db.set_market_halt(true, Some("grid incident"), "admin").await.unwrap();
+    assert!(matches!(
+        db.create_order(order(&db, &buyer, "buy", 1.0, 0.2)).await,
+        Err(DatabaseError::MarketHalted(_))
+    ));
+    assert!(matches!(db.execute_trade(buy.id, sell.id, None).await, Err(DatabaseError::MarketHalted(_))));
+    assert!(matches!(db.match_orders().await, Err(DatabaseError::MarketHalted(_))));
+    // Reads still work and report the halt
+    let stats = db.get_market_stats(&[]).await.unwrap();
+    assert_eq!((stats.halted, stats.halt_reason.as_deref()), (true, Some("grid incident")));
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "active");
+
+    db.set_market_halt(false, None, "admin").await.unwrap();
+    assert!(!db.get_market_stats(&[]).await.unwrap().halted);
+    place(&db, &buyer, "buy", 1.0, 0.2).await;
+    assert_eq!(db.execute_trade(buy.id, sell.id, None).await.unwrap().energy_amount, 5.0);
+}
+
+// 1 USD buys 2 GRID, the base currency
+fn usd_rates() -> Arc<StaticRateTable> {
+    Arc::new(StaticRateTable::new("GRID", HashMap::from([("USD".to_string(), 2.0)])))
+}
+
+fn usd_order(db: &DatabaseService, address: &str, order_type: &str, energy_amount: f64, quoted_price: f64) -> Order {
+    Order {
+        currency: "USD".to_string(),
+        quoted_price_per_unit: quoted_price,
+        ..order(db, address, order_type, energy_amount, quoted_price)
+    }
+}
+
+#[tokio::test]
+async fn usd_quoted_buy_matches_a_grid_quoted_sell_at_the_converted_price() {
+    // Matching runs over the whole book, so it needs its own schema
+    let Some(db) = isolated_test_db().await else { return };
+    let db = db.with_exchange_rates(usd_rates());
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = db.create_order(usd_order(&db, &buyer, "buy", 5.0, 0.1)).await.unwrap();
+    assert_eq!((buy.currency.as_str(), buy.quoted_price_per_unit, buy.price_per_unit), ("USD", 0.1, 0.2));
+    assert!((buy.total_price - 1.0).abs() < 1e-9);
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+
+    let result = db.match_orders().await.unwrap();
+    assert_eq!(result.trades.len(), 1);
+    let trade = &result.trades[0];
+    assert_eq!((trade.buy_order_id, trade.sell_order_id), (buy.id, sell.id));
+    assert_eq!((trade.energy_amount, trade.price_per_unit), (5.0, 0.2));
+    assert!((trade.total_price - 1.0).abs() < 1e-9);
+
+    // A USD buy bidding below the converted ask doesn't cross
+    let low_buy = db.create_order(usd_order(&db, &buyer, "buy", 5.0, 0.09)).await.unwrap();
+    place(&db, &seller, "sell", 5.0, 0.2).await;
+    assert!(db.match_orders().await.unwrap().trades.is_empty());
+    assert_eq!(db.get_order(low_buy.id).await.unwrap().filled_amount, 0.0);
+}
+
+#[tokio::test]
+async fn amending_an_order_recomputes_its_total_in_the_base_currency() {
+    let Some(db) = test_db().await else { return };
+    let db = db.with_exchange_rates(usd_rates());
+    let buyer = prosumer(&db, 100.0).await;
+    let buy = db.create_order(usd_order(&db, &buyer, "buy", 5.0, 0.1)).await.unwrap();
+
+    // A new quote is converted and the total follows it
+    let repriced = db.update_order(buy.id, None, Some(0.15)).await.unwrap();
+    assert_eq!((repriced.quoted_price_per_unit, repriced.price_per_unit), (0.15, 0.3));
+    assert!((repriced.total_price - 1.5).abs() < 1e-9, "{}", repriced.total_price);
+
+    // So does a new amount at the existing price
+    let resized = db.update_order(buy.id, Some(8.0), None).await.unwrap();
+    assert!((resized.total_price - 2.4).abs() < 1e-9, "{}", resized.total_price);
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert!((balance.reserved_grid_tokens - 2.4).abs() < 1e-9);
+}
+
+// Snapshots cover the whole book, so this uses a database of its own
+#[tokio::test]
+async fn order_book_snapshot_round_trips_and_verify_reports_drift() {
+    let Some(db) = isolated_test_db().await else { return };
+    assert!(matches!(db.verify_order_book().await, Err(DatabaseError::NotFound(_))));
+
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    place(&db, &buyer, "buy", 5.0, 0.2).await;
+    place(&db, &buyer, "buy", 3.0, 0.2).await;
+    let ask = place(&db, &seller, "sell", 4.0, 0.4).await;
+    let snapshot = db.snapshot_order_book().await.unwrap();
+    assert_eq!(snapshot.book.bids.len(), 1);
+    assert_eq!((snapshot.book.bids[0].energy, snapshot.book.bids[0].order_count), (8.0, 2));
+
+    // Read back unchanged, the stored snapshot matches the live book
+    let verification = db.verify_order_book().await.unwrap();
+    assert_eq!(verification.snapshot_id, snapshot.id);
+    assert_eq!(verification.snapshot_checksum, snapshot.checksum);
+    assert_eq!(verification.current_checksum, snapshot.checksum);
+    assert!(!verification.drifted);
+    assert!(verification.changed_levels.is_empty());
+
+    db.cancel_order(ask.id).await.unwrap();
+    place(&db, &seller, "sell", 2.0, 0.5).await;
+    let verification = db.verify_order_book().await.unwrap();
+    assert!(verification.drifted);
+    assert_ne!(verification.current_checksum, snapshot.checksum);
+    let changed: Vec<(&str, f64, f64, f64)> = verification
+        .changed_levels
+        .iter()
+        .map(|level| (level.side.as_str(), level.price, level.snapshot_energy, level.current_energy))
+        .collect();
+    assert_eq!(changed, vec![("ask", 0.4, 4.0, 0.0), ("ask", 0.5, 0.0, 2.0)]);
+}
+
+// An unfiltered listing covers every order, so this uses a database of its own
+#[ntex::test]
+async fn order_listing_applies_each_filter_alone_and_none_without_them() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db);
+    let a = prosumer(&db, 100.0).await;
+    let b = prosumer(&db, 100.0).await;
+    let c = prosumer(&db, 0.0).await;
+    let a_buy = place(&db, &a, "buy", 5.0, 0.2).await;
+    let a_sell = place(&db, &a, "sell", 2.0, 0.5).await;
+    let b_cancelled = place(&db, &b, "buy", 3.0, 0.1).await;
+    db.cancel_order(b_cancelled.id).await.unwrap();
+    let b_filled = place(&db, &b, "buy", 4.0, 0.3).await;
+    let c_filled = place(&db, &c, "sell", 4.0, 0.3).await;
+    db.execute_trade(b_filled.id, c_filled.id, None).await.unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/orders").route(web::get().to(handlers::get_all_energy_orders))),
+    )
+    .await;
+    let list = |query: String| {
+        let app = &app;
+        async move {
+            let request = test::TestRequest::get().uri(&format!("/orders?limit=50&{}", query)).to_request();
+            let response = test::call_service(app, request).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", query);
+            let page: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+            let mut ids: Vec<Uuid> = page["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|order| order["id"].as_str().unwrap().parse().unwrap())
+                .collect();
+            ids.sort();
+            ids
+        }
+    };
+    let sorted = |mut ids: Vec<Uuid>| {
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(list(String::new()).await, sorted(vec![a_buy.id, a_sell.id, b_cancelled.id, b_filled.id, c_filled.id]));
+    assert_eq!(list("status=active".to_string()).await, sorted(vec![a_buy.id, a_sell.id]));
+    assert_eq!(list("status=completed".to_string()).await, sorted(vec![b_filled.id, c_filled.id]));
+    assert_eq!(list("status=cancelled".to_string()).await, vec![b_cancelled.id]);
+    assert_eq!(list("order_type=sell".to_string()).await, sorted(vec![a_sell.id, c_filled.id]));
+    assert_eq!(list(format!("prosumer_address={}", b)).await, sorted(vec![b_cancelled.id, b_filled.id]));
+
+    for query in ["status=open", "order_type=bid"] {
+        let request = test::TestRequest::get().uri(&format!("/orders?{}", query)).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[ntex::test]
+async fn order_listing_sorts_by_an_allowlisted_column_in_either_direction() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db);
+    let seller = prosumer(&db, 0.0).await;
+    let mut placed = Vec::new();
+    for price in [0.3, 0.1, 0.2] {
+        placed.push(place(&db, &seller, "sell", 1.0, price).await.id);
+    }
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/orders").route(web::get().to(handlers::get_all_energy_orders))),
+    )
+    .await;
+    let listed = |query: &str| {
+        let request = test::TestRequest::get().uri(&format!("/orders?{}", query)).to_request();
+        let app = &app;
+        async move {
+            let response = test::call_service(app, request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let page: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+            page["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|order| (order["id"].as_str().unwrap().parse::<Uuid>().unwrap(), order["price_per_unit"].as_f64().unwrap()))
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let ascending: Vec<f64> = listed("sort=price_per_unit:asc").await.into_iter().map(|(_, price)| price).collect();
+    assert_eq!(ascending, vec![0.1, 0.2, 0.3]);
+    let descending: Vec<f64> = listed("sort=price_per_unit:desc").await.into_iter().map(|(_, price)| price).collect();
+    assert_eq!(descending, vec![0.3, 0.2, 0.1]);
+    // Without a sort the newest order comes first
+    let newest_first: Vec<Uuid> = listed("").await.into_iter().map(|(id, _)| id).collect();
+    assert_eq!(newest_first, placed.into_iter().rev().collect::<Vec<_>>());
+
+    for query in ["sort=password", "sort=price_per_unit:sideways", "sort=price_per_unit;DROP%20TABLE%20orders"] {
+        let request = test::TestRequest::get().uri(&format!("/orders?{}", query)).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
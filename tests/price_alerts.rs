@@ -0,0 +1,104 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{auth_store, bearer, isolated_test_db, owned_prosumer, place, prosumer, test_db, user};
+use energy_trading_api::handlers;
+use ntex::http::{header, StatusCode};
+use ntex::web::{self, test, App};
+use serde_json::{json, Value};
+
+// Alerts fire on the market-wide best bid/ask, so this runs on a database of its own
+#[tokio::test]
+async fn alert_fires_once_when_the_best_bid_crosses_it() {
+    let Some(db) = isolated_test_db().await else { return };
+    let buyer = prosumer(&db, 100.0).await;
+    let crossed = db.create_price_alert(&buyer, "bid", "above", 0.15).await.unwrap();
+    let not_crossed = db.create_price_alert(&buyer, "bid", "above", 0.5).await.unwrap();
+    assert!(db.trigger_price_alerts().await.unwrap().is_empty(), "empty book fires nothing");
+
+    place(&db, &buyer, "buy", 10.0, 0.2).await;
+    let fired = db.trigger_price_alerts().await.unwrap();
+    assert_eq!(fired.iter().map(|a| a.id).collect::<Vec<_>>(), vec![crossed.id]);
+    assert_eq!(fired[0].triggered_price, Some(0.2));
+
+    // One-shot: a later check leaves both alerts alone
+    assert!(db.trigger_price_alerts().await.unwrap().is_empty());
+    let alerts = db.get_price_alerts(Some(&buyer), None).await.unwrap();
+    assert!(alerts.iter().any(|a| a.id == crossed.id && a.triggered));
+    assert!(alerts.iter().any(|a| a.id == not_crossed.id && !a.triggered));
+}
+
+#[ntex::test]
+async fn alerts_are_created_listed_and_deleted_only_by_the_prosumers_owner_or_an_admin() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let owner = user(&store, "trader");
+    let stranger = user(&store, "trader");
+    let admin = user(&store, "admin");
+    let address = owned_prosumer(&db, 0.0, Some(&owner.id)).await;
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(
+                web::resource("/price-alerts")
+                    .route(web::post().to(handlers::create_price_alert))
+                    .route(web::get().to(handlers::get_price_alerts)),
+            )
+            .service(web::resource("/price-alerts/{alert_id}").route(web::delete().to(handlers::delete_price_alert))),
+    )
+    .await;
+    // Far above any test's bids, so the alert stays pending
+    let create = |auth: Option<String>| {
+        let mut request = test::TestRequest::post()
+            .uri("/price-alerts")
+            .set_json(&json!({ "address": address, "side": "bid", "direction": "above", "price": 1_000_000.0 }));
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+    let list = |auth: String| {
+        test::TestRequest::get()
+            .uri(&format!("/price-alerts?address={}", address))
+            .header(header::AUTHORIZATION, auth)
+            .to_request()
+    };
+    let delete = |id: &str, auth: String| {
+        test::TestRequest::delete()
+            .uri(&format!("/price-alerts/{}", id))
+            .header(header::AUTHORIZATION, auth)
+            .to_request()
+    };
+
+    let response = test::call_service(&app, create(None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = test::call_service(&app, create(Some(bearer(&store, &stranger)))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = test::call_service(&app, create(Some(bearer(&store, &owner)))).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let alert: Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    let id = alert["id"].as_str().unwrap().to_string();
+    let response = test::call_service(&app, create(Some(bearer(&store, &admin)))).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = test::call_service(&app, list(bearer(&store, &owner))).await;
+    let listed: Vec<Value> = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(listed.len(), 2);
+    let response = test::call_service(&app, list(bearer(&store, &stranger))).await;
+    let listed: Vec<Value> = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert!(listed.is_empty());
+
+    // Someone else's alert is indistinguishable from a missing one
+    let response = test::call_service(&app, delete(&id, bearer(&store, &stranger))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = test::call_service(&app, delete(&id, bearer(&store, &owner))).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let admin_alert = db.get_price_alerts(Some(&address), None).await.unwrap().remove(0);
+    let response = test::call_service(&app, delete(&admin_alert.id.to_string(), bearer(&store, &admin))).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(db.get_price_alerts(Some(&address), None).await.unwrap().is_empty());
+}
@@ -0,0 +1,554 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{auth_store, bearer, connect, isolated_test_db, isolated_test_url, place, prosumer, test_db, user};
+use energy_trading_api::config::{CompressionConfig, InitialBalanceConfig, MarketConfig, PaginationConfig};
+use energy_trading_api::database::{DatabaseError, DatabaseService, Prosumer, SortOrder, TagFilter, MINT_ADDRESS};
+use energy_trading_api::handlers;
+use energy_trading_api::middleware::Compress;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use energy_trading_api::models::UpdateProsumerRequest;
+use ntex::http::{header, StatusCode};
+use ntex::web::{self, test, App};
+use uuid::Uuid;
+
+#[ntex::test]
+async fn configured_starting_balance_is_minted_to_prosumers_created_through_the_api() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db.with_initial_balances(InitialBalanceConfig { grid_tokens: 25.0, watt_tokens: 10.0 }));
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/prosumers").route(web::post().to(handlers::create_prosumer))),
+    )
+    .await;
+    let address = format!("0x{}", Uuid::new_v4().simple());
+    let request = test::TestRequest::post()
+        .uri("/prosumers")
+        .set_json(&serde_json::json!({ "address": address, "name": "New prosumer" }))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: Prosumer = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!((created.grid_tokens, created.watt_tokens), (25.0, 10.0));
+
+    let balance = db.get_prosumer_balance(&address).await.unwrap();
+    assert_eq!((balance.grid_tokens, balance.watt_tokens), (25.0, 10.0));
+    assert_eq!(balance.tokens.get("grid_tokens"), Some(&25.0));
+    assert_eq!(balance.tokens.get("watt_tokens"), Some(&10.0));
+
+    // The grant is on record as a mint, one transfer per token type
+    let statement = db
+        .get_account_statement(&address, Utc::now() - Duration::hours(1), Utc::now() + Duration::hours(1))
+        .await
+        .unwrap();
+    let mut minted: Vec<_> = statement
+        .transfers
+        .iter()
+        .map(|t| (t.from_address.as_str(), t.to_address.as_str(), t.token_type.as_str(), t.amount))
+        .collect();
+    minted.sort_by(|a, b| a.2.cmp(b.2));
+    assert_eq!(
+        minted,
+        [
+            (MINT_ADDRESS, address.as_str(), "grid_tokens", 25.0),
+            (MINT_ADDRESS, address.as_str(), "watt_tokens", 10.0),
+        ]
+    );
+}
+
+#[ntex::test]
+async fn nothing_is_minted_without_a_configured_starting_balance() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db.with_initial_balances(InitialBalanceConfig::default()));
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/prosumers").route(web::post().to(handlers::create_prosumer))),
+    )
+    .await;
+    let address = format!("0x{}", Uuid::new_v4().simple());
+    let request = test::TestRequest::post()
+        .uri("/prosumers")
+        .set_json(&serde_json::json!({ "address": address, "name": "New prosumer" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, request).await.status(), StatusCode::CREATED);
+
+    let balance = db.get_prosumer_balance(&address).await.unwrap();
+    assert_eq!((balance.grid_tokens, balance.watt_tokens), (0.0, 0.0));
+    let statement = db
+        .get_account_statement(&address, Utc::now() - Duration::hours(1), Utc::now() + Duration::hours(1))
+        .await
+        .unwrap();
+    assert!(statement.transfers.is_empty());
+}
+
+#[test]
+fn update_request_tells_omitted_fields_from_cleared_ones() {
+    let omitted: UpdateProsumerRequest = serde_json::from_str("{}").unwrap();
+    assert_eq!(omitted.changes(), (None, None, None));
+
+    let cleared: UpdateProsumerRequest =
+        serde_json::from_str(r#"{"name": null, "energy_generated": null}"#).unwrap();
+    assert_eq!(cleared.changes(), (Some(String::new()), Some(0.0), None));
+
+    let set: UpdateProsumerRequest =
+        serde_json::from_str(r#"{"name": "Rooftop", "energy_consumed": 5}"#).unwrap();
+    assert_eq!(set.changes(), (Some("Rooftop".to_string()), None, Some(5.0)));
+}
+
+async fn new_prosumer(db: &DatabaseService) -> Prosumer {
+    let now = Utc::now();
+    db.create_prosumer(Prosumer {
+        address: format!("0x{}", Uuid::new_v4().simple()),
+        name: "Tagged prosumer".to_string(),
+        energy_generated: 0.0,
+        energy_consumed: 0.0,
+        grid_tokens: 0.0,
+        watt_tokens: 0.0,
+        is_active: true,
+        created_at: now,
+        updated_at: now,
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn region_filter_scopes_listings_and_stats_to_tagged_prosumers() {
+    let Some(db) = test_db().await else { return };
+    let region = format!("region-{}", Uuid::new_v4().simple());
+    let tagged = new_prosumer(&db).await;
+    let untagged = new_prosumer(&db).await;
+    let tags = BTreeMap::from([("region".to_string(), region.clone()), ("feeder".to_string(), "f1".to_string())]);
+    db.set_prosumer_tags(&tagged.address, &tags).await.unwrap();
+    db.set_prosumer_tags(&untagged.address, &BTreeMap::from([("feeder".to_string(), "f1".to_string())])).await.unwrap();
+    assert_eq!(db.get_prosumer_tags(&tagged.address).await.unwrap(), tags);
+
+    let filters = TagFilter::from_query(None, Some(&region)).unwrap();
+    let listed = db.get_prosumers(1, 50, SortOrder::default(), &filters).await.unwrap();
+    let addresses: Vec<_> = listed.iter().map(|p| p.address.as_str()).collect();
+    assert_eq!(addresses, vec![tagged.address.as_str()]);
+
+    let stats = db.get_market_stats(&filters).await.unwrap();
+    assert_eq!((stats.total_prosumers, stats.total_orders, stats.total_trades), (1, 0, 0));
+}
+
+#[ntex::test]
+async fn fields_query_limits_the_response_to_the_requested_fields() {
+    let Some(db) = test_db().await else { return };
+    let address = prosumer(&db, 25.0).await;
+    let app = test::init_service(
+        App::new()
+            .state(Arc::new(db))
+            .service(web::resource("/prosumers/{address}").route(web::get().to(handlers::get_prosumer))),
+    )
+    .await;
+    let get = |query: &str| test::TestRequest::get().uri(&format!("/prosumers/{}?{}", address, query)).to_request();
+
+    let response = test::call_service(&app, get("fields=address,grid_tokens")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(body, serde_json::json!({ "address": address, "grid_tokens": 25.0 }));
+
+    // Fields added by include_counts can be selected too
+    let response = test::call_service(&app, get("include_counts=true&fields=orders_count")).await;
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(body, serde_json::json!({ "orders_count": 0 }));
+
+    let response = test::call_service(&app, get("fields=address,password")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[ntex::test]
+async fn counts_cover_the_prosumers_orders_and_trades_only_when_requested() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let trader = prosumer(&db, 100.0).await;
+    let counterparty = prosumer(&db, 100.0).await;
+    // Three orders of the trader's, one of them filled by the single trade
+    let buy = place(&db, &trader, "buy", 5.0, 0.2).await;
+    place(&db, &trader, "buy", 2.0, 0.1).await;
+    place(&db, &trader, "sell", 1.0, 0.5).await;
+    let sell = place(&db, &counterparty, "sell", 5.0, 0.2).await;
+    db.execute_trade(buy.id, sell.id, None).await.unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/prosumers/{address}").route(web::get().to(handlers::get_prosumer))),
+    )
+    .await;
+    let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+
+    let response = test::call_service(&app, get(format!("/prosumers/{}?include_counts=true", trader))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(body["address"], trader);
+    assert_eq!(body["orders_count"], 3);
+    assert_eq!(body["trades_count"], 1);
+
+    let response = test::call_service(&app, get(format!("/prosumers/{}?include_counts=true", counterparty))).await;
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!((body["orders_count"].as_i64(), body["trades_count"].as_i64()), (Some(1), Some(1)));
+
+    let response = test::call_service(&app, get(format!("/prosumers/{}", trader))).await;
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(body["address"], trader);
+    assert!(body.get("orders_count").is_none() && body.get("trades_count").is_none(), "{}", body);
+}
+
+#[tokio::test]
+async fn closing_cancels_open_orders_and_sweeps_the_balance() {
+    let Some(db) = test_db().await else { return };
+    let leaving = prosumer(&db, 50.0).await;
+    let destination = prosumer(&db, 5.0).await;
+    let buy = place(&db, &leaving, "buy", 10.0, 0.2).await;
+    let sell = place(&db, &leaving, "sell", 4.0, 0.3).await;
+    db.credit_tokens(&leaving, &[("watt_tokens", 7.0)]).await.unwrap();
+
+    let closure = db.close_prosumer(&leaving, &destination).await.unwrap();
+    let mut cancelled: Vec<Uuid> = closure.cancelled_orders.iter().map(|o| o.id).collect();
+    cancelled.sort();
+    let mut expected = vec![buy.id, sell.id];
+    expected.sort();
+    assert_eq!(cancelled, expected);
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "cancelled");
+
+    // The buy order's escrow is released before the sweep, so all 50 grid tokens move
+    let swept: Vec<(&str, f64)> = closure.sweeps.iter().map(|s| (s.token_type.as_str(), s.amount)).collect();
+    assert_eq!(swept, vec![("grid_tokens", 50.0), ("watt_tokens", 7.0)]);
+    let left = db.get_prosumer_balance(&leaving).await.unwrap();
+    assert_eq!((left.grid_tokens, left.reserved_grid_tokens, left.watt_tokens), (0.0, 0.0, 0.0));
+    let received = db.get_prosumer_balance(&destination).await.unwrap();
+    assert_eq!((received.grid_tokens, received.watt_tokens), (55.0, 7.0));
+    assert!(!db.get_prosumer(&leaving).await.unwrap().is_active);
+
+    let statement = db.get_account_statement(&leaving, Utc::now() - Duration::hours(1), Utc::now() + Duration::hours(1)).await.unwrap();
+    // The two sweeps, after the mints of its starting balance and of the credited watt tokens
+    let swept_out = statement.transfers.iter().filter(|t| t.from_address == leaving).count();
+    assert_eq!((statement.transfers.len(), swept_out), (4, 2));
+    assert!(matches!(db.close_prosumer(&leaving, &destination).await, Err(DatabaseError::Conflict(_))));
+}
+
+#[tokio::test]
+async fn prosumer_with_a_trade_awaiting_settlement_cannot_be_closed() {
+    let Some(db) = test_db().await else { return };
+    let db = db.with_market_config(MarketConfig {
+        settlement_delay_secs: 3_600,
+        ..MarketConfig::default()
+    });
+    let buyer = prosumer(&db, 10.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+    db.execute_trade(buy.id, sell.id, None).await.unwrap();
+
+    assert!(matches!(db.close_prosumer(&seller, &buyer).await, Err(DatabaseError::Conflict(_))));
+    assert!(db.get_prosumer(&seller).await.unwrap().is_active);
+}
+
+#[ntex::test]
+async fn only_the_owner_or_an_admin_can_close_a_prosumer() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let owner = user(&store, "trader");
+    let stranger = user(&store, "trader");
+    let admin = user(&store, "admin");
+    let destination = prosumer(&db, 0.0).await;
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(web::resource("/prosumers").route(web::post().to(handlers::create_prosumer)))
+            .service(web::resource("/prosumers/{address}/close").route(web::post().to(handlers::close_prosumer))),
+    )
+    .await;
+    let create = |auth: Option<String>| {
+        let mut request = test::TestRequest::post().uri("/prosumers").set_json(&serde_json::json!({
+            "address": format!("0x{}", Uuid::new_v4().simple()),
+            "name": "Owned prosumer"
+        }));
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+    let close = |address: &str, auth: Option<String>| {
+        let mut request = test::TestRequest::post()
+            .uri(&format!("/prosumers/{}/close", address))
+            .set_json(&serde_json::json!({ "destination_address": destination }));
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+
+    let response = test::call_service(&app, create(Some(bearer(&store, &owner)))).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let owned: Prosumer = serde_json::from_slice(&test::read_body(response).await).unwrap();
+
+    let response = test::call_service(&app, close(&owned.address, None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = test::call_service(&app, close(&owned.address, Some(bearer(&store, &stranger)))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(db.get_prosumer(&owned.address).await.unwrap().is_active);
+    let response = test::call_service(&app, close(&owned.address, Some(bearer(&store, &owner)))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Registered anonymously, so it has no owner: only an admin may close it
+    let response = test::call_service(&app, create(None)).await;
+    let unowned: Prosumer = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    let response = test::call_service(&app, close(&unowned.address, Some(bearer(&store, &owner)))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = test::call_service(&app, close(&unowned.address, Some(bearer(&store, &admin)))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+// Matching runs over the whole book, so this uses a database of its own
+#[ntex::test]
+async fn exposure_counts_the_unfilled_remainder_of_active_orders() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db);
+    let trader = prosumer(&db, 1_000.0).await;
+    let counterparty = prosumer(&db, 1_000.0).await;
+
+    // A buy of 10 filled by 4, and a sell of 3 filled by 1, both left active with the rest
+    place(&db, &trader, "buy", 10.0, 0.20).await;
+    place(&db, &counterparty, "sell", 4.0, 0.20).await;
+    place(&db, &trader, "sell", 3.0, 0.50).await;
+    place(&db, &counterparty, "buy", 1.0, 0.50).await;
+    assert_eq!(db.match_orders().await.unwrap().trades.len(), 2);
+    // And an open buy nothing crosses
+    place(&db, &trader, "buy", 2.0, 0.10).await;
+
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/prosumers/{address}/exposure").route(web::get().to(handlers::get_prosumer_exposure))),
+    )
+    .await;
+    let request = test::TestRequest::get().uri(&format!("/prosumers/{}/exposure", trader)).to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let exposure: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(exposure["active_buy_orders"], 2);
+    assert_eq!(exposure["active_sell_orders"], 1);
+    assert_eq!(exposure["buy_energy_committed"], 8.0);
+    assert_eq!(exposure["sell_energy_committed"], 2.0);
+    assert_eq!(exposure["net_position"], 6.0);
+
+    // The counterparty's orders were both filled completely
+    let exposure = db.get_prosumer_exposure(&counterparty).await.unwrap();
+    assert_eq!((exposure.active_buy_orders, exposure.active_sell_orders), (0, 0));
+    assert_eq!(exposure.net_position, 0.0);
+
+    let request = test::TestRequest::get().uri("/prosumers/0xunknown/exposure").to_request();
+    assert_eq!(test::call_service(&app, request).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[ntex::test]
+async fn open_order_summary_counts_only_open_orders_on_each_side() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let trader = prosumer(&db, 1_000.0).await;
+    let counterparty = prosumer(&db, 1_000.0).await;
+
+    // Two open buys, one of them part filled, and a filled buy priced above both
+    place(&db, &trader, "buy", 5.0, 0.20).await;
+    let partial = place(&db, &trader, "buy", 3.0, 0.25).await;
+    let sell = place(&db, &counterparty, "sell", 1.0, 0.25).await;
+    db.execute_trade(partial.id, sell.id, None).await.unwrap();
+    let filled = place(&db, &trader, "buy", 2.0, 0.30).await;
+    let sell = place(&db, &counterparty, "sell", 2.0, 0.30).await;
+    db.execute_trade(filled.id, sell.id, None).await.unwrap();
+    // Two open sells, and a cancelled sell priced below both
+    place(&db, &trader, "sell", 4.0, 0.40).await;
+    place(&db, &trader, "sell", 2.0, 0.35).await;
+    let cancelled = place(&db, &trader, "sell", 1.0, 0.32).await;
+    db.cancel_order(cancelled.id).await.unwrap();
+    let idle = prosumer(&db, 0.0).await;
+
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/prosumers/{address}/orders/summary").route(web::get().to(handlers::get_open_order_summary))),
+    )
+    .await;
+    let get = |address: &str| test::TestRequest::get().uri(&format!("/prosumers/{}/orders/summary", address)).to_request();
+
+    let response = test::call_service(&app, get(&trader)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let summary: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(summary["address"], trader);
+    assert_eq!(summary["open_buy_orders"], 2);
+    assert_eq!(summary["buy_energy_open"], 7.0);
+    assert_eq!(summary["best_bid"], 0.25);
+    assert_eq!(summary["open_sell_orders"], 2);
+    assert_eq!(summary["sell_energy_open"], 6.0);
+    assert_eq!(summary["best_ask"], 0.35);
+
+    let response = test::call_service(&app, get(&idle)).await;
+    let summary: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(summary["open_buy_orders"], 0);
+    assert_eq!(summary["open_sell_orders"], 0);
+    assert!(summary["best_bid"].is_null() && summary["best_ask"].is_null(), "{}", summary);
+
+    let response = test::call_service(&app, get("0xunknown")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+// The listing covers every prosumer, so this uses a database of its own
+#[ntex::test]
+async fn prosumer_stats_listing_matches_each_prosumers_own_stats() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db);
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let idle = prosumer(&db, 0.0).await;
+    for (energy_amount, price_per_unit) in [(5.0, 0.2), (4.0, 0.5)] {
+        let buy = place(&db, &buyer, "buy", energy_amount, price_per_unit).await;
+        let sell = place(&db, &seller, "sell", energy_amount, price_per_unit).await;
+        db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    }
+    place(&db, &buyer, "buy", 1.0, 0.1).await;
+
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/stats/prosumers").route(web::get().to(handlers::get_all_prosumer_stats))),
+    )
+    .await;
+    let get = |query: &str| test::TestRequest::get().uri(&format!("/stats/prosumers?{}", query)).to_request();
+
+    let response = test::call_service(&app, get("sort_by=orders_count")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    let items = page["items"].as_array().unwrap();
+    let ranked: Vec<&str> = items.iter().map(|item| item["address"].as_str().unwrap()).collect();
+    assert_eq!(ranked, vec![buyer.as_str(), seller.as_str(), idle.as_str()]);
+    for item in items {
+        let own = db.get_prosumer_stats(item["address"].as_str().unwrap()).await.unwrap();
+        assert_eq!(item, &serde_json::to_value(&own).unwrap());
+    }
+    assert_eq!((items[0]["orders_count"].as_i64(), items[0]["trades_count"].as_i64()), (Some(3), Some(2)));
+    assert_eq!(items[0]["total_volume"], 3.0);
+    assert_eq!(items[1]["total_energy_traded"], 9.0);
+    assert_eq!(items[2]["trades_count"], 0);
+
+    let response = test::call_service(&app, get("sort_by=orders_count&limit=1&page=2")).await;
+    let page: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(page["items"].as_array().unwrap().len(), 1);
+    assert_eq!(page["items"][0]["address"], seller);
+
+    let response = test::call_service(&app, get("sort_by=password")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+// HEAD is only stripped of its body on the wire, so this runs a server. The server's
+// workers connect on their own runtime; the schema is isolated so no other test's rows
+// change the listings between the GET and the HEAD.
+#[ntex::test]
+async fn head_on_listings_sends_the_get_headers_without_a_body() {
+    let Some(url) = isolated_test_url().await else { return };
+    let db = connect(&url).await;
+    let address = prosumer(&db, 100.0).await;
+    place(&db, &address, "sell", 5.0, 0.25).await;
+    place(&db, &address, "buy", 2.0, 0.20).await;
+    let northern = prosumer(&db, 0.0).await;
+    db.set_prosumer_tags(&northern, &[("region".to_string(), "north".to_string())].into()).await.unwrap();
+
+    let server = web::test::server(move || {
+        let url = url.clone();
+        App::new()
+            .state_factory(move || {
+                let url = url.clone();
+                async move { Ok::<_, ()>(Arc::new(connect(&url).await)) }
+            })
+            .wrap(Compress::new(CompressionConfig { enabled: true, min_size: 64 }))
+            .service(
+                web::resource("/prosumers")
+                    .route(web::get().to(handlers::get_all_prosumers))
+                    .route(web::head().to(handlers::head_all_prosumers)),
+            )
+            .service(
+                web::resource("/orders")
+                    .route(web::get().to(handlers::get_all_energy_orders))
+                    .route(web::head().to(handlers::head_all_energy_orders)),
+            )
+    });
+
+    // X-Total-Count counts what the filters match, not the whole table
+    for (path, total) in [
+        ("/prosumers", "2"),
+        ("/prosumers?region=north", "1"),
+        ("/prosumers?tag=region:north", "1"),
+        ("/orders?order_type=sell", "1"),
+        (&format!("/orders?prosumer_address={}&status=active", address), "2"),
+        ("/orders?limit=1", "2"),
+    ] {
+        for encoding in ["identity", "gzip"] {
+            let mut get = server.get(path).header(header::ACCEPT_ENCODING, encoding).send().await.unwrap();
+            assert_eq!(get.status(), StatusCode::OK);
+            let body = get.body().await.unwrap();
+            assert!(!body.is_empty());
+            assert_eq!(get.headers().contains_key(header::CONTENT_ENCODING), encoding == "gzip");
+
+            let mut head = server.head(path).header(header::ACCEPT_ENCODING, encoding).send().await.unwrap();
+            assert_eq!(head.status(), StatusCode::OK);
+            let length = |headers: &header::HeaderMap| headers.get(header::CONTENT_LENGTH).cloned();
+            assert_eq!(length(head.headers()), Some(body.len().to_string().parse().unwrap()), "{} {}", path, encoding);
+            assert_eq!(length(head.headers()), length(get.headers()), "{} {}", path, encoding);
+            assert_eq!(
+                head.headers().get(header::CONTENT_ENCODING),
+                get.headers().get(header::CONTENT_ENCODING),
+                "{} {}",
+                path,
+                encoding
+            );
+            assert_eq!(head.headers().get("X-Total-Count").unwrap(), total, "{}", path);
+            assert_eq!(get.headers().get("X-Total-Count"), head.headers().get("X-Total-Count"), "{}", path);
+            assert!(head.body().await.unwrap().is_empty());
+        }
+    }
+
+    server.stop().await;
+}
+
+// Listings in a schema of their own, so the page contents are known
+#[ntex::test]
+async fn listing_clamps_the_page_size_and_tolerates_out_of_range_pages() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db.with_pagination(PaginationConfig { default_page_size: 2, max_page_size: 3 }));
+    for _ in 0..4 {
+        prosumer(&db, 0.0).await;
+    }
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/prosumers").route(web::get().to(handlers::get_all_prosumers))),
+    )
+    .await;
+    let list = |query: &str| test::TestRequest::get().uri(&format!("/prosumers{}", query)).to_request();
+
+    for (query, page, limit, items) in [
+        ("", 1, 2, 2),
+        ("?limit=100", 1, 3, 3),
+        ("?page=2&limit=100", 2, 3, 1),
+        ("?page=0", 1, 2, 2),
+        ("?page=4294967295&limit=3", 4294967295u32, 3, 0),
+    ] {
+        let response = test::call_service(&app, list(query)).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", query);
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+        assert_eq!(body["page"], page, "{}", query);
+        assert_eq!(body["limit"], limit, "{}", query);
+        assert_eq!(body["items"].as_array().unwrap().len(), items, "{}", query);
+    }
+
+    // A page past the range of u32 is a bad query rather than a wrapped offset
+    let response = test::call_service(&app, list("?page=4294967296")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
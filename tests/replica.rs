@@ -0,0 +1,57 @@
+mod common;
+
+use common::{connect, isolated_test_url, place, prosumer};
+use energy_trading_api::database::DatabaseError;
+use sqlx::{Connection, PgConnection};
+
+// The replica is a schema of its own that the primary never replicates into, opened read-only
+// the way a hot standby would be. A write sent to it fails, and a read served by it sees only
+// what was put in that schema, so each query shows which pool it went through.
+#[tokio::test]
+async fn writes_go_to_the_primary_and_reads_through_the_replica() {
+    let Some(primary_url) = isolated_test_url().await else { return };
+    let Some(replica_schema_url) = isolated_test_url().await else { return };
+    connect(&replica_schema_url).await;
+    let replica_url = format!("{}&options[default_transaction_read_only]=on", replica_schema_url);
+
+    // The replica really refuses writes
+    let mut replica = PgConnection::connect(&replica_url).await.unwrap();
+    let refused = sqlx::query("INSERT INTO prosumers (address, name) VALUES ('0xrefused', 'Refused')")
+        .execute(&mut replica)
+        .await
+        .unwrap_err();
+    assert!(refused.to_string().contains("read-only"), "{}", refused);
+
+    let db = connect(&primary_url).await.with_replica(&replica_url).await.unwrap();
+    let buyer = prosumer(&db, 10.0).await;
+    let order = place(&db, &buyer, "buy", 5.0, 0.2).await;
+
+    let mut primary = PgConnection::connect(&primary_url).await.unwrap();
+    let (orders,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM orders WHERE id = $1 AND prosumer_address = $2")
+        .bind(order.id)
+        .bind(&buyer)
+        .fetch_one(&mut primary)
+        .await
+        .unwrap();
+    assert_eq!(orders, 1);
+
+    // Nothing has reached the replica yet, so a read there doesn't find the prosumer
+    assert!(matches!(db.get_prosumer(&buyer).await, Err(DatabaseError::NotFound(_))));
+    let mut replica_schema = PgConnection::connect(&replica_schema_url).await.unwrap();
+    sqlx::query("INSERT INTO prosumers (address, name) VALUES ($1, 'Replicated')")
+        .bind(&buyer)
+        .execute(&mut replica_schema)
+        .await
+        .unwrap();
+    assert_eq!(db.get_prosumer(&buyer).await.unwrap().name, "Replicated");
+
+    let health = db.get_health_details(&Default::default()).await;
+    assert!(health.replica.is_some_and(|replica| replica.reachable));
+}
+
+#[tokio::test]
+async fn replica_on_another_backend_is_rejected() {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else { return };
+    let result = connect(&url).await.with_replica("sqlite::memory:").await;
+    assert!(matches!(result, Err(DatabaseError::Validation(_))));
+}
@@ -0,0 +1,19 @@
+mod common;
+
+use common::isolated_test_db;
+use energy_trading_api::seed::{seed_demo_data, SeedSummary};
+
+#[tokio::test]
+async fn seeding_twice_writes_nothing_the_second_time() {
+    let Some(db) = isolated_test_db().await else { return };
+
+    let summary = seed_demo_data(&db).await.unwrap();
+    assert_eq!(summary, Some(SeedSummary { prosumers: 4, orders: 6, trades: 2 }));
+    let orders = db.get_orders(1, 100, None, None, None, Default::default()).await.unwrap();
+    assert_eq!(orders.len(), 6);
+
+    assert_eq!(seed_demo_data(&db).await.unwrap(), None);
+    let orders = db.get_orders(1, 100, None, None, None, Default::default()).await.unwrap();
+    assert_eq!(orders.len(), 6);
+    assert_eq!(db.get_trades(1, 100, None, None, Default::default()).await.unwrap().len(), 2);
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use chrono::{Duration, TimeZone, Utc};
+use common::{place, prosumer, test_db};
+use energy_trading_api::config::PnlMethod;
+use energy_trading_api::database::MINT_ADDRESS;
+
+#[tokio::test]
+async fn statement_lists_the_activity_inside_its_window() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let transfer_id = db.transfer_tokens(&buyer, &seller, 5.0, "grid_tokens").await.unwrap();
+    let buy = place(&db, &buyer, "buy", 2.0, 0.5).await;
+    let sell = place(&db, &seller, "sell", 2.0, 0.5).await;
+    let trade = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+
+    let now = Utc::now();
+    let statement = db
+        .get_account_statement(&buyer, now - Duration::hours(1), now + Duration::minutes(1))
+        .await
+        .unwrap();
+    // The transfer, after the mint of the buyer's starting balance
+    let transfers: Vec<_> = statement.transfers.iter().map(|t| (t.from_address.as_str(), t.id.to_string())).collect();
+    assert_eq!(transfers.len(), 2);
+    assert_eq!(transfers[0].0, MINT_ADDRESS);
+    assert_eq!(transfers[1], (buyer.as_str(), transfer_id.clone()));
+    assert_eq!(statement.orders.iter().map(|o| o.id).collect::<Vec<_>>(), vec![buy.id]);
+    assert_eq!(statement.trades.iter().map(|t| t.id).collect::<Vec<_>>(), vec![trade.id]);
+
+    let csv = statement.to_csv();
+    assert!(csv.starts_with("record_type,id,timestamp,"));
+    assert!(csv.contains(&transfer_id));
+    assert!(csv.contains(&trade.id.to_string()));
+
+    let old = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+    let empty = db.get_account_statement(&buyer, old - Duration::days(30), old).await.unwrap();
+    assert!(empty.transfers.is_empty() && empty.orders.is_empty() && empty.trades.is_empty());
+}
+
+#[tokio::test]
+async fn buy_then_sell_realizes_the_price_difference() {
+    let Some(db) = test_db().await else { return };
+    let trader = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buyer = prosumer(&db, 100.0).await;
+
+    // Bought 10 kWh at 0.2, then sold 6 kWh of it at 0.5
+    let buy = place(&db, &trader, "buy", 10.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 10.0, 0.2).await;
+    db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    let resale = place(&db, &trader, "sell", 6.0, 0.5).await;
+    let purchase = place(&db, &buyer, "buy", 6.0, 0.5).await;
+    db.execute_trade(purchase.id, resale.id, None).await.unwrap();
+
+    let now = Utc::now();
+    let pnl = db
+        .get_prosumer_pnl(&trader, now - Duration::hours(1), now + Duration::minutes(1), PnlMethod::Fifo)
+        .await
+        .unwrap();
+    assert_eq!(pnl.periods.len(), 1);
+    assert!((pnl.energy_sold - 6.0).abs() < 1e-9);
+    assert!((pnl.proceeds - 3.0).abs() < 1e-9);
+    assert!((pnl.cost_basis - 1.2).abs() < 1e-9);
+    assert!((pnl.realized_pnl - 1.8).abs() < 1e-9);
+
+    // The purchase alone realizes nothing
+    let buyer_pnl = db
+        .get_prosumer_pnl(&buyer, now - Duration::hours(1), now + Duration::minutes(1), PnlMethod::AverageCost)
+        .await
+        .unwrap();
+    assert!(buyer_pnl.periods.is_empty());
+    assert_eq!(buyer_pnl.realized_pnl, 0.0);
+}
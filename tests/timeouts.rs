@@ -0,0 +1,27 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{connect, isolated_test_url, prosumer};
+use energy_trading_api::database::{DatabaseError, DatabaseService};
+use sqlx::{Connection, Executor, PgConnection};
+
+#[tokio::test]
+async fn query_stuck_past_the_statement_timeout_fails_instead_of_hanging() {
+    let Some(url) = isolated_test_url().await else { return };
+    let address = prosumer(&connect(&url).await, 10.0).await;
+    let db = DatabaseService::connect(&url, 200).await.unwrap();
+
+    // Another session holds the table, so the read waits until its timeout cancels it
+    let mut blocker = PgConnection::connect(&url).await.unwrap();
+    blocker.execute("BEGIN").await.unwrap();
+    blocker.execute("LOCK TABLE prosumers IN ACCESS EXCLUSIVE MODE").await.unwrap();
+
+    let result = tokio::time::timeout(Duration::from_secs(10), db.get_prosumer(&address))
+        .await
+        .expect("the statement timeout should end the query");
+    assert!(matches!(result, Err(DatabaseError::Timeout(_))), "expected Timeout, got {:?}", result);
+
+    blocker.execute("ROLLBACK").await.unwrap();
+    assert_eq!(db.get_prosumer(&address).await.unwrap().grid_tokens, 10.0);
+}
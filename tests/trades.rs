@@ -0,0 +1,642 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use common::{auth_store, bearer, isolated_test_db, owned_prosumer, place, prosumer, test_db, user};
+use energy_trading_api::config::{AutoMatchConfig, FeeSchedule, FeeTier, MarketConfig, MatchBatchConfig};
+use energy_trading_api::clock::MockClock;
+use energy_trading_api::database::DatabaseError;
+use energy_trading_api::{auth_handlers, handlers, matching};
+use ntex::http::{header, StatusCode};
+use ntex::time::{sleep, Millis};
+use ntex::web::{self, test, App};
+
+#[tokio::test]
+async fn concurrent_fills_never_overfill_an_order() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 1_000.0).await;
+    let seller_a = prosumer(&db, 0.0).await;
+    let seller_b = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 10.0, 0.2).await;
+    let sell_a = place(&db, &seller_a, "sell", 10.0, 0.2).await;
+    let sell_b = place(&db, &seller_b, "sell", 10.0, 0.2).await;
+
+    // Each fill alone would fill the whole buy order; only one of them may land
+    let (a, b) = tokio::join!(
+        db.execute_trade(buy.id, sell_a.id, None),
+        db.execute_trade(buy.id, sell_b.id, None),
+    );
+    assert_eq!(a.is_ok() as u8 + b.is_ok() as u8, 1, "exactly one fill succeeds: {:?} / {:?}", a, b);
+
+    let buy = db.get_order(buy.id).await.unwrap();
+    assert_eq!(buy.filled_amount, 10.0);
+    assert_eq!(buy.status, "completed");
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!(balance.grid_tokens, 998.0);
+    assert_eq!(balance.reserved_grid_tokens, 0.0);
+}
+
+#[tokio::test]
+async fn fill_is_rejected_once_the_order_is_cancelled() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+
+    db.cancel_order(buy.id).await.unwrap();
+    assert!(matches!(
+        db.execute_trade(buy.id, sell.id, None).await,
+        Err(DatabaseError::Conflict(_))
+    ));
+
+    let buy = db.get_order(buy.id).await.unwrap();
+    assert_eq!(buy.status, "cancelled");
+    assert_eq!(buy.filled_amount, 0.0);
+    assert_eq!(db.get_prosumer_balance(&buyer).await.unwrap().grid_tokens, 100.0);
+}
+
+#[ntex::test]
+async fn order_trades_are_listed_and_embedded_on_request_and_404_for_an_unknown_order() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+    let untraded = place(&db, &buyer, "buy", 5.0, 0.1).await;
+    let trade = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/orders/{order_id}").route(web::get().to(handlers::get_energy_order)))
+            .service(web::resource("/orders/{order_id}/trades").route(web::get().to(handlers::get_order_trades))),
+    )
+    .await;
+    let get = |id: uuid::Uuid| test::TestRequest::get().uri(&format!("/orders/{}/trades", id)).to_request();
+    let get_order = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+
+    let response = test::call_service(&app, get(sell.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let trades: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(trades.as_array().unwrap().len(), 1);
+    assert_eq!(trades[0]["id"], trade.id.to_string());
+
+    let response = test::call_service(&app, get(untraded.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(test::read_body(response).await, "[]");
+
+    let response = test::call_service(&app, get(uuid::Uuid::new_v4())).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // The order itself only embeds its trades when asked to
+    let response = test::call_service(&app, get_order(format!("/orders/{}?include_trades=true", sell.id))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let order: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(order["id"], sell.id.to_string());
+    assert_eq!(order["trades"].as_array().unwrap().len(), 1);
+    assert_eq!(order["trades"][0]["id"], trade.id.to_string());
+
+    let response = test::call_service(&app, get_order(format!("/orders/{}?include_trades=true", untraded.id))).await;
+    let order: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(order["trades"], serde_json::json!([]));
+
+    let response = test::call_service(&app, get_order(format!("/orders/{}", sell.id))).await;
+    let order: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(order["id"], sell.id.to_string());
+    assert!(order.get("trades").is_none(), "{}", order);
+}
+
+#[ntex::test]
+async fn prosumer_trades_cover_both_sides_and_only_that_prosumer() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let trader = prosumer(&db, 100.0).await;
+    let supplier = prosumer(&db, 100.0).await;
+    let customer = prosumer(&db, 100.0).await;
+
+    let buy = place(&db, &trader, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &supplier, "sell", 5.0, 0.2).await;
+    let bought = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    let sell = place(&db, &trader, "sell", 3.0, 0.3).await;
+    let buy = place(&db, &customer, "buy", 3.0, 0.3).await;
+    let sold = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    // A trade between the other two doesn't involve the trader
+    let buy = place(&db, &customer, "buy", 2.0, 0.2).await;
+    let sell = place(&db, &supplier, "sell", 2.0, 0.2).await;
+    let unrelated = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/prosumers/{address}/trades").route(web::get().to(handlers::get_prosumer_trades))),
+    )
+    .await;
+    let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+
+    let response = test::call_service(&app, get(format!("/prosumers/{}/trades", trader))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    let mut roles: Vec<(String, String)> = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|trade| (trade["id"].as_str().unwrap().to_string(), trade["role"].as_str().unwrap().to_string()))
+        .collect();
+    roles.sort();
+    let mut expected = vec![(bought.id.to_string(), "buyer".to_string()), (sold.id.to_string(), "seller".to_string())];
+    expected.sort();
+    assert_eq!(roles, expected);
+    assert!(!roles.iter().any(|(id, _)| *id == unrelated.id.to_string()));
+
+    // Paged one at a time, the two trades come back once each
+    let mut paged = Vec::new();
+    for page in 1..=3 {
+        let response = test::call_service(&app, get(format!("/prosumers/{}/trades?page={}&limit=1", trader, page))).await;
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+        paged.extend(body["items"].as_array().unwrap().iter().map(|trade| trade["id"].as_str().unwrap().to_string()));
+    }
+    paged.sort();
+    assert_eq!(paged, expected.into_iter().map(|(id, _)| id).collect::<Vec<_>>());
+
+    let response = test::call_service(&app, get("/prosumers/0xunknown/trades".to_string())).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn fills_sum_to_the_filled_amount() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 10.0, 0.2).await;
+    for energy_amount in [3.0, 4.0] {
+        let sell = place(&db, &seller, "sell", energy_amount, 0.2).await;
+        db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    }
+
+    let fills = db.get_order_fills(buy.id).await.unwrap();
+    assert_eq!(fills.fills.len(), 2);
+    assert_eq!(fills.fills.iter().map(|t| t.energy_amount).sum::<f64>(), fills.filled_amount);
+    assert_eq!(fills.filled_amount, 7.0);
+    assert_eq!(fills.remaining_amount, 3.0);
+}
+
+#[tokio::test]
+async fn executed_trade_pays_the_fee_of_its_tier() {
+    let Some(db) = test_db().await else { return };
+    let fee_schedule = FeeSchedule::new(vec![
+        FeeTier { min_volume: 0.0, max_volume: Some(2.0), rate: 0.05 },
+        FeeTier { min_volume: 2.0, max_volume: None, rate: 0.01 },
+    ])
+    .unwrap();
+    let db = db.with_market_config(MarketConfig { fee_schedule, ..MarketConfig::default() });
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+
+    // Exactly on the boundary: the upper tier applies
+    let buy = place(&db, &buyer, "buy", 10.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 10.0, 0.2).await;
+    let trade = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    assert_eq!(trade.total_price, 2.0);
+    assert_eq!(trade.grid_fee, 0.02);
+
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+    let trade = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    assert_eq!(trade.grid_fee, 0.05);
+}
+
+#[tokio::test]
+async fn market_stats_report_the_seller_payout_net_of_fees() {
+    // Market stats cover every trade in the database
+    let Some(db) = isolated_test_db().await else { return };
+    let db = db
+        .with_market_config(MarketConfig { fee_schedule: FeeSchedule::flat(0.1), ..MarketConfig::default() })
+        .with_stats_cache_secs(0);
+    let stats = db.get_market_stats(&[]).await.unwrap();
+    assert_eq!((stats.gross_volume, stats.net_volume, stats.total_fees_collected), (0.0, 0.0, 0.0));
+
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    for (energy_amount, price_per_unit) in [(10.0, 0.2), (5.0, 0.2)] {
+        let buy = place(&db, &buyer, "buy", energy_amount, price_per_unit).await;
+        let sell = place(&db, &seller, "sell", energy_amount, price_per_unit).await;
+        db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    }
+
+    let stats = db.get_market_stats(&[]).await.unwrap();
+    assert!((stats.gross_volume - 3.0).abs() < 1e-9, "{:?}", stats);
+    assert_eq!(stats.total_volume, stats.gross_volume);
+    assert!((stats.total_fees_collected - 0.3).abs() < 1e-9, "{:?}", stats);
+    // The seller's side of the volume is the gross less every trade's fee
+    assert!((stats.net_volume - 2.7).abs() < 1e-9, "{:?}", stats);
+}
+
+#[tokio::test]
+async fn cancelled_order_is_reactivated_behind_newer_orders() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 10.0).await;
+    let buy = place(&db, &buyer, "buy", 10.0, 0.2).await;
+    db.cancel_order(buy.id).await.unwrap();
+
+    let reactivated = db.reactivate_order(buy.id).await.unwrap();
+    assert_eq!(reactivated.id, buy.id);
+    assert_eq!(reactivated.status, "active");
+    assert!(reactivated.created_at >= buy.created_at);
+    // Its escrow is taken again
+    assert_eq!(db.get_prosumer_balance(&buyer).await.unwrap().reserved_grid_tokens, 2.0);
+}
+
+#[tokio::test]
+async fn completed_order_cannot_be_reactivated() {
+    let Some(db) = test_db().await else { return };
+    let buyer = prosumer(&db, 10.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+    db.execute_trade(buy.id, sell.id, None).await.unwrap();
+
+    assert!(db.reactivate_order(buy.id).await.is_err());
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "completed");
+}
+
+#[ntex::test]
+async fn auto_match_fills_crossing_orders_without_a_manual_run() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db);
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+
+    ntex::rt::spawn(matching::run_auto_match(
+        db.clone(),
+        AutoMatchConfig {
+            enabled: true,
+            interval_ms: 10,
+            ..AutoMatchConfig::default()
+        },
+    ));
+    for _ in 0..200 {
+        if db.get_order(buy.id).await.unwrap().status == "completed" {
+            break;
+        }
+        sleep(Millis(10)).await;
+    }
+    assert_eq!(db.get_order(buy.id).await.unwrap().status, "completed");
+    assert_eq!(db.get_order(sell.id).await.unwrap().status, "completed");
+}
+
+#[tokio::test]
+async fn fill_past_the_daily_energy_limit_is_rejected() {
+    let Some(db) = test_db().await else { return };
+    let db = db.with_market_config(MarketConfig {
+        daily_energy_limit: 10.0,
+        ..MarketConfig::default()
+    });
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 15.0, 0.2).await;
+    let first = place(&db, &seller, "sell", 6.0, 0.2).await;
+    let second = place(&db, &seller, "sell", 6.0, 0.2).await;
+
+    db.execute_trade(buy.id, first.id, None).await.unwrap();
+    // 6 + 6 kWh would take both parties past their 10 kWh for the day
+    assert!(matches!(
+        db.execute_trade(buy.id, second.id, None).await,
+        Err(DatabaseError::LimitExceeded(_))
+    ));
+    assert_eq!(db.get_order(buy.id).await.unwrap().filled_amount, 6.0);
+    assert_eq!(db.get_order(second.id).await.unwrap().filled_amount, 0.0);
+}
+
+#[tokio::test]
+async fn one_match_run_works_through_more_than_a_batch() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = db.with_match_batch(MatchBatchConfig {
+        batch_size: 10,
+        ..MatchBatchConfig::default()
+    });
+    let buyer = prosumer(&db, 1_000.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    for _ in 0..25 {
+        place(&db, &buyer, "buy", 1.0, 0.2).await;
+        place(&db, &seller, "sell", 1.0, 0.2).await;
+    }
+
+    let result = db.match_orders().await.unwrap();
+    assert_eq!(result.trades.len(), 25);
+    assert!(db.match_orders().await.unwrap().trades.is_empty());
+}
+
+#[tokio::test]
+async fn delayed_trade_settles_only_once_the_delay_has_passed() {
+    // Settlement sweeps every pending trade in the database
+    let Some(db) = isolated_test_db().await else { return };
+    let db = db.with_market_config(MarketConfig {
+        settlement_delay_secs: 3_600,
+        ..MarketConfig::default()
+    });
+    let buyer = prosumer(&db, 10.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 10.0, 0.25).await;
+    let sell = place(&db, &seller, "sell", 10.0, 0.2).await;
+
+    let trade = db.execute_trade(buy.id, sell.id, Some(0.2)).await.unwrap();
+    assert_eq!(trade.status, "pending");
+    // The price improvement is refunded now; the 2.0 payment stays reserved
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!((balance.grid_tokens, balance.reserved_grid_tokens), (8.0, 2.0));
+    assert_eq!(db.get_prosumer_balance(&seller).await.unwrap().grid_tokens, 0.0);
+
+    assert!(db.settle_due_trades(Utc::now(), 100).await.unwrap().is_empty());
+    assert_eq!(db.get_trade(trade.id).await.unwrap().status, "pending");
+
+    let settled = db.settle_due_trades(Utc::now() + Duration::hours(2), 100).await.unwrap();
+    assert_eq!(settled.iter().map(|t| t.id).collect::<Vec<_>>(), vec![trade.id]);
+    assert_eq!(db.get_trade(trade.id).await.unwrap().status, "completed");
+    let balance = db.get_prosumer_balance(&buyer).await.unwrap();
+    assert_eq!((balance.grid_tokens, balance.reserved_grid_tokens), (8.0, 0.0));
+    assert_eq!(db.get_prosumer_balance(&seller).await.unwrap().grid_tokens, 2.0);
+}
+
+#[tokio::test]
+async fn disputed_trade_waits_for_an_admin_to_settle_it() {
+    let Some(db) = test_db().await else { return };
+    let db = db.with_market_config(MarketConfig {
+        settlement_delay_secs: 3_600,
+        ..MarketConfig::default()
+    });
+    let buyer = prosumer(&db, 10.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 10.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 10.0, 0.2).await;
+    let trade = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+
+    let outsider = prosumer(&db, 0.0).await;
+    assert!(matches!(db.dispute_trade(trade.id, &outsider, Utc::now()).await, Err(DatabaseError::Validation(_))));
+    assert!(matches!(
+        db.dispute_trade(trade.id, &buyer, Utc::now() + Duration::hours(2)).await,
+        Err(DatabaseError::Conflict(_))
+    ));
+    assert_eq!(db.dispute_trade(trade.id, &buyer, Utc::now()).await.unwrap().status, "disputed");
+    assert!(matches!(db.dispute_trade(trade.id, &seller, Utc::now()).await, Err(DatabaseError::Conflict(_))));
+
+    let settled = db.settle_disputed_trade(trade.id).await.unwrap();
+    assert_eq!(settled.status, "completed");
+    assert_eq!(db.get_prosumer_balance(&seller).await.unwrap().grid_tokens, 2.0);
+    assert_eq!(db.get_prosumer_balance(&buyer).await.unwrap().reserved_grid_tokens, 0.0);
+}
+
+#[ntex::test]
+async fn only_an_owner_of_a_party_can_dispute_a_trade() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db.with_market_config(MarketConfig {
+        settlement_delay_secs: 3_600,
+        ..MarketConfig::default()
+    }));
+    let store = auth_store();
+    let buyer_owner = user(&store, "trader");
+    let stranger = user(&store, "trader");
+    let buyer = owned_prosumer(&db, 10.0, Some(&buyer_owner.id)).await;
+    let seller = prosumer(&db, 0.0).await;
+    // Owning some prosumer is not enough; it has to be one of the trade's parties
+    owned_prosumer(&db, 0.0, Some(&stranger.id)).await;
+    let buy = place(&db, &buyer, "buy", 10.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 10.0, 0.2).await;
+    let trade = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(web::resource("/trades/{trade_id}/dispute").route(web::post().to(handlers::dispute_trade))),
+    )
+    .await;
+    let dispute = |auth: Option<String>| {
+        let mut request = test::TestRequest::post().uri(&format!("/trades/{}/dispute", trade.id));
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+
+    let response = test::call_service(&app, dispute(None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = test::call_service(&app, dispute(Some(bearer(&store, &stranger)))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(db.get_trade(trade.id).await.unwrap().status, "pending");
+
+    let response = test::call_service(&app, dispute(Some(bearer(&store, &buyer_owner)))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(db.get_trade(trade.id).await.unwrap().status, "disputed");
+}
+
+#[tokio::test]
+async fn market_stats_are_cached_until_the_window_passes_or_a_trade_executes() {
+    // Counts every prosumer and trade, so it needs its own schema
+    let Some(db) = isolated_test_db().await else { return };
+    let clock = Arc::new(MockClock::new(Utc::now()));
+    let db = db.with_clock(clock.clone()).with_stats_cache_secs(30);
+    let buyer = prosumer(&db, 10.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    assert_eq!(db.get_market_stats(&[]).await.unwrap().total_prosumers, 2);
+
+    // A new prosumer doesn't show until the cached figures are 30 seconds old
+    prosumer(&db, 0.0).await;
+    clock.advance(Duration::seconds(29));
+    assert_eq!(db.get_market_stats(&[]).await.unwrap().total_prosumers, 2);
+    clock.advance(Duration::seconds(1));
+    assert_eq!(db.get_market_stats(&[]).await.unwrap().total_prosumers, 3);
+
+    // An executed trade is reflected straight away
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+    assert_eq!(db.get_market_stats(&[]).await.unwrap().total_trades, 0);
+    db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    let stats = db.get_market_stats(&[]).await.unwrap();
+    assert_eq!((stats.total_trades, stats.total_energy_traded), (1, 5.0));
+}
+
+#[tokio::test]
+async fn timeseries_aggregates_completed_trades_per_bucket() {
+    // Every completed trade in the window is counted, so it needs its own schema
+    let Some(db) = isolated_test_db().await else { return };
+    let start = Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap();
+    let clock = Arc::new(MockClock::new(start));
+    let db = db.with_clock(clock.clone());
+    let buyer = prosumer(&db, 1_000.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let trade_at = |minutes: i64, energy_amount: f64, price: f64| {
+        let (db, clock, buyer, seller) = (&db, &clock, &buyer, &seller);
+        async move {
+            clock.set(start + Duration::minutes(minutes));
+            let buy = place(db, buyer, "buy", energy_amount, price).await;
+            let sell = place(db, seller, "sell", energy_amount, price).await;
+            db.execute_trade(buy.id, sell.id, Some(price)).await.unwrap();
+        }
+    };
+
+    // Before the window, in the first hour, on the 02:00 boundary and at the (exclusive) end
+    trade_at(20, 9.0, 0.9).await;
+    trade_at(40, 5.0, 0.2).await;
+    trade_at(50, 2.0, 0.3).await;
+    trade_at(59, 1.0, 0.25).await;
+    trade_at(120, 3.0, 0.4).await;
+    trade_at(240, 7.0, 0.7).await;
+
+    let points = db
+        .get_market_timeseries(3600, start + Duration::minutes(30), start + Duration::hours(4))
+        .await
+        .unwrap();
+    let starts: Vec<_> = points.iter().map(|p| p.bucket_start).collect();
+    assert_eq!(starts, (0..4).map(|h| start + Duration::hours(h)).collect::<Vec<_>>());
+
+    let first = &points[0];
+    assert_eq!(first.trade_count, 3);
+    assert!((first.energy_traded - 8.0).abs() < 1e-9);
+    assert!((first.volume - 1.85).abs() < 1e-9, "{}", first.volume);
+    assert!((first.average_price - 0.25).abs() < 1e-9);
+    assert_eq!((first.open, first.high, first.low, first.close), (0.2, 0.3, 0.2, 0.25));
+
+    // The quiet hour and the last hour are present with zeros
+    for quiet in [&points[1], &points[3]] {
+        assert_eq!(quiet.trade_count, 0);
+        assert_eq!((quiet.energy_traded, quiet.volume, quiet.open, quiet.close), (0.0, 0.0, 0.0, 0.0));
+    }
+
+    let boundary = &points[2];
+    assert_eq!(boundary.trade_count, 1);
+    assert!((boundary.volume - 1.2).abs() < 1e-9);
+    assert_eq!((boundary.open, boundary.high, boundary.low, boundary.close), (0.4, 0.4, 0.4, 0.4));
+}
+
+#[tokio::test]
+async fn timeseries_rejects_out_of_range_intervals_and_spans() {
+    let Some(db) = test_db().await else { return };
+    let app = test::init_service(
+        App::new()
+            .state(Arc::new(db))
+            .service(web::resource("/stats/timeseries").route(web::get().to(handlers::get_market_timeseries))),
+    )
+    .await;
+
+    for query in [
+        "interval_secs=0",
+        "interval_secs=-60",
+        // Would overflow the bucket arithmetic if it were accepted
+        "interval_secs=9223372036854775807",
+        // 100 days of one-second buckets
+        "interval_secs=1&from=2030-01-01T00:00:00Z&to=2030-04-11T00:00:00Z",
+        "from=2030-01-02T00:00:00Z&to=2030-01-01T00:00:00Z",
+    ] {
+        let request = test::TestRequest::get().uri(&format!("/stats/timeseries?{}", query)).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+
+    let request = test::TestRequest::get().uri("/stats/timeseries?interval_secs=900").to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let points: Vec<serde_json::Value> = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert!(points.len() == 96 || points.len() == 97, "{}", points.len());
+}
+
+#[ntex::test]
+async fn a_self_trade_shows_up_in_the_admin_match_diagnostics() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let trader = user(&store, "trader");
+    let admin = user(&store, "admin");
+    // One prosumer on both sides of a crossing pair
+    let address = prosumer(&db, 10.0).await;
+    let buy = place(&db, &address, "buy", 5.0, 0.25).await;
+    let sell = place(&db, &address, "sell", 5.0, 0.2).await;
+    assert!(db.match_orders().await.unwrap().trades.is_empty());
+
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(
+                web::resource("/admin/match-diagnostics").route(web::get().to(auth_handlers::get_match_diagnostics)),
+            ),
+    )
+    .await;
+    let diagnostics = |auth: String| {
+        test::TestRequest::get()
+            .uri("/admin/match-diagnostics")
+            .header(header::AUTHORIZATION, auth)
+            .to_request()
+    };
+
+    let response = test::call_service(&app, diagnostics(bearer(&store, &trader))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = test::call_service(&app, diagnostics(bearer(&store, &admin))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    let skipped = body["skipped"].as_array().unwrap();
+    assert_eq!(skipped.len(), 1, "{}", body);
+    assert_eq!(skipped[0]["reason"], "self_trade");
+    assert_eq!(skipped[0]["buy_order_id"], buy.id.to_string());
+    assert_eq!(skipped[0]["sell_order_id"], sell.id.to_string());
+}
+
+// The summary counts across the whole market, so this uses a database of its own
+#[ntex::test]
+async fn admin_summary_counts_active_orders_and_pending_trades() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db.with_market_config(MarketConfig {
+        settlement_delay_secs: 3_600,
+        ..MarketConfig::default()
+    }));
+    let store = auth_store();
+    let trader = user(&store, "trader");
+    let admin = user(&store, "admin");
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(web::resource("/admin/summary").route(web::get().to(auth_handlers::get_system_summary))),
+    )
+    .await;
+    let summary = |auth: String| {
+        let app = &app;
+        async move {
+            let request = test::TestRequest::get().uri("/admin/summary").header(header::AUTHORIZATION, auth).to_request();
+            let response = test::call_service(app, request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_slice::<serde_json::Value>(&test::read_body(response).await).unwrap()
+        }
+    };
+
+    let request = test::TestRequest::get()
+        .uri("/admin/summary")
+        .header(header::AUTHORIZATION, bearer(&store, &trader))
+        .to_request();
+    assert_eq!(test::call_service(&app, request).await.status(), StatusCode::FORBIDDEN);
+
+    let empty = summary(bearer(&store, &admin)).await;
+    assert_eq!((empty["active_orders"].as_i64(), empty["pending_trades"].as_i64()), (Some(0), Some(0)));
+    assert!(empty["last_match_at"].is_null() && empty["last_trade_at"].is_null(), "{}", empty);
+
+    // One trade awaiting settlement, and two orders nothing crosses
+    let buyer = prosumer(&db, 100.0).await;
+    let seller = prosumer(&db, 0.0).await;
+    let buy = place(&db, &buyer, "buy", 5.0, 0.2).await;
+    let sell = place(&db, &seller, "sell", 5.0, 0.2).await;
+    let trade = db.execute_trade(buy.id, sell.id, None).await.unwrap();
+    place(&db, &buyer, "buy", 1.0, 0.1).await;
+    place(&db, &seller, "sell", 1.0, 0.9).await;
+    assert!(db.match_orders().await.unwrap().trades.is_empty());
+
+    let summary = summary(bearer(&store, &admin)).await;
+    assert_eq!(summary["active_orders"], 2);
+    assert_eq!(summary["pending_trades"], 1);
+    let last_trade_at: DateTime<Utc> = serde_json::from_value(summary["last_trade_at"].clone()).unwrap();
+    assert_eq!(last_trade_at.timestamp_millis(), trade.executed_at.timestamp_millis());
+    assert!(summary["last_match_at"].is_string(), "{}", summary);
+    assert!(summary["db_latency_ms"].as_f64().is_some_and(|ms| ms >= 0.0));
+}
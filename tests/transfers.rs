@@ -0,0 +1,356 @@
+mod common;
+
+use common::{auth_store, bearer, prosumer, test_db, user};
+use chrono::{Duration, Utc};
+use energy_trading_api::config::FaucetConfig;
+use energy_trading_api::database::{DatabaseError, Prosumer, TokenType, MAX_BULK_BALANCE_ADDRESSES, MINT_ADDRESS};
+use energy_trading_api::faucet::Faucet;
+use energy_trading_api::{auth_handlers, handlers};
+use ntex::http::{header, StatusCode};
+use ntex::web::{self, test, App};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn non_finite_and_negative_amounts_are_rejected() {
+    let Some(db) = test_db().await else { return };
+    let sender = prosumer(&db, 10.0).await;
+    let recipient = prosumer(&db, 0.0).await;
+
+    for amount in [f64::NAN, f64::INFINITY, -1.0, 0.0] {
+        assert!(
+            matches!(
+                db.transfer_tokens(&sender, &recipient, amount, "grid_tokens").await,
+                Err(DatabaseError::Validation(_))
+            ),
+            "{}",
+            amount
+        );
+    }
+    assert_eq!(db.get_prosumer_balance(&sender).await.unwrap().grid_tokens, 10.0);
+    assert_eq!(db.get_prosumer_balance(&recipient).await.unwrap().grid_tokens, 0.0);
+
+    db.transfer_tokens(&sender, &recipient, 4.0, "grid_tokens").await.unwrap();
+    assert_eq!(db.get_prosumer_balance(&recipient).await.unwrap().grid_tokens, 4.0);
+}
+
+#[tokio::test]
+async fn transfer_beyond_the_balance_reports_the_shortfall() {
+    let Some(db) = test_db().await else { return };
+    let sender = prosumer(&db, 3.0).await;
+    let recipient = prosumer(&db, 0.0).await;
+
+    match db.transfer_tokens(&sender, &recipient, 5.0, "grid_tokens").await {
+        Err(DatabaseError::InsufficientBalance { token_type, required, available }) => {
+            assert_eq!((token_type.as_str(), required, available), ("grid_tokens", 5.0, 3.0));
+        }
+        other => panic!("expected InsufficientBalance, got {:?}", other),
+    }
+}
+
+#[ntex::test]
+async fn admins_register_token_types_that_can_then_be_held_and_transferred() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let trader = user(&store, "trader");
+    let admin = user(&store, "admin");
+    let app = test::init_service(
+        App::new().state(db.clone()).state(store.clone()).service(
+            web::resource("/token-types")
+                .route(web::get().to(handlers::get_token_types))
+                .route(web::post().to(auth_handlers::create_token_type)),
+        ),
+    )
+    .await;
+    let name = format!("carbon_{}", Uuid::new_v4().simple());
+    let create = |auth: Option<String>| {
+        let mut request = test::TestRequest::post()
+            .uri("/token-types")
+            .set_json(&json!({ "name": name, "description": "Carbon offsets" }));
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+
+    let response = test::call_service(&app, create(None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = test::call_service(&app, create(Some(bearer(&store, &trader)))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = test::call_service(&app, create(Some(bearer(&store, &admin)))).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Listing stays public
+    let response = test::call_service(&app, test::TestRequest::get().uri("/token-types").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed: Vec<TokenType> = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert!(listed.iter().any(|token_type| token_type.name == name));
+
+    let sender = prosumer(&db, 0.0).await;
+    let recipient = prosumer(&db, 0.0).await;
+    db.credit_tokens(&sender, &[(name.as_str(), 10.0)]).await.unwrap();
+    db.transfer_tokens(&sender, &recipient, 4.0, &name).await.unwrap();
+
+    let sender_balance = db.get_prosumer_balance(&sender).await.unwrap();
+    let recipient_balance = db.get_prosumer_balance(&recipient).await.unwrap();
+    assert_eq!(sender_balance.tokens.get(&name), Some(&6.0));
+    assert_eq!(recipient_balance.tokens.get(&name), Some(&4.0));
+}
+
+#[ntex::test]
+async fn authenticated_callers_get_a_hint_for_a_mistyped_recipient() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let trader = user(&store, "trader");
+    let sender = prosumer(&db, 10.0).await;
+    let recipient = format!("solar_{}", Uuid::new_v4().simple());
+    db.create_prosumer(Prosumer {
+        address: recipient.clone(),
+        name: "Solar recipient".to_string(),
+        energy_generated: 0.0,
+        energy_consumed: 0.0,
+        grid_tokens: 0.0,
+        watt_tokens: 0.0,
+        is_active: true,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    })
+    .await
+    .unwrap();
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(web::resource("/transfer").route(web::post().to(handlers::transfer_tokens))),
+    )
+    .await;
+    let transfer = |to: &str, auth: Option<String>| {
+        let mut request = test::TestRequest::post().uri("/transfer").set_json(&json!({
+            "from_address": sender,
+            "to_address": to,
+            "amount": 1.0,
+            "token_type": "grid_tokens"
+        }));
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+    let error = |response| async move {
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+        body["error"].as_str().unwrap().to_string()
+    };
+
+    // One character wrong, at the start (outside the shared prefix) and in the middle
+    let leading_typo = format!("x{}", &recipient[1..]);
+    let middle_typo = format!("{}x{}", &recipient[..10], &recipient[11..]);
+    for typo in [&leading_typo, &middle_typo] {
+        let response = test::call_service(&app, transfer(typo, Some(bearer(&store, &trader)))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            error(response).await,
+            format!("Prosumer '{}' not found (did you mean '{}'?)", typo, recipient)
+        );
+
+        // Anonymous callers aren't told which addresses exist
+        let response = test::call_service(&app, transfer(typo, None)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error(response).await, format!("Prosumer '{}' not found", typo));
+    }
+
+    // Nothing registered is close to an unrelated address
+    let distant = format!("wind_{}", Uuid::new_v4().simple());
+    let response = test::call_service(&app, transfer(&distant, Some(bearer(&store, &trader)))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(error(response).await, format!("Prosumer '{}' not found", distant));
+}
+
+#[ntex::test]
+async fn balance_endpoint_reports_a_known_prosumer_and_404s_an_unknown_one() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let sender = prosumer(&db, 10.0).await;
+    let recipient = prosumer(&db, 0.0).await;
+    db.transfer_tokens(&sender, &recipient, 4.0, "grid_tokens").await.unwrap();
+    db.create_order(common::order(&db, &sender, "buy", 10.0, 0.1)).await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/tokens/{address}/balance").route(web::get().to(handlers::get_prosumer_balance))),
+    )
+    .await;
+    let get = |address: &str| test::TestRequest::get().uri(&format!("/tokens/{}/balance", address)).to_request();
+
+    let response = test::call_service(&app, get(&sender)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let balance: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(balance["address"], json!(sender));
+    assert_eq!(balance["grid_tokens"], 5.0);
+    assert_eq!(balance["reserved_grid_tokens"], 1.0);
+    assert_eq!(balance["watt_tokens"], 0.0);
+    assert_eq!(balance["staked"], 0.0);
+
+    let response = test::call_service(&app, get(&recipient)).await;
+    let balance: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(balance["grid_tokens"], 4.0);
+
+    let response = test::call_service(&app, get(&format!("0x{}", Uuid::new_v4().simple()))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[ntex::test]
+async fn bulk_balances_list_unknown_addresses_and_cap_the_batch() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let first = prosumer(&db, 10.0).await;
+    let second = prosumer(&db, 3.0).await;
+    let unknown = format!("0x{}", Uuid::new_v4().simple());
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/prosumers/balances").route(web::post().to(handlers::get_prosumer_balances))),
+    )
+    .await;
+    let query = |addresses: Vec<String>| {
+        test::TestRequest::post()
+            .uri("/prosumers/balances")
+            .set_json(&json!({ "addresses": addresses }))
+            .to_request()
+    };
+
+    // In the caller's order, with a repeated address reported once
+    let response = test::call_service(&app, query(vec![second.clone(), unknown.clone(), first.clone(), format!(" {} ", second)])).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    let balances = body["balances"].as_array().unwrap();
+    assert_eq!(balances.len(), 2);
+    assert_eq!((&balances[0]["address"], &balances[0]["grid_tokens"]), (&json!(second), &json!(3.0)));
+    assert_eq!((&balances[1]["address"], &balances[1]["grid_tokens"]), (&json!(first), &json!(10.0)));
+    assert_eq!(body["not_found"], json!([unknown]));
+
+    // The cap counts distinct addresses
+    let batch = |count: usize| (0..count).map(|i| format!("0xmissing{}", i)).collect::<Vec<_>>();
+    let response = test::call_service(&app, query(batch(MAX_BULK_BALANCE_ADDRESSES))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(body["not_found"].as_array().unwrap().len(), MAX_BULK_BALANCE_ADDRESSES);
+    let response = test::call_service(&app, query(batch(MAX_BULK_BALANCE_ADDRESSES + 1))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = test::call_service(&app, query(vec![first.clone(); MAX_BULK_BALANCE_ADDRESSES + 1])).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[ntex::test]
+async fn dry_run_reports_failures_and_leaves_balances_and_transfers_alone() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let sender = prosumer(&db, 10.0).await;
+    let recipient = prosumer(&db, 0.0).await;
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .service(web::resource("/transfer/validate").route(web::post().to(handlers::validate_transfer))),
+    )
+    .await;
+    let validate = |to: &str, amount: f64| {
+        test::TestRequest::post()
+            .uri("/transfer/validate")
+            .set_json(&json!({ "from_address": sender, "to_address": to, "amount": amount, "token_type": "grid_tokens" }))
+            .to_request()
+    };
+    let transfers = |address: String| {
+        let db = db.clone();
+        async move {
+            let now = Utc::now();
+            db.get_account_statement(&address, now - Duration::hours(1), now + Duration::minutes(1))
+                .await
+                .unwrap()
+                .transfers
+                .len()
+        }
+    };
+    let sent_before = transfers(sender.clone()).await;
+
+    let response = test::call_service(&app, validate(&recipient, 4.0)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let preview: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(preview["would_succeed"], true);
+    assert!(preview["reason"].is_null());
+    assert_eq!((preview["from_balance"].as_f64(), preview["to_balance"].as_f64()), (Some(6.0), Some(4.0)));
+
+    let unknown = format!("0x{}", Uuid::new_v4().simple());
+    for (to, amount) in [(recipient.as_str(), 50.0), (recipient.as_str(), -1.0), (unknown.as_str(), 1.0)] {
+        let response = test::call_service(&app, validate(to, amount)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let preview: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+        assert_eq!(preview["would_succeed"], false, "{} {}: {}", to, amount, preview);
+        assert!(preview["reason"].as_str().is_some_and(|reason| !reason.is_empty()), "{}", preview);
+        assert_eq!(preview["from_balance"].as_f64(), Some(10.0), "{}", preview);
+    }
+
+    // Nothing was moved or recorded
+    assert_eq!(db.get_prosumer_balance(&sender).await.unwrap().grid_tokens, 10.0);
+    assert_eq!(db.get_prosumer_balance(&recipient).await.unwrap().grid_tokens, 0.0);
+    assert_eq!(transfers(sender).await, sent_before);
+    assert_eq!(transfers(recipient).await, 0);
+}
+
+#[ntex::test]
+async fn faucet_credits_only_its_configured_amounts_as_recorded_mints() {
+    let Some(db) = test_db().await else { return };
+    let db = Arc::new(db);
+    let address = prosumer(&db, 0.0).await;
+    let app = |config: FaucetConfig| {
+        test::init_service(
+            App::new()
+                .state(db.clone())
+                .state(Arc::new(Faucet::new(config)))
+                .service(web::resource("/faucet").route(web::post().to(handlers::faucet))),
+        )
+    };
+    // Amounts in the request are not the faucet's to take
+    let drip = || {
+        test::TestRequest::post()
+            .uri("/faucet")
+            .set_json(&json!({ "address": address, "grid_tokens": 1_000_000.0, "watt_tokens": 1_000_000.0 }))
+            .to_request()
+    };
+
+    let disabled = app(FaucetConfig::default()).await;
+    let response = test::call_service(&disabled, drip()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let enabled = app(FaucetConfig { enabled: true, grid_amount: 5.0, watt_amount: 2.0, cooldown_secs: 3_600 }).await;
+    let response = test::call_service(&enabled, drip()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(body["credited"], json!({ "grid_tokens": 5.0, "watt_tokens": 2.0 }));
+    let balance = db.get_prosumer_balance(&address).await.unwrap();
+    assert_eq!((balance.grid_tokens, balance.watt_tokens), (5.0, 2.0));
+
+    let response = test::call_service(&enabled, drip()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(db.get_prosumer_balance(&address).await.unwrap().grid_tokens, 5.0);
+
+    let now = Utc::now();
+    let mut minted: Vec<_> = db
+        .get_account_statement(&address, now - Duration::hours(1), now + Duration::minutes(1))
+        .await
+        .unwrap()
+        .transfers
+        .into_iter()
+        .map(|t| (t.from_address, t.to_address, t.token_type, t.amount))
+        .collect();
+    minted.sort_by(|a, b| a.2.cmp(&b.2));
+    assert_eq!(
+        minted,
+        vec![
+            (MINT_ADDRESS.to_string(), address.clone(), "grid_tokens".to_string(), 5.0),
+            (MINT_ADDRESS.to_string(), address.clone(), "watt_tokens".to_string(), 2.0),
+        ]
+    );
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use common::{auth_store, bearer, isolated_test_db, user};
+use energy_trading_api::database::Webhook;
+use energy_trading_api::handlers;
+use energy_trading_api::webhooks;
+use ntex::http::{header, StatusCode};
+use ntex::time::{sleep, Millis};
+use ntex::util::Bytes;
+use ntex::web::{self, test, App, HttpRequest, HttpResponse};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+// What the mock receiver saw: the raw body and the event and signature headers
+#[derive(Clone, Debug)]
+struct Delivery {
+    body: Bytes,
+    event: Option<String>,
+    signature: Option<String>,
+}
+
+type Received = Arc<Mutex<Vec<Delivery>>>;
+
+async fn receive(received: web::types::State<Received>, req: HttpRequest, body: Bytes) -> HttpResponse {
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    received.lock().unwrap().push(Delivery {
+        body,
+        event: header(webhooks::EVENT_HEADER),
+        signature: header(webhooks::SIGNATURE_HEADER),
+    });
+    HttpResponse::Ok().finish()
+}
+
+#[ntex::test]
+async fn webhook_targets_must_be_public_http_urls() {
+    let Some(db) = isolated_test_db().await else { return };
+    let store = auth_store();
+    let owner = user(&store, "trader");
+    let app = test::init_service(
+        App::new()
+            .state(Arc::new(db))
+            .state(store.clone())
+            .service(web::resource("/webhooks").route(web::post().to(handlers::create_webhook))),
+    )
+    .await;
+    let create = |url: &str, auth: Option<String>| {
+        let mut request = test::TestRequest::post()
+            .uri("/webhooks")
+            .set_json(&json!({ "url": url, "events": [webhooks::TRADE_EXECUTED] }));
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+
+    let response = test::call_service(&app, create("https://93.184.216.34/hooks", None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    for url in [
+        "ftp://93.184.216.34/hooks",
+        "file:///etc/passwd",
+        "http://127.0.0.1:8080/hooks",
+        "http://localhost/hooks",
+        "http://169.254.169.254/latest/meta-data",
+        "http://10.0.0.5/hooks",
+        "http://192.168.1.1/hooks",
+        "http://100.64.0.1/hooks",
+        "http://[::1]/hooks",
+        "http://[fd00::1]/hooks",
+        "http://[::ffff:127.0.0.1]/hooks",
+        "not a url",
+    ] {
+        let response = test::call_service(&app, create(url, Some(bearer(&store, &owner)))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", url);
+    }
+
+    let response = test::call_service(&app, create("https://93.184.216.34/hooks", Some(bearer(&store, &owner)))).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(body["webhook"]["owner_id"], json!(owner.id));
+}
+
+#[ntex::test]
+async fn webhooks_are_listed_and_deleted_only_by_their_owner_or_an_admin() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db);
+    let store = auth_store();
+    let owner = user(&store, "trader");
+    let stranger = user(&store, "trader");
+    let admin = user(&store, "admin");
+    let events = vec![webhooks::TRADE_EXECUTED.to_string()];
+    let owned = db.create_webhook("https://93.184.216.34/a", &events, "secret", &owner.id).await.unwrap();
+    let other = db.create_webhook("https://93.184.216.34/b", &events, "secret", &stranger.id).await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .state(db.clone())
+            .state(store.clone())
+            .service(web::resource("/webhooks").route(web::get().to(handlers::get_webhooks)))
+            .service(web::resource("/webhooks/{webhook_id}").route(web::delete().to(handlers::delete_webhook))),
+    )
+    .await;
+    let list = |auth: Option<String>| {
+        let mut request = test::TestRequest::get().uri("/webhooks");
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        request.to_request()
+    };
+    let delete = |hook: &Webhook, auth: String| {
+        test::TestRequest::delete()
+            .uri(&format!("/webhooks/{}", hook.id))
+            .header(header::AUTHORIZATION, auth)
+            .to_request()
+    };
+
+    let response = test::call_service(&app, list(None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = test::call_service(&app, list(Some(bearer(&store, &owner)))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed: Vec<Value> = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["id"], json!(owned.id));
+
+    let response = test::call_service(&app, list(Some(bearer(&store, &admin)))).await;
+    let listed: Vec<Value> = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(listed.len(), 2);
+
+    // Someone else's webhook is indistinguishable from a missing one
+    let response = test::call_service(&app, delete(&other, bearer(&store, &owner))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = test::call_service(&app, delete(&owned, bearer(&store, &owner))).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = test::call_service(&app, delete(&other, bearer(&store, &admin))).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(db.get_webhooks(None).await.unwrap().is_empty());
+}
+
+// The receiver listens on loopback, which the API refuses as a target, so it is registered
+// directly in the database
+#[ntex::test]
+async fn events_are_delivered_signed_to_subscribed_webhooks() {
+    let Some(db) = isolated_test_db().await else { return };
+    let db = Arc::new(db);
+    let received: Received = Arc::default();
+    let receiver = {
+        let received = received.clone();
+        web::test::server(move || {
+            App::new()
+                .state(received.clone())
+                .service(web::resource("/hooks").route(web::post().to(receive)))
+        })
+    };
+    let secret = "delivery-secret";
+    db.create_webhook(&receiver.url("/hooks"), &[webhooks::TRADE_EXECUTED.to_string()], secret, "owner")
+        .await
+        .unwrap();
+
+    webhooks::notify(&db, webhooks::ORDER_CREATED, &json!({ "ignored": true }));
+    webhooks::notify(&db, webhooks::TRADE_EXECUTED, &json!({ "trade_id": "t-1", "energy_amount": 5.0 }));
+    for _ in 0..50 {
+        if !received.lock().unwrap().is_empty() {
+            break;
+        }
+        sleep(Millis(100)).await;
+    }
+    // Give a wrongly delivered ORDER_CREATED event the chance to arrive too
+    sleep(Millis(200)).await;
+
+    let deliveries = received.lock().unwrap().clone();
+    assert_eq!(deliveries.len(), 1, "{:?}", deliveries);
+    let delivery = &deliveries[0];
+    let payload: Value = serde_json::from_slice(&delivery.body).unwrap();
+    assert_eq!(payload["event"], webhooks::TRADE_EXECUTED);
+    assert_eq!(payload["data"], json!({ "trade_id": "t-1", "energy_amount": 5.0 }));
+    assert!(payload["timestamp"].is_string());
+    assert_eq!(delivery.event.as_deref(), Some(webhooks::TRADE_EXECUTED));
+    assert_eq!(delivery.signature, Some(webhooks::sign(secret, &delivery.body)));
+
+    receiver.stop().await;
+}